@@ -54,19 +54,64 @@ async fn main() -> Result<(), String> {
     }
 
     let token_manager = Arc::new(proxy::TokenManager::new(data_dir));
-    token_manager
-        .update_sticky_config(proxy_config.scheduling.clone())
-        .await;
+    // 选中账号变化时同步桌面端的当前账号 (后台执行，不阻塞请求)
+    token_manager.set_selection_hook(Box::new(|selected| {
+        let account_id = selected.account_id.clone();
+        tokio::spawn(async move {
+            if let Err(e) = modules::account::set_current_account_id(&account_id) {
+                tracing::debug!("Failed to update current_account_id: {}", e);
+            }
+        });
+    }));
+    // 已保存的调度配置优先，首次启动时使用配置文件中的值
+    if !token_manager.has_saved_sticky_config() {
+        token_manager
+            .update_sticky_config(proxy_config.scheduling.clone())
+            .await;
+    }
 
-    let active_accounts = token_manager
+    let load_report = token_manager
         .load_accounts()
         .await
         .map_err(|e| format!("failed to load accounts: {}", e))?;
+    if !load_report.failed.is_empty() {
+        tracing::warn!(
+            "{} account file(s) could not be loaded; see the warnings above",
+            load_report.failed.len()
+        );
+    }
+    let active_accounts = load_report.active();
 
     if active_accounts == 0 {
         tracing::warn!("no active accounts found; open the web console to add accounts");
     }
 
+    // 后台预热：提前刷新即将过期的 token 并解析缺失的 project_id，避免首批请求变慢
+    let warm_up_manager = token_manager.clone();
+    tokio::spawn(async move {
+        warm_up_manager.warm_up(4).await;
+    });
+
+    // 后台任务句柄需存活到进程退出 (drop 即停止)；退出时由 shutdown 统一停止
+    let _session_sweeper =
+        token_manager.start_session_sweeper(std::time::Duration::from_secs(300));
+    // 开启会话持久化时，绑定变化后最多 5 秒写入 sessions.json
+    let _session_persister =
+        token_manager.start_session_persister(std::time::Duration::from_secs(5));
+    let shutdown_manager = token_manager.clone();
+    let _token_refresher =
+        token_manager.start_background_refresh(std::time::Duration::from_secs(60));
+    let _account_watcher = token_manager.watch_accounts();
+    // 因临时故障（如 Google 侧 invalid_grant 事故）被禁用的账号，冷却后自动尝试恢复
+    let _account_reviver =
+        token_manager.start_account_reviver(std::time::Duration::from_secs(300));
+    // 排空中的账号在会话全部结束或超时后自动暂停 (需开启 pause_when_drained)
+    let _drain_monitor =
+        token_manager.start_drain_monitor(std::time::Duration::from_secs(30));
+    // 使用统计写回账号文件的 proxy_stats，每个账号最多每分钟写一次
+    let _stats_flusher =
+        token_manager.start_stats_flusher(std::time::Duration::from_secs(10));
+
     let monitor = Arc::new(proxy::monitor::ProxyMonitor::new(1000));
     monitor.set_enabled(proxy_config.enable_logging);
 
@@ -98,6 +143,13 @@ async fn main() -> Result<(), String> {
     tracing::info!("shutdown requested, stopping server...");
     server.stop();
     let _ = handle.await;
+    // 停止所有后台任务，并写回会话绑定与使用统计
+    if let Err(e) = shutdown_manager
+        .shutdown(std::time::Duration::from_secs(10))
+        .await
+    {
+        tracing::warn!("token manager shutdown incomplete: {}", e);
+    }
 
     Ok(())
 }
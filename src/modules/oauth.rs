@@ -22,6 +22,113 @@ const USERINFO_URL: &str = "https://www.googleapis.com/oauth2/v2/userinfo";
 
 const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 
+const DEVICE_CODE_URL: &str = "https://oauth2.googleapis.com/device/code";
+
+const REVOKE_URL: &str = "https://oauth2.googleapis.com/revoke";
+
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+const JWT_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// OAuth 调用失败的类别，调用方据此决定是否重试
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OAuthErrorKind {
+    /// 连接失败，请求没有得到响应
+    Connect,
+    /// 请求超时
+    Timeout,
+    /// 服务器返回了非成功的状态码
+    Status(u16),
+    /// 其他错误，例如响应解析失败
+    Other,
+}
+
+/// 带类别的 OAuth 调用错误，`message` 与原先返回的错误字符串相同
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OAuthError {
+    pub kind: OAuthErrorKind,
+    pub message: String,
+}
+
+impl OAuthError {
+    pub fn new(kind: OAuthErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// 请求没有得到响应：按 reqwest 的错误区分超时和连接失败
+    pub fn request_failed(context: &str, error: reqwest::Error) -> Self {
+        let kind = if error.is_timeout() {
+            OAuthErrorKind::Timeout
+        } else if error.is_connect() || error.is_request() {
+            OAuthErrorKind::Connect
+        } else {
+            OAuthErrorKind::Other
+        };
+        Self::new(kind, format!("{}: {}", context, error))
+    }
+
+    /// 服务器返回了错误状态码，消息里带上状态码和响应内容
+    async fn status(context: &str, response: reqwest::Response) -> Self {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_default();
+        Self::new(
+            OAuthErrorKind::Status(status.as_u16()),
+            format!("{} ({}): {}", context, status, error_text),
+        )
+    }
+}
+
+impl std::fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<String> for OAuthError {
+    fn from(message: String) -> Self {
+        Self::new(OAuthErrorKind::Other, message)
+    }
+}
+
+impl From<OAuthError> for String {
+    fn from(error: OAuthError) -> Self {
+        error.message
+    }
+}
+
+/// 授权时申请的 scope
+const SCOPES: [&str; 5] = [
+    "https://www.googleapis.com/auth/cloud-platform",
+    "https://www.googleapis.com/auth/userinfo.email",
+    "https://www.googleapis.com/auth/userinfo.profile",
+    "https://www.googleapis.com/auth/cclog",
+    "https://www.googleapis.com/auth/experimentsandconfigs",
+];
+
+/// 设备授权流程用到的 OAuth 端点
+/// 默认指向 Google，测试时可以换成本地的模拟服务
+#[derive(Debug, Clone)]
+pub struct OAuthEndpoints {
+    pub device_code_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    pub revoke_url: String,
+}
+
+impl Default for OAuthEndpoints {
+    fn default() -> Self {
+        Self {
+            device_code_url: DEVICE_CODE_URL.to_string(),
+            token_url: TOKEN_URL.to_string(),
+            userinfo_url: USERINFO_URL.to_string(),
+            revoke_url: REVOKE_URL.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenResponse {
     pub access_token: String,
@@ -64,13 +171,7 @@ impl UserInfo {
 
 /// 生成 OAuth 授权 URL
 pub fn get_auth_url(redirect_uri: &str) -> String {
-    let scopes = vec![
-        "https://www.googleapis.com/auth/cloud-platform",
-        "https://www.googleapis.com/auth/userinfo.email",
-        "https://www.googleapis.com/auth/userinfo.profile",
-        "https://www.googleapis.com/auth/cclog",
-        "https://www.googleapis.com/auth/experimentsandconfigs"
-    ].join(" ");
+    let scopes = SCOPES.join(" ");
 
     let params = vec![
         ("client_id", CLIENT_ID.as_str()),
@@ -136,23 +237,46 @@ pub async fn exchange_code(code: &str, redirect_uri: &str) -> Result<TokenRespon
 
 /// 使用 refresh_token 刷新 access_token
 pub async fn refresh_access_token(refresh_token: &str) -> Result<TokenResponse, String> {
-    let client = crate::utils::http::create_client(15);
-    
-    let params = [
-        ("client_id", CLIENT_ID.as_str()),
-        ("client_secret", CLIENT_SECRET.as_str()),
-        ("refresh_token", refresh_token),
-        ("grant_type", "refresh_token"),
-    ];
+    refresh_access_token_with(&OAuthEndpoints::default(), None, None, refresh_token)
+        .await
+        .map_err(String::from)
+}
+
+/// 使用指定的 OAuth 客户端刷新 access_token
+/// `client` 为 (client_id, client_secret)，为 None 时使用全局客户端
+/// refresh_token 只对签发它的客户端有效，用错客户端会得到 invalid_client
+/// `proxy` 为出站代理地址，为 None 时使用全局上游代理配置
+pub async fn refresh_access_token_with(
+    endpoints: &OAuthEndpoints,
+    client: Option<(&str, Option<&str>)>,
+    proxy: Option<&str>,
+    refresh_token: &str,
+) -> Result<TokenResponse, OAuthError> {
+    let http_client = match proxy {
+        Some(proxy) => crate::utils::http::create_client_via_proxy(15, proxy)
+            .map_err(|e| format!("刷新请求失败: {}", e))?,
+        None => crate::utils::http::create_client(15),
+    };
+
+    let (client_id, client_secret) = match client {
+        Some((client_id, client_secret)) => (client_id, client_secret),
+        None => (CLIENT_ID.as_str(), Some(CLIENT_SECRET.as_str())),
+    };
+    let mut params = vec![("client_id", client_id)];
+    if let Some(client_secret) = client_secret {
+        params.push(("client_secret", client_secret));
+    }
+    params.push(("refresh_token", refresh_token));
+    params.push(("grant_type", "refresh_token"));
 
     crate::modules::logger::log_info("正在刷新 Token...");
     
-    let response = client
-        .post(TOKEN_URL)
+    let response = http_client
+        .post(&endpoints.token_url)
         .form(&params)
         .send()
         .await
-        .map_err(|e| format!("刷新请求失败: {}", e))?;
+        .map_err(|e| OAuthError::request_failed("刷新请求失败", e))?;
 
     if response.status().is_success() {
         let token_data = response
@@ -163,17 +287,53 @@ pub async fn refresh_access_token(refresh_token: &str) -> Result<TokenResponse,
         crate::modules::logger::log_info(&format!("Token 刷新成功！有效期: {} 秒", token_data.expires_in));
         Ok(token_data)
     } else {
-        let error_text = response.text().await.unwrap_or_default();
-        Err(format!("刷新失败: {}", error_text))
+        // 带上状态码，以便调用方区分 5xx 等可重试的错误
+        Err(OAuthError::status("刷新失败", response).await)
+    }
+}
+
+/// 用服务账号签名的 JWT 断言换取 access_token（JWT bearer 授权）
+/// 错误格式与 refresh_token 刷新一致，调用方可以用同一套规则分类
+pub async fn exchange_jwt_assertion(
+    token_uri: &str,
+    proxy: Option<&str>,
+    assertion: &str,
+) -> Result<TokenResponse, OAuthError> {
+    let http_client = match proxy {
+        Some(proxy) => crate::utils::http::create_client_via_proxy(15, proxy)
+            .map_err(|e| format!("刷新请求失败: {}", e))?,
+        None => crate::utils::http::create_client(15),
+    };
+
+    let params = [("grant_type", JWT_BEARER_GRANT_TYPE), ("assertion", assertion)];
+    let response = http_client
+        .post(token_uri)
+        .form(&params)
+        .send()
+        .await
+        .map_err(|e| OAuthError::request_failed("刷新请求失败", e))?;
+
+    if response.status().is_success() {
+        response
+            .json::<TokenResponse>()
+            .await
+            .map_err(|e| format!("刷新数据解析失败: {}", e).into())
+    } else {
+        Err(OAuthError::status("刷新失败", response).await)
     }
 }
 
 /// 获取用户信息
 pub async fn get_user_info(access_token: &str) -> Result<UserInfo, String> {
+    get_user_info_from(USERINFO_URL, access_token).await
+}
+
+/// 从指定的 userinfo 端点获取用户信息
+pub async fn get_user_info_from(userinfo_url: &str, access_token: &str) -> Result<UserInfo, String> {
     let client = crate::utils::http::create_client(15);
     
     let response = client
-        .get(USERINFO_URL)
+        .get(userinfo_url)
         .bearer_auth(access_token)
         .send()
         .await
@@ -189,6 +349,152 @@ pub async fn get_user_info(access_token: &str) -> Result<UserInfo, String> {
     }
 }
 
+/// 在 Google 撤销 access_token 或 refresh_token
+/// 撤销 refresh_token 会同时让它换出的 access_token 失效
+pub async fn revoke_token(token: &str) -> Result<(), String> {
+    revoke_token_with(&OAuthEndpoints::default(), None, token)
+        .await
+        .map_err(String::from)
+}
+
+/// 经由指定端点和出站代理撤销 Token
+/// Token 放在表单里而不是 URL 中，错误信息和日志都不会带上它
+pub async fn revoke_token_with(endpoints: &OAuthEndpoints, proxy: Option<&str>, token: &str) -> Result<(), OAuthError> {
+    let client = match proxy {
+        Some(proxy) => crate::utils::http::create_client_via_proxy(15, proxy)
+            .map_err(|e| format!("撤销请求失败: {}", e))?,
+        None => crate::utils::http::create_client(15),
+    };
+
+    let response = client
+        .post(&endpoints.revoke_url)
+        .form(&[("token", token)])
+        .send()
+        .await
+        // reqwest 的错误信息只包含 URL，不包含表单内容
+        .map_err(|e| OAuthError::request_failed("撤销请求失败", e))?;
+
+    let status = response.status();
+    // 400 表示 Token 已被撤销或已失效，目的已经达到
+    if status.is_success() || status == reqwest::StatusCode::BAD_REQUEST {
+        Ok(())
+    } else {
+        Err(OAuthError::status("撤销失败", response).await)
+    }
+}
+
+/// 设备授权 (Device Authorization Grant) 的授权码
+/// 用户在另一台设备上打开 `verification_url` 并输入 `user_code` 完成授权
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceCode {
+    pub device_code: String,
+    pub user_code: String,
+    #[serde(alias = "verification_uri")]
+    pub verification_url: String,
+    /// device_code 的有效期（秒）
+    pub expires_in: i64,
+    /// 轮询间隔（秒），未返回时按 RFC 8628 默认 5 秒
+    #[serde(default = "default_poll_interval")]
+    pub interval: u64,
+}
+
+fn default_poll_interval() -> u64 {
+    5
+}
+
+/// 轮询设备授权的结果
+#[derive(Debug)]
+pub enum DeviceTokenPoll {
+    /// 用户还没有完成授权
+    Pending,
+    /// 轮询太频繁，需要加大间隔
+    SlowDown,
+    /// device_code 已过期，需要重新发起授权
+    Expired,
+    /// 用户拒绝了授权
+    Denied,
+    /// 授权完成
+    Granted(TokenResponse),
+}
+
+#[derive(Deserialize)]
+struct OAuthErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// 发起设备授权，获取 user_code 与验证地址
+pub async fn request_device_code(endpoints: &OAuthEndpoints) -> Result<DeviceCode, String> {
+    let client = crate::utils::http::create_client(15);
+    let scopes = SCOPES.join(" ");
+
+    let params = [
+        ("client_id", CLIENT_ID.as_str()),
+        ("scope", scopes.as_str()),
+    ];
+
+    let response = client
+        .post(&endpoints.device_code_url)
+        .form(&params)
+        .send()
+        .await
+        .map_err(|e| format!("设备授权请求失败: {}", e))?;
+
+    if response.status().is_success() {
+        response.json::<DeviceCode>()
+            .await
+            .map_err(|e| format!("设备授权解析失败: {}", e))
+    } else {
+        let error_text = response.text().await.unwrap_or_default();
+        Err(format!("设备授权失败: {}", error_text))
+    }
+}
+
+/// 用 device_code 轮询一次 Token
+/// 授权未完成、需要降速、过期和被拒绝都作为正常状态返回，只有其他错误返回 Err
+pub async fn poll_device_token(endpoints: &OAuthEndpoints, device_code: &str) -> Result<DeviceTokenPoll, String> {
+    let client = crate::utils::http::create_client(15);
+
+    let params = [
+        ("client_id", CLIENT_ID.as_str()),
+        ("client_secret", CLIENT_SECRET.as_str()),
+        ("device_code", device_code),
+        ("grant_type", DEVICE_CODE_GRANT_TYPE),
+    ];
+
+    let response = client
+        .post(&endpoints.token_url)
+        .form(&params)
+        .send()
+        .await
+        .map_err(|e| format!("设备授权轮询失败: {}", e))?;
+
+    if response.status().is_success() {
+        let token_res = response.json::<TokenResponse>()
+            .await
+            .map_err(|e| format!("Token 解析失败: {}", e))?;
+        return Ok(DeviceTokenPoll::Granted(token_res));
+    }
+
+    // Google 对未完成的授权返回 428，对其余状态返回 400/403，统一按 error 字段判断
+    let error_text = response.text().await.unwrap_or_default();
+    match serde_json::from_str::<OAuthErrorResponse>(&error_text) {
+        Ok(error) => match error.error.as_str() {
+            "authorization_pending" => Ok(DeviceTokenPoll::Pending),
+            "slow_down" => Ok(DeviceTokenPoll::SlowDown),
+            "expired_token" => Ok(DeviceTokenPoll::Expired),
+            "access_denied" => Ok(DeviceTokenPoll::Denied),
+            _ => Err(format!(
+                "设备授权失败: {} {}",
+                error.error,
+                error.error_description.unwrap_or_default()
+            )),
+        },
+        Err(_) => Err(format!("设备授权失败: {}", error_text)),
+    }
+}
+
 /// 检查并在需要时刷新 Token
 /// 返回最新的 access_token
 pub async fn ensure_fresh_token(
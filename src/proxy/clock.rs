@@ -0,0 +1,110 @@
+// 时钟抽象 - 让过期、冷却和 TTL 相关逻辑可在测试中控制时间
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// 当前时间的来源
+///
+/// 生产环境使用 [`SystemClock`]；测试使用 [`MockClock`] 手动推进时间，
+/// 无需构造人为的时间戳或真正等待。
+pub trait Clock: Send + Sync {
+    /// 当前 Unix 时间戳(秒)
+    fn now(&self) -> i64;
+
+    /// 当前 Unix 时间戳(毫秒)
+    fn now_millis(&self) -> i64 {
+        self.now() * 1000
+    }
+
+    /// 当前时间的 SystemTime 形式
+    fn system_time(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(self.now().max(0) as u64)
+    }
+}
+
+/// 共享的时钟引用
+pub type SharedClock = Arc<dyn Clock>;
+
+/// 系统时钟
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+    /// 以共享引用形式返回系统时钟
+    pub fn shared() -> SharedClock {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+
+    fn now_millis(&self) -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// 手动推进的时钟，用于测试
+#[derive(Debug)]
+pub struct MockClock {
+    now: AtomicI64,
+}
+
+impl MockClock {
+    /// 从指定 Unix 时间戳开始
+    pub fn new(start: i64) -> Arc<Self> {
+        Arc::new(Self {
+            now: AtomicI64::new(start),
+        })
+    }
+
+    /// 从当前系统时间开始，便于与真实时间戳的数据混用
+    pub fn starting_now() -> Arc<Self> {
+        Self::new(chrono::Utc::now().timestamp())
+    }
+
+    /// 前进 `seconds` 秒
+    pub fn advance(&self, seconds: i64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+
+    /// 跳到指定时间戳
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_advances_only_when_told() {
+        let clock = MockClock::new(1_700_000_000);
+        assert_eq!(clock.now(), 1_700_000_000);
+        assert_eq!(clock.now_millis(), 1_700_000_000_000);
+
+        clock.advance(90);
+        assert_eq!(clock.now(), 1_700_000_090);
+        assert_eq!(
+            clock.system_time(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_090)
+        );
+
+        clock.set(5);
+        assert_eq!(clock.now(), 5);
+    }
+}
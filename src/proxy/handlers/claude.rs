@@ -17,6 +17,7 @@ use crate::proxy::mappers::claude::{
 };
 use crate::proxy::session_manager::SessionManager;
 use crate::proxy::server::AppState;
+use crate::proxy::token_manager::RequestOutcome;
 use axum::http::HeaderMap;
 
 const MAX_RETRY_ATTEMPTS: usize = 3;
@@ -505,7 +506,7 @@ pub async fn handle_messages(
         // 使用 force_rotate_next 而不是 attempt > 0，这样只有在确定需要轮换时才轮换账号
         let force_rotate_token = force_rotate_next;
         let selected = match token_manager
-            .get_token(quota_group, &config.request_type, force_rotate_token, session_id)
+            .get_token(quota_group, &config.request_type, Some(&config.final_model), force_rotate_token, session_id)
             .await
         {
             Ok(t) => t,
@@ -530,10 +531,11 @@ pub async fn handle_messages(
 
         let access_token = selected.access_token;
         let project_id = selected.project_id;
-        let email = selected.email;
+        let selected_name = selected.display_name;
         let account_id = selected.account_id;
+        let in_flight = selected.in_flight;
 
-        info!("✓ Using account: {} (type: {})", email, config.request_type);
+        info!("✓ Using account: {} (type: {})", selected_name, config.request_type);
         
         
         // ===== 【优化】后台任务智能检测与降级 =====
@@ -623,6 +625,7 @@ pub async fn handle_messages(
     let method = if is_stream { "streamGenerateContent" } else { "generateContent" };
     let query = if is_stream { Some("alt=sse") } else { None };
 
+    let started_at = std::time::Instant::now();
     let response = match upstream.call_v1_internal(
         method,
         &access_token,
@@ -641,11 +644,21 @@ pub async fn handle_messages(
         
         // 成功
         if status.is_success() {
+            token_manager
+                .report_result(
+                    quota_group,
+                    &config.request_type,
+                    Some(&config.final_model),
+                    &account_id,
+                    RequestOutcome::Success { latency_ms: started_at.elapsed().as_millis() as u64 },
+                )
+                .await;
+
             // 处理流式响应
             if request.stream {
                 let stream = response.bytes_stream();
                 let gemini_stream = Box::pin(stream);
-                let claude_stream = create_claude_sse_stream(gemini_stream, trace_id, email);
+                let claude_stream = create_claude_sse_stream(gemini_stream, trace_id, selected_name);
 
                 // 转换为 Bytes stream
                 let sse_stream = claude_stream.map(|result| -> Result<Bytes, std::io::Error> {
@@ -660,7 +673,7 @@ pub async fn handle_messages(
                     .header(header::CONTENT_TYPE, "text/event-stream")
                     .header(header::CACHE_CONTROL, "no-cache")
                     .header(header::CONNECTION, "keep-alive")
-                    .body(Body::from_stream(sse_stream))
+                    .body(Body::from_stream(in_flight.hold(sse_stream)))
                     .unwrap();
             } else {
                 // 处理非流式响应
@@ -728,6 +741,7 @@ pub async fn handle_messages(
             token_manager.mark_rate_limited(
                 quota_group,
                 &config.request_type,
+                Some(&config.final_model),
                 &account_id,
                 status_code,
                 retry_after.as_deref(),
@@ -735,6 +749,13 @@ pub async fn handle_messages(
             );
         }
 
+        // 反馈请求结果（5xx 冷却、401 立即刷新 token）
+        if let Some(outcome) = RequestOutcome::from_error_status(status_code) {
+            token_manager
+                .report_result(quota_group, &config.request_type, Some(&config.final_model), &account_id, outcome)
+                .await;
+        }
+
         // 4. 处理 400 错误 (Thinking 签名失效)
         // 由于已经主动过滤,这个错误应该很少发生
         if status_code == 400
@@ -1022,7 +1043,7 @@ pub async fn handle_count_tokens(
 
     let input_tokens = match state
         .token_manager
-        .get_token("claude", &config.request_type, false, Some(&stable_session_id))
+        .get_token("claude", &config.request_type, Some(&config.final_model), false, Some(&stable_session_id))
         .await
     {
         Ok(selected) => match transform_claude_request_in(&request_with_mapped, &selected.project_id) {
@@ -6,6 +6,7 @@ use tracing::{debug, error, info};
 use crate::proxy::mappers::gemini::{wrap_request, unwrap_response};
 use crate::proxy::server::AppState;
 use crate::proxy::session_manager::SessionManager;
+use crate::proxy::token_manager::RequestOutcome;
  
 const MAX_RETRY_ATTEMPTS: usize = 3;
  
@@ -70,7 +71,7 @@ pub async fn handle_generate(
         // 4. 获取 Token (使用预计算的 session_id 和 force_rotate_next)
         let quota_group = "gemini";
         let selected = match token_manager
-            .get_token(quota_group, &config.request_type, force_rotate_next, Some(&stable_session_id))
+            .get_token(quota_group, &config.request_type, Some(&config.final_model), force_rotate_next, Some(&stable_session_id))
             .await
         {
             Ok(t) => t,
@@ -81,10 +82,11 @@ pub async fn handle_generate(
 
         let access_token = selected.access_token;
         let project_id = selected.project_id;
-        let email = selected.email;
+        let selected_name = selected.display_name;
         let account_id = selected.account_id;
+        let in_flight = selected.in_flight;
 
-        info!("✓ Using account: {} (type: {})", email, config.request_type);
+        info!("✓ Using account: {} (type: {})", selected_name, config.request_type);
 
         // 5. 包装请求 (project injection)
         let wrapped_body = wrap_request(&body, &project_id, &mapped_model);
@@ -93,6 +95,7 @@ pub async fn handle_generate(
         let query_string = if is_stream { Some("alt=sse") } else { None };
         let upstream_method = if is_stream { "streamGenerateContent" } else { "generateContent" };
 
+        let started_at = std::time::Instant::now();
         let response = match upstream
             .call_v1_internal(upstream_method, &access_token, wrapped_body, query_string)
             .await {
@@ -106,6 +109,16 @@ pub async fn handle_generate(
 
         let status = response.status();
         if status.is_success() {
+            token_manager
+                .report_result(
+                    quota_group,
+                    &config.request_type,
+                    Some(&config.final_model),
+                    &account_id,
+                    RequestOutcome::Success { latency_ms: started_at.elapsed().as_millis() as u64 },
+                )
+                .await;
+
             // 6. 响应处理
             if is_stream {
                 use axum::body::Body;
@@ -169,7 +182,7 @@ pub async fn handle_generate(
                     }
                 };
                 
-                let body = Body::from_stream(stream);
+                let body = Body::from_stream(in_flight.hold(stream));
                 return Ok(Response::builder()
                     .header("Content-Type", "text/event-stream")
                     .header("Cache-Control", "no-cache")
@@ -194,6 +207,13 @@ pub async fn handle_generate(
         let error_text = response.text().await.unwrap_or_else(|_| format!("HTTP {}", status_code));
         last_error = format!("HTTP {}: {}", status_code, error_text);
 
+        // 反馈请求结果（5xx 冷却、401 立即刷新 token）
+        if let Some(outcome) = RequestOutcome::from_error_status(status_code) {
+            token_manager
+                .report_result(quota_group, &config.request_type, Some(&config.final_model), &account_id, outcome)
+                .await;
+        }
+
         // 判断是否应该轮换账号
         fn should_rotate_account(status_code: u16) -> bool {
             match status_code {
@@ -212,6 +232,7 @@ pub async fn handle_generate(
             token_manager.mark_rate_limited(
                 quota_group,
                 &config.request_type,
+                Some(&config.final_model),
                 &account_id,
                 status_code,
                 retry_after.as_deref(),
@@ -222,7 +243,7 @@ pub async fn handle_generate(
             force_rotate_next = should_rotate_account(status_code);
             tracing::warn!(
                 "Gemini Upstream {} on account {}, will rotate: {}",
-                status_code, email, force_rotate_next
+                status_code, selected_name, force_rotate_next
             );
             continue;
         }
@@ -274,7 +295,7 @@ pub async fn handle_get_model(Path(model_name): Path<String>) -> impl IntoRespon
 pub async fn handle_count_tokens(State(state): State<AppState>, Path(_model_name): Path<String>, Json(_body): Json<Value>) -> Result<impl IntoResponse, (StatusCode, String)> {
     let _ = state
         .token_manager
-        .get_token("gemini", "agent", false, None)
+        .get_token("gemini", "agent", None, false, None)
         .await
         .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, format!("Token error: {}", e)))?;
     
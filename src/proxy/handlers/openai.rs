@@ -12,6 +12,7 @@ use crate::proxy::mappers::openai::{
 };
 use crate::proxy::server::AppState;
 use crate::proxy::TokenManager;
+use crate::proxy::token_manager::RequestOutcome;
 use crate::proxy::upstream::client::UpstreamClient;
 
 const MAX_RETRY_ATTEMPTS: usize = 3;
@@ -80,7 +81,7 @@ async fn execute_openai_request_v2(
 
     // 2. 获取 Token (使用传入的 session_id 和 force_rotate)
     let selected = match token_manager
-        .get_token(quota_group, &config.request_type, force_rotate, Some(session_id))
+        .get_token(quota_group, &config.request_type, Some(&config.final_model), force_rotate, Some(session_id))
         .await
     {
         Ok(t) => t,
@@ -94,10 +95,11 @@ async fn execute_openai_request_v2(
 
     let access_token = selected.access_token;
     let project_id = selected.project_id;
-    let email = selected.email;
+    let selected_name = selected.display_name;
     let account_id = selected.account_id;
+    let in_flight = selected.in_flight;
 
-    info!("✓ Using account: {} (type: {})", email, config.request_type);
+    info!("✓ Using account: {} (type: {})", selected_name, config.request_type);
 
     // 3. 转换请求
     let gemini_body = transform_openai_request(openai_req, &project_id, &mapped_model);
@@ -115,6 +117,7 @@ async fn execute_openai_request_v2(
     };
     let query_string = if is_stream { Some("alt=sse") } else { None };
 
+    let started_at = std::time::Instant::now();
     let response = match upstream
         .call_v1_internal(method, &access_token, gemini_body, query_string)
         .await
@@ -131,6 +134,16 @@ async fn execute_openai_request_v2(
 
     // 5. 处理成功响应
     if status.is_success() {
+        token_manager
+            .report_result(
+                quota_group,
+                &config.request_type,
+                Some(&config.final_model),
+                &account_id,
+                RequestOutcome::Success { latency_ms: started_at.elapsed().as_millis() as u64 },
+            )
+            .await;
+
         if is_stream {
             let gemini_stream = response.bytes_stream();
             let model_clone = openai_req.model.clone();
@@ -140,17 +153,17 @@ async fn execute_openai_request_v2(
                 ResponseFormat::Chat => {
                     use crate::proxy::mappers::openai::streaming::create_openai_sse_stream;
                     let stream = create_openai_sse_stream(Box::pin(gemini_stream), model_clone);
-                    Body::from_stream(stream)
+                    Body::from_stream(in_flight.hold(stream))
                 }
                 ResponseFormat::Codex => {
                     use crate::proxy::mappers::openai::streaming::create_codex_sse_stream;
                     let stream = create_codex_sse_stream(Box::pin(gemini_stream), model_clone);
-                    Body::from_stream(stream)
+                    Body::from_stream(in_flight.hold(stream))
                 }
                 ResponseFormat::LegacyCompletion => {
                     use crate::proxy::mappers::openai::streaming::create_legacy_sse_stream;
                     let stream = create_legacy_sse_stream(Box::pin(gemini_stream), model_clone);
-                    Body::from_stream(stream)
+                    Body::from_stream(in_flight.hold(stream))
                 }
             };
 
@@ -187,6 +200,13 @@ async fn execute_openai_request_v2(
         .await
         .unwrap_or_else(|_| format!("HTTP {}", status_code));
 
+    // 反馈请求结果（5xx 冷却、401 立即刷新 token）
+    if let Some(outcome) = RequestOutcome::from_error_status(status_code) {
+        token_manager
+            .report_result(quota_group, &config.request_type, Some(&config.final_model), &account_id, outcome)
+            .await;
+    }
+
     tracing::error!(
         "[OpenAI-Upstream] Error Response {}: {}",
         status_code,
@@ -210,6 +230,7 @@ async fn execute_openai_request_v2(
         token_manager.mark_rate_limited(
             quota_group,
             &config.request_type,
+            Some(&config.final_model),
             &account_id,
             status_code,
             retry_after.as_deref(),
@@ -221,7 +242,7 @@ async fn execute_openai_request_v2(
             tracing::warn!(
                 "OpenAI Upstream {} on {}, waiting {}ms then retrying",
                 status_code,
-                email,
+                selected_name,
                 actual_delay
             );
             tokio::time::sleep(tokio::time::Duration::from_millis(actual_delay)).await;
@@ -234,7 +255,7 @@ async fn execute_openai_request_v2(
         tracing::warn!(
             "OpenAI Upstream {} on {}, will rotate: {}",
             status_code,
-            email,
+            selected_name,
             should_rotate_account(status_code)
         );
         return ExecuteResult::Retry {
@@ -248,7 +269,7 @@ async fn execute_openai_request_v2(
         tracing::warn!(
             "OpenAI Upstream {} on account {}, rotating account",
             status_code,
-            email
+            selected_name
         );
         return ExecuteResult::Retry {
             error: format!("HTTP {}: {}", status_code, error_text),
@@ -259,7 +280,7 @@ async fn execute_openai_request_v2(
     // 其他错误不可重试
     error!(
         "OpenAI Upstream non-retryable error {} on account {}: {}",
-        status_code, email, error_text
+        status_code, selected_name, error_text
     );
     ExecuteResult::FatalError {
         status,
@@ -793,7 +814,7 @@ pub async fn handle_images_generations(
     let token_manager = state.token_manager;
 
     let selected = match token_manager
-        .get_token("gemini", "image_gen", false, None)
+        .get_token("gemini", "image_gen", None, false, None)
         .await
     {
         Ok(t) => t,
@@ -807,9 +828,9 @@ pub async fn handle_images_generations(
 
     let access_token = selected.access_token;
     let project_id = selected.project_id;
-    let email = selected.email;
+    let selected_name = selected.display_name;
 
-    info!("✓ Using account: {} for image generation", email);
+    info!("✓ Using account: {} for image generation", selected_name);
 
     // 4. 并发发送请求 (解决 candidateCount > 1 不支持的问题)
     let mut tasks = Vec::new();
@@ -1049,7 +1070,7 @@ pub async fn handle_images_edits(
     let token_manager = state.token_manager;
     // Fix: Proper get_token call with correct signature and unwrap (using image_gen quota)
     let selected = match token_manager
-        .get_token("gemini", "image_gen", false, None)
+        .get_token("gemini", "image_gen", None, false, None)
         .await
     {
         Ok(t) => t,
@@ -20,7 +20,7 @@ use std::pin::Pin;
 pub fn create_claude_sse_stream(
     mut gemini_stream: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
     trace_id: String,
-    email: String,
+    account_name: String,
 ) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
     use async_stream::stream;
     use bytes::BytesMut;
@@ -42,7 +42,7 @@ pub fn create_claude_sse_stream(
                             let line = line_str.trim();
                             if line.is_empty() { continue; }
 
-                            if let Some(sse_chunks) = process_sse_line(line, &mut state, &trace_id, &email) {
+                            if let Some(sse_chunks) = process_sse_line(line, &mut state, &trace_id, &account_name) {
                                 for sse_chunk in sse_chunks {
                                     yield Ok(sse_chunk);
                                 }
@@ -65,7 +65,7 @@ pub fn create_claude_sse_stream(
 }
 
 /// 处理单行 SSE 数据
-fn process_sse_line(line: &str, state: &mut StreamingState, trace_id: &str, email: &str) -> Option<Vec<Bytes>> {
+fn process_sse_line(line: &str, state: &mut StreamingState, trace_id: &str, account_name: &str) -> Option<Vec<Bytes>> {
     if !line.starts_with("data: ") {
         return None;
     }
@@ -169,7 +169,7 @@ fn process_sse_line(line: &str, state: &mut StreamingState, trace_id: &str, emai
              tracing::info!(
                  "[{}] ✓ Stream completed | Account: {} | In: {} tokens | Out: {} tokens{}", 
                  trace_id,
-                 email,
+                 account_name,
                  u.prompt_token_count.unwrap_or(0).saturating_sub(cached_tokens), 
                  u.candidates_token_count.unwrap_or(0),
                  cache_info
@@ -17,6 +17,7 @@ pub mod monitor;           // 监控
 pub mod rate_limit;        // 限流跟踪
 pub mod sticky_config;     // 粘性调度配置
 pub mod session_manager;   // 会话指纹管理
+pub mod clock;             // 时钟抽象
 
 
 pub use config::ProxyConfig;
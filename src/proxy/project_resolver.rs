@@ -1,8 +1,15 @@
 use serde_json::Value;
 
+use crate::modules::oauth::{OAuthError, OAuthErrorKind};
+
 /// 使用 Antigravity 的 loadCodeAssist API 获取 project_id
 /// 这是获取 cloudaicompanionProject 的正确方式
 pub async fn fetch_project_id(access_token: &str) -> Result<String, String> {
+    fetch_project_id_via(access_token, None).await.map_err(String::from)
+}
+
+/// 经由指定的出站代理获取 project_id，`proxy` 为 None 时使用全局上游代理配置
+pub async fn fetch_project_id_via(access_token: &str, proxy: Option<&str>) -> Result<String, OAuthError> {
     let url = "https://cloudcode-pa.googleapis.com/v1internal:loadCodeAssist";
     
     let request_body = serde_json::json!({
@@ -11,7 +18,11 @@ pub async fn fetch_project_id(access_token: &str) -> Result<String, String> {
         }
     });
     
-    let client = crate::utils::http::create_client(30);
+    let client = match proxy {
+        Some(proxy) => crate::utils::http::create_client_via_proxy(30, proxy)
+            .map_err(|e| format!("loadCodeAssist 请求失败: {}", e))?,
+        None => crate::utils::http::create_client(30),
+    };
     let response = client
         .post(url)
         .bearer_auth(access_token)
@@ -21,12 +32,15 @@ pub async fn fetch_project_id(access_token: &str) -> Result<String, String> {
         .json(&request_body)
         .send()
         .await
-        .map_err(|e| format!("loadCodeAssist 请求失败: {}", e))?;
+        .map_err(|e| OAuthError::request_failed("loadCodeAssist 请求失败", e))?;
     
     if !response.status().is_success() {
         let status = response.status();
         let body = response.text().await.unwrap_or_default();
-        return Err(format!("loadCodeAssist 返回错误 {}: {}", status, body));
+        return Err(OAuthError::new(
+            OAuthErrorKind::Status(status.as_u16()),
+            format!("loadCodeAssist 返回错误 {}: {}", status, body),
+        ));
     }
     
     let data: Value = response.json()
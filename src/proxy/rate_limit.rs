@@ -1,9 +1,14 @@
 use dashmap::DashMap;
+use std::sync::Arc;
 use std::time::{SystemTime, Duration};
+use tokio::sync::Notify;
 use regex::Regex;
+use serde::Serialize;
+use crate::proxy::clock::{SharedClock, SystemClock};
 
 /// 限流原因类型
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RateLimitReason {
     /// 配额耗尽 (QUOTA_EXHAUSTED)
     QuotaExhausted,
@@ -28,17 +33,149 @@ pub struct RateLimitInfo {
     pub detected_at: SystemTime,
     /// 限流原因
     pub reason: RateLimitReason,
+    /// 触发限流的 HTTP 状态码 (直接标记时为 None)
+    pub status: Option<u16>,
+}
+
+/// 一条生效中的限流记录，供 UI 展示
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RateLimitEntry {
+    pub scope_group: String,
+    /// 被限流的账号，整个分组被限流时为 None
+    pub account_id: Option<String>,
+    /// 距离重置的剩余秒数
+    pub remaining_seconds: u64,
+    /// 触发限流的 HTTP 状态码 (直接标记时为 None)
+    pub status: Option<u16>,
+    pub reason: RateLimitReason,
+    /// 连续限流次数
+    pub streak: u32,
+}
+
+/// Google 结构化错误 (RESOURCE_EXHAUSTED) 中与限流相关的字段
+///
+/// 形如 `{"error":{"status":"RESOURCE_EXHAUSTED","details":[...]}}`，
+/// details 中 ErrorInfo / QuotaFailure / RetryInfo 的顺序不固定。
+#[derive(Debug, Clone, Default, PartialEq)]
+struct GoogleQuotaError {
+    /// RetryInfo.retryDelay (秒)
+    retry_delay: Option<u64>,
+    /// ErrorInfo.metadata.quotaResetDelay (秒)
+    quota_reset_delay: Option<u64>,
+    /// ErrorInfo.metadata.quotaResetTimeStamp
+    quota_reset_at: Option<SystemTime>,
+    /// ErrorInfo.reason
+    reason: Option<String>,
+    /// QuotaFailure.violations[].quotaId
+    quota_ids: Vec<String>,
+    /// QuotaFailure.violations[].quotaMetric
+    quota_metrics: Vec<String>,
+}
+
+impl GoogleQuotaError {
+    /// 是否为按天计算的配额 (如 GenerateRequestsPerDayPerProjectPerModel)
+    fn is_daily_quota(&self) -> bool {
+        self.quota_ids
+            .iter()
+            .chain(self.quota_metrics.iter())
+            .map(|s| s.to_ascii_lowercase())
+            .any(|s| s.contains("perday") || s.contains("per_day") || s.contains("daily"))
+    }
+}
+
+/// Retry-After 的最大等待时间 (24 小时)
+const MAX_RETRY_AFTER_SECONDS: u64 = 86_400;
+
+/// 限流到期后的观察期(秒)，期间账号同一时间只放行一个试探请求
+pub const PROBATION_SECONDS: u64 = 60;
+
+/// 连续限流惩罚翻倍后的冷却时间上限(秒)
+pub const MAX_PENALTY_SECONDS: u64 = 3600;
+
+/// 上一次限流结束后这么久(秒)没有再被限流，连续限流计数归零
+pub const STREAK_RESET_SECONDS: u64 = 3600;
+
+/// 解析 Retry-After header：整数秒或 HTTP-date (RFC 7231)
+///
+/// 已过去的日期视为无需等待 (0)，结果最多 24 小时；无法解析时返回 None。
+fn parse_retry_after_header(value: &str, now: SystemTime) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(seconds.min(MAX_RETRY_AFTER_SECONDS));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = SystemTime::from(date)
+        .duration_since(now)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Some(wait.min(MAX_RETRY_AFTER_SECONDS))
+}
+
+/// 距离下一个 UTC 零点的秒数
+fn seconds_until_next_utc_midnight(now: SystemTime) -> u64 {
+    let secs = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    86_400 - secs % 86_400
+}
+
+/// 限流到期后的观察期 (半开状态)
+///
+/// 上游的限流未必与我们记录的同时解除，到期后立即放开全部流量可能再次触发 429。
+/// 观察期内只放行一个试探请求：成功则结束观察期，再次 429 则重新限流并计入连续限流次数。
+#[derive(Debug, Clone, Copy)]
+struct Probation {
+    /// 观察期结束时间
+    until: SystemTime,
+    /// 是否已有试探请求在途
+    trial_in_flight: bool,
+}
+
+/// 同一分组内账号的连续限流记录
+///
+/// 第 n 次连续限流的冷却时间为 `base_sec × 2^(n-1)` (不超过 MAX_PENALTY_SECONDS)，
+/// 更长的 Retry-After 仍然优先。成功请求或 STREAK_RESET_SECONDS 内没有再被限流时归零。
+#[derive(Debug, Clone, Copy)]
+struct Streak {
+    /// 连续限流次数
+    count: u32,
+    /// 本轮第一次限流的冷却时间(秒)
+    base_sec: u64,
+    /// 最近一次限流的重置时间
+    last_reset: SystemTime,
 }
 
 /// 限流跟踪器
 pub struct RateLimitTracker {
     limits: DashMap<String, RateLimitInfo>,
+    /// 限流到期后处于观察期的账号，键与 limits 相同
+    probation: DashMap<String, Probation>,
+    /// 连续限流计数，键与 limits 相同
+    streaks: DashMap<String, Streak>,
+    /// 整个分组的限流，按 scope group 记录，与账号限流相互独立
+    group_limits: DashMap<String, RateLimitInfo>,
+    /// 等待限流解除的 CacheFirst 请求，键与 limits 相同
+    waiters: DashMap<String, Arc<Notify>>,
+    /// 判断限流是否到期所用的时钟
+    clock: SharedClock,
 }
 
 impl RateLimitTracker {
     pub fn new() -> Self {
+        Self::with_clock(SystemClock::shared())
+    }
+
+    /// 使用指定时钟创建 (测试中可传入 MockClock)
+    pub fn with_clock(clock: SharedClock) -> Self {
         Self {
             limits: DashMap::new(),
+            probation: DashMap::new(),
+            streaks: DashMap::new(),
+            group_limits: DashMap::new(),
+            waiters: DashMap::new(),
+            clock,
         }
     }
     
@@ -50,7 +187,7 @@ impl RateLimitTracker {
     pub fn get_remaining_wait(&self, quota_group: &str, account_id: &str) -> u64 {
         let key = self.make_key(quota_group, account_id);
         if let Some(info) = self.limits.get(&key) {
-            let now = SystemTime::now();
+            let now = self.clock.system_time();
             if info.reset_time > now {
                 return info.reset_time.duration_since(now).unwrap_or(Duration::from_secs(0)).as_secs();
             }
@@ -74,13 +211,84 @@ impl RateLimitTracker {
         retry_after_header: Option<&str>,
         body: &str,
     ) -> Option<RateLimitInfo> {
+        let (reason, retry_sec) = self.parse_limit(status, retry_after_header, body)?;
+
+        // 连续的 429 逐次加长冷却时间 (观察期内再次 429 说明限流并未真正解除)
+        let key = self.make_key(quota_group, account_id);
+        self.end_probation(&key);
+        let (retry_sec, streak) = if status == 429 {
+            self.penalize(&key, retry_sec)
+        } else {
+            (retry_sec, self.streak_count(&key))
+        };
+
+        let info = RateLimitInfo {
+            reset_time: self.clock.system_time() + Duration::from_secs(retry_sec),
+            retry_after_sec: retry_sec,
+            detected_at: self.clock.system_time(),
+            reason,
+            status: Some(status),
+        };
+        
+        // 存储
+        self.limits.insert(key, info.clone());
+        
+        tracing::warn!(
+            "账号 {} (group {}) [{}] 限流类型: {:?}, 重置延时: {}秒, 连续限流: {}次",
+            account_id,
+            quota_group,
+            status,
+            reason,
+            retry_sec,
+            streak
+        );
+        
+        Some(info)
+    }
+
+    /// 从错误响应解析整个分组的限流 (如按来源 IP 或共享项目限流)
+    ///
+    /// 与账号限流分开记录，不计入任何账号的连续限流次数。
+    pub fn parse_group_from_error(
+        &self,
+        scope_group: &str,
+        status: u16,
+        retry_after_header: Option<&str>,
+        body: &str,
+    ) -> Option<RateLimitInfo> {
+        let (reason, retry_sec) = self.parse_limit(status, retry_after_header, body)?;
+        let info = RateLimitInfo {
+            reset_time: self.clock.system_time() + Duration::from_secs(retry_sec),
+            retry_after_sec: retry_sec,
+            detected_at: self.clock.system_time(),
+            reason,
+            status: Some(status),
+        };
+        self.group_limits.insert(scope_group.to_string(), info.clone());
+        tracing::warn!(
+            "分组 {} [{}] 整体被限流, 限流类型: {:?}, 重置延时: {}秒",
+            scope_group,
+            status,
+            reason,
+            retry_sec
+        );
+        Some(info)
+    }
+
+    /// 解析限流原因与冷却时间，不是限流类错误时返回 None
+    fn parse_limit(
+        &self,
+        status: u16,
+        retry_after_header: Option<&str>,
+        body: &str,
+    ) -> Option<(RateLimitReason, u64)> {
         // 支持 429 (限流) 以及 500/503/529 (后端故障软避让)
         if status != 429 && status != 500 && status != 503 && status != 529 {
             return None;
         }
         
         // 1. 解析限流原因类型
-        let reason = if status == 429 {
+        let mut reason = if status == 429 {
             self.parse_rate_limit_reason(body)
         } else {
             RateLimitReason::ServerError
@@ -88,19 +296,46 @@ impl RateLimitTracker {
         
         let mut retry_after_sec = None;
         
-        // 2. 从 Retry-After header 提取
-        if let Some(retry_after) = retry_after_header {
-            if let Ok(seconds) = retry_after.parse::<u64>() {
-                retry_after_sec = Some(seconds);
+        // 2. 优先使用 Google 结构化错误：按天配额锁到重置时间，否则使用 retryDelay
+        if status == 429 {
+            if let Some(google) = self.parse_google_error(body) {
+                let now = self.clock.system_time();
+                match google.reason.as_deref() {
+                    Some("QUOTA_EXHAUSTED") => reason = RateLimitReason::QuotaExhausted,
+                    Some("RATE_LIMIT_EXCEEDED") => reason = RateLimitReason::RateLimitExceeded,
+                    _ => {}
+                }
+                if google.is_daily_quota() {
+                    reason = RateLimitReason::QuotaExhausted;
+                    let secs = google
+                        .quota_reset_at
+                        .and_then(|at| at.duration_since(now).ok())
+                        .map(|d| d.as_secs().max(1))
+                        .unwrap_or_else(|| seconds_until_next_utc_midnight(now));
+                    tracing::warn!("检测到按天配额耗尽 ({:?})，锁定 {} 秒直到配额重置", google.quota_ids, secs);
+                    retry_after_sec = Some(secs);
+                } else {
+                    retry_after_sec = google.retry_delay.or(google.quota_reset_delay);
+                }
+            }
+        }
+        
+        // 3. 从 Retry-After header 提取
+        if retry_after_sec.is_none() {
+            if let Some(retry_after) = retry_after_header {
+                retry_after_sec = parse_retry_after_header(retry_after, self.clock.system_time());
+                if retry_after_sec.is_none() {
+                    tracing::debug!("无法解析 Retry-After header: '{}'", retry_after);
+                }
             }
         }
         
-        // 3. 从错误消息提取 (优先尝试 JSON 解析，再试正则)
+        // 4. 从错误消息提取 (优先尝试 JSON 解析，再试正则)
         if retry_after_sec.is_none() {
             retry_after_sec = self.parse_retry_time_from_body(body);
         }
         
-        // 4. 处理默认值与软避让逻辑（根据限流类型设置不同默认值）
+        // 5. 处理默认值与软避让逻辑（根据限流类型设置不同默认值）
         let retry_sec = match retry_after_sec {
             Some(s) => {
                 // 引入 PR #28 的安全缓冲区：最小 2 秒，防止极高频无效重试
@@ -132,29 +367,52 @@ impl RateLimitTracker {
             }
         };
         
-        let info = RateLimitInfo {
-            reset_time: SystemTime::now() + Duration::from_secs(retry_sec),
-            retry_after_sec: retry_sec,
-            detected_at: SystemTime::now(),
-            reason,
-        };
-        
-        // 存储
-        let key = self.make_key(quota_group, account_id);
-        self.limits.insert(key, info.clone());
-        
-        tracing::warn!(
-            "账号 {} (group {}) [{}] 限流类型: {:?}, 重置延时: {}秒",
-            account_id,
-            quota_group,
-            status,
-            reason,
-            retry_sec
-        );
-        
-        Some(info)
+        Some((reason, retry_sec))
     }
     
+    /// 解析 Google 结构化错误 body，非该格式时返回 None
+    fn parse_google_error(&self, body: &str) -> Option<GoogleQuotaError> {
+        let json: serde_json::Value = serde_json::from_str(body.trim()).ok()?;
+        let details = json.get("error")?.get("details")?.as_array()?;
+
+        let mut parsed = GoogleQuotaError::default();
+        for detail in details {
+            // RetryInfo
+            if let Some(delay) = detail.get("retryDelay").and_then(|v| v.as_str()) {
+                parsed.retry_delay = self.parse_duration_string(delay);
+            }
+
+            // ErrorInfo
+            if let Some(reason) = detail.get("reason").and_then(|v| v.as_str()) {
+                parsed.reason = Some(reason.to_string());
+            }
+            if let Some(metadata) = detail.get("metadata") {
+                if let Some(delay) = metadata.get("quotaResetDelay").and_then(|v| v.as_str()) {
+                    parsed.quota_reset_delay = self.parse_duration_string(delay);
+                }
+                if let Some(at) = metadata.get("quotaResetTimeStamp").and_then(|v| v.as_str()) {
+                    parsed.quota_reset_at = chrono::DateTime::parse_from_rfc3339(at)
+                        .ok()
+                        .map(SystemTime::from);
+                }
+            }
+
+            // QuotaFailure
+            if let Some(violations) = detail.get("violations").and_then(|v| v.as_array()) {
+                for violation in violations {
+                    if let Some(id) = violation.get("quotaId").and_then(|v| v.as_str()) {
+                        parsed.quota_ids.push(id.to_string());
+                    }
+                    if let Some(metric) = violation.get("quotaMetric").and_then(|v| v.as_str()) {
+                        parsed.quota_metrics.push(metric.to_string());
+                    }
+                }
+            }
+        }
+
+        Some(parsed)
+    }
+
     /// 解析限流原因类型
     fn parse_rate_limit_reason(&self, body: &str) -> RateLimitReason {
         // 尝试从 JSON 中提取 reason 字段
@@ -322,17 +580,26 @@ impl RateLimitTracker {
     /// 检查账号是否仍在限流中
     pub fn is_rate_limited(&self, quota_group: &str, account_id: &str) -> bool {
         if let Some(info) = self.get(quota_group, account_id) {
-            info.reset_time > SystemTime::now()
+            info.reset_time > self.clock.system_time()
         } else {
             false
         }
     }
     
+    /// 检查账号是否在任意分组中仍处于限流
+    pub fn is_limited_in_any_group(&self, account_id: &str) -> bool {
+        let suffix = format!("::{}", account_id);
+        let now = self.clock.system_time();
+        self.limits
+            .iter()
+            .any(|entry| entry.key().ends_with(&suffix) && entry.value().reset_time > now)
+    }
+    
     /// 获取距离限流重置还有多少秒
     pub fn get_reset_seconds(&self, quota_group: &str, account_id: &str) -> Option<u64> {
         if let Some(info) = self.get(quota_group, account_id) {
             info.reset_time
-                .duration_since(SystemTime::now())
+                .duration_since(self.clock.system_time())
                 .ok()
                 .map(|d| d.as_secs())
         } else {
@@ -340,20 +607,215 @@ impl RateLimitTracker {
         }
     }
     
-    /// 清除过期的限流记录
+    /// 获取分组整体限流还有多少秒重置，分组未被整体限流时返回 None
+    pub fn group_reset_seconds(&self, scope_group: &str) -> Option<u64> {
+        let now = self.clock.system_time();
+        let info = self.group_limits.get(scope_group)?;
+        if info.reset_time <= now {
+            return None;
+        }
+        info.reset_time.duration_since(now).ok().map(|d| d.as_secs())
+    }
+
+    /// 将整个分组标记为限流指定秒数
+    pub fn mark_group_limited(&self, scope_group: &str, seconds: u64) {
+        let info = RateLimitInfo {
+            reset_time: self.clock.system_time() + Duration::from_secs(seconds),
+            retry_after_sec: seconds,
+            detected_at: self.clock.system_time(),
+            reason: RateLimitReason::RateLimitExceeded,
+            status: None,
+        };
+        self.group_limits.insert(scope_group.to_string(), info);
+    }
+
+    /// 清除分组的整体限流，返回是否存在生效中的记录
+    pub fn clear_group(&self, scope_group: &str) -> bool {
+        let now = self.clock.system_time();
+        self.group_limits
+            .remove(scope_group)
+            .is_some_and(|(_, info)| info.reset_time > now)
+    }
+
+    /// 将已到期的限流记录转入观察期，返回该键当前的观察期状态
+    fn probation_at(&self, key: &str, now: SystemTime) -> Option<Probation> {
+        if let Some((_, info)) = self.limits.remove_if(key, |_, info| info.reset_time <= now) {
+            self.probation.insert(
+                key.to_string(),
+                Probation {
+                    until: info.reset_time + Duration::from_secs(PROBATION_SECONDS),
+                    trial_in_flight: false,
+                },
+            );
+            self.wake(key);
+        }
+        let probation = *self.probation.get(key)?;
+        if probation.until <= now {
+            self.probation.remove_if(key, |_, p| p.until <= now);
+            return None;
+        }
+        Some(probation)
+    }
+
+    /// 重新限流时结束观察期
+    fn end_probation(&self, key: &str) {
+        self.probation_at(key, self.clock.system_time());
+        self.probation.remove(key);
+    }
+
+    /// 记入一次限流，返回叠加惩罚后的冷却时间和连续限流次数
+    ///
+    /// 仍在限流中时重复上报的 429 (如并发请求先后失败) 不重复计数。
+    fn penalize(&self, key: &str, retry_sec: u64) -> (u64, u32) {
+        let now = self.clock.system_time();
+        let still_limited = self.limits.get(key).is_some_and(|info| info.reset_time > now);
+        let fresh = Streak {
+            count: 0,
+            base_sec: retry_sec,
+            last_reset: now,
+        };
+        let mut streak = self.streaks.entry(key.to_string()).or_insert(fresh);
+        let stale = now
+            .duration_since(streak.last_reset)
+            .is_ok_and(|idle| idle.as_secs() >= STREAK_RESET_SECONDS);
+        if stale {
+            *streak = fresh;
+        }
+        if !still_limited || streak.count == 0 {
+            streak.count += 1;
+        }
+
+        let penalty = streak
+            .base_sec
+            .saturating_mul(2u64.saturating_pow(streak.count - 1))
+            .min(MAX_PENALTY_SECONDS);
+        let cooldown = retry_sec.max(penalty);
+        if cooldown > retry_sec {
+            tracing::warn!("{} 连续第 {} 次被限流，冷却时间增加到 {} 秒", key, streak.count, cooldown);
+        }
+        streak.last_reset = now + Duration::from_secs(cooldown);
+        (cooldown, streak.count)
+    }
+
+    /// 当前的连续限流次数
+    fn streak_count(&self, key: &str) -> u32 {
+        self.streaks.get(key).map_or(0, |streak| streak.count)
+    }
+
+    /// 检查账号是否处于限流到期后的观察期
+    pub fn is_on_probation(&self, quota_group: &str, account_id: &str) -> bool {
+        let key = self.make_key(quota_group, account_id);
+        self.probation_at(&key, self.clock.system_time()).is_some()
+    }
+
+    /// 检查账号能否接收新请求：观察期内仅在没有试探请求在途时放行
+    pub fn probation_allows(&self, quota_group: &str, account_id: &str) -> bool {
+        let key = self.make_key(quota_group, account_id);
+        self.probation_at(&key, self.clock.system_time())
+            .is_none_or(|p| !p.trial_in_flight)
+    }
+
+    /// 为观察期内的账号占用试探名额，已有试探请求在途时返回 false
+    ///
+    /// 不在观察期的账号总是返回 true。检查与占用是一步原子操作。
+    pub fn try_start_trial(&self, quota_group: &str, account_id: &str) -> bool {
+        let key = self.make_key(quota_group, account_id);
+        if self.probation_at(&key, self.clock.system_time()).is_none() {
+            return true;
+        }
+        match self.probation.get_mut(&key) {
+            Some(probation) if probation.trial_in_flight => false,
+            Some(mut probation) => {
+                probation.trial_in_flight = true;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// 试探请求结束但无法判断限流是否解除 (如 5xx)，释放试探名额
+    pub fn end_trial(&self, quota_group: &str, account_id: &str) {
+        if let Some(mut probation) = self.probation.get_mut(&self.make_key(quota_group, account_id)) {
+            probation.trial_in_flight = false;
+        }
+    }
+
+    /// 请求成功，结束账号的观察期并将连续限流计数归零，返回账号此前是否处于观察期
+    ///
+    /// 仍在限流中的账号不归零：那是限流前发出的请求迟到的结果。
+    pub fn record_success(&self, quota_group: &str, account_id: &str) -> bool {
+        let key = self.make_key(quota_group, account_id);
+        let now = self.clock.system_time();
+        let on_probation = self.probation_at(&key, now).is_some();
+        self.probation.remove(&key);
+        if self.limits.get(&key).is_none_or(|info| info.reset_time <= now) {
+            self.streaks.remove(&key);
+        }
+        on_probation
+    }
+
+    /// 登记等待账号限流解除，返回的 Notify 在限流被提前清除时唤醒
+    ///
+    /// 清除方式包括 clear / clear_account / clear_all / cleanup_expired，
+    /// 以及 wake_all (如调度配置变更)。调用方应先 enable `notified()`
+    /// 再复查限流，避免错过登记前发生的清除。
+    pub fn waiter(&self, quota_group: &str, account_id: &str) -> Arc<Notify> {
+        // 顺带清理已无人等待的条目
+        self.waiters.retain(|_, notify| Arc::strong_count(notify) > 1);
+        self.waiters
+            .entry(self.make_key(quota_group, account_id))
+            .or_insert_with(|| Arc::new(Notify::new()))
+            .clone()
+    }
+
+    /// 唤醒某个限流键上的所有等待者
+    fn wake(&self, key: &str) {
+        if let Some((_, notify)) = self.waiters.remove(key) {
+            notify.notify_waiters();
+        }
+    }
+
+    /// 唤醒所有等待者，由其自行复查限流
+    pub fn wake_all(&self) {
+        self.waiters.retain(|_, notify| {
+            notify.notify_waiters();
+            false
+        });
+    }
+
+    /// 清除过期的限流记录，到期的账号转入观察期
     #[allow(dead_code)]
     pub fn cleanup_expired(&self) -> usize {
-        let now = SystemTime::now();
-        let mut count = 0;
+        let now = self.clock.system_time();
+        let mut expired = Vec::new();
         
-        self.limits.retain(|_k, v| {
+        self.limits.retain(|k, v| {
             if v.reset_time <= now {
-                count += 1;
+                expired.push((k.clone(), v.reset_time));
                 false
             } else {
                 true
             }
         });
+        let count = expired.len();
+        for (key, reset_time) in expired {
+            self.probation.insert(
+                key.clone(),
+                Probation {
+                    until: reset_time + Duration::from_secs(PROBATION_SECONDS),
+                    trial_in_flight: false,
+                },
+            );
+            self.wake(&key);
+        }
+        self.probation.retain(|_, p| p.until > now);
+        self.streaks.retain(|_, streak| {
+            now.duration_since(streak.last_reset)
+                .map_or(true, |idle| idle.as_secs() < STREAK_RESET_SECONDS)
+        });
+        let groups_before = self.group_limits.len();
+        self.group_limits.retain(|_, info| info.reset_time > now);
+        let count = count + groups_before.saturating_sub(self.group_limits.len());
         
         if count > 0 {
             tracing::debug!("清除了 {} 个过期的限流记录", count);
@@ -362,32 +824,117 @@ impl RateLimitTracker {
         count
     }
     
-    /// 清除指定账号的限流记录
-    #[allow(dead_code)]
+    /// 清除指定账号的限流记录 (同时结束观察期)
+    ///
+    /// 连续限流计数保留，仍由成功请求或长时间没有限流归零。
     pub fn clear(&self, quota_group: &str, account_id: &str) -> bool {
         let key = self.make_key(quota_group, account_id);
-        self.limits.remove(&key).is_some()
+        self.probation.remove(&key);
+        let cleared = self.limits.remove(&key).is_some();
+        self.wake(&key);
+        cleared
     }
     
+    /// 列出账号当前生效的限流：(分组, 剩余秒数)
+    pub fn active_limits_for_account(&self, account_id: &str) -> Vec<(String, u64)> {
+        let suffix = format!("::{}", account_id);
+        let now = self.clock.system_time();
+        let mut limits: Vec<(String, u64)> = self
+            .limits
+            .iter()
+            .filter_map(|entry| {
+                let group = entry.key().strip_suffix(&suffix)?;
+                let remaining = entry.value().reset_time.duration_since(now).ok()?;
+                Some((group.to_string(), remaining.as_secs()))
+            })
+            .collect();
+        limits.sort();
+        limits
+    }
+
+    /// 列出所有生效中的限流，按分组和账号排序，已过期的记录不包含在内
+    ///
+    /// 整个分组的限流 (account_id 为 None) 排在该分组的账号限流之前。
+    pub fn snapshot(&self) -> Vec<RateLimitEntry> {
+        let now = self.clock.system_time();
+        let mut entries: Vec<RateLimitEntry> = self
+            .limits
+            .iter()
+            .filter_map(|entry| {
+                let (scope_group, account_id) = entry.key().rsplit_once("::")?;
+                let remaining = entry.value().reset_time.duration_since(now).ok()?;
+                Some(RateLimitEntry {
+                    scope_group: scope_group.to_string(),
+                    account_id: Some(account_id.to_string()),
+                    remaining_seconds: remaining.as_secs(),
+                    status: entry.value().status,
+                    reason: entry.value().reason,
+                    streak: self.streak_count(entry.key()),
+                })
+            })
+            .collect();
+        entries.extend(self.group_limits.iter().filter_map(|entry| {
+            let remaining = entry.value().reset_time.duration_since(now).ok()?;
+            Some(RateLimitEntry {
+                scope_group: entry.key().clone(),
+                account_id: None,
+                remaining_seconds: remaining.as_secs(),
+                status: entry.value().status,
+                reason: entry.value().reason,
+                streak: 0,
+            })
+        }));
+        entries.sort_by(|a, b| (&a.scope_group, &a.account_id).cmp(&(&b.scope_group, &b.account_id)));
+        entries
+    }
+
+    /// 清除账号在所有分组中的限流记录、观察期和连续限流计数，返回清除的限流条数
+    pub fn clear_account(&self, account_id: &str) -> usize {
+        let suffix = format!("::{}", account_id);
+        let before = self.limits.len();
+        self.limits.retain(|key, _| !key.ends_with(&suffix));
+        self.probation.retain(|key, _| !key.ends_with(&suffix));
+        self.streaks.retain(|key, _| !key.ends_with(&suffix));
+        let keys: Vec<String> = self
+            .waiters
+            .iter()
+            .filter(|entry| entry.key().ends_with(&suffix))
+            .map(|entry| entry.key().clone())
+            .collect();
+        for key in &keys {
+            self.wake(key);
+        }
+        before.saturating_sub(self.limits.len())
+    }
+
     /// Clear all rate limit records
     #[allow(dead_code)]
     pub fn clear_all(&self) {
         let count = self.limits.len();
         self.limits.clear();
+        self.probation.clear();
+        self.streaks.clear();
+        self.group_limits.clear();
+        self.wake_all();
         tracing::debug!("清除了所有 {} 条限流记录", count);
     }
 
-    /// Mark an account as rate limited for a specific duration (for testing)
-    #[allow(dead_code)]
-    pub fn mark_limited(&self, quota_group: &str, account_id: &str, seconds: u64) {
+    /// Mark an account as rate limited for a specific duration
+    ///
+    /// Returns the cooldown applied, which repeated limits lengthen.
+    pub fn mark_limited(&self, quota_group: &str, account_id: &str, seconds: u64) -> u64 {
         let key = self.make_key(quota_group, account_id);
+        self.end_probation(&key);
+        let (seconds, _) = self.penalize(&key, seconds);
         let info = RateLimitInfo {
-            reset_time: SystemTime::now() + Duration::from_secs(seconds),
+            reset_time: self.clock.system_time() + Duration::from_secs(seconds),
             retry_after_sec: seconds,
-            detected_at: SystemTime::now(),
+            detected_at: self.clock.system_time(),
             reason: RateLimitReason::RateLimitExceeded,
+            status: None,
         };
         self.limits.insert(key, info);
+        seconds
     }
 }
 
@@ -400,6 +947,12 @@ impl Default for RateLimitTracker {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proxy::clock::{Clock, MockClock};
+
+    fn tracker_with_clock() -> (RateLimitTracker, Arc<MockClock>) {
+        let clock = MockClock::new(1_761_031_600);
+        (RateLimitTracker::with_clock(clock.clone()), clock)
+    }
     
     #[test]
     fn test_parse_retry_time_minutes_seconds() {
@@ -438,19 +991,420 @@ mod tests {
 
     #[test]
     fn test_get_remaining_wait() {
-        let tracker = RateLimitTracker::new();
+        let (tracker, clock) = tracker_with_clock();
         tracker.parse_from_error("gemini", "acc1", 429, Some("30"), "");
-        let wait = tracker.get_remaining_wait("gemini", "acc1");
-        assert!(wait > 25 && wait <= 30);
+        assert_eq!(tracker.get_remaining_wait("gemini", "acc1"), 30);
+
+        clock.advance(10);
+        assert_eq!(tracker.get_remaining_wait("gemini", "acc1"), 20);
+        assert_eq!(tracker.get_reset_seconds("gemini", "acc1"), Some(20));
+
+        // 到期后不再限流，过期记录可被清理
+        clock.advance(20);
+        assert_eq!(tracker.get_remaining_wait("gemini", "acc1"), 0);
+        assert!(!tracker.is_rate_limited("gemini", "acc1"));
+        assert_eq!(tracker.cleanup_expired(), 1);
     }
 
     #[test]
-    fn test_safety_buffer() {
+    fn test_limited_in_any_group() {
         let tracker = RateLimitTracker::new();
+        assert!(!tracker.is_limited_in_any_group("acc1"));
+
+        tracker.mark_limited("claude::image_gen", "acc1", 60);
+        assert!(tracker.is_limited_in_any_group("acc1"));
+        assert!(!tracker.is_limited_in_any_group("c1"));
+    }
+
+    #[test]
+    fn test_active_limits_for_account() {
+        let (tracker, clock) = tracker_with_clock();
+        tracker.mark_limited("claude::image_gen", "acc1", 60);
+        tracker.mark_limited("claude", "acc1", 120);
+        tracker.mark_limited("gemini", "acc2", 60);
+
+        let limits = tracker.active_limits_for_account("acc1");
+        assert_eq!(
+            limits,
+            vec![("claude".to_string(), 120), ("claude::image_gen".to_string(), 60)]
+        );
+        assert!(tracker.active_limits_for_account("acc3").is_empty());
+
+        clock.advance(61);
+        assert_eq!(tracker.active_limits_for_account("acc1"), vec![("claude".to_string(), 59)]);
+    }
+
+    #[test]
+    fn test_clear_account() {
+        let tracker = RateLimitTracker::new();
+        tracker.mark_limited("claude", "acc1", 60);
+        tracker.mark_limited("claude::image_gen", "acc1", 60);
+        tracker.mark_limited("claude", "acc2", 60);
+
+        assert_eq!(tracker.clear_account("acc1"), 2);
+        assert!(!tracker.is_limited_in_any_group("acc1"));
+        assert!(tracker.is_rate_limited("claude", "acc2"));
+    }
+
+    const RESOURCE_EXHAUSTED_PER_MINUTE: &str = r#"{
+        "error": {
+            "code": 429,
+            "message": "Resource has been exhausted (e.g. check quota).",
+            "status": "RESOURCE_EXHAUSTED",
+            "details": [
+                {
+                    "@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                    "violations": [
+                        {
+                            "quotaMetric": "generativelanguage.googleapis.com/generate_content_requests",
+                            "quotaId": "GenerateRequestsPerMinutePerProjectPerModel"
+                        }
+                    ]
+                },
+                {
+                    "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                    "retryDelay": "37s"
+                }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn test_resource_exhausted_retry_delay() {
+        let tracker = RateLimitTracker::new();
+        let info = tracker
+            .parse_from_error("gemini", "acc1", 429, None, RESOURCE_EXHAUSTED_PER_MINUTE)
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 37);
+
+        // 结构化 retryDelay 优先于 Retry-After header
+        let info = tracker
+            .parse_from_error("gemini", "acc2", 429, Some("5"), RESOURCE_EXHAUSTED_PER_MINUTE)
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 37);
+    }
+
+    #[test]
+    fn test_resource_exhausted_per_day_until_midnight() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{
+            "error": {
+                "code": 429,
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [
+                    {
+                        "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                        "retryDelay": "12s"
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                        "violations": [
+                            {
+                                "quotaMetric": "generativelanguage.googleapis.com/generate_content_free_tier_requests",
+                                "quotaId": "GenerateRequestsPerDayPerProjectPerModel-FreeTier"
+                            }
+                        ]
+                    }
+                ]
+            }
+        }"#;
+
+        let expected = seconds_until_next_utc_midnight(SystemTime::now());
+        let info = tracker.parse_from_error("gemini", "acc1", 429, None, body).unwrap();
+        assert_eq!(info.reason, RateLimitReason::QuotaExhausted);
+        assert!(info.retry_after_sec.abs_diff(expected) <= 1);
+    }
+
+    #[test]
+    fn test_resource_exhausted_per_day_with_reset_time() {
+        let tracker = RateLimitTracker::new();
+        let reset_at = chrono::Utc::now() + chrono::Duration::hours(5);
+        let body = serde_json::json!({
+            "error": {
+                "code": 429,
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [
+                    {
+                        "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+                        "reason": "QUOTA_EXHAUSTED",
+                        "metadata": { "quotaResetTimeStamp": reset_at.to_rfc3339() }
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                        "violations": [{ "quotaId": "RequestsPerDay" }]
+                    }
+                ]
+            }
+        })
+        .to_string();
+
+        let info = tracker.parse_from_error("gemini", "acc1", 429, None, &body).unwrap();
+        assert!(info.retry_after_sec > 5 * 3600 - 5 && info.retry_after_sec <= 5 * 3600);
+    }
+
+    #[test]
+    fn test_header_fallback_without_structured_delay() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{"error": {"code": 429, "status": "RESOURCE_EXHAUSTED", "details": []}}"#;
+        let info = tracker.parse_from_error("gemini", "acc1", 429, Some("45"), body).unwrap();
+        assert_eq!(info.retry_after_sec, 45);
+    }
+
+    #[test]
+    fn test_retry_after_header_forms() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_761_031_600); // 2025-10-21 07:26:40 UTC
+
+        assert_eq!(parse_retry_after_header("120", now), Some(120));
+        assert_eq!(parse_retry_after_header(" 7 ", now), Some(7));
+        assert_eq!(parse_retry_after_header("Tue, 21 Oct 2025 07:28:00 GMT", now), Some(80));
+        // 已过去的日期视为无需等待
+        assert_eq!(parse_retry_after_header("Tue, 21 Oct 2025 07:00:00 GMT", now), Some(0));
+        // 超过 24 小时的等待被截断
+        assert_eq!(parse_retry_after_header("Fri, 31 Oct 2025 00:00:00 GMT", now), Some(MAX_RETRY_AFTER_SECONDS));
+        assert_eq!(parse_retry_after_header("999999999", now), Some(MAX_RETRY_AFTER_SECONDS));
+
+        assert_eq!(parse_retry_after_header("soon", now), None);
+        assert_eq!(parse_retry_after_header("-5", now), None);
+        assert_eq!(parse_retry_after_header("", now), None);
+    }
+
+    #[test]
+    fn test_retry_after_http_date() {
+        let (tracker, clock) = tracker_with_clock();
+        let at = chrono::DateTime::from_timestamp(clock.now() + 600, 0).unwrap().to_rfc2822();
+        let info = tracker.parse_from_error("gemini", "acc1", 429, Some(&at), "").unwrap();
+        assert_eq!(info.retry_after_sec, 600);
+
+        // 无法解析的 header 回退到默认值
+        let info = tracker.parse_from_error("gemini", "acc2", 429, Some("later"), "").unwrap();
+        assert_eq!(info.retry_after_sec, 60);
+    }
+
+    #[test]
+    fn test_probation_after_expiry() {
+        let (tracker, clock) = tracker_with_clock();
+        tracker.mark_limited("claude", "acc1", 30);
+        assert!(!tracker.is_on_probation("claude", "acc1"));
+
+        // 到期后只放行一个试探请求
+        clock.advance(30);
+        assert!(!tracker.is_rate_limited("claude", "acc1"));
+        assert!(tracker.is_on_probation("claude", "acc1"));
+        assert!(tracker.probation_allows("claude", "acc1"));
+        assert!(tracker.try_start_trial("claude", "acc1"));
+        assert!(!tracker.probation_allows("claude", "acc1"));
+        assert!(!tracker.try_start_trial("claude", "acc1"));
+        // 其他分组和账号不受影响
+        assert!(tracker.try_start_trial("gemini", "acc1"));
+        assert!(tracker.try_start_trial("claude", "acc2"));
+
+        // 结果不明时释放试探名额
+        tracker.end_trial("claude", "acc1");
+        assert!(tracker.try_start_trial("claude", "acc1"));
+
+        // 成功后结束观察期，流量全部恢复
+        assert!(tracker.record_success("claude", "acc1"));
+        assert!(!tracker.is_on_probation("claude", "acc1"));
+        assert!(tracker.try_start_trial("claude", "acc1"));
+        assert!(tracker.try_start_trial("claude", "acc1"));
+        assert!(!tracker.record_success("claude", "acc1"));
+
+        // 无人试探时，观察期在 PROBATION_SECONDS 后自然结束
+        tracker.mark_limited("claude", "acc1", 30);
+        clock.advance(30 + PROBATION_SECONDS as i64 - 1);
+        assert!(tracker.is_on_probation("claude", "acc1"));
+        clock.advance(1);
+        assert!(!tracker.is_on_probation("claude", "acc1"));
+    }
+
+    #[test]
+    fn test_limit_during_probation_grows_cooldown() {
+        let (tracker, clock) = tracker_with_clock();
+        tracker.parse_from_error("claude", "acc1", 429, Some("30"), "");
+
+        // 到期后试探请求立即再次 429：冷却时间逐次翻倍
+        let mut expected = 30;
+        for _ in 0..3 {
+            clock.advance(expected as i64);
+            assert!(tracker.try_start_trial("claude", "acc1"));
+            expected *= 2;
+            let info = tracker.parse_from_error("claude", "acc1", 429, Some("30"), "").unwrap();
+            assert_eq!(info.retry_after_sec, expected);
+            assert_eq!(tracker.get_remaining_wait("claude", "acc1"), expected);
+            assert!(!tracker.is_on_probation("claude", "acc1"));
+        }
+
+        // 更长的 Retry-After 优先
+        clock.advance(expected as i64);
+        let info = tracker.parse_from_error("claude", "acc1", 429, Some("1000"), "").unwrap();
+        assert_eq!(info.retry_after_sec, 1000);
+
+        // 惩罚仍以第一次限流为基数翻倍，且有上限
+        clock.advance(1000);
+        tracker.mark_limited("claude", "acc1", 30);
+        assert_eq!(tracker.get_remaining_wait("claude", "acc1"), 960);
+        clock.advance(960);
+        tracker.mark_limited("claude", "acc1", 30);
+        assert_eq!(tracker.get_remaining_wait("claude", "acc1"), 1920);
+        clock.advance(1920);
+        tracker.mark_limited("claude", "acc1", 30);
+        assert_eq!(tracker.get_remaining_wait("claude", "acc1"), MAX_PENALTY_SECONDS);
+
+        // 观察期内成功后，下一次限流恢复正常冷却时间
+        clock.advance(MAX_PENALTY_SECONDS as i64);
+        tracker.record_success("claude", "acc1");
+        tracker.parse_from_error("claude", "acc1", 429, Some("30"), "");
+        assert_eq!(tracker.get_remaining_wait("claude", "acc1"), 30);
+
+        // 观察期外 (仍在限流中) 的重复 429 不翻倍
+        tracker.parse_from_error("claude", "acc1", 429, Some("30"), "");
+        assert_eq!(tracker.get_remaining_wait("claude", "acc1"), 30);
+
+        // 到期清理后同样进入观察期
+        clock.advance(30);
+        assert_eq!(tracker.cleanup_expired(), 1);
+        assert!(tracker.is_on_probation("claude", "acc1"));
+        assert_eq!(tracker.parse_from_error("claude", "acc1", 429, Some("30"), "").unwrap().retry_after_sec, 60);
+
+        // 5xx 软避让结束观察期但不翻倍；手动清除同样结束观察期
+        clock.advance(60);
+        tracker.parse_from_error("claude", "acc1", 503, None, "");
+        assert_eq!(tracker.get_remaining_wait("claude", "acc1"), 20);
+        clock.advance(20);
+        assert!(tracker.is_on_probation("claude", "acc1"));
+        tracker.clear("claude", "acc1");
+        assert!(!tracker.is_on_probation("claude", "acc1"));
+    }
+
+    #[test]
+    fn test_streak_grows_and_resets() {
+        let (tracker, clock) = tracker_with_clock();
+        let streak = |tracker: &RateLimitTracker| tracker.snapshot().first().map(|e| e.streak);
+
+        // 每次限流 (手动清除不归零) 冷却时间翻倍
+        for (count, expected) in [(1, 30), (2, 60), (3, 120), (4, 240), (5, 480), (6, 960), (7, 1920), (8, 3600), (9, 3600)] {
+            tracker.mark_limited("claude", "acc1", 30);
+            assert_eq!(tracker.get_remaining_wait("claude", "acc1"), expected);
+            assert_eq!(streak(&tracker), Some(count));
+            // 限流期间重复上报不计数
+            tracker.mark_limited("claude", "acc1", 30);
+            assert_eq!(tracker.get_remaining_wait("claude", "acc1"), expected);
+            assert!(tracker.clear("claude", "acc1"));
+        }
+
+        // 比惩罚更长的 Retry-After 优先
+        tracker.parse_from_error("claude", "acc1", 429, Some("7200"), "");
+        assert_eq!(tracker.get_remaining_wait("claude", "acc1"), 7200);
+        assert_eq!(streak(&tracker), Some(10));
+
+        // 限流结束后 STREAK_RESET_SECONDS 内没有再被限流，计数归零
+        clock.advance(7200 + STREAK_RESET_SECONDS as i64 - 1);
+        tracker.mark_limited("claude", "acc1", 30);
+        assert_eq!(streak(&tracker), Some(11));
+        tracker.clear("claude", "acc1");
+        clock.advance(3600 + STREAK_RESET_SECONDS as i64);
+        tracker.mark_limited("claude", "acc1", 30);
+        assert_eq!(tracker.get_remaining_wait("claude", "acc1"), 30);
+        assert_eq!(streak(&tracker), Some(1));
+
+        // 成功请求归零，但限流期间迟到的成功不算
+        tracker.record_success("claude", "acc1");
+        tracker.clear("claude", "acc1");
+        tracker.mark_limited("claude", "acc1", 30);
+        assert_eq!(streak(&tracker), Some(2));
+        tracker.clear("claude", "acc1");
+        tracker.record_success("claude", "acc1");
+        tracker.mark_limited("claude", "acc1", 30);
+        assert_eq!(streak(&tracker), Some(1));
+
+        // 计数按分组和账号分开；5xx 软避让不计数
+        tracker.mark_limited("gemini", "acc1", 30);
+        tracker.parse_from_error("claude", "acc2", 503, None, "");
+        let streaks: Vec<(String, String, u32)> = tracker
+            .snapshot()
+            .into_iter()
+            .map(|e| (e.scope_group, e.account_id.unwrap(), e.streak))
+            .collect();
+        assert_eq!(
+            streaks,
+            vec![
+                ("claude".to_string(), "acc1".to_string(), 1),
+                ("claude".to_string(), "acc2".to_string(), 0),
+                ("gemini".to_string(), "acc1".to_string(), 1),
+            ]
+        );
+
+        // 清除账号的全部限流同时清除计数
+        tracker.clear_account("acc1");
+        tracker.mark_limited("claude", "acc1", 30);
+        assert_eq!(streak(&tracker), Some(1));
+    }
+
+    #[test]
+    fn test_group_limit_is_tracked_apart() {
+        let (tracker, clock) = tracker_with_clock();
+        let info = tracker
+            .parse_group_from_error("claude", 429, Some("45"), "Too many requests from this IP")
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 45);
+        assert!(tracker.parse_group_from_error("claude", 400, None, "").is_none());
+        tracker.mark_limited("claude", "acc1", 30);
+
+        assert_eq!(tracker.group_reset_seconds("claude"), Some(45));
+        assert_eq!(tracker.group_reset_seconds("gemini"), None);
+        // 账号限流与分组限流互不影响
+        assert!(!tracker.is_rate_limited("claude", "acc2"));
+        assert_eq!(tracker.get_remaining_wait("claude", "acc1"), 30);
+
+        let entries: Vec<(Option<String>, u64)> = tracker
+            .snapshot()
+            .into_iter()
+            .map(|e| (e.account_id, e.remaining_seconds))
+            .collect();
+        assert_eq!(entries, vec![(None, 45), (Some("acc1".to_string()), 30)]);
+
+        // 到期后自动失效并被清理
+        clock.advance(45);
+        assert_eq!(tracker.group_reset_seconds("claude"), None);
+        assert_eq!(tracker.cleanup_expired(), 2);
+        assert!(tracker.snapshot().is_empty());
+
+        tracker.mark_group_limited("claude", 10);
+        assert!(tracker.clear_group("claude"));
+        assert!(!tracker.clear_group("claude"));
+        assert_eq!(tracker.group_reset_seconds("claude"), None);
+    }
+
+    #[test]
+    fn test_safety_buffer() {
+        let (tracker, _clock) = tracker_with_clock();
         // 如果 API 返回 1s，我们强制设为 2s
         tracker.parse_from_error("gemini", "acc1", 429, Some("1"), "");
-        let wait = tracker.get_remaining_wait("gemini", "acc1");
-        // 由于时间流逝，剩余时间可能是 1s 或 2s
-        assert!(wait >= 1 && wait <= 2);
+        assert_eq!(tracker.get_remaining_wait("gemini", "acc1"), 2);
+    }
+
+    #[test]
+    fn test_snapshot_lists_active_limits_only() {
+        let tracker = RateLimitTracker::new();
+        tracker.parse_from_error("gemini::image_gen", "acc2", 503, None, "");
+        tracker.mark_limited("claude", "acc1", 60);
+        tracker.mark_limited("claude", "acc3", 0);
+
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot.len(), 2);
+
+        assert_eq!(snapshot[0].scope_group, "claude");
+        assert_eq!(snapshot[0].account_id.as_deref(), Some("acc1"));
+        assert_eq!(snapshot[0].status, None);
+        assert!(snapshot[0].remaining_seconds > 55);
+
+        assert_eq!(snapshot[1].scope_group, "gemini::image_gen");
+        assert_eq!(snapshot[1].account_id.as_deref(), Some("acc2"));
+        assert_eq!(snapshot[1].status, Some(503));
+        assert_eq!(snapshot[1].reason, RateLimitReason::ServerError);
+
+        assert!(tracker.clear("claude", "acc1"));
+        assert!(!tracker.clear("claude", "acc1"));
+        assert_eq!(tracker.snapshot().len(), 1);
     }
 }
@@ -1,6 +1,6 @@
 use crate::proxy::TokenManager;
 use axum::{
-    extract::DefaultBodyLimit,
+    extract::{DefaultBodyLimit, State},
     http::StatusCode,
     response::{IntoResponse, Json, Response},
     routing::{get, post},
@@ -331,11 +331,22 @@ impl AxumServer {
 // ===== API 处理器 (旧代码已移除，由 src/proxy/handlers/* 接管) =====
 
 /// 健康检查处理器
-async fn health_check_handler() -> Response {
-    Json(serde_json::json!({
-        "status": "ok"
-    }))
-    .into_response()
+/// 后台任务 panic，或 claude / gemini 都没有可用账号时返回 503
+async fn health_check_handler(State(state): State<AppState>) -> Response {
+    let liveness = state.token_manager.liveness();
+    let claude = state.token_manager.readiness("claude");
+    let gemini = state.token_manager.readiness("gemini");
+    let ok = liveness.alive && (claude.ready || gemini.ready);
+    let status = if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (
+        status,
+        Json(serde_json::json!({
+            "status": if ok { "ok" } else { "unavailable" },
+            "liveness": liveness,
+            "readiness": { "claude": claude, "gemini": gemini },
+        })),
+    )
+        .into_response()
 }
 
 /// 静默成功处理器 (用于拦截遥测日志等)
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 /// 调度模式枚举
@@ -5,10 +7,14 @@ use serde::{Deserialize, Serialize};
 pub enum SchedulingMode {
     /// 缓存优先 (Cache-first): 尽可能锁定同一账号，限流时优先等待，极大提升 Prompt Caching 命中率
     CacheFirst,
-    /// 平衡模式 (Balance): 锁定同一账号，限流时立即切换到备选账号，兼顾成功率和性能
+    /// 平衡模式 (Balance): 锁定同一账号，限流时立即切换到备选账号；新会话分配给绑定会话最少的账号，兼顾成功率和性能
     Balance,
     /// 性能优先 (Performance-first): 纯轮询模式 (Round-robin)，账号负载最均衡，但不利用缓存
     PerformanceFirst,
+    /// 最近最少使用 (Least-recently-used): 优先选择最久未被调度的账号，重启或重载后也不会总是先压同一个账号
+    LeastRecentlyUsed,
+    /// 最少连接 (Least-connections): 优先选择当前并发请求最少的账号，避免长流式请求堆积在同一账号上
+    LeastConnections,
 }
 
 impl Default for SchedulingMode {
@@ -24,6 +30,145 @@ pub struct StickySessionConfig {
     pub mode: SchedulingMode,
     /// 缓存优先模式下的最大等待时间 (秒)
     pub max_wait_seconds: u64,
+    /// 账号限流的冷却时间超过 `max_wait_seconds` 时，立即解除该分组内绑定到它的会话，
+    /// 这些会话的下一个请求直接重新选号，不再各自白等一轮
+    #[serde(default)]
+    pub unbind_on_long_limit: bool,
+    /// 会话绑定的空闲过期时间 (秒)，0 表示永不过期
+    #[serde(default = "default_session_ttl_seconds")]
+    pub session_ttl_seconds: u64,
+    /// 熔断阈值：时间窗口内连续多少次上游 5xx 后暂停调度该账号
+    #[serde(default = "default_circuit_breaker_threshold")]
+    pub circuit_breaker_threshold: u32,
+    /// 熔断计数的时间窗口 (秒)
+    #[serde(default = "default_circuit_breaker_window_seconds")]
+    pub circuit_breaker_window_seconds: u64,
+    /// 熔断后的冷却时间 (秒)，之后放行一次试探请求
+    #[serde(default = "default_circuit_breaker_cooldown_seconds")]
+    pub circuit_breaker_cooldown_seconds: u64,
+    /// 按模型细分限流与调度范围 (如 `gemini::gemini-2.5-pro`)，某个模型配额耗尽时不影响该账号服务其他模型；
+    /// 会话绑定仍按分组，不会因模型切换账号
+    #[serde(default)]
+    pub scope_by_model: bool,
+    /// 将会话绑定持久化到 `sessions.json`，重启后会话仍落在原账号上以保住 Prompt Cache (全局，只取 default 条目)
+    #[serde(default)]
+    pub persist_sessions: bool,
+    /// 客户端未提供 session_id 时，用系统提示词与首条用户消息的指纹作为会话 ID，
+    /// 让不带会话头的客户端也能命中 Prompt Cache；偏好纯负载均衡的部署保持关闭
+    #[serde(default)]
+    pub fingerprint_sessions: bool,
+    /// 会话绑定的账号不可用而切换时，优先切到同级或更高级别订阅的账号 (如 PRO 不降到 FREE)，
+    /// 只有这些账号都不可用时才降级
+    #[serde(default)]
+    pub prefer_same_tier_on_failover: bool,
+    /// 为新请求选号时，同一等级内优先选上游报告剩余配额最多的账号 (没有报告的账号视为配额充足)
+    #[serde(default)]
+    pub prefer_most_remaining_quota: bool,
+    /// 会话绑定数量上限，超出时淘汰最久未使用的绑定，0 表示不限制 (全局，只取 default 条目)
+    #[serde(default = "default_max_session_bindings")]
+    pub max_session_bindings: usize,
+    /// 订阅等级从高到低的排序，大小写不敏感 (全局，只取 default 条目)
+    #[serde(default = "default_tier_order")]
+    pub tier_order: Vec<String>,
+    /// 未知等级 (如 "standard-tier"、"g1-pro") 插在 `tier_order` 的第几位之前，
+    /// 不设置则排在所有已知等级之后
+    #[serde(default)]
+    pub unknown_tier_position: Option<usize>,
+    /// 按请求类型限制可用账号，如 `{"image_gen": {"allowed_tiers": ["ULTRA"]}}` (全局，只取 default 条目)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub request_type_policies: BTreeMap<String, RequestTypePolicy>,
+    /// 排空中的账号最多等待多久 (秒)，0 表示等到绑定全部结束 (全局，只取 default 条目)
+    #[serde(default)]
+    pub max_drain_seconds: u64,
+    /// 排空完成 (绑定清零或超时) 后自动暂停该账号 (全局，只取 default 条目)
+    #[serde(default)]
+    pub pause_when_drained: bool,
+    /// 整个分组被限流 (如按来源 IP 或共享项目) 的错误特征：命中时限流的是分组而不是账号，
+    /// 期间请求直接返回等待时间，不再逐个试遍账号 (全局，只取 default 条目)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group_limit_signatures: Vec<GroupLimitSignature>,
+}
+
+/// 分组级限流的错误特征
+///
+/// 状态码一致，且错误 body 包含 `body_patterns` 中任一子串 (大小写不敏感) 时命中；
+/// `body_patterns` 为空时不命中任何错误。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupLimitSignature {
+    #[serde(default = "default_group_limit_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub body_patterns: Vec<String>,
+}
+
+impl GroupLimitSignature {
+    /// 判断某个错误响应是否符合该特征
+    pub fn matches(&self, status: u16, body: &str) -> bool {
+        if status != self.status {
+            return false;
+        }
+        let body = body.to_lowercase();
+        self.body_patterns
+            .iter()
+            .any(|pattern| !pattern.is_empty() && body.contains(&pattern.to_lowercase()))
+    }
+}
+
+fn default_group_limit_status() -> u16 {
+    429
+}
+
+/// 某一请求类型的账号准入规则
+///
+/// 两个列表都为空时不做限制；否则账号等级在 `allowed_tiers` 中，
+/// 或带有 `allowed_tags` 中任一标签即可使用。等级与标签均大小写不敏感。
+/// 账号文件所在的子目录名 (如 `accounts/gemini/` 中的 `gemini`) 也视为标签。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestTypePolicy {
+    #[serde(default)]
+    pub allowed_tiers: Vec<String>,
+    #[serde(default)]
+    pub allowed_tags: Vec<String>,
+}
+
+impl RequestTypePolicy {
+    /// 判断某个账号是否允许处理该类型的请求
+    pub fn allows(&self, tier: Option<&str>, tags: &[String]) -> bool {
+        if self.allowed_tiers.is_empty() && self.allowed_tags.is_empty() {
+            return true;
+        }
+        let tier_allowed = tier.is_some_and(|tier| {
+            self.allowed_tiers.iter().any(|t| t.eq_ignore_ascii_case(tier))
+        });
+        let tag_allowed = tags.iter().any(|tag| {
+            self.allowed_tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+        });
+        tier_allowed || tag_allowed
+    }
+}
+
+fn default_session_ttl_seconds() -> u64 {
+    3600  // 空闲 1 小时后释放绑定
+}
+
+fn default_max_session_bindings() -> usize {
+    50_000  // 防止客户端每次请求都生成新的会话 ID 导致内存无限增长
+}
+
+fn default_tier_order() -> Vec<String> {
+    vec!["ULTRA".to_string(), "PRO".to_string(), "FREE".to_string()]
+}
+
+fn default_circuit_breaker_threshold() -> u32 {
+    3
+}
+
+fn default_circuit_breaker_window_seconds() -> u64 {
+    60
+}
+
+fn default_circuit_breaker_cooldown_seconds() -> u64 {
+    120
 }
 
 impl Default for StickySessionConfig {
@@ -33,6 +178,194 @@ impl Default for StickySessionConfig {
             // 当账号被限流时，会等待（最多 max_wait_seconds）而不是切换账号
             mode: SchedulingMode::CacheFirst,
             max_wait_seconds: 120,  // 最多等待 2 分钟
+            unbind_on_long_limit: false,
+            session_ttl_seconds: default_session_ttl_seconds(),
+            circuit_breaker_threshold: default_circuit_breaker_threshold(),
+            circuit_breaker_window_seconds: default_circuit_breaker_window_seconds(),
+            circuit_breaker_cooldown_seconds: default_circuit_breaker_cooldown_seconds(),
+            scope_by_model: false,
+            persist_sessions: false,
+            fingerprint_sessions: false,
+            prefer_same_tier_on_failover: false,
+            prefer_most_remaining_quota: false,
+            max_session_bindings: default_max_session_bindings(),
+            tier_order: default_tier_order(),
+            unknown_tier_position: None,
+            request_type_policies: BTreeMap::new(),
+            max_drain_seconds: 0,
+            pause_when_drained: false,
+            group_limit_signatures: Vec::new(),
+        }
+    }
+}
+
+/// 默认条目的键名
+pub const DEFAULT_GROUP: &str = "default";
+
+/// 按配额分组的调度配置
+///
+/// JSON 形如 `{"default": {...}, "claude": {...}, "gemini::image_gen": {...}}`。
+/// 查找顺序：完整 scope group → 配额分组 → default。
+/// 会话 TTL、会话持久化、会话数量上限、等级排序、请求类型准入规则、熔断参数与分组级限流特征是全局的，只取 default 条目。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GroupedStickyConfig {
+    /// 兜底配置
+    #[serde(default)]
+    pub default: StickySessionConfig,
+    /// 各分组的覆盖配置
+    #[serde(flatten)]
+    pub groups: BTreeMap<String, StickySessionConfig>,
+}
+
+impl GroupedStickyConfig {
+    /// 从 JSON 解析，尽量宽容
+    ///
+    /// 同时接受分组格式和旧的单一 `StickySessionConfig` 格式 (视为 default)；
+    /// 无法解析的分组条目会被跳过，未知字段被忽略。
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| format!("无效的调度配置 JSON: {}", e))?;
+        let object = value.as_object().ok_or("调度配置必须是 JSON 对象")?;
+
+        // 旧格式：整个文件就是一份配置
+        if object.contains_key("mode") {
+            let default = serde_json::from_value(value.clone())
+                .map_err(|e| format!("无效的调度配置: {}", e))?;
+            return Ok(Self {
+                default,
+                groups: BTreeMap::new(),
+            });
         }
+
+        let mut configs = Self::default();
+        for (group, entry) in object {
+            match serde_json::from_value::<StickySessionConfig>(entry.clone()) {
+                Ok(config) => configs.set(group, config),
+                Err(e) => tracing::warn!("忽略无法解析的调度配置条目 {}: {}", group, e),
+            }
+        }
+        Ok(configs)
+    }
+
+    /// 解析某个 scope group 生效的配置
+    pub fn resolve(&self, scope_group: &str) -> &StickySessionConfig {
+        if let Some(config) = self.groups.get(scope_group) {
+            return config;
+        }
+        let quota_group = scope_group.split("::").next().unwrap_or(scope_group);
+        self.groups.get(quota_group).unwrap_or(&self.default)
+    }
+
+    /// 设置某个分组的配置，"default" 设置兜底配置
+    pub fn set(&mut self, group: &str, config: StickySessionConfig) {
+        if group == DEFAULT_GROUP {
+            self.default = config;
+        } else {
+            self.groups.insert(group.to_string(), config);
+        }
+    }
+
+    /// 删除某个分组的覆盖配置，之后回落到 default
+    pub fn remove(&mut self, group: &str) -> Option<StickySessionConfig> {
+        self.groups.remove(group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_mode(mode: SchedulingMode, max_wait_seconds: u64) -> StickySessionConfig {
+        StickySessionConfig {
+            mode,
+            max_wait_seconds,
+            ..StickySessionConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_falls_back_to_quota_group_then_default() {
+        let mut configs = GroupedStickyConfig::default();
+        configs.set("claude", with_mode(SchedulingMode::CacheFirst, 300));
+        configs.set("gemini::image_gen", with_mode(SchedulingMode::Balance, 0));
+
+        assert_eq!(configs.resolve("claude").max_wait_seconds, 300);
+        assert_eq!(configs.resolve("claude::image_gen").max_wait_seconds, 300);
+        assert_eq!(configs.resolve("gemini::image_gen").mode, SchedulingMode::Balance);
+        assert_eq!(configs.resolve("gemini").mode, SchedulingMode::CacheFirst);
+        assert_eq!(configs.resolve("openai").max_wait_seconds, 120);
+
+        configs.remove("claude");
+        assert_eq!(configs.resolve("claude").max_wait_seconds, 120);
+    }
+
+    #[test]
+    fn test_from_json_is_lenient() {
+        // 旧的单一配置格式
+        let legacy = GroupedStickyConfig::from_json(r#"{"mode": "Balance", "max_wait_seconds": 30}"#).unwrap();
+        assert_eq!(legacy.default.mode, SchedulingMode::Balance);
+        assert!(legacy.groups.is_empty());
+
+        // 未知字段与坏条目被忽略
+        let configs = GroupedStickyConfig::from_json(
+            r#"{
+                "version": 2,
+                "default": {"mode": "Balance", "max_wait_seconds": 30, "future_option": true},
+                "claude": {"mode": "CacheFirst", "max_wait_seconds": 300},
+                "gemini": {"mode": "NoSuchMode", "max_wait_seconds": 1}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(configs.default.max_wait_seconds, 30);
+        assert_eq!(configs.resolve("claude").max_wait_seconds, 300);
+        assert_eq!(configs.resolve("gemini").max_wait_seconds, 30);
+        assert_eq!(configs.groups.len(), 1);
+
+        assert!(GroupedStickyConfig::from_json("not json").is_err());
+        assert!(GroupedStickyConfig::from_json("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_serialized_shape() {
+        let mut configs = GroupedStickyConfig::default();
+        configs.set(DEFAULT_GROUP, with_mode(SchedulingMode::Balance, 60));
+        configs.set("claude", with_mode(SchedulingMode::CacheFirst, 300));
+
+        let json = serde_json::to_value(&configs).unwrap();
+        assert_eq!(json["default"]["mode"], "Balance");
+        assert_eq!(json["claude"]["max_wait_seconds"], 300);
+
+        let parsed: GroupedStickyConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.default.mode, SchedulingMode::Balance);
+        assert_eq!(parsed.resolve("claude").max_wait_seconds, 300);
+    }
+
+    #[test]
+    fn test_group_limit_signature_matches() {
+        let signature: GroupLimitSignature =
+            serde_json::from_str(r#"{"body_patterns": ["too many requests from this IP", ""]}"#).unwrap();
+        assert_eq!(signature.status, 429);
+        assert!(signature.matches(429, r#"{"error": {"message": "Too many requests from this ip"}}"#));
+        assert!(!signature.matches(503, "Too many requests from this IP"));
+        assert!(!signature.matches(429, "Resource has been exhausted"));
+
+        // 空特征不命中任何错误
+        let empty = GroupLimitSignature { status: 429, body_patterns: Vec::new() };
+        assert!(!empty.matches(429, "anything"));
+    }
+
+    #[test]
+    fn test_request_type_policy_allows() {
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+
+        // 空规则不做限制
+        assert!(RequestTypePolicy::default().allows(None, &[]));
+
+        let policy: RequestTypePolicy =
+            serde_json::from_str(r#"{"allowed_tiers": ["ULTRA"], "allowed_tags": ["image"]}"#).unwrap();
+        assert!(policy.allows(Some("ultra"), &[]));
+        assert!(policy.allows(Some("FREE"), &tags(&["team-a", "Image"])));
+        assert!(!policy.allows(Some("PRO"), &tags(&["team-a"])));
+        assert!(!policy.allows(None, &[]));
     }
 }
@@ -0,0 +1,284 @@
+//! Operational Alerts
+//!
+//! A hook for the few events someone should be woken up for: an account got
+//! disabled, a scope group is running out of healthy accounts, or every
+//! account of a group is rate limited. Each alert fires at most once per
+//! cooldown window, so a pool stuck in a bad state doesn't page on every
+//! request.
+//!
+//! The hook runs on the request path; [`webhook_hook`] hands delivery off to
+//! a task and retries failures there, so a slow or broken endpoint never
+//! delays a request.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use serde::Serialize;
+
+use super::types::DisabledKind;
+use crate::proxy::clock::SharedClock;
+
+/// Default seconds before the same alert may fire again
+pub const ALERT_COOLDOWN_SECONDS: u64 = 900;
+
+/// Webhook deliveries retried after the first attempt fails
+pub const WEBHOOK_RETRIES: u32 = 2;
+
+/// Timeout of one webhook delivery
+const WEBHOOK_TIMEOUT_SECONDS: u64 = 10;
+
+/// Something an operator should hear about
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertEvent {
+    /// An account was disabled and dropped from the pool
+    AccountDisabled {
+        account_id: String,
+        reason: String,
+        kind: DisabledKind,
+    },
+    /// Fewer accounts than the alert threshold can serve a scope group
+    PoolHealthBelowThreshold {
+        scope_group: String,
+        healthy: usize,
+        total: usize,
+    },
+    /// A request found every account of a scope group rate limited
+    AllAccountsRateLimited {
+        scope_group: String,
+        /// Seconds until the first account is usable again, if known
+        min_wait_seconds: Option<u64>,
+    },
+}
+
+impl AlertEvent {
+    /// Alerts with the same key are deduplicated against each other
+    fn dedup_key(&self) -> String {
+        match self {
+            Self::AccountDisabled { account_id, .. } => format!("account_disabled::{}", account_id),
+            Self::PoolHealthBelowThreshold { scope_group, .. } => {
+                format!("pool_health::{}", scope_group)
+            }
+            Self::AllAccountsRateLimited { scope_group, .. } => {
+                format!("all_rate_limited::{}", scope_group)
+            }
+        }
+    }
+}
+
+/// Callback receiving alerts
+///
+/// Runs on the request path, so it should hand any slow work off to a task.
+pub type AlertHook = Box<dyn Fn(AlertEvent) + Send + Sync>;
+
+/// An installed [`AlertHook`], shared so it can be called without the lock
+type SharedAlertHook = Arc<dyn Fn(AlertEvent) + Send + Sync>;
+
+/// Deduplicates alerts and passes them to the installed hook
+pub struct Alerter {
+    /// Cloned out before it is called, so a slow hook never blocks
+    /// `set_hook`
+    hook: RwLock<Option<SharedAlertHook>>,
+    /// Healthy accounts below which a scope group alerts (0: never)
+    threshold: AtomicUsize,
+    cooldown_seconds: AtomicU64,
+    /// When each alert last fired, by dedup key; entries past the cooldown
+    /// are dropped whenever an alert fires
+    last_fired: DashMap<String, i64>,
+    clock: SharedClock,
+}
+
+impl Alerter {
+    pub fn new(clock: SharedClock) -> Self {
+        Self {
+            hook: RwLock::new(None),
+            threshold: AtomicUsize::new(0),
+            cooldown_seconds: AtomicU64::new(ALERT_COOLDOWN_SECONDS),
+            last_fired: DashMap::new(),
+            clock,
+        }
+    }
+
+    /// Install a hook, replacing any previous one and its dedup state
+    pub fn set_hook(&self, threshold: usize, hook: AlertHook) {
+        *self.hook.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::from(hook));
+        self.threshold.store(threshold, Ordering::Relaxed);
+        self.last_fired.clear();
+    }
+
+    /// Set the seconds before the same alert may fire again
+    pub fn set_cooldown(&self, seconds: u64) {
+        self.cooldown_seconds.store(seconds, Ordering::Relaxed);
+    }
+
+    /// Check whether a hook is installed
+    pub fn is_enabled(&self) -> bool {
+        self.hook
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some()
+    }
+
+    /// Healthy accounts below which a scope group alerts (0: never)
+    pub fn threshold(&self) -> usize {
+        self.threshold.load(Ordering::Relaxed)
+    }
+
+    /// Pass an alert to the hook unless the same one fired within the cooldown
+    ///
+    /// Returns whether the hook was called.
+    pub fn fire(&self, event: AlertEvent) -> bool {
+        let Some(hook) = self.hook.read().unwrap_or_else(|e| e.into_inner()).clone() else {
+            return false;
+        };
+
+        let now = self.clock.now();
+        let cooldown = self.cooldown_seconds.load(Ordering::Relaxed) as i64;
+        let mut due = false;
+        self.last_fired
+            .entry(event.dedup_key())
+            .and_modify(|last| {
+                if now - *last >= cooldown {
+                    *last = now;
+                    due = true;
+                }
+            })
+            .or_insert_with(|| {
+                due = true;
+                now
+            });
+        if !due {
+            return false;
+        }
+        self.last_fired.retain(|_, last| now - *last < cooldown);
+
+        tracing::warn!("[TokenManager] Alert: {:?}", event);
+        hook(event);
+        true
+    }
+}
+
+/// Hook that POSTs each alert as JSON to `url`
+///
+/// Deliveries run on a spawned task and are retried [`WEBHOOK_RETRIES`]
+/// times with a growing delay; failures are only logged. Needs a Tokio
+/// runtime; alerts raised outside of one are dropped with a warning.
+pub fn webhook_hook(url: String) -> AlertHook {
+    let client = crate::utils::http::create_client_with_proxy(WEBHOOK_TIMEOUT_SECONDS, None);
+    Box::new(move |event| {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!("[TokenManager] No runtime to deliver alert {:?}", event);
+            return;
+        };
+        let client = client.clone();
+        let url = url.clone();
+        runtime.spawn(async move {
+            for attempt in 0..=WEBHOOK_RETRIES {
+                if attempt > 0 {
+                    tokio::time::sleep(Duration::from_millis(500 << attempt)).await;
+                }
+                let error = match client.post(&url).json(&event).send().await {
+                    Ok(response) if response.status().is_success() => return,
+                    Ok(response) => format!("HTTP {}", response.status()),
+                    Err(e) => e.to_string(),
+                };
+                tracing::warn!(
+                    "[TokenManager] Alert webhook delivery {}/{} failed: {}",
+                    attempt + 1,
+                    WEBHOOK_RETRIES + 1,
+                    error
+                );
+            }
+            tracing::error!("[TokenManager] Gave up delivering alert {:?}", event);
+        });
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::clock::MockClock;
+    use std::sync::Mutex;
+
+    fn capturing_alerter() -> (Alerter, Arc<MockClock>, Arc<Mutex<Vec<AlertEvent>>>) {
+        let clock = MockClock::starting_now();
+        let alerter = Alerter::new(clock.clone());
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let sink = fired.clone();
+        alerter.set_hook(1, Box::new(move |event| sink.lock().unwrap().push(event)));
+        (alerter, clock, fired)
+    }
+
+    #[test]
+    fn test_alerts_are_deduplicated_per_cooldown() {
+        let (alerter, clock, fired) = capturing_alerter();
+        let degraded = |healthy| AlertEvent::PoolHealthBelowThreshold {
+            scope_group: "claude".to_string(),
+            healthy,
+            total: 3,
+        };
+
+        assert!(alerter.fire(degraded(0)));
+        assert!(!alerter.fire(degraded(0)));
+        // Other scope groups and kinds of alert are deduplicated apart
+        assert!(alerter.fire(AlertEvent::AllAccountsRateLimited {
+            scope_group: "claude".to_string(),
+            min_wait_seconds: Some(30),
+        }));
+        assert!(alerter.fire(AlertEvent::PoolHealthBelowThreshold {
+            scope_group: "gemini".to_string(),
+            healthy: 0,
+            total: 1,
+        }));
+
+        clock.advance(ALERT_COOLDOWN_SECONDS as i64 - 1);
+        assert!(!alerter.fire(degraded(0)));
+        clock.advance(1);
+        assert!(alerter.fire(degraded(0)));
+        assert_eq!(fired.lock().unwrap().len(), 4);
+        // Alerts past their cooldown are forgotten
+        assert_eq!(alerter.last_fired.len(), 1);
+    }
+
+    #[test]
+    fn test_hook_may_replace_itself() {
+        let alerter = Arc::new(Alerter::new(MockClock::starting_now()));
+        let inner = alerter.clone();
+        alerter.set_hook(1, Box::new(move |_| inner.set_hook(1, Box::new(|_| {}))));
+        // Would deadlock if the hook ran under the lock
+        assert!(alerter.fire(AlertEvent::AllAccountsRateLimited {
+            scope_group: "claude".to_string(),
+            min_wait_seconds: None,
+        }));
+    }
+
+    #[test]
+    fn test_no_hook_fires_nothing() {
+        let alerter = Alerter::new(MockClock::starting_now());
+        assert!(!alerter.is_enabled());
+        assert!(!alerter.fire(AlertEvent::AllAccountsRateLimited {
+            scope_group: "claude".to_string(),
+            min_wait_seconds: None,
+        }));
+    }
+
+    #[test]
+    fn test_alert_serialization() {
+        let event = AlertEvent::PoolHealthBelowThreshold {
+            scope_group: "claude".to_string(),
+            healthy: 0,
+            total: 2,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "type": "pool_health_below_threshold",
+                "scope_group": "claude",
+                "healthy": 0,
+                "total": 2,
+            })
+        );
+    }
+}
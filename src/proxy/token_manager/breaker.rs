@@ -0,0 +1,337 @@
+//! Per-Scope Circuit Breaker
+//!
+//! Upstream 5xx errors are not rate limits, so without a breaker the
+//! scheduler keeps handing out an account whose project is broken. After
+//! `threshold` consecutive 5xx errors within `window` seconds the breaker
+//! opens and the account is skipped in that scope group for `cooldown`
+//! seconds. It then half-opens: exactly one trial request is let through,
+//! and its outcome either closes the breaker or opens it again.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use dashmap::DashMap;
+use serde::Serialize;
+
+/// Default consecutive 5xx errors that open the breaker
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// Default window (seconds) the consecutive errors must fall within
+pub const DEFAULT_WINDOW_SECONDS: u64 = 60;
+
+/// Default time (seconds) an open breaker keeps the account out of rotation
+pub const DEFAULT_COOLDOWN_SECONDS: u64 = 120;
+
+/// Breaker state for one account in one scope group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Circuit {
+    /// Counting consecutive failures since `first_failure_at`
+    Closed {
+        failures: u32,
+        first_failure_at: i64,
+    },
+    /// Rejecting the account until `until`
+    Open { until: i64 },
+    /// Cooldown over; `trial_started_at` is set once the trial request is out
+    HalfOpen { trial_started_at: Option<i64> },
+}
+
+/// Externally visible breaker state of a non-closed circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum BreakerState {
+    Open { remaining_seconds: u64 },
+    HalfOpen,
+}
+
+/// A non-closed breaker on one scope group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScopeBreaker {
+    pub scope_group: String,
+    #[serde(flatten)]
+    pub state: BreakerState,
+}
+
+/// Circuit breakers keyed by `scope_group::account_id`
+pub struct CircuitBreaker {
+    circuits: DashMap<String, Circuit>,
+    threshold: AtomicU32,
+    window_seconds: AtomicU64,
+    cooldown_seconds: AtomicU64,
+}
+
+impl CircuitBreaker {
+    /// Create a breaker with the default settings
+    pub fn new() -> Self {
+        Self {
+            circuits: DashMap::new(),
+            threshold: AtomicU32::new(DEFAULT_FAILURE_THRESHOLD),
+            window_seconds: AtomicU64::new(DEFAULT_WINDOW_SECONDS),
+            cooldown_seconds: AtomicU64::new(DEFAULT_COOLDOWN_SECONDS),
+        }
+    }
+
+    /// Update the threshold, window and cooldown
+    ///
+    /// Circuits that are already open keep their current deadline.
+    pub fn configure(&self, threshold: u32, window_seconds: u64, cooldown_seconds: u64) {
+        self.threshold.store(threshold.max(1), Ordering::Relaxed);
+        self.window_seconds.store(window_seconds, Ordering::Relaxed);
+        self.cooldown_seconds
+            .store(cooldown_seconds, Ordering::Relaxed);
+    }
+
+    fn key(scope_group: &str, account_id: &str) -> String {
+        format!("{}::{}", scope_group, account_id)
+    }
+
+    fn cooldown(&self) -> i64 {
+        self.cooldown_seconds.load(Ordering::Relaxed) as i64
+    }
+
+    /// Check whether the account may take a request in a scope group as of `now`
+    ///
+    /// An expired open circuit half-opens here; a half-open circuit admits
+    /// a request only while no trial is out, or once the last trial has gone
+    /// unanswered for a whole cooldown.
+    pub fn allows_at(&self, scope_group: &str, account_id: &str, now: i64) -> bool {
+        let key = Self::key(scope_group, account_id);
+        let Some(mut circuit) = self.circuits.get_mut(&key) else {
+            return true;
+        };
+
+        match *circuit {
+            Circuit::Closed { .. } => true,
+            Circuit::Open { until } if now < until => false,
+            Circuit::Open { .. } => {
+                *circuit = Circuit::HalfOpen {
+                    trial_started_at: None,
+                };
+                true
+            }
+            Circuit::HalfOpen {
+                trial_started_at: None,
+            } => true,
+            Circuit::HalfOpen {
+                trial_started_at: Some(started),
+            } => now - started >= self.cooldown(),
+        }
+    }
+
+    /// Note that a request was sent with the account, claiming the trial if half-open
+    pub fn on_selected_at(&self, scope_group: &str, account_id: &str, now: i64) {
+        let key = Self::key(scope_group, account_id);
+        if let Some(mut circuit) = self.circuits.get_mut(&key) {
+            if let Circuit::HalfOpen { .. } = *circuit {
+                *circuit = Circuit::HalfOpen {
+                    trial_started_at: Some(now),
+                };
+            }
+        }
+    }
+
+    /// Close the circuit after a successful request
+    pub fn record_success(&self, scope_group: &str, account_id: &str) {
+        self.circuits.remove(&Self::key(scope_group, account_id));
+    }
+
+    /// Record an upstream 5xx error as of `now`
+    ///
+    /// Returns true if this error opened the circuit.
+    pub fn record_failure_at(&self, scope_group: &str, account_id: &str, now: i64) -> bool {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        let window = self.window_seconds.load(Ordering::Relaxed) as i64;
+        let open = Circuit::Open {
+            until: now + self.cooldown(),
+        };
+
+        let mut circuit = self
+            .circuits
+            .entry(Self::key(scope_group, account_id))
+            .or_insert(Circuit::Closed {
+                failures: 0,
+                first_failure_at: now,
+            });
+
+        let next = match *circuit {
+            Circuit::Closed {
+                failures,
+                first_failure_at,
+            } => {
+                let (failures, first_failure_at) = if now - first_failure_at > window {
+                    (1, now)
+                } else {
+                    (failures + 1, first_failure_at)
+                };
+                if failures >= threshold {
+                    open
+                } else {
+                    Circuit::Closed {
+                        failures,
+                        first_failure_at,
+                    }
+                }
+            }
+            // The trial failed
+            Circuit::HalfOpen { .. } => open,
+            Circuit::Open { until } => Circuit::Open { until },
+        };
+
+        let opened =
+            matches!(next, Circuit::Open { .. }) && !matches!(*circuit, Circuit::Open { .. });
+        *circuit = next;
+        opened
+    }
+
+    /// Seconds until an open circuit half-opens, if it is open
+    pub fn remaining_at(&self, scope_group: &str, account_id: &str, now: i64) -> Option<u64> {
+        match *self.circuits.get(&Self::key(scope_group, account_id))? {
+            Circuit::Open { until } if until > now => Some((until - now) as u64),
+            _ => None,
+        }
+    }
+
+    /// List the account's non-closed circuits, sorted by scope group
+    pub fn states_for_account_at(&self, account_id: &str, now: i64) -> Vec<ScopeBreaker> {
+        let suffix = format!("::{}", account_id);
+        let mut states: Vec<ScopeBreaker> = self
+            .circuits
+            .iter()
+            .filter_map(|entry| {
+                let scope_group = entry.key().strip_suffix(&suffix)?;
+                let state = match *entry.value() {
+                    Circuit::Closed { .. } => return None,
+                    Circuit::Open { until } if until > now => BreakerState::Open {
+                        remaining_seconds: (until - now) as u64,
+                    },
+                    Circuit::Open { .. } | Circuit::HalfOpen { .. } => BreakerState::HalfOpen,
+                };
+                Some(ScopeBreaker {
+                    scope_group: scope_group.to_string(),
+                    state,
+                })
+            })
+            .collect();
+        states.sort_by(|a, b| a.scope_group.cmp(&b.scope_group));
+        states
+    }
+
+    /// Forget every circuit of an account
+    pub fn remove_account(&self, account_id: &str) {
+        let suffix = format!("::{}", account_id);
+        self.circuits.retain(|key, _| !key.ends_with(&suffix));
+    }
+}
+
+impl Clone for CircuitBreaker {
+    fn clone(&self) -> Self {
+        Self {
+            circuits: self.circuits.clone(),
+            threshold: AtomicU32::new(self.threshold.load(Ordering::Relaxed)),
+            window_seconds: AtomicU64::new(self.window_seconds.load(Ordering::Relaxed)),
+            cooldown_seconds: AtomicU64::new(self.cooldown_seconds.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn test_opens_after_threshold_within_window() {
+        let breaker = CircuitBreaker::new();
+
+        assert!(!breaker.record_failure_at("claude", "a", NOW));
+        assert!(!breaker.record_failure_at("claude", "a", NOW + 1));
+        assert!(breaker.allows_at("claude", "a", NOW + 1));
+
+        assert!(breaker.record_failure_at("claude", "a", NOW + 2));
+        assert!(!breaker.allows_at("claude", "a", NOW + 2));
+        assert_eq!(
+            breaker.remaining_at("claude", "a", NOW + 2),
+            Some(DEFAULT_COOLDOWN_SECONDS)
+        );
+
+        // Other scopes and accounts are unaffected
+        assert!(breaker.allows_at("gemini", "a", NOW + 2));
+        assert!(breaker.allows_at("claude", "b", NOW + 2));
+    }
+
+    #[test]
+    fn test_failures_outside_window_start_over() {
+        let breaker = CircuitBreaker::new();
+        let window = DEFAULT_WINDOW_SECONDS as i64;
+
+        breaker.record_failure_at("claude", "a", NOW);
+        breaker.record_failure_at("claude", "a", NOW + 1);
+        assert!(!breaker.record_failure_at("claude", "a", NOW + window + 1));
+        assert!(breaker.allows_at("claude", "a", NOW + window + 1));
+    }
+
+    #[test]
+    fn test_half_open_allows_single_trial() {
+        let breaker = CircuitBreaker::new();
+        breaker.configure(1, 60, 10);
+
+        assert!(breaker.record_failure_at("claude", "a", NOW));
+        assert!(!breaker.allows_at("claude", "a", NOW + 9));
+
+        // Cooldown over: one trial goes through
+        assert!(breaker.allows_at("claude", "a", NOW + 10));
+        breaker.on_selected_at("claude", "a", NOW + 10);
+        assert!(!breaker.allows_at("claude", "a", NOW + 11));
+        assert_eq!(
+            breaker.states_for_account_at("a", NOW + 11),
+            vec![ScopeBreaker {
+                scope_group: "claude".to_string(),
+                state: BreakerState::HalfOpen,
+            }]
+        );
+
+        // A failed trial reopens the circuit
+        assert!(breaker.record_failure_at("claude", "a", NOW + 12));
+        assert!(!breaker.allows_at("claude", "a", NOW + 13));
+
+        // A successful trial closes it
+        assert!(breaker.allows_at("claude", "a", NOW + 22));
+        breaker.on_selected_at("claude", "a", NOW + 22);
+        breaker.record_success("claude", "a");
+        assert!(breaker.allows_at("claude", "a", NOW + 23));
+        assert!(breaker.states_for_account_at("a", NOW + 23).is_empty());
+    }
+
+    #[test]
+    fn test_unanswered_trial_is_retried_after_cooldown() {
+        let breaker = CircuitBreaker::new();
+        breaker.configure(1, 60, 10);
+
+        breaker.record_failure_at("claude", "a", NOW);
+        assert!(breaker.allows_at("claude", "a", NOW + 10));
+        breaker.on_selected_at("claude", "a", NOW + 10);
+
+        assert!(!breaker.allows_at("claude", "a", NOW + 19));
+        assert!(breaker.allows_at("claude", "a", NOW + 20));
+    }
+
+    #[test]
+    fn test_state_serialization() {
+        let state = ScopeBreaker {
+            scope_group: "claude".to_string(),
+            state: BreakerState::Open {
+                remaining_seconds: 42,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&state).unwrap(),
+            serde_json::json!({"scope_group": "claude", "state": "open", "remaining_seconds": 42})
+        );
+    }
+}
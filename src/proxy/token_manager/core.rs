@@ -1,22 +1,104 @@
 //! Core TokenManager Implementation
-//! 
+//!
 //! The main TokenManager struct that coordinates account loading,
 //! token selection, and refresh operations.
 
-use dashmap::DashMap;
+use arc_swap::ArcSwap;
+use dashmap::{DashMap, DashSet};
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{broadcast, watch, Mutex, OnceCell};
+use tracing::Instrument;
 
-use super::refresh::{RefreshCoordinator, TokenResponse};
+use super::alerts::{webhook_hook, AlertEvent, AlertHook, Alerter};
+use super::crypto::AccountKey;
+use super::events::{EventBus, TokenManagerEvent};
+use super::health::{AccountStats, HealthTracker, RequestOutcome};
+use super::history::{EventFilter, EventHistory, RecordedEvent};
+use super::metrics::{
+    email_hash, AccountLabel, Metrics, MetricsSnapshot, StickinessReport, StickyOutcome,
+};
+use super::migrations::{Migration, CURRENT_SCHEMA_VERSION};
+use super::oauth_client::{GoogleOAuthClient, OAuthClient, OAuthError};
+use super::quota::QuotaObservation;
+use super::redact::{
+    mask_secret, redact_proxy_url, redact_secrets, redact_webhook_url, LogIdentity,
+};
+use super::refresh::{
+    OAuthCallPolicy, RefreshCoordinator, RefreshError, RefreshErrorKind, TokenResponse,
+};
 use super::scheduling::{AccountScheduler, SchedulingDecision};
-use super::session::SessionManager;
-use super::types::{ProxyToken, SelectedToken};
-use crate::proxy::rate_limit::RateLimitTracker;
-use crate::proxy::sticky_config::StickySessionConfig;
+use super::service_account::build_assertion;
+use super::session::{fingerprint_request, tenant_session_id, PersistedBinding, SessionManager};
+use super::storage::{
+    find_account_files, source_dir_of, write_atomic, AccountFileStore, DISABLED_ACCOUNTS_DIR,
+    INVALID_ACCOUNT_FILE,
+};
+use super::tasks::{BackgroundTask, TaskRegistry};
+use super::types::{
+    provider_for, AccountConflict, AccountFile, AccountStatus, AccountTokenError, AddAccountError,
+    AttemptInfo, AttemptOutcome, AuthType, ConflictField, ConversationPrefix, DisabledKind,
+    GetTokenError, GetTokenErrorKind, GetTokenOptions, InFlightGuard, Liveness, LoadReport,
+    OAuthClientCredentials, PendingAccount, PoolHealth, ProxyStats, ProxyToken, Readiness,
+    RetireOptions, RetireReport, RetiredAccountFile, ScopeRateLimit, SelectedToken,
+    SelectionPreview, SelectionReason, SessionInfo, TenantPolicy, TierOrder, TokenSection,
+    UpsertOutcome, WarmUpReport, WarmUpResult, DEFAULT_EXPIRY_BUFFER_SECONDS,
+    DEFAULT_REVIVE_COOLDOWN_SECONDS,
+};
+use super::usage::{UsageTracker, STATS_FLUSH_INTERVAL_SECONDS};
+use super::watcher::AccountWatcher;
+use crate::proxy::clock::{SharedClock, SystemClock};
+use crate::proxy::rate_limit::{RateLimitEntry, RateLimitTracker};
+use crate::proxy::sticky_config::{
+    GroupedStickyConfig, RequestTypePolicy, SchedulingMode, StickySessionConfig, DEFAULT_GROUP,
+};
+
+/// Minimum expiry buffer for image generation, whose batches can stream
+/// for several minutes on one access token
+const IMAGE_GEN_EXPIRY_BUFFER_SECONDS: u64 = 600;
+
+/// Account files read and parsed at the same time by `load_accounts`
+const ACCOUNT_LOAD_CONCURRENCY: usize = 16;
+
+/// How long a disabled account counts toward `PoolHealth::disabled_recently`
+const RECENT_DISABLE_WINDOW_SECONDS: i64 = 3600;
+
+/// Default seconds of rate limit beyond which an account doesn't count
+/// toward readiness
+pub const DEFAULT_READINESS_RATE_LIMIT_SECONDS: u64 = 300;
+
+/// File in the data directory naming the OAuth clients accounts may use
+///
+/// Maps a name to `{ "client_id": …, "client_secret": … }`.
+const OAUTH_CLIENTS_FILE: &str = "oauth_clients.json";
+
+/// Seconds added to the poll interval each time Google asks a device login
+/// to slow down (RFC 8628)
+const DEVICE_SLOW_DOWN_SECONDS: u64 = 5;
+
+/// Requests that may share one selection under `set_coalesce_selections`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SelectionKey {
+    scope_group: String,
+    request_type: String,
+    tenant: Option<String>,
+    session_id: Option<String>,
+}
+
+/// A selection that callers with the same key wait on together
+type SharedSelection = Arc<OnceCell<Result<SelectedToken, GetTokenError>>>;
+
+/// Callback run when a request gets a different account than the last one
+///
+/// Runs on the request path, so it should hand any slow work off to a task.
+pub type SelectionHook = Box<dyn Fn(&SelectedToken) + Send + Sync>;
 
 /// Token Manager - the brain of the proxy's account rotation system
-/// 
+///
 /// Manages multiple Google accounts and intelligently selects the best
 /// account for each request based on:
 /// - Subscription tier (ULTRA > PRO > FREE)
@@ -25,7 +107,10 @@ use crate::proxy::sticky_config::StickySessionConfig;
 /// - Token expiration
 pub struct TokenManager {
     /// All loaded tokens, keyed by account_id
-    tokens: Arc<DashMap<String, ProxyToken>>,
+    ///
+    /// Entries are shared with request snapshots and replaced on change,
+    /// never mutated in place.
+    tokens: Arc<DashMap<String, Arc<ProxyToken>>>,
     /// Data directory for account files
     data_dir: PathBuf,
     /// Rate limit tracker
@@ -34,492 +119,4814 @@ pub struct TokenManager {
     session_manager: SessionManager,
     /// Refresh coordinator for OAuth token refresh
     refresh_coordinator: RefreshCoordinator,
+    /// Serialized writes to account files, shared with the refresh coordinator
+    account_files: Arc<AccountFileStore>,
+    /// Google OAuth and project discovery
+    oauth_client: Arc<dyn OAuthClient>,
+    /// Named OAuth clients account files may refer to, from `oauth_clients.json`
+    oauth_clients: std::sync::RwLock<HashMap<String, OAuthClientCredentials>>,
+    /// Resolved project IDs, one in-flight lookup per account
+    project_ids: DashMap<String, Arc<OnceCell<String>>>,
+    /// Per-account request outcome statistics
+    health: Arc<HealthTracker>,
     /// Account scheduler
     scheduler: AccountScheduler,
-    /// Scheduling configuration
-    sticky_config: Arc<RwLock<StickySessionConfig>>,
+    /// Scheduling configuration, swapped whole on update so requests read
+    /// it without locking
+    sticky_config: ArcSwap<GroupedStickyConfig>,
+    /// Serializes configuration updates and their saves
+    sticky_config_update: Mutex<()>,
+    /// Seconds before expiry at which a token is refreshed
+    expiry_buffer_seconds: AtomicU64,
+    /// Seconds a temporarily disabled account waits before a revival attempt
+    revive_cooldown_seconds: AtomicU64,
+    /// Where events for subscribers are sent
+    events: EventBus,
+    /// The last events, for `recent_events`
+    history: EventHistory,
+    /// Counters behind `metrics_snapshot`
+    metrics: Metrics,
+    /// Whether session bindings are saved to `sessions.json`
+    session_persistence: AtomicBool,
+    /// Session revision last written to disk
+    saved_session_revision: AtomicU64,
+    /// Accounts kept out of rotation until resumed; never written to disk
+    paused: DashSet<String>,
+    /// Accounts taking no new sessions, with the time their drain started
+    draining: DashMap<String, i64>,
+    /// Accounts each downstream tenant may use; never written to disk
+    tenant_policies: DashMap<String, TenantPolicy>,
+    /// Where alerts about disabled accounts and a degraded pool go
+    alerts: Alerter,
+    /// Usage counters written back into the account files
+    usage: UsageTracker,
+    /// Turns `true` on `shutdown`; every background task holds a receiver
+    /// until it exits
+    shutdown: watch::Sender<bool>,
+    /// Whether account files in an older schema are rewritten on load
+    upgrade_account_files: AtomicBool,
+    /// Whether disabled account files are moved to `accounts/disabled/`
+    quarantine_disabled: AtomicBool,
+    /// Called when the selected account changes
+    selection_hook: std::sync::RwLock<Option<SelectionHook>>,
+    /// Account last passed to the selection hook
+    last_hooked_account: std::sync::Mutex<Option<String>>,
+    /// Accounts disabled in the last `RECENT_DISABLE_WINDOW_SECONDS`, with
+    /// the time of the disable
+    recently_disabled: DashMap<String, i64>,
+    /// Whether identical concurrent requests share one selection
+    coalesce_selections: AtomicBool,
+    /// Selections in progress that later identical requests join
+    selection_flights: DashMap<SelectionKey, SharedSelection>,
+    /// Selections served by the single-account fast path
+    single_account_selections: AtomicU64,
+    /// How accounts are named in logs and events (a `LogIdentity`)
+    log_identity: AtomicU8,
+    /// Background tasks started by this manager, for `liveness`
+    tasks: TaskRegistry,
+    /// Seconds of rate limit beyond which an account doesn't count toward
+    /// readiness
+    readiness_rate_limit_seconds: AtomicU64,
+    /// Source of the current time for everything time-based
+    clock: SharedClock,
 }
 
 impl TokenManager {
-    /// Create a new TokenManager
+    /// Create a new TokenManager talking to Google
+    ///
+    /// Encrypted account files are read with the key in
+    /// `ANTI_PROXY_ACCOUNT_KEY`, if set.
     pub fn new(data_dir: PathBuf) -> Self {
-        let rate_limit_tracker = Arc::new(RateLimitTracker::new());
-        
-        Self {
-            tokens: Arc::new(DashMap::new()),
-            data_dir,
-            rate_limit_tracker: rate_limit_tracker.clone(),
-            session_manager: SessionManager::new(),
-            refresh_coordinator: RefreshCoordinator::new(),
-            scheduler: AccountScheduler::new(rate_limit_tracker),
-            // Use CacheFirst with 120s to match existing StickySessionConfig defaults
-            sticky_config: Arc::new(RwLock::new(StickySessionConfig::default())),
-        }
+        Self::new_with_client(data_dir, Arc::new(GoogleOAuthClient::new()))
     }
 
-    /// Load all accounts from the data directory
-    /// 
-    /// Returns the number of active accounts loaded.
-    pub async fn load_accounts(&self) -> Result<usize, String> {
-        let accounts_dir = self.data_dir.join("accounts");
-
-        if !accounts_dir.exists() {
-            return Err(format!("Accounts directory does not exist: {:?}", accounts_dir));
-        }
+    /// Create a new TokenManager talking to Google that reads and writes
+    /// encrypted account files with `key`
+    pub fn new_with_key(data_dir: PathBuf, key: AccountKey) -> Self {
+        Self::new_with_client_and_key(data_dir, Arc::new(GoogleOAuthClient::new()), Some(key))
+    }
 
-        // Clear existing tokens (reload should reflect current disk state)
-        self.tokens.clear();
-        self.session_manager.clear_all();
+    /// Create a new TokenManager using `oauth_client` for refreshes and
+    /// project discovery
+    ///
+    /// Encrypted account files are read with the key in
+    /// `ANTI_PROXY_ACCOUNT_KEY`, if set.
+    pub fn new_with_client(data_dir: PathBuf, oauth_client: Arc<dyn OAuthClient>) -> Self {
+        Self::new_with_client_and_key(data_dir, oauth_client, AccountKey::from_env())
+    }
 
-        // Read directory entries in blocking task
-        let accounts_dir_clone = accounts_dir.clone();
-        let entries: Vec<PathBuf> = tokio::task::spawn_blocking(move || {
-            std::fs::read_dir(&accounts_dir_clone)
-                .map(|read_dir| {
-                    read_dir
-                        .filter_map(|e| e.ok())
-                        .map(|e| e.path())
-                        .filter(|p| p.extension().and_then(|s| s.to_str()) == Some("json"))
-                        .collect::<Vec<_>>()
-                })
-        })
-        .await
-        .map_err(|e| format!("Task failed: {}", e))?
-        .map_err(|e| format!("Failed to read accounts directory: {}", e))?;
+    /// Create a new TokenManager using `oauth_client` and, for encrypted
+    /// account files, `key`
+    pub fn new_with_client_and_key(
+        data_dir: PathBuf,
+        oauth_client: Arc<dyn OAuthClient>,
+        key: Option<AccountKey>,
+    ) -> Self {
+        Self::new_with_clock(data_dir, oauth_client, key, SystemClock::shared())
+    }
 
-        let mut count = 0;
+    /// Create a new TokenManager that reads the current time from `clock`
+    ///
+    /// Token expiry, rate limits, breakers, throttles and session TTLs all
+    /// follow `clock`, so tests can pass a `MockClock` and advance it.
+    pub fn new_with_clock(
+        data_dir: PathBuf,
+        oauth_client: Arc<dyn OAuthClient>,
+        key: Option<AccountKey>,
+        clock: SharedClock,
+    ) -> Self {
+        let rate_limit_tracker = Arc::new(RateLimitTracker::with_clock(clock.clone()));
+        let sticky_config = Self::load_sticky_configs(&data_dir);
+        let events = EventBus::default();
+        let session_manager = SessionManager::with_events_and_clock(events.clone(), clock.clone());
+        session_manager.set_ttl(sticky_config.default.session_ttl_seconds);
+        session_manager.set_max_bindings(sticky_config.default.max_session_bindings);
+        let session_persistence = AtomicBool::new(sticky_config.default.persist_sessions);
+        if sticky_config.default.persist_sessions {
+            Self::restore_sessions(&session_manager, &data_dir, clock.now());
+        }
+        let saved_session_revision = AtomicU64::new(session_manager.revision());
+        let scheduler = AccountScheduler::with_clock(rate_limit_tracker.clone(), clock.clone());
+        Self::configure_scheduler(&scheduler, &sticky_config.default);
+        let account_files = Arc::new(AccountFileStore::with_key(key));
+        let oauth_clients = Self::load_oauth_clients(&data_dir);
 
-        for path in entries {
-            match self.load_single_account(&path).await {
-                Ok(Some(token)) => {
-                    self.tokens.insert(token.account_id.clone(), token);
-                    count += 1;
-                }
-                Ok(None) => {
-                    // Account is disabled, skip
-                }
-                Err(e) => {
-                    tracing::debug!("Failed to load account {:?}: {}", path, e);
-                }
-            }
+        Self {
+            tokens: Arc::new(DashMap::new()),
+            data_dir,
+            rate_limit_tracker,
+            session_manager,
+            refresh_coordinator: RefreshCoordinator::with_client_and_clock(
+                oauth_client.clone(),
+                account_files.clone(),
+                clock.clone(),
+            ),
+            account_files,
+            oauth_client,
+            oauth_clients: std::sync::RwLock::new(oauth_clients),
+            project_ids: DashMap::new(),
+            health: Arc::new(HealthTracker::with_clock(clock.clone())),
+            scheduler,
+            // Saved scheduling configs, or the defaults when none are saved
+            sticky_config: ArcSwap::from_pointee(sticky_config),
+            sticky_config_update: Mutex::new(()),
+            expiry_buffer_seconds: AtomicU64::new(DEFAULT_EXPIRY_BUFFER_SECONDS),
+            revive_cooldown_seconds: AtomicU64::new(DEFAULT_REVIVE_COOLDOWN_SECONDS),
+            events,
+            history: EventHistory::default(),
+            metrics: Metrics::default(),
+            session_persistence,
+            saved_session_revision,
+            paused: DashSet::new(),
+            draining: DashMap::new(),
+            tenant_policies: DashMap::new(),
+            alerts: Alerter::new(clock.clone()),
+            usage: UsageTracker::new(),
+            shutdown: watch::Sender::new(false),
+            upgrade_account_files: AtomicBool::new(false),
+            quarantine_disabled: AtomicBool::new(false),
+            selection_hook: std::sync::RwLock::new(None),
+            last_hooked_account: std::sync::Mutex::new(None),
+            recently_disabled: DashMap::new(),
+            coalesce_selections: AtomicBool::new(false),
+            selection_flights: DashMap::new(),
+            single_account_selections: AtomicU64::new(0),
+            log_identity: AtomicU8::new(LogIdentity::default().as_u8()),
+            tasks: TaskRegistry::default(),
+            readiness_rate_limit_seconds: AtomicU64::new(DEFAULT_READINESS_RATE_LIMIT_SECONDS),
+            clock,
         }
+    }
 
-        Ok(count)
+    /// Current Unix timestamp according to the manager's clock
+    fn now(&self) -> i64 {
+        self.clock.now()
     }
 
-    /// Load a single account from a JSON file
-    async fn load_single_account(&self, path: &PathBuf) -> Result<Option<ProxyToken>, String> {
-        let path_clone = path.clone();
-        let content = tokio::task::spawn_blocking(move || std::fs::read_to_string(&path_clone))
-            .await
-            .map_err(|e| format!("Task failed: {}", e))?
-            .map_err(|e| format!("Failed to read file: {}", e))?;
+    /// Subscribe to events from now on
+    ///
+    /// A subscriber that falls more than `EVENT_BUFFER_SIZE` events behind
+    /// loses the oldest ones; it never holds up token selection.
+    pub fn subscribe(&self) -> broadcast::Receiver<TokenManagerEvent> {
+        self.events.subscribe()
+    }
 
-        let account: serde_json::Value = serde_json::from_str(&content)
-            .map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    /// Count an event in the metrics, record it and send it to subscribers
+    fn emit(&self, event: TokenManagerEvent) {
+        self.metrics.record(&event);
+        self.history.record(event.clone(), self.clock.now_millis());
+        self.events.emit(event);
+    }
 
-        // Check if account is disabled
-        if account.get("disabled").and_then(|v| v.as_bool()).unwrap_or(false) {
-            return Ok(None);
-        }
+    /// The last `limit` recorded events matching `filter`, oldest first
+    ///
+    /// Selections, rate limits, refreshes, disables and configuration
+    /// changes are kept, up to `HISTORY_CAPACITY` of them. Session bindings
+    /// show up in the selections that made them.
+    pub fn recent_events(&self, limit: usize, filter: &EventFilter) -> Vec<RecordedEvent> {
+        self.history.recent(limit, filter)
+    }
 
-        // Check if proxy is disabled for this account
-        if account.get("proxy_disabled").and_then(|v| v.as_bool()).unwrap_or(false) {
-            return Ok(None);
-        }
+    /// Set how many seconds before expiry a token is refreshed
+    pub fn set_expiry_buffer(&self, buffer_secs: u64) {
+        self.expiry_buffer_seconds
+            .store(buffer_secs, Ordering::Relaxed);
+    }
 
-        // Extract required fields
-        let account_id = account["id"]
-            .as_str()
-            .ok_or("Missing id field")?
-            .to_string();
+    /// Get how many seconds before expiry a token is refreshed
+    pub fn expiry_buffer(&self) -> u64 {
+        self.expiry_buffer_seconds.load(Ordering::Relaxed)
+    }
 
-        let email = account["email"]
-            .as_str()
-            .ok_or("Missing email field")?
-            .to_string();
+    /// Expiry buffer for a request type; image generation gets a longer one
+    fn expiry_buffer_for(&self, request_type: &str) -> u64 {
+        let buffer = self.expiry_buffer();
+        if request_type == "image_gen" {
+            buffer.max(IMAGE_GEN_EXPIRY_BUFFER_SECONDS)
+        } else {
+            buffer
+        }
+    }
 
-        let token_obj = account["token"]
-            .as_object()
-            .ok_or("Missing token field")?;
+    /// Set how long a temporarily disabled account waits before a revival attempt
+    pub fn set_revive_cooldown(&self, cooldown_secs: u64) {
+        self.revive_cooldown_seconds
+            .store(cooldown_secs, Ordering::Relaxed);
+    }
 
-        let access_token = token_obj["access_token"]
-            .as_str()
-            .ok_or("Missing access_token")?
-            .to_string();
+    /// Get how long a temporarily disabled account waits before a revival attempt
+    pub fn revive_cooldown(&self) -> u64 {
+        self.revive_cooldown_seconds.load(Ordering::Relaxed)
+    }
 
-        let refresh_token = token_obj["refresh_token"]
-            .as_str()
-            .ok_or("Missing refresh_token")?
-            .to_string();
+    /// Set whether account files in an older schema are rewritten in the
+    /// current one when loaded
+    ///
+    /// Off by default: older files are upgraded in memory only, until the
+    /// proxy next writes them for another reason.
+    pub fn set_upgrade_account_files(&self, enabled: bool) {
+        self.upgrade_account_files.store(enabled, Ordering::Relaxed);
+    }
 
-        let expires_in = token_obj["expires_in"]
-            .as_i64()
-            .ok_or("Missing expires_in")?;
+    /// Install a callback for account selections, replacing any previous one
+    ///
+    /// The hook runs when a request is served by a different account than
+    /// the one it was last called with; repeat selections of the same
+    /// account are skipped. No hook is installed by default.
+    pub fn set_selection_hook(&self, hook: SelectionHook) {
+        *self
+            .selection_hook
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Some(hook);
+        *self
+            .last_hooked_account
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = None;
+    }
 
-        let timestamp = token_obj["expiry_timestamp"]
-            .as_i64()
-            .ok_or("Missing expiry_timestamp")?;
+    /// Run the selection hook if the selected account changed
+    fn notify_selection(&self, selected: &SelectedToken) {
+        let hook = self
+            .selection_hook
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        let Some(hook) = hook.as_ref() else {
+            return;
+        };
+        {
+            let mut last = self
+                .last_hooked_account
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            if last.as_deref() == Some(selected.account_id.as_str()) {
+                return;
+            }
+            *last = Some(selected.account_id.clone());
+        }
+        hook(selected);
+    }
 
-        let project_id = token_obj
-            .get("project_id")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
+    /// Install a callback for alerts, replacing any previous one
+    ///
+    /// The hook hears about disabled accounts, scope groups with fewer than
+    /// `threshold` healthy accounts (0 turns those off) and requests that
+    /// found every account rate limited. The same alert fires at most once
+    /// per `ALERT_COOLDOWN_SECONDS`. No hook is installed by default.
+    pub fn set_alert_hook(&self, threshold: usize, hook: AlertHook) {
+        self.alerts.set_hook(threshold, hook);
+    }
 
-        let subscription_tier = account
-            .get("quota")
-            .and_then(|q| q.get("subscription_tier"))
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
+    /// Send alerts to a webhook as JSON POSTs, replacing any previous hook
+    ///
+    /// See [`TokenManager::set_alert_hook`]. Failed deliveries are retried
+    /// in the background and never hold up a request.
+    pub fn set_alert_webhook(&self, threshold: usize, url: &str) {
+        tracing::info!(
+            "[TokenManager] Sending alerts to {}",
+            redact_webhook_url(url)
+        );
+        self.alerts
+            .set_hook(threshold, webhook_hook(url.to_string()));
+    }
 
-        Ok(Some(ProxyToken {
-            account_id,
-            access_token,
-            refresh_token,
-            expires_in,
-            timestamp,
-            email,
-            account_path: path.clone(),
-            project_id,
-            subscription_tier,
-        }))
+    /// Set the seconds before the same alert may fire again
+    pub fn set_alert_cooldown(&self, seconds: u64) {
+        self.alerts.set_cooldown(seconds);
     }
 
-    /// Get a token for a request
-    /// 
-    /// # Arguments
-    /// * `quota_group` - "claude" or "gemini"
-    /// * `request_type` - "chat", "image_gen", etc.
-    /// * `force_rotate` - Force account rotation
-    /// * `session_id` - Optional session ID for sticky binding
-    pub async fn get_token(
-        &self,
-        quota_group: &str,
-        request_type: &str,
-        force_rotate: bool,
-        session_id: Option<&str>,
-    ) -> Result<SelectedToken, String> {
-        // Take snapshot of tokens
-        let mut tokens_snapshot: Vec<ProxyToken> = self
-            .tokens
-            .iter()
-            .map(|e| e.value().clone())
-            .collect();
+    /// Alert if fewer accounts than the threshold can serve a scope group
+    fn check_pool_health(&self, quota_group: &str, request_type: &str) {
+        self.check_pool_health_as(quota_group, request_type, false);
+    }
 
-        if tokens_snapshot.is_empty() {
-            return Err("Token pool is empty".to_string());
+    /// Like [`Self::check_pool_health`]; with `emptied`, an account just
+    /// left the pool, so a pool with no accounts at all also alerts
+    fn check_pool_health_as(&self, quota_group: &str, request_type: &str, emptied: bool) {
+        let threshold = self.alerts.threshold();
+        if threshold == 0 || !self.alerts.is_enabled() {
+            return;
+        }
+        let health = self.pool_health(quota_group, request_type);
+        if (health.total > 0 || emptied) && health.healthy < threshold {
+            self.alerts.fire(AlertEvent::PoolHealthBelowThreshold {
+                scope_group: AccountScheduler::scope_group(quota_group, request_type, None),
+                healthy: health.healthy,
+                total: health.total,
+            });
         }
+    }
 
-        // Sort by subscription tier priority
-        AccountScheduler::sort_by_tier(&mut tokens_snapshot);
+    /// Set whether identical concurrent requests share one selection
+    ///
+    /// Off by default. With it on, requests for the same scope group,
+    /// request type and session that arrive while a selection for them is
+    /// running wait for it and get the same account, so a cold start
+    /// refreshes and resolves each token once. Sessionless requests are
+    /// only coalesced while a token refresh is in progress, so they still
+    /// spread across accounts the rest of the time. Requests with a
+    /// preferred account, exclusions, tags or forced rotation are never
+    /// coalesced.
+    pub fn set_coalesce_selections(&self, enabled: bool) {
+        self.coalesce_selections.store(enabled, Ordering::Relaxed);
+    }
 
-        let scope_group = AccountScheduler::scope_group(quota_group, request_type);
-        let scheduling = self.sticky_config.read().await.clone();
+    /// Number of selections served by the single-account fast path
+    pub fn single_account_selections(&self) -> u64 {
+        self.single_account_selections.load(Ordering::Relaxed)
+    }
 
-        // Get session binding if exists
-        let bound_account = session_id
-            .and_then(|sid| self.session_manager.get_binding(&scope_group, sid));
+    /// Set whether disabling an account moves its file to
+    /// `accounts/disabled/`
+    ///
+    /// Off by default. Quarantined files keep their path relative to
+    /// `accounts/`, are only listed (never parsed) on reload, and move back
+    /// when the account is enabled or revived.
+    pub fn set_quarantine_disabled(&self, enabled: bool) {
+        self.quarantine_disabled.store(enabled, Ordering::Relaxed);
+    }
 
-        tracing::info!(
-            "[TokenManager] get_token: group={}, type={}, force_rotate={}, session={:?}",
-            quota_group,
-            request_type,
-            force_rotate,
-            session_id
-        );
+    /// Load all accounts from the data directory
+    ///
+    /// Reloading is incremental: accounts whose files are unchanged keep
+    /// their live state (refreshed access tokens, session bindings), and
+    /// only accounts whose files vanished or became disabled are removed.
+    pub async fn load_accounts(&self) -> Result<LoadReport, String> {
+        let accounts_dir = self.accounts_dir();
 
-        let mut attempted = std::collections::HashSet::new();
-        let mut last_error: Option<String> = None;
+        if !accounts_dir.exists() {
+            return Err(format!(
+                "Accounts directory does not exist: {:?}",
+                accounts_dir
+            ));
+        }
 
-        // Try each account until one works
-        for attempt in 0..tokens_snapshot.len() {
-            let rotate = force_rotate || attempt > 0;
+        // Files must not be read with older tokens than the pool holds
+        self.flush_token_writes().await;
+        let entries = Self::list_account_files(accounts_dir).await?;
+        *self
+            .oauth_clients
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Self::load_oauth_clients(&self.data_dir);
 
-            // Get scheduling decision
-            let decision = if rotate {
-                // Force round-robin on rotation
-                match self.scheduler.select_round_robin(&tokens_snapshot, &scope_group, &attempted) {
-                    Some(token) => SchedulingDecision::UseAccount(token),
-                    None => SchedulingDecision::AllUnavailable { min_wait_seconds: 60 },
-                }
-            } else {
-                self.scheduler.select_with_session(
-                    &tokens_snapshot,
-                    &scope_group,
-                    bound_account.as_deref(),
-                    &scheduling,
-                    &attempted,
-                )
-            };
+        let mut report = LoadReport::default();
+        let mut seen = HashSet::new();
+        // First file to claim each id and email; files come newest first
+        let mut claimed_ids: HashMap<String, PathBuf> = HashMap::new();
+        let mut claimed_emails: HashMap<String, PathBuf> = HashMap::new();
 
-            let mut token = match decision {
-                SchedulingDecision::UseAccount(t) => t,
-                SchedulingDecision::WaitAndUse { token, wait_seconds } => {
-                    tracing::warn!(
-                        "CacheFirst mode: waiting {}s for account {} to become available",
-                        wait_seconds,
-                        token.email
-                    );
-                    tokio::time::sleep(std::time::Duration::from_secs(wait_seconds)).await;
-                    token
-                }
-                SchedulingDecision::AllUnavailable { min_wait_seconds } => {
-                    return Err(format!(
-                        "All accounts are currently limited. Please wait {}s.",
-                        min_wait_seconds
-                    ));
-                }
-            };
+        // Files are read concurrently but handled in listing order, so the
+        // newest file still wins a conflict
+        let mut loaded = futures::stream::iter(entries)
+            .map(|path| async move {
+                let result = self.load_single_account(&path).await;
+                (path, result)
+            })
+            .buffered(ACCOUNT_LOAD_CONCURRENCY);
 
-            // Check if token needs refresh
-            if token.is_expired() {
-                match self.refresh_token(&mut token).await {
-                    Ok(()) => {
-                        // Update token in storage
-                        if let Some(mut entry) = self.tokens.get_mut(&token.account_id) {
-                            entry.access_token = token.access_token.clone();
-                            entry.expires_in = token.expires_in;
-                            entry.timestamp = token.timestamp;
+        while let Some((path, result)) = loaded.next().await {
+            match result {
+                Ok(Some(token)) => {
+                    let email = token.email.to_lowercase();
+                    let conflict = match (
+                        claimed_ids.get(&token.account_id),
+                        claimed_emails.get(&email),
+                    ) {
+                        (Some(kept), _) => {
+                            Some((ConflictField::Id, token.account_id.clone(), kept))
                         }
-                    }
-                    Err(e) => {
-                        tracing::error!("Token refresh failed for {}: {}", token.email, e);
-                        
-                        if RefreshCoordinator::is_permanent_error(&e) {
-                            tracing::error!("Disabling account due to permanent error: {}", token.email);
-                            let _ = self.disable_account(&token.account_id, &e).await;
-                            self.tokens.remove(&token.account_id);
+                        (None, Some(kept)) => {
+                            Some((ConflictField::Email, token.email.clone(), kept))
                         }
-                        
-                        last_error = Some(format!("Token refresh failed: {}", e));
-                        attempted.insert(token.account_id.clone());
+                        (None, None) => None,
+                    };
+                    if let Some((field, value, kept)) = conflict {
+                        tracing::warn!(
+                            "[TokenManager] Skipping account file {:?}: {} {} is already loaded from newer file {:?}",
+                            path,
+                            field,
+                            value,
+                            kept
+                        );
+                        report.conflicts.push(AccountConflict {
+                            field,
+                            value,
+                            kept: kept.clone(),
+                            skipped: path,
+                        });
                         continue;
                     }
-                }
-            }
+                    claimed_ids.insert(token.account_id.clone(), path.clone());
+                    claimed_emails.insert(email, path.clone());
 
-            // Ensure we have a project_id
-            let project_id = match &token.project_id {
-                Some(pid) => pid.clone(),
-                None => {
-                    match self.fetch_and_save_project_id(&token).await {
-                        Ok(pid) => pid,
-                        Err(e) => {
-                            last_error = Some(format!("Failed to fetch project_id: {}", e));
-                            attempted.insert(token.account_id.clone());
-                            continue;
-                        }
+                    seen.insert(token.account_id.clone());
+                    match self.upsert_account(token) {
+                        UpsertOutcome::Added => report.added += 1,
+                        UpsertOutcome::Updated => report.updated += 1,
+                        UpsertOutcome::Unchanged => report.kept += 1,
                     }
                 }
-            };
-
-            // Bind session to this account
-            if let Some(sid) = session_id {
-                if !rotate {
-                    self.session_manager.set_binding(&scope_group, sid, &token.account_id);
+                Ok(None) => {
+                    // Account is disabled, skip
+                    report.skipped_disabled += 1;
+                    report.disabled.push(path);
                 }
-            }
-
-            tracing::info!(
-                "[TokenManager] Selected account: {} (id: {})",
-                token.email,
-                token.account_id
-            );
-
-            // Update current account in background
-            let account_id = token.account_id.clone();
-            tokio::spawn(async move {
-                if let Err(e) = crate::modules::account::set_current_account_id(&account_id) {
-                    tracing::debug!("Failed to update current_account_id: {}", e);
+                Err(e) => {
+                    tracing::warn!(
+                        "[TokenManager] Could not load account file {}: {}",
+                        path.file_name().unwrap_or_default().to_string_lossy(),
+                        e
+                    );
+                    // Keep whatever was loaded from this file before; it may be mid-write
+                    if let Some(account_id) = self.account_id_for_path(&path) {
+                        seen.insert(account_id);
+                        report.kept += 1;
+                    }
+                    report.failed.push((path, e));
                 }
-            });
-
-            return Ok(SelectedToken {
-                access_token: token.access_token,
-                project_id,
-                email: token.email,
-                account_id: token.account_id,
-            });
+            }
         }
 
-        Err(last_error.unwrap_or_else(|| "All accounts failed".to_string()))
-    }
+        // Quarantined files are disabled by definition; list them without parsing
+        let quarantined = self.quarantined_account_files().await;
+        report.skipped_disabled += quarantined.len();
+        report.disabled.extend(quarantined);
 
-    /// Refresh a token using OAuth
-    async fn refresh_token(&self, token: &mut ProxyToken) -> Result<(), String> {
-        let lock = self.refresh_coordinator.get_lock(&token.account_id);
-        let _guard = lock.lock().await;
-
-        // Double-check if token still needs refresh
-        if !token.is_expired() {
-            // Another request already refreshed it
-            if let Some(entry) = self.tokens.get(&token.account_id) {
-                token.access_token = entry.access_token.clone();
-                token.expires_in = entry.expires_in;
-                token.timestamp = entry.timestamp;
+        let departed: Vec<String> = self
+            .tokens
+            .iter()
+            .filter(|e| !seen.contains(e.key()))
+            .map(|e| e.key().clone())
+            .collect();
+        for account_id in departed {
+            if self.evict_account(&account_id) {
+                report.removed += 1;
             }
-            return Ok(());
         }
 
-        let response = crate::modules::oauth::refresh_access_token(&token.refresh_token)
-            .await
-            .map_err(|e| e.to_string())?;
+        let retained = self.account_ids();
+        self.refresh_coordinator.cleanup(&retained);
+        // Restored sessions may point at accounts that are gone
+        self.session_manager.retain_accounts(&retained);
+        self.paused.retain(|id| retained.contains(id));
+        self.usage.retain(&retained);
+        self.draining.retain(|id, _| retained.contains(id));
+        if report.removed > 0 {
+            self.scheduler.cleanup(&retained);
+        }
 
-        let now = chrono::Utc::now().timestamp();
-        token.access_token = response.access_token.clone();
-        token.expires_in = response.expires_in;
-        token.timestamp = now + response.expires_in;
-
-        // Save to disk
-        RefreshCoordinator::save_refreshed_token(
-            token,
-            &TokenResponse {
-                access_token: response.access_token,
-                expires_in: response.expires_in,
-            },
-        )
-        .await?;
+        tracing::info!(
+            "[TokenManager] Accounts reloaded: {} added, {} removed, {} updated, {} kept, {} disabled, {} failed, {} conflicting",
+            report.added,
+            report.removed,
+            report.updated,
+            report.kept,
+            report.skipped_disabled,
+            report.failed.len(),
+            report.conflicts.len()
+        );
 
-        Ok(())
+        Ok(report)
     }
 
-    /// Fetch and save project ID for an account
-    async fn fetch_and_save_project_id(&self, token: &ProxyToken) -> Result<String, String> {
-        let project_id = crate::proxy::project_resolver::fetch_project_id(&token.access_token)
-            .await
-            .map_err(|e| format!("Failed to fetch project_id: {}", e))?;
+    /// List the account JSON files in a directory and its subdirectories,
+    /// most recently modified first and then by path
+    async fn list_account_files(accounts_dir: PathBuf) -> Result<Vec<PathBuf>, String> {
+        // Read directory entries in blocking task
+        tokio::task::spawn_blocking(move || {
+            find_account_files(&accounts_dir).map(|paths| {
+                let mut files: Vec<(std::time::SystemTime, PathBuf)> = paths
+                    .into_iter()
+                    .map(|p| {
+                        let mtime = std::fs::metadata(&p)
+                            .and_then(|m| m.modified())
+                            .unwrap_or(std::time::UNIX_EPOCH);
+                        (mtime, p)
+                    })
+                    .collect();
+                files.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+                files.into_iter().map(|(_, p)| p).collect::<Vec<_>>()
+            })
+        })
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+        .map_err(|e| format!("Failed to read accounts directory: {}", e))
+    }
 
-        // Update in memory
-        if let Some(mut entry) = self.tokens.get_mut(&token.account_id) {
-            entry.project_id = Some(project_id.clone());
+    /// Insert or update an account loaded from disk, keeping its live state
+    ///
+    /// Session bindings are untouched, and an in-memory access token that is
+    /// newer than the file's (e.g. refreshed but not yet visible on disk) is
+    /// kept.
+    pub(super) fn upsert_account(&self, mut token: ProxyToken) -> UpsertOutcome {
+        match self.tokens.get_mut(&token.account_id) {
+            Some(mut entry) => {
+                if entry.timestamp > token.timestamp {
+                    token.access_token = entry.access_token.clone();
+                    token.refresh_token = entry.refresh_token.clone();
+                    token.expires_in = entry.expires_in;
+                    token.timestamp = entry.timestamp;
+                }
+                if token.project_id.is_none() {
+                    token.project_id = entry.project_id.clone();
+                }
+                if **entry == token {
+                    UpsertOutcome::Unchanged
+                } else {
+                    *entry = Arc::new(token);
+                    UpsertOutcome::Updated
+                }
+            }
+            None => {
+                self.tokens
+                    .insert(token.account_id.clone(), Arc::new(token));
+                UpsertOutcome::Added
+            }
         }
+    }
 
-        // Save to disk
-        self.save_project_id(&token.account_id, &project_id).await?;
-
-        Ok(project_id)
+    /// Drop an account from the pool along with its session bindings
+    ///
+    /// Returns `true` if the account was loaded.
+    pub(super) fn evict_account(&self, account_id: &str) -> bool {
+        let removed = self.tokens.remove(account_id).is_some();
+        self.health.remove(account_id);
+        self.scheduler.circuit_breaker().remove_account(account_id);
+        self.scheduler.quota().remove_account(account_id);
+        self.project_ids.remove(account_id);
+        self.metrics.remove_account(account_id);
+        let unbound = self.session_manager.remove_bindings_for_account(account_id);
+        if unbound > 0 {
+            tracing::debug!(
+                "[TokenManager] Removed {} session binding(s) for account {}",
+                unbound,
+                account_id
+            );
+        }
+        removed
     }
 
-    /// Save project_id to account file
-    async fn save_project_id(&self, account_id: &str, project_id: &str) -> Result<(), String> {
-        let entry = self.tokens.get(account_id).ok_or("Account not found")?;
-        let path = entry.account_path.clone();
-        drop(entry);
+    /// Find the loaded account backed by a file
+    pub(super) fn account_id_for_path(&self, path: &std::path::Path) -> Option<String> {
+        self.tokens
+            .iter()
+            .find(|e| e.value().account_path == path)
+            .map(|e| e.key().clone())
+    }
 
-        let path_clone = path.clone();
-        let content_str = tokio::task::spawn_blocking(move || std::fs::read_to_string(&path_clone))
-            .await
-            .map_err(|e| format!("Task failed: {}", e))?
-            .map_err(|e| format!("Failed to read file: {}", e))?;
+    /// A loaded account from another file that already claims `token`'s id
+    /// or email, as `load_accounts` would report it
+    pub(super) fn conflicting_account(
+        &self,
+        token: &ProxyToken,
+    ) -> Option<(ConflictField, String, PathBuf)> {
+        let email = token.email.to_lowercase();
+        self.tokens
+            .iter()
+            .filter(|e| e.value().account_path != token.account_path)
+            .find_map(|e| {
+                let loaded = e.value();
+                if loaded.account_id == token.account_id {
+                    Some((
+                        ConflictField::Id,
+                        token.account_id.clone(),
+                        loaded.account_path.clone(),
+                    ))
+                } else if loaded.email.to_lowercase() == email {
+                    Some((
+                        ConflictField::Email,
+                        token.email.clone(),
+                        loaded.account_path.clone(),
+                    ))
+                } else {
+                    None
+                }
+            })
+    }
 
-        let mut content: serde_json::Value = serde_json::from_str(&content_str)
-            .map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    /// Directory holding the account JSON files
+    pub(super) fn accounts_dir(&self) -> PathBuf {
+        self.data_dir.join("accounts")
+    }
 
-        content["token"]["project_id"] = serde_json::Value::String(project_id.to_string());
+    /// Directory disabled account files are quarantined in
+    fn disabled_accounts_dir(&self) -> PathBuf {
+        self.accounts_dir().join(DISABLED_ACCOUNTS_DIR)
+    }
 
-        let json_str = serde_json::to_string_pretty(&content)
-            .map_err(|e| format!("Failed to serialize JSON: {}", e))?;
+    /// Account files in the quarantine, most recently modified first
+    async fn quarantined_account_files(&self) -> Vec<PathBuf> {
+        let dir = self.disabled_accounts_dir();
+        if !dir.is_dir() {
+            return Vec::new();
+        }
+        Self::list_account_files(dir).await.unwrap_or_else(|e| {
+            tracing::warn!("[TokenManager] Could not list disabled accounts: {}", e);
+            Vec::new()
+        })
+    }
 
-        let path_clone = path.clone();
-        tokio::task::spawn_blocking(move || std::fs::write(&path_clone, json_str))
+    /// File of an account that may not be in the pool: `<id>.json` directly
+    /// in the accounts directory, or else in one of its subdirectories or
+    /// the quarantine
+    ///
+    /// Ids that could name a file outside the accounts directory are refused.
+    async fn account_file_path(&self, account_id: &str) -> Result<PathBuf, String> {
+        if account_id.is_empty() || account_id.contains(['/', '\\']) || account_id.contains("..") {
+            return Err(format!("Invalid account id {:?}", account_id));
+        }
+        let file_name = format!("{}.json", account_id);
+        let top_level = self.accounts_dir().join(&file_name);
+        if top_level.exists() {
+            return Ok(top_level);
+        }
+        let mut candidates = Self::list_account_files(self.accounts_dir())
             .await
-            .map_err(|e| format!("Task failed: {}", e))?
-            .map_err(|e| format!("Failed to write file: {}", e))?;
+            .unwrap_or_default();
+        candidates.extend(self.quarantined_account_files().await);
+        Ok(candidates
+            .into_iter()
+            .find(|p| p.file_name().is_some_and(|n| n == file_name.as_str()))
+            .unwrap_or(top_level))
+    }
 
+    /// Move a disabled account file into the quarantine, keeping its path
+    /// relative to the accounts directory
+    ///
+    /// Files outside the accounts directory or already quarantined stay
+    /// where they are.
+    async fn quarantine_account_file(&self, path: &std::path::Path) -> Result<(), String> {
+        let disabled_dir = self.disabled_accounts_dir();
+        if path.starts_with(&disabled_dir) {
+            return Ok(());
+        }
+        let Ok(relative) = path.strip_prefix(self.accounts_dir()) else {
+            return Ok(());
+        };
+        let target = disabled_dir.join(relative);
+        self.account_files.relocate(path, &target).await?;
+        tracing::info!(
+            "[TokenManager] Quarantined disabled account file {:?}",
+            target
+        );
         Ok(())
     }
 
-    /// Disable an account due to errors
-    async fn disable_account(&self, account_id: &str, reason: &str) -> Result<(), String> {
-        let path = if let Some(entry) = self.tokens.get(account_id) {
-            entry.account_path.clone()
-        } else {
-            self.data_dir.join("accounts").join(format!("{}.json", account_id))
+    /// Move a quarantined account file back to its place in the accounts
+    /// directory, returning where the file now is
+    async fn release_account_file(&self, path: &std::path::Path) -> Result<PathBuf, String> {
+        let Ok(relative) = path.strip_prefix(self.disabled_accounts_dir()) else {
+            return Ok(path.to_path_buf());
         };
+        let target = self.accounts_dir().join(relative);
+        self.account_files.relocate(path, &target).await?;
+        Ok(target)
+    }
 
-        let path_clone = path.clone();
-        let content_str = tokio::task::spawn_blocking(move || std::fs::read_to_string(&path_clone))
-            .await
-            .map_err(|e| format!("Task failed: {}", e))?
-            .map_err(|e| format!("Failed to read file: {}", e))?;
-
-        let mut content: serde_json::Value = serde_json::from_str(&content_str)
-            .map_err(|e| format!("Failed to parse JSON: {}", e))?;
-
-        let now = chrono::Utc::now().timestamp();
-        content["disabled"] = serde_json::Value::Bool(true);
-        content["disabled_at"] = serde_json::Value::Number(now.into());
-        content["disabled_reason"] = serde_json::Value::String(truncate_string(reason, 800));
+    /// Add or replace one account from its file without reloading the pool
+    ///
+    /// Other accounts and their session bindings are left untouched.
+    pub async fn add_account(&self, path: PathBuf) -> Result<ProxyToken, String> {
+        let token = self
+            .load_single_account(&path)
+            .await?
+            .ok_or_else(|| format!("Account file {:?} is disabled", path))?;
 
-        let json_str = serde_json::to_string_pretty(&content)
-            .map_err(|e| format!("Failed to serialize JSON: {}", e))?;
+        // The file may have been re-keyed since it was last loaded
+        if let Some(old_id) = self.account_id_for_path(&path) {
+            if old_id != token.account_id {
+                self.remove_account(&old_id);
+            }
+        }
 
-        let path_clone = path.clone();
-        tokio::task::spawn_blocking(move || std::fs::write(&path_clone, json_str))
-            .await
-            .map_err(|e| format!("Task failed: {}", e))?
-            .map_err(|e| format!("Failed to write file: {}", e))?;
+        self.upsert_account(token.clone());
+        tracing::info!(
+            "[TokenManager] Added account {} (id: {})",
+            self.display_identity(&token),
+            token.account_id
+        );
+        Ok(token)
+    }
 
-        tracing::warn!("Account disabled: {} ({:?})", account_id, path);
+    /// Send OAuth refreshes and project lookups through `url` (http, https
+    /// or socks5), or clear the setting with `None`
+    ///
+    /// Accounts with an `outbound_proxy` of their own keep using it.
+    pub fn set_outbound_proxy(&self, url: Option<&str>) -> Result<(), String> {
+        if let Some(url) = url {
+            crate::utils::http::parse_proxy_url(url)
+                .map_err(|e| format!("Invalid outbound proxy: {}", e))?;
+        }
+        self.refresh_coordinator
+            .set_outbound_proxy(url.map(str::to_string));
+        tracing::info!(
+            "[TokenManager] Outbound proxy {}",
+            url.map(redact_proxy_url)
+                .unwrap_or_else(|| "cleared".to_string())
+        );
         Ok(())
     }
 
-    /// Get the number of loaded accounts
-    pub fn len(&self) -> usize {
-        self.tokens.len()
+    /// Set the timeout and retries of OAuth refreshes and project lookups
+    ///
+    /// A call that is still hanging after `policy.timeout` fails as a
+    /// temporary error, releasing the account's refresh lock.
+    pub fn set_oauth_call_policy(&self, policy: OAuthCallPolicy) {
+        self.refresh_coordinator.set_call_policy(policy);
     }
 
-    /// Check if no accounts are loaded
-    pub fn is_empty(&self) -> bool {
-        self.tokens.is_empty()
+    /// Set how long a refreshed token waits before it is written to its
+    /// account file ([`DEFAULT_TOKEN_WRITE_DELAY`] by default)
+    ///
+    /// Refreshes of one account within the delay share a single write. A
+    /// crash loses the tokens still waiting, which only costs a refresh after
+    /// restart; rotated refresh tokens are always written right away. Zero
+    /// writes every refreshed token at once.
+    ///
+    /// [`DEFAULT_TOKEN_WRITE_DELAY`]: super::DEFAULT_TOKEN_WRITE_DELAY
+    pub fn set_token_write_delay(&self, delay: Duration) {
+        self.refresh_coordinator.set_token_write_delay(delay);
     }
 
-    // ===== Rate Limit Management =====
+    /// Write every refreshed token still waiting for its delay; returns the
+    /// number of account files written
+    pub async fn flush_token_writes(&self) -> usize {
+        self.refresh_coordinator.flush_token_writes().await
+    }
 
-    /// Mark an account as rate limited
-    pub fn mark_rate_limited(
+    /// Run an OAuth call under the configured timeout and retries
+    async fn oauth_call<T, F, Fut>(&self, call: F) -> Result<T, String>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, OAuthError>>,
+    {
+        self.refresh_coordinator
+            .call_policy()
+            .run(call)
+            .await
+            .map_err(String::from)
+    }
+
+    /// Get a fresh access token for an account file outside the pool's
+    /// refresh path: a refresh token exchange, or a signed JWT assertion for
+    /// service accounts
+    async fn exchange_account_token(
         &self,
-        quota_group: &str,
-        request_type: &str,
-        account_id: &str,
-        status: u16,
-        retry_after_header: Option<&str>,
-        error_body: &str,
+        account: &AccountFile,
+        credentials: Option<&OAuthClientCredentials>,
+        proxy: Option<&str>,
+    ) -> Result<TokenResponse, String> {
+        match account.service_account() {
+            Some(key) => {
+                let assertion = build_assertion(&key, self.now())?;
+                self.oauth_call(|| {
+                    self.oauth_client
+                        .exchange_assertion(&key.token_uri, &assertion, proxy)
+                })
+                .await
+            }
+            None => {
+                self.oauth_call(|| {
+                    self.oauth_client
+                        .refresh(&account.token.refresh_token, credentials, proxy)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Start adding an account through Google's device login
+    ///
+    /// Show the returned URL and code to the user, then call
+    /// [`complete_add_account`](Self::complete_add_account) to wait for
+    /// the approval. Works without a browser on this machine.
+    pub async fn begin_add_account(&self) -> Result<PendingAccount, String> {
+        let code = self.oauth_client.request_device_code().await?;
+        Ok(PendingAccount {
+            verification_url: code.verification_url,
+            user_code: code.user_code,
+            expires_at: self.now() + code.expires_in,
+            interval_seconds: code.interval,
+            device_code: code.device_code,
+        })
+    }
+
+    /// Wait for a device login to be approved and add its account
+    ///
+    /// Polls Google at the login's interval, backing off when asked to,
+    /// until the user approves, declines or the code expires. The account
+    /// file is written to the accounts directory with the user's email and
+    /// project; a project that cannot be resolved yet is resolved on first
+    /// use. Logging in again to an account that is already loaded replaces
+    /// its tokens in place.
+    pub async fn complete_add_account(
+        &self,
+        pending: &PendingAccount,
+    ) -> Result<ProxyToken, AddAccountError> {
+        use crate::modules::oauth::DeviceTokenPoll;
+
+        let mut interval = pending.interval_seconds;
+        let granted = loop {
+            if self.now() >= pending.expires_at {
+                return Err(AddAccountError::Expired);
+            }
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            match self
+                .oauth_client
+                .poll_device_token(&pending.device_code)
+                .await
+                .map_err(AddAccountError::Failed)?
+            {
+                DeviceTokenPoll::Pending => {}
+                DeviceTokenPoll::SlowDown => interval += DEVICE_SLOW_DOWN_SECONDS,
+                DeviceTokenPoll::Expired => return Err(AddAccountError::Expired),
+                DeviceTokenPoll::Denied => return Err(AddAccountError::Denied),
+                DeviceTokenPoll::Granted(response) => break response,
+            }
+        };
+        let refresh_token = granted.refresh_token.ok_or_else(|| {
+            AddAccountError::Failed("Google returned no refresh token".to_string())
+        })?;
+
+        let email = self
+            .oauth_client
+            .fetch_email(&granted.access_token)
+            .await
+            .map_err(AddAccountError::Failed)?;
+        let proxy = self.refresh_coordinator.outbound_proxy_for(None);
+        let project_id = match self
+            .oauth_call(|| {
+                self.oauth_client
+                    .fetch_project_id(&granted.access_token, proxy.as_deref())
+            })
+            .await
+        {
+            Ok(project_id) => Some(project_id),
+            Err(e) => {
+                tracing::warn!(
+                    "[TokenManager] Could not resolve project for new account {}: {}",
+                    self.log_identity().display("(unsaved)", &email),
+                    e
+                );
+                None
+            }
+        };
+        let token = TokenSection {
+            access_token: granted.access_token,
+            refresh_token,
+            expires_in: granted.expires_in,
+            expiry_timestamp: self.now() + granted.expires_in,
+            project_id,
+            extra: serde_json::Map::new(),
+        };
+
+        let existing = self
+            .tokens
+            .iter()
+            .find(|e| e.value().email.eq_ignore_ascii_case(&email))
+            .map(|e| e.value().account_path.clone());
+        let existing = match existing {
+            Some(path) => Some(path),
+            None => self.account_file_for_email(&email).await,
+        };
+        let path = match existing {
+            Some(path) => {
+                let path = self
+                    .release_account_file(&path)
+                    .await
+                    .map_err(AddAccountError::Failed)?;
+                let account_id = self
+                    .read_account_file(&path)
+                    .await
+                    .map_err(AddAccountError::Failed)?
+                    .id;
+                self.account_files
+                    .update(&path, move |account| {
+                        let extra = std::mem::take(&mut account.token.extra);
+                        account.token = TokenSection { extra, ..token };
+                        // The new tokens belong to the global client
+                        account.oauth_client_id = None;
+                        account.oauth_client_secret = None;
+                        account.oauth_client = None;
+                        account.disabled = false;
+                        account.disabled_at = None;
+                        account.disabled_reason = None;
+                        account.disabled_kind = None;
+                    })
+                    .await
+                    .map_err(AddAccountError::Failed)?;
+                // Start over without the refresh failures of the old tokens
+                self.refresh_coordinator.remove_lock(&account_id);
+                self.recently_disabled.remove(&account_id);
+                path
+            }
+            None => {
+                let id = uuid::Uuid::new_v4().to_string();
+                let path = self.accounts_dir().join(format!("{}.json", id));
+                let account = AccountFile {
+                    schema_version: Some(CURRENT_SCHEMA_VERSION),
+                    id,
+                    email,
+                    auth_type: AuthType::Oauth,
+                    service_account_key: None,
+                    token,
+                    quota: None,
+                    disabled: false,
+                    disabled_reason: None,
+                    disabled_at: None,
+                    disabled_kind: None,
+                    proxy_disabled: false,
+                    proxy_weight: None,
+                    max_concurrent: None,
+                    max_rpm: None,
+                    tags: Vec::new(),
+                    oauth_client_id: None,
+                    oauth_client_secret: None,
+                    oauth_client: None,
+                    outbound_proxy: None,
+                    providers: None,
+                    base_url_override: None,
+                    proxy_stats: None,
+                    extra: serde_json::Map::new(),
+                };
+                self.account_files
+                    .create(&path, &account)
+                    .await
+                    .map_err(AddAccountError::Failed)?;
+                path
+            }
+        };
+        self.add_account(path)
+            .await
+            .map_err(AddAccountError::Failed)
+    }
+
+    /// File of an account with this email that is not in the pool, such as a
+    /// disabled or quarantined one
+    async fn account_file_for_email(&self, email: &str) -> Option<PathBuf> {
+        let mut candidates = Self::list_account_files(self.accounts_dir())
+            .await
+            .unwrap_or_default();
+        candidates.extend(self.quarantined_account_files().await);
+        for path in candidates {
+            match self.read_account_file(&path).await {
+                Ok(account) if account.email.eq_ignore_ascii_case(email) => return Some(path),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Remove one account and everything tracked for it
+    ///
+    /// Drops its session bindings, refresh lock and rate-limit entries, so a
+    /// session bound to it rebinds on its next request. Returns false if the
+    /// account was not loaded.
+    pub fn remove_account(&self, account_id: &str) -> bool {
+        let removed = self.evict_account(account_id);
+        self.rate_limit_tracker.clear_account(account_id);
+        self.paused.remove(account_id);
+        self.draining.remove(account_id);
+        self.usage.remove(account_id);
+
+        let retained = self.account_ids();
+        self.refresh_coordinator.cleanup(&retained);
+        if removed {
+            self.scheduler.cleanup(&retained);
+            tracing::info!("[TokenManager] Removed account {}", account_id);
+        }
+        removed
+    }
+
+    /// Revoke an account's refresh token at Google, then remove the account
+    ///
+    /// Use this to retire an account for good: a revoked token cannot be
+    /// replayed from an old copy of the data directory. Service accounts
+    /// have no refresh token, so there is nothing to revoke. A failed
+    /// revocation is reported in the result and the account removed anyway,
+    /// unless `require_revocation` is set. The account file is then disabled,
+    /// quarantined or deleted as `options.file` says; a kept file has its
+    /// refresh token masked, whether or not the revocation went through.
+    pub async fn remove_account_and_revoke(
+        &self,
+        account_id: &str,
+        options: RetireOptions,
+    ) -> Result<RetireReport, String> {
+        let token = self
+            .tokens
+            .get(account_id)
+            .map(|entry| Arc::clone(entry.value()))
+            .ok_or_else(|| format!("Account {} is not loaded", account_id))?;
+
+        let proxy = self
+            .refresh_coordinator
+            .outbound_proxy_for(token.outbound_proxy.as_deref());
+        let revocation = match token.service_account {
+            // Keys are deleted in the Cloud console; there is no token to revoke
+            Some(_) => None,
+            None => Some(
+                self.oauth_call(|| {
+                    self.oauth_client
+                        .revoke(&token.refresh_token, proxy.as_deref())
+                })
+                .await,
+            ),
+        };
+        let revocation_error = match revocation {
+            None => None,
+            Some(Ok(())) => {
+                tracing::info!(
+                    "[TokenManager] Revoked the refresh token of account {}",
+                    account_id
+                );
+                None
+            }
+            Some(Err(e)) => {
+                let e = redact_secrets(&e);
+                if options.require_revocation {
+                    return Err(format!(
+                        "Failed to revoke the token of account {}: {}",
+                        account_id, e
+                    ));
+                }
+                tracing::warn!("[TokenManager] Failed to revoke the token of account {}, removing it anyway: {}", account_id, e);
+                Some(e)
+            }
+        };
+
+        self.remove_account(account_id);
+        let path = &token.account_path;
+        match options.file {
+            RetiredAccountFile::Delete => {
+                if let Err(e) = tokio::fs::remove_file(path).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        return Err(format!("Failed to delete {:?}: {}", path, e));
+                    }
+                }
+            }
+            RetiredAccountFile::Disable | RetiredAccountFile::Quarantine => {
+                let reason = match revocation_error {
+                    None => "Retired, refresh token revoked",
+                    Some(_) => "Retired, refresh token revocation failed",
+                };
+                self.mark_disabled(path, reason, DisabledKind::Permanent)
+                    .await?;
+                let masked = self
+                    .account_files
+                    .update(path, |account| {
+                        account.token.refresh_token = mask_secret(&account.token.refresh_token);
+                    })
+                    .await;
+                match masked {
+                    // A corrupted file was replaced without its tokens
+                    Err(e) if !e.starts_with(INVALID_ACCOUNT_FILE) => return Err(e),
+                    _ => {}
+                }
+                if options.file == RetiredAccountFile::Quarantine {
+                    self.quarantine_account_file(path).await?;
+                }
+            }
+        }
+
+        Ok(RetireReport {
+            account_id: account_id.to_string(),
+            revocation_error,
+        })
+    }
+
+    /// IDs of every loaded account
+    fn account_ids(&self) -> HashSet<String> {
+        self.tokens.iter().map(|e| e.key().clone()).collect()
+    }
+
+    /// Take a loaded account out of rotation without touching its file
+    ///
+    /// Sessions bound to it fail over on their next request as if it were
+    /// rate limited. The pause lasts until `resume_account` or a restart.
+    /// Returns false if the account is not loaded.
+    pub fn pause_account(&self, account_id: &str) -> bool {
+        if !self.tokens.contains_key(account_id) {
+            return false;
+        }
+        if self.paused.insert(account_id.to_string()) {
+            tracing::info!("[TokenManager] Paused account {}", account_id);
+        }
+        true
+    }
+
+    /// Put a paused or draining account back into rotation
+    ///
+    /// Returns false if the account was neither paused nor draining.
+    pub fn resume_account(&self, account_id: &str) -> bool {
+        let undrained = self.draining.remove(account_id).is_some();
+        let resumed = self.paused.remove(account_id).is_some() || undrained;
+        if resumed {
+            tracing::info!("[TokenManager] Resumed account {}", account_id);
+        }
+        resumed
+    }
+
+    /// Stop placing new sessions on an account while its current ones finish
+    ///
+    /// Sessions already bound to it keep using it; rotation and new bindings
+    /// skip it. Once no binding is left, or `max_drain_seconds` have passed,
+    /// `finish_drains` pauses it if `pause_when_drained` is set. Returns false
+    /// if the account is not loaded.
+    pub fn drain_account(&self, account_id: &str) -> bool {
+        if !self.tokens.contains_key(account_id) {
+            return false;
+        }
+        let now = self.now();
+        if self.draining.insert(account_id.to_string(), now).is_none() {
+            tracing::info!(
+                "[TokenManager] Draining account {} ({} session(s) bound)",
+                account_id,
+                self.session_manager
+                    .bindings_for_account(account_id)
+                    .values()
+                    .sum::<usize>()
+            );
+        }
+        true
+    }
+
+    /// Sessions still bound to a draining account, across all scope groups
+    ///
+    /// Returns `None` if the account is not draining.
+    pub fn draining_sessions_remaining(&self, account_id: &str) -> Option<usize> {
+        if !self.draining.contains_key(account_id) {
+            return None;
+        }
+        Some(
+            self.session_manager
+                .bindings_for_account(account_id)
+                .values()
+                .sum(),
+        )
+    }
+
+    /// Pause draining accounts that are done, if `pause_when_drained` is set
+    ///
+    /// A drain is done when no session is bound to the account any more or
+    /// `max_drain_seconds` have passed. Returns the accounts paused.
+    pub async fn finish_drains(&self) -> Vec<String> {
+        let (max_drain_seconds, pause_when_drained) = {
+            let config = self.sticky_config.load();
+            (
+                config.default.max_drain_seconds,
+                config.default.pause_when_drained,
+            )
+        };
+        if !pause_when_drained || self.draining.is_empty() {
+            return Vec::new();
+        }
+
+        let now = self.now();
+        let done: Vec<String> = self
+            .draining
+            .iter()
+            .filter(|entry| {
+                let timed_out =
+                    max_drain_seconds > 0 && now - *entry.value() >= max_drain_seconds as i64;
+                timed_out
+                    || self
+                        .session_manager
+                        .bindings_for_account(entry.key())
+                        .is_empty()
+            })
+            .map(|entry| entry.key().clone())
+            .collect();
+        for account_id in &done {
+            self.draining.remove(account_id);
+            self.paused.insert(account_id.clone());
+            tracing::info!(
+                "[TokenManager] Account {} finished draining and was paused",
+                account_id
+            );
+        }
+        done
+    }
+
+    /// Start a background task that pauses finished drains every `interval`
+    ///
+    /// See [`TokenManager::finish_drains`].
+    pub fn start_drain_monitor(self: &Arc<Self>, interval: Duration) -> BackgroundTask {
+        let manager = Arc::downgrade(self);
+        self.spawn_task("drain-monitor", interval, move || {
+            let manager = manager.clone();
+            async move {
+                match manager.upgrade() {
+                    Some(manager) => {
+                        manager.finish_drains().await;
+                        true
+                    }
+                    None => false,
+                }
+            }
+        })
+    }
+
+    /// Limit a downstream tenant to the accounts its policy allows
+    ///
+    /// Requests naming the tenant in `GetTokenOptions::tenant` only get
+    /// those accounts. Rate limits stay per account: tenants sharing an
+    /// account share its upstream quota. A tenant without a policy may use
+    /// every account; its sessions are kept apart all the same.
+    pub fn set_tenant_policy(&self, tenant: &str, policy: TenantPolicy) {
+        tracing::info!(
+            "[TokenManager] Tenant {} may use accounts {:?} and tags {:?}",
+            tenant,
+            policy.allowed_account_ids,
+            policy.allowed_tags
+        );
+        self.tenant_policies.insert(tenant.to_string(), policy);
+    }
+
+    /// Drop a tenant's policy, letting it use every account
+    pub fn remove_tenant_policy(&self, tenant: &str) -> Option<TenantPolicy> {
+        self.tenant_policies
+            .remove(tenant)
+            .map(|(_, policy)| policy)
+    }
+
+    /// Get a tenant's policy
+    pub fn tenant_policy(&self, tenant: &str) -> Option<TenantPolicy> {
+        self.tenant_policies
+            .get(tenant)
+            .map(|policy| policy.clone())
+    }
+
+    /// Re-enable a disabled account and put it back into the pool
+    ///
+    /// `account` is an account id or the path of its account file, which
+    /// must lie inside the accounts directory. Only
+    /// `disabled`, `disabled_at`, `disabled_reason` and `disabled_kind` are
+    /// cleared, whatever kind of disable it was. With
+    /// `validate` set the refresh token is tried first, and the file stays
+    /// disabled if the refresh fails.
+    pub async fn enable_account(
+        &self,
+        account: &str,
+        validate: bool,
+    ) -> Result<AccountStatus, String> {
+        let path = if account.contains(['/', '\\']) {
+            self.account_path_inside(std::path::Path::new(account))?
+        } else {
+            self.account_file_path(account).await?
+        };
+        if !path.exists() {
+            return Err(format!("Account file {:?} not found", path));
+        }
+
+        let mut account_file = self.read_account_file(&path).await?;
+        account_file.disabled = false;
+        let token = account_file
+            .to_proxy_token(&path)
+            .ok_or_else(|| format!("Account file {:?} is disabled for the proxy", path))?;
+
+        let refreshed = if validate {
+            let credentials = self.oauth_client_for(&account_file)?;
+            let proxy = self
+                .refresh_coordinator
+                .outbound_proxy_for(token.outbound_proxy.as_deref());
+            let response = self
+                .exchange_account_token(&account_file, credentials.as_ref(), proxy.as_deref())
+                .await
+                .map_err(|e| {
+                    format!(
+                        "Refresh token check failed for {}: {}",
+                        self.display_identity(&token),
+                        e
+                    )
+                })?;
+            Some(response)
+        } else {
+            None
+        };
+
+        let token = self
+            .restore_account(&path, &token.account_id, refreshed)
+            .await?;
+        tracing::info!(
+            "[TokenManager] Enabled account {} (id: {})",
+            self.display_identity(&token),
+            token.account_id
+        );
+
+        Ok(self.account_status(&token, self.now()))
+    }
+
+    /// Resolve a caller-supplied account file path, refusing anything that
+    /// is not inside the accounts directory
+    fn account_path_inside(&self, path: &std::path::Path) -> Result<PathBuf, String> {
+        let resolved = path
+            .canonicalize()
+            .map_err(|_| format!("Account file {:?} not found", path))?;
+        let accounts_dir = self
+            .accounts_dir()
+            .canonicalize()
+            .map_err(|e| format!("Failed to resolve accounts directory: {}", e))?;
+        // Rebuilt from the accounts directory so it matches the pool's paths
+        match resolved.strip_prefix(&accounts_dir) {
+            Ok(relative) => Ok(self.accounts_dir().join(relative)),
+            Err(_) => Err(format!(
+                "Account file {:?} is outside the accounts directory",
+                path
+            )),
+        }
+    }
+
+    /// Clear an account file's disable, store a validated token and load it
+    async fn restore_account(
+        &self,
+        path: &std::path::Path,
+        account_id: &str,
+        refreshed: Option<TokenResponse>,
+    ) -> Result<ProxyToken, String> {
+        let path = self.release_account_file(path).await?;
+        let refreshed_at = self.now();
+        self.account_files
+            .update(&path, move |account| {
+                account.disabled = false;
+                account.disabled_at = None;
+                account.disabled_reason = None;
+                account.disabled_kind = None;
+                if let Some(response) = refreshed {
+                    account.token.access_token = response.access_token;
+                    account.token.expires_in = response.expires_in;
+                    account.token.expiry_timestamp = refreshed_at + response.expires_in;
+                    if let Some(refresh_token) = response.refresh_token {
+                        account.token.refresh_token = refresh_token;
+                    }
+                }
+            })
+            .await?;
+
+        // Start over without the refresh failures that led to the disable
+        self.refresh_coordinator.remove_lock(account_id);
+        self.recently_disabled.remove(account_id);
+        self.add_account(path).await
+    }
+
+    /// Retry temporarily disabled accounts whose cooldown is over
+    ///
+    /// Each candidate gets one refresh. On success the account is enabled
+    /// and joins the pool; on failure it stays disabled with a new
+    /// `disabled_at`, and turns permanent if the new error says so.
+    /// Permanently disabled accounts are never touched. Returns the number
+    /// of accounts revived.
+    pub async fn revive_disabled_accounts(&self) -> usize {
+        let mut paths = match Self::list_account_files(self.accounts_dir()).await {
+            Ok(paths) => paths,
+            Err(e) => {
+                tracing::warn!("[TokenManager] Account revival skipped: {}", e);
+                return 0;
+            }
+        };
+        paths.extend(self.quarantined_account_files().await);
+        let now = self.now();
+        let cooldown = self.revive_cooldown();
+        let mut revived = 0;
+
+        for path in paths {
+            let Ok(account) = self.read_account_file(&path).await else {
+                continue;
+            };
+            if !account.is_revivable_at(now, cooldown) {
+                continue;
+            }
+            let credentials = match self.oauth_client_for(&account) {
+                Ok(credentials) => credentials,
+                Err(e) => {
+                    tracing::warn!(
+                        "[TokenManager] Cannot revive {}: {}",
+                        self.display_account(&account),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            let proxy = self
+                .refresh_coordinator
+                .outbound_proxy_for(account.outbound_proxy.as_deref());
+
+            match self
+                .exchange_account_token(&account, credentials.as_ref(), proxy.as_deref())
+                .await
+            {
+                Ok(response) => match self
+                    .restore_account(&path, &account.id, Some(response))
+                    .await
+                {
+                    Ok(token) => {
+                        tracing::info!(
+                            "[TokenManager] Revived account {} (id: {})",
+                            self.display_identity(&token),
+                            token.account_id
+                        );
+                        revived += 1;
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "[TokenManager] Failed to revive {}: {}",
+                            self.display_account(&account),
+                            e
+                        );
+                    }
+                },
+                Err(e) => {
+                    let kind = RefreshCoordinator::disabled_kind(&e);
+                    tracing::warn!(
+                        "[TokenManager] Account {} still failing, staying disabled ({:?}): {}",
+                        self.display_account(&account),
+                        kind,
+                        e
+                    );
+                    if let Err(err) = self.mark_disabled(&path, &e, kind).await {
+                        tracing::warn!(
+                            "Failed to mark {} disabled: {}",
+                            self.display_account(&account),
+                            err
+                        );
+                    }
+                }
+            }
+        }
+
+        revived
+    }
+
+    /// Read and parse an account file
+    async fn read_account_file(&self, path: &std::path::Path) -> Result<AccountFile, String> {
+        self.account_files
+            .read(path)
+            .await
+            .map(|(account, _)| account)
+    }
+
+    /// Load a single account from a JSON file
+    pub(super) async fn load_single_account(
+        &self,
+        path: &std::path::Path,
+    ) -> Result<Option<ProxyToken>, String> {
+        let (account, migration) = self.account_files.read(path).await?;
+        if let Migration::Upgraded { from } = migration {
+            if self.upgrade_account_files.load(Ordering::Relaxed) {
+                // The store migrates on every read, so an empty update writes the upgrade
+                match self.account_files.update(path, |_| {}).await {
+                    Ok(()) => tracing::info!(
+                        "[TokenManager] Upgraded {:?} from schema version {}",
+                        path,
+                        from
+                    ),
+                    Err(e) => tracing::warn!("[TokenManager] Failed to upgrade {:?}: {}", path, e),
+                }
+            }
+        }
+        let oauth_client = self.oauth_client_for(&account)?;
+        if let Some(proxy) = &account.outbound_proxy {
+            crate::utils::http::parse_proxy_url(proxy)
+                .map_err(|e| format!("Invalid outbound_proxy in {:?}: {}", path, e))?;
+        }
+        let token = account.to_proxy_token(path).map(|token| ProxyToken {
+            source_dir: source_dir_of(&self.accounts_dir(), path),
+            oauth_client,
+            ..token
+        });
+        if token.is_some() {
+            self.usage
+                .seed(&account.id, account.proxy_stats.clone().unwrap_or_default());
+        }
+        Ok(token)
+    }
+
+    /// Get a token for a request
+    ///
+    /// # Arguments
+    /// * `quota_group` - "claude" or "gemini"
+    /// * `request_type` - "chat", "image_gen", etc.
+    /// * `model` - Model the request is for, used by groups scoped by model
+    /// * `force_rotate` - Force account rotation
+    /// * `session_id` - Optional session ID for sticky binding
+    pub async fn get_token(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+        force_rotate: bool,
+        session_id: Option<&str>,
+    ) -> Result<SelectedToken, String> {
+        self.get_token_with_budget(
+            quota_group,
+            request_type,
+            model,
+            force_rotate,
+            session_id,
+            None,
+        )
+        .await
+    }
+
+    /// Get a token for a request, waiting at most `wait_budget` in total
+    ///
+    /// In CacheFirst mode a rate-limited sticky account may be waited on; a
+    /// wait that would overrun the budget is skipped and the request rotates
+    /// to another account instead. `None` leaves only `max_wait_seconds` in
+    /// effect. Dropping the returned future cancels any wait in progress.
+    pub async fn get_token_with_budget(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+        force_rotate: bool,
+        session_id: Option<&str>,
+        wait_budget: Option<Duration>,
+    ) -> Result<SelectedToken, String> {
+        let options = GetTokenOptions {
+            force_rotate,
+            session_id: session_id.map(str::to_string),
+            wait_budget,
+            model: model.map(str::to_string),
+            ..GetTokenOptions::default()
+        };
+        self.get_token_with_options(quota_group, request_type, &options)
+            .await
+            .map_err(|e| e.message)
+    }
+
+    /// Get a token for a request from a client that may not send a session ID
+    ///
+    /// When `session_id` is `None` and the group enables
+    /// `fingerprint_sessions`, the conversation's opening is hashed into a
+    /// session ID so every turn lands on the same account.
+    pub async fn get_token_for_conversation(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+        force_rotate: bool,
+        session_id: Option<&str>,
+        conversation: &ConversationPrefix,
+    ) -> Result<SelectedToken, String> {
+        let options = GetTokenOptions {
+            force_rotate,
+            session_id: session_id.map(str::to_string),
+            model: model.map(str::to_string),
+            conversation: Some(conversation.clone()),
+            ..GetTokenOptions::default()
+        };
+        self.get_token_with_options(quota_group, request_type, &options)
+            .await
+            .map_err(|e| e.message)
+    }
+
+    /// Get a token for a request with per-request options
+    ///
+    /// Excluded accounts are never selected. A preferred account takes the
+    /// place of the session binding on the first attempt; it only replaces
+    /// an existing binding when `rebind_session` is set.
+    ///
+    /// On failure the error traces what happened to every account.
+    pub async fn get_token_with_options(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        options: &GetTokenOptions,
+    ) -> Result<SelectedToken, GetTokenError> {
+        let started_at = std::time::Instant::now();
+        let scope_group = self.limit_scope(quota_group, request_type, options.model.as_deref());
+        // Phases fill in the empty fields as the selection runs
+        let span = tracing::info_span!(
+            "token_select",
+            scope_group = %scope_group,
+            session = ?options.session_id,
+            force_rotate = options.force_rotate,
+            attempts = tracing::field::Empty,
+            refreshes = tracing::field::Empty,
+            wait_seconds = tracing::field::Empty,
+            account_id = tracing::field::Empty,
+            email_hash = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+            duration_ms = tracing::field::Empty,
+        );
+        let selection = async {
+            match self.coalescing_key(quota_group, request_type, options) {
+                Some(key) => {
+                    self.select_coalesced(key, quota_group, request_type, options)
+                        .await
+                }
+                None => self.select_token(quota_group, request_type, options).await,
+            }
+        };
+        let result = selection.instrument(span.clone()).await;
+        let result = result.map_err(|mut error| {
+            error.scope_group = Some(scope_group);
+            error
+        });
+
+        match &result {
+            Ok(selected) => {
+                span.record("account_id", selected.account_id.as_str());
+                span.record("email_hash", email_hash(&selected.email).as_str());
+                span.record("outcome", "selected");
+            }
+            Err(error) => {
+                span.record("attempts", error.attempts.len());
+                span.record("outcome", tracing::field::debug(error.kind));
+            }
+        }
+        span.record("duration_ms", started_at.elapsed().as_millis() as u64);
+        self.metrics.observe_get_token(started_at.elapsed());
+        result
+    }
+
+    /// Key under which a request may join a running selection, if it may
+    fn coalescing_key(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        options: &GetTokenOptions,
+    ) -> Option<SelectionKey> {
+        if !self.coalesce_selections.load(Ordering::Relaxed) {
+            return None;
+        }
+        let plain = !options.force_rotate
+            && options.preferred_account.is_none()
+            && options.excluded_accounts.is_empty()
+            && options.required_tags.is_empty()
+            && options.excluded_tags.is_empty();
+        if !plain || (options.session_id.is_none() && !self.refresh_coordinator.is_refreshing()) {
+            return None;
+        }
+        Some(SelectionKey {
+            scope_group: self.limit_scope(quota_group, request_type, options.model.as_deref()),
+            request_type: request_type.to_string(),
+            tenant: options.tenant.clone(),
+            session_id: options.session_id.clone(),
+        })
+    }
+
+    /// Run a selection shared with every request that joins it under `key`
+    ///
+    /// The caller that runs the selection keeps its result as is; callers
+    /// that joined take their own in-flight slot on the same account, and
+    /// select on their own if the account has no slot left.
+    async fn select_coalesced(
+        &self,
+        key: SelectionKey,
+        quota_group: &str,
+        request_type: &str,
+        options: &GetTokenOptions,
+    ) -> Result<SelectedToken, GetTokenError> {
+        let flight = self
+            .selection_flights
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let mut led = false;
+        let result = flight
+            .get_or_init(|| {
+                led = true;
+                self.select_token(quota_group, request_type, options)
+            })
+            .await
+            .clone();
+        if led {
+            self.selection_flights
+                .remove_if(&key, |_, f| Arc::ptr_eq(f, &flight));
+            return result;
+        }
+
+        let Ok(mut selected) = result else {
+            return result;
+        };
+        let Some(token) = self
+            .tokens
+            .get(&selected.account_id)
+            .map(|e| e.value().clone())
+        else {
+            return self.select_token(quota_group, request_type, options).await;
+        };
+        // A follower may not join a probation trial already out on the account
+        let in_flight = self.scheduler.try_acquire_in_flight(&token).filter(|_| {
+            self.rate_limit_tracker
+                .try_start_trial(&selected.scope_group, &selected.account_id)
+        });
+        match in_flight {
+            Some(in_flight) => {
+                let now = self.now();
+                selected.in_flight = in_flight;
+                self.usage.record_request(&selected.account_id, now);
+                self.scheduler.throttle().record_at(
+                    &selected.scope_group,
+                    &selected.account_id,
+                    now,
+                );
+                Ok(selected)
+            }
+            None => self.select_token(quota_group, request_type, options).await,
+        }
+    }
+
+    /// Run one token selection for `get_token_with_options`
+    async fn select_token(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        options: &GetTokenOptions,
+    ) -> Result<SelectedToken, GetTokenError> {
+        if let Some(result) = self.select_single(quota_group, request_type, options).await {
+            return result;
+        }
+
+        let force_rotate = options.force_rotate;
+        let expiry_buffer = self.expiry_buffer_for(request_type);
+        let deadline = options
+            .wait_budget
+            .map(|budget| tokio::time::Instant::now() + budget);
+
+        let SelectionPlan {
+            tokens: tokens_snapshot,
+            mut trace,
+            session_group,
+            scope_group,
+            scheduling,
+            session_id,
+            bound_account,
+            binding_gone,
+            preferred_account,
+            session_counts,
+        } = self
+            .plan_selection(&self.scheduler, quota_group, request_type, options, false)
+            .await?;
+        if binding_gone {
+            self.metrics
+                .record_sticky(&scope_group, StickyOutcome::MissAccountGone);
+        } else if force_rotate && bound_account.is_some() {
+            self.metrics
+                .record_sticky(&scope_group, StickyOutcome::ForcedRotation);
+        }
+        let session_id = session_id.as_deref();
+        let preferred_account = preferred_account.as_deref();
+
+        tracing::debug!(
+            "[TokenManager] get_token: group={}, type={}, force_rotate={}, session={:?}, preferred={:?}",
+            quota_group,
+            request_type,
+            force_rotate,
+            session_id,
+            preferred_account
+        );
+
+        let mut attempted = std::collections::HashSet::new();
+        let mut last_error: Option<String> = None;
+        let span = tracing::Span::current();
+        let mut refreshes = 0;
+        let mut waited = Duration::ZERO;
+
+        // A forced rotation steers clear of the bound account and the ones
+        // the session left recently, so it doesn't bounce straight back
+        let recent_accounts: Vec<String> = match session_id.filter(|_| force_rotate) {
+            Some(sid) => {
+                let mut recent: Vec<String> = bound_account.iter().cloned().collect();
+                for id in self.session_manager.recent_accounts(&session_group, sid) {
+                    if !recent.contains(&id) {
+                        recent.push(id);
+                    }
+                }
+                recent
+            }
+            None => Vec::new(),
+        };
+
+        // Try each account until one works
+        for attempt in 0..tokens_snapshot.len() {
+            let rotate = force_rotate || attempt > 0;
+            span.record("attempts", attempt + 1);
+
+            // Get scheduling decision
+            let preferred_now = preferred_account.filter(|_| attempt == 0);
+            let decision = if let Some(preferred) = preferred_now {
+                // The preferred account stands in for the sticky binding
+                self.scheduler.select_with_session(
+                    &tokens_snapshot,
+                    &scope_group,
+                    Some(preferred),
+                    &session_counts,
+                    &scheduling,
+                    &attempted,
+                )
+            } else if rotate {
+                // Skip the sticky binding on rotation
+                match self.scheduler.select_next_avoiding(
+                    &tokens_snapshot,
+                    &scope_group,
+                    &scheduling,
+                    &attempted,
+                    &recent_accounts,
+                ) {
+                    Some(token) => SchedulingDecision::UseAccount(token),
+                    None if self
+                        .scheduler
+                        .any_busy(&tokens_snapshot, &scope_group, &attempted) =>
+                    {
+                        SchedulingDecision::AllBusy
+                    }
+                    None => SchedulingDecision::AllUnavailable {
+                        min_wait_seconds: self.scheduler.min_wait(&tokens_snapshot, &scope_group),
+                    },
+                }
+            } else {
+                self.scheduler.select_with_session(
+                    &tokens_snapshot,
+                    &scope_group,
+                    bound_account.as_deref(),
+                    &session_counts,
+                    &scheduling,
+                    &attempted,
+                )
+            };
+
+            // The first pick tells whether the session stays on its account
+            let first_sticky_pick = attempt == 0 && !rotate && preferred_now.is_none();
+            if let Some(bound) = bound_account.as_deref().filter(|_| first_sticky_pick) {
+                match &decision {
+                    // Counted once selected or after the wait
+                    SchedulingDecision::UseAccount(t) if t.account_id == bound => {}
+                    SchedulingDecision::WaitAndUse { .. } => {}
+                    _ => self
+                        .metrics
+                        .record_sticky(&scope_group, self.sticky_miss(&scope_group, bound)),
+                }
+            }
+
+            let (token, reason) = match decision {
+                SchedulingDecision::UseAccount(t) => {
+                    let reason = if preferred_now == Some(t.account_id.as_str()) {
+                        SelectionReason::Preferred
+                    } else if !rotate && bound_account.as_deref() == Some(t.account_id.as_str()) {
+                        SelectionReason::StickyHit
+                    } else if force_rotate {
+                        SelectionReason::ForcedRotation
+                    } else {
+                        SelectionReason::Scheduled
+                    };
+                    (t, reason)
+                }
+                SchedulingDecision::WaitAndUse {
+                    token,
+                    wait_seconds,
+                } => {
+                    let wake_at = tokio::time::Instant::now() + Duration::from_secs(wait_seconds);
+                    if deadline.is_some_and(|deadline| wake_at > deadline) {
+                        tracing::warn!(
+                            "CacheFirst mode: {}s wait for account {} exceeds the wait budget, rotating",
+                            wait_seconds,
+                            self.display_identity(&token)
+                        );
+                        last_error = Some(format!(
+                            "Account {} is rate limited",
+                            self.display_identity(&token)
+                        ));
+                        attempted.insert(token.account_id.clone());
+                        trace.push(AttemptInfo::new(
+                            &token,
+                            AttemptOutcome::RateLimited {
+                                remaining_seconds: wait_seconds,
+                            },
+                            self.log_identity(),
+                        ));
+                        continue;
+                    }
+
+                    tracing::warn!(
+                        "CacheFirst mode: waiting {}s for account {} to become available",
+                        wait_seconds,
+                        self.display_identity(&token)
+                    );
+                    // Woken early when the limit is cleared or the configuration
+                    // changes; the re-check below decides what happens next
+                    let waiter = self
+                        .rate_limit_tracker
+                        .waiter(&scope_group, &token.account_id);
+                    let cleared = waiter.notified();
+                    tokio::pin!(cleared);
+                    cleared.as_mut().enable();
+                    let mut shutdown = self.shutdown.subscribe();
+                    let wait_started = tokio::time::Instant::now();
+                    if self
+                        .rate_limit_tracker
+                        .is_rate_limited(&scope_group, &token.account_id)
+                    {
+                        tokio::select! {
+                            _ = tokio::time::sleep_until(wake_at) => {}
+                            _ = &mut cleared => {
+                                tracing::debug!("CacheFirst mode: woken early for account {}", self.display_identity(&token));
+                            }
+                            _ = async { shutdown.wait_for(|stop| *stop).await.is_ok() } => {
+                                return Err(GetTokenError {
+                                    kind: GetTokenErrorKind::ShutDown,
+                                    attempts: trace,
+                                    ..GetTokenError::new("Token manager is shut down".to_string())
+                                });
+                            }
+                        }
+                    }
+                    let wait_elapsed = wait_started.elapsed();
+                    waited += wait_elapsed;
+                    span.record("wait_seconds", waited.as_secs_f64());
+
+                    // The limit may have been extended, or the account refreshed
+                    // or removed, while we slept
+                    let remaining = self
+                        .rate_limit_tracker
+                        .get_remaining_wait(&scope_group, &token.account_id);
+                    if remaining > 0
+                        || self
+                            .rate_limit_tracker
+                            .is_rate_limited(&scope_group, &token.account_id)
+                    {
+                        tracing::warn!(
+                            "CacheFirst mode: account {} is still limited ({}s) after waiting, rotating",
+                            self.display_identity(&token),
+                            remaining
+                        );
+                        self.metrics
+                            .record_sticky(&scope_group, StickyOutcome::MissRateLimited);
+                        last_error = Some(format!(
+                            "Account {} is rate limited",
+                            self.display_identity(&token)
+                        ));
+                        attempted.insert(token.account_id.clone());
+                        trace.push(AttemptInfo::new(
+                            &token,
+                            AttemptOutcome::RateLimited {
+                                remaining_seconds: remaining.max(1),
+                            },
+                            self.log_identity(),
+                        ));
+                        continue;
+                    }
+
+                    match self.tokens.get(&token.account_id) {
+                        Some(entry) => {
+                            self.metrics.record_sticky(
+                                &scope_group,
+                                StickyOutcome::Waited {
+                                    elapsed: wait_elapsed,
+                                },
+                            );
+                            (entry.value().clone(), SelectionReason::WaitedForSticky)
+                        }
+                        None => {
+                            self.metrics
+                                .record_sticky(&scope_group, StickyOutcome::MissAccountGone);
+                            last_error = Some(format!(
+                                "Account {} was removed",
+                                self.display_identity(&token)
+                            ));
+                            attempted.insert(token.account_id.clone());
+                            trace.push(AttemptInfo::new(
+                                &token,
+                                AttemptOutcome::DisabledMidFlight {
+                                    reason: "removed from the pool".to_string(),
+                                },
+                                self.log_identity(),
+                            ));
+                            continue;
+                        }
+                    }
+                }
+                SchedulingDecision::AllUnavailable { min_wait_seconds } => {
+                    let error = self.all_limited_failure(
+                        min_wait_seconds,
+                        trace,
+                        &tokens_snapshot,
+                        &scope_group,
+                    );
+                    self.alerts.fire(AlertEvent::AllAccountsRateLimited {
+                        scope_group: scope_group.clone(),
+                        min_wait_seconds: error.retry_after_seconds,
+                    });
+                    return Err(error);
+                }
+                SchedulingDecision::AllBusy => {
+                    let message =
+                        "All accounts are at their concurrency limit. Please retry shortly."
+                            .to_string();
+                    let mut error =
+                        self.selection_failure(message, trace, &tokens_snapshot, &scope_group);
+                    error.kind = GetTokenErrorKind::AllBusy;
+                    return Err(error);
+                }
+            };
+
+            // Claim a slot before any refresh work; another request may have
+            // taken the last one since the scheduler looked
+            let Some(in_flight) = self.scheduler.try_acquire_in_flight(&token) else {
+                last_error = Some(format!("Account {} is busy", self.display_identity(&token)));
+                attempted.insert(token.account_id.clone());
+                trace.push(AttemptInfo::new(
+                    &token,
+                    self.busy_outcome(&token),
+                    self.log_identity(),
+                ));
+                continue;
+            };
+            // An account just out of a rate limit takes one trial request at a time
+            if !self
+                .rate_limit_tracker
+                .try_start_trial(&scope_group, &token.account_id)
+            {
+                last_error = Some(format!(
+                    "Account {} is on probation",
+                    self.display_identity(&token)
+                ));
+                attempted.insert(token.account_id.clone());
+                trace.push(AttemptInfo::new(
+                    &token,
+                    AttemptOutcome::Probation,
+                    self.log_identity(),
+                ));
+                continue;
+            }
+
+            // Only the chosen account is copied out of the pool
+            let mut token = ProxyToken::clone(&token);
+            if token.is_expired_at(self.now(), expiry_buffer) {
+                refreshes += 1;
+                span.record("refreshes", refreshes);
+            }
+            let project_id = match self.prepare_token(&mut token, expiry_buffer).await {
+                Ok(pid) => pid,
+                Err(e) => {
+                    self.rate_limit_tracker
+                        .end_trial(&scope_group, &token.account_id);
+                    last_error = Some(e.to_string());
+                    attempted.insert(token.account_id.clone());
+                    trace.push(AttemptInfo::new(&token, e.into(), self.log_identity()));
+                    continue;
+                }
+            };
+
+            // Bind session to this account; after a rotation this replaces the
+            // binding so the next request doesn't snap back to the old account.
+            // A preferred account leaves an existing binding alone unless asked.
+            let keep_binding = preferred_account == Some(token.account_id.as_str())
+                && bound_account.is_some()
+                && !options.rebind_session;
+            if let Some(sid) = session_id.filter(|_| !keep_binding) {
+                self.session_manager
+                    .set_binding(&session_group, sid, &token.account_id);
+            }
+            if reason == SelectionReason::StickyHit {
+                self.metrics.record_sticky(&scope_group, StickyOutcome::Hit);
+            }
+
+            return Ok(self.complete_selection(
+                token,
+                project_id,
+                in_flight,
+                quota_group,
+                scope_group,
+                reason,
+                session_id,
+            ));
+        }
+
+        let message = last_error.unwrap_or_else(|| "All accounts failed".to_string());
+        let mut error = self.selection_failure(message, trace, &tokens_snapshot, &scope_group);
+        if error.attempts.iter().all(|a| {
+            matches!(
+                a.outcome,
+                AttemptOutcome::Busy { .. } | AttemptOutcome::Probation
+            )
+        }) {
+            error.kind = GetTokenErrorKind::AllBusy;
+        }
+        Err(error)
+    }
+
+    /// Record a selection and hand out the prepared token
+    #[allow(clippy::too_many_arguments)]
+    fn complete_selection(
+        &self,
+        token: ProxyToken,
+        project_id: String,
+        in_flight: InFlightGuard,
+        quota_group: &str,
+        scope_group: String,
+        reason: SelectionReason,
+        session_id: Option<&str>,
+    ) -> SelectedToken {
+        self.scheduler.record_selection(&token.account_id);
+        self.usage.record_request(&token.account_id, self.now());
+        self.scheduler
+            .throttle()
+            .record_at(&scope_group, &token.account_id, self.now());
+        self.scheduler.circuit_breaker().on_selected_at(
+            &scope_group,
+            &token.account_id,
+            self.now(),
+        );
+
+        tracing::debug!(
+            "[TokenManager] Selected account: {} (id: {}, reason: {:?})",
+            self.display_identity(&token),
+            token.account_id,
+            reason
+        );
+        self.emit(TokenManagerEvent::AccountSelected {
+            account_id: token.account_id.clone(),
+            email: self.display_identity(&token),
+            scope_group: scope_group.clone(),
+            reason,
+            session_id: session_id.map(str::to_string),
+        });
+
+        let selected = SelectedToken {
+            display_name: self.display_identity(&token),
+            access_token: token.access_token,
+            project_id,
+            email: token.email,
+            in_flight,
+            account_id: token.account_id,
+            subscription_tier: token.subscription_tier,
+            expires_at: token.timestamp,
+            scope_group,
+            selected_reason: reason,
+            provider: provider_for(quota_group),
+            base_url_override: token.base_url_override,
+        };
+        self.notify_selection(&selected);
+        selected
+    }
+
+    /// Select the only account of a one-account pool without planning
+    ///
+    /// Skips the pool snapshot, tier sort, scheduler and session lookups
+    /// that can only ever arrive at this account. A session is still bound
+    /// to it, so a CacheFirst session waits for it after a rate limit and
+    /// stays on it when more accounts are added.
+    ///
+    /// Returns `None` when the full selection has to run: for a pool of
+    /// another size, options that steer the pick (rotation, preferred or
+    /// excluded accounts, tags, tenants, fingerprinted sessions), and an
+    /// account that cannot take the request right now, so limits, waits
+    /// and their errors behave exactly as before. The one exception is a
+    /// rate limit without a session, answered here with the same error the
+    /// full selection gives.
+    async fn select_single(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        options: &GetTokenOptions,
+    ) -> Option<Result<SelectedToken, GetTokenError>> {
+        let plain = !options.force_rotate
+            && options.preferred_account.is_none()
+            && options.tenant.is_none()
+            && options.excluded_accounts.is_empty()
+            && options.required_tags.is_empty()
+            && options.excluded_tags.is_empty()
+            && (options.session_id.is_some() || options.conversation.is_none());
+        if !plain || self.tokens.len() != 1 || self.is_shut_down() {
+            return None;
+        }
+        let token = self.tokens.iter().next()?.value().clone();
+
+        let scope_group = self.limit_scope(quota_group, request_type, options.model.as_deref());
+        if self
+            .rate_limit_tracker
+            .group_reset_seconds(&scope_group)
+            .is_some()
+            || !token.serves(&provider_for(quota_group))
+            || self.paused.contains(&token.account_id)
+            || self.draining.contains_key(&token.account_id)
+        {
+            return None;
+        }
+        let config = self.sticky_config.load();
+        if config
+            .default
+            .request_type_policies
+            .get(request_type)
+            .is_some_and(|policy| !allowed_for_request_type(policy, &token))
+        {
+            return None;
+        }
+        let expiry_buffer = self.expiry_buffer_for(request_type);
+        let now = self.now();
+        if token.is_expired_at(now, expiry_buffer)
+            && self
+                .refresh_coordinator
+                .backoff_remaining(&token.account_id, now)
+                .is_some()
+        {
+            return None;
+        }
+
+        if !self.scheduler.can_take(&token, &scope_group) {
+            let limited = self
+                .rate_limit_tracker
+                .is_rate_limited(&scope_group, &token.account_id);
+            if options.session_id.is_some() || !limited || !self.scheduler.has_capacity(&token) {
+                return None;
+            }
+            let tokens = [token];
+            let min_wait_seconds = self.scheduler.min_wait(&tokens, &scope_group);
+            let error =
+                self.all_limited_failure(min_wait_seconds, Vec::new(), &tokens, &scope_group);
+            self.alerts.fire(AlertEvent::AllAccountsRateLimited {
+                scope_group,
+                min_wait_seconds: error.retry_after_seconds,
+            });
+            return Some(Err(error));
+        }
+        let in_flight = self.scheduler.try_acquire_in_flight(&token)?;
+        if !self
+            .rate_limit_tracker
+            .try_start_trial(&scope_group, &token.account_id)
+        {
+            return None;
+        }
+
+        let session_group = AccountScheduler::scope_group(quota_group, request_type, None);
+        let session_id = options.session_id.as_deref();
+        let bound =
+            session_id.and_then(|sid| self.session_manager.peek_binding(&session_group, sid));
+        let reason = if bound.as_deref() == Some(token.account_id.as_str()) {
+            SelectionReason::StickyHit
+        } else {
+            SelectionReason::Scheduled
+        };
+        if bound.is_some() {
+            let outcome = match reason {
+                SelectionReason::StickyHit => StickyOutcome::Hit,
+                _ => StickyOutcome::MissAccountGone,
+            };
+            self.metrics.record_sticky(&scope_group, outcome);
+        }
+
+        let span = tracing::Span::current();
+        span.record("attempts", 1);
+        let mut prepared = ProxyToken::clone(&token);
+        if prepared.is_expired_at(self.now(), expiry_buffer) {
+            span.record("refreshes", 1);
+        }
+        let project_id = match self.prepare_token(&mut prepared, expiry_buffer).await {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                self.rate_limit_tracker
+                    .end_trial(&scope_group, &token.account_id);
+                let message = e.to_string();
+                let trace = vec![AttemptInfo::new(&prepared, e.into(), self.log_identity())];
+                return Some(Err(self.selection_failure(
+                    message,
+                    trace,
+                    &[token],
+                    &scope_group,
+                )));
+            }
+        };
+        if let Some(sid) = session_id {
+            self.session_manager
+                .set_binding(&session_group, sid, &prepared.account_id);
+        }
+
+        self.single_account_selections
+            .fetch_add(1, Ordering::Relaxed);
+        Some(Ok(self.complete_selection(
+            prepared,
+            project_id,
+            in_flight,
+            quota_group,
+            scope_group,
+            reason,
+            session_id,
+        )))
+    }
+
+    /// Show which account the next request would get, without side effects
+    ///
+    /// Runs the same filtering, tier sort and session logic as `get_token` on
+    /// a preview copy of the scheduler: no round-robin slot is used, no
+    /// session is bound or refreshed, and no token or project_id is fetched.
+    pub async fn dry_run_selection(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        session_id: Option<&str>,
+    ) -> Result<SelectionPreview, GetTokenError> {
+        let scheduler = self.scheduler.preview();
+        let options = GetTokenOptions {
+            session_id: session_id.map(str::to_string),
+            ..GetTokenOptions::default()
+        };
+        let plan = self
+            .plan_selection(&scheduler, quota_group, request_type, &options, true)
+            .await?;
+
+        let decision = scheduler.select_with_session(
+            &plan.tokens,
+            &plan.scope_group,
+            plan.bound_account.as_deref(),
+            &plan.session_counts,
+            &plan.scheduling,
+            &HashSet::new(),
+        );
+        let (token, reason, would_wait_seconds) = match decision {
+            SchedulingDecision::UseAccount(token) => {
+                let reason = if plan.bound_account.as_deref() == Some(token.account_id.as_str()) {
+                    SelectionReason::StickyHit
+                } else {
+                    SelectionReason::Scheduled
+                };
+                (token, reason, 0)
+            }
+            SchedulingDecision::WaitAndUse {
+                token,
+                wait_seconds,
+            } => (token, SelectionReason::WaitedForSticky, wait_seconds),
+            SchedulingDecision::AllUnavailable { min_wait_seconds } => {
+                return Err(self.all_limited_failure(
+                    min_wait_seconds,
+                    plan.trace,
+                    &plan.tokens,
+                    &plan.scope_group,
+                ));
+            }
+            SchedulingDecision::AllBusy => {
+                let message = "All accounts are at their concurrency limit. Please retry shortly."
+                    .to_string();
+                let mut error =
+                    self.selection_failure(message, plan.trace, &plan.tokens, &plan.scope_group);
+                error.kind = GetTokenErrorKind::AllBusy;
+                return Err(error);
+            }
+        };
+
+        Ok(SelectionPreview {
+            account_id: token.account_id.clone(),
+            email: token.email.clone(),
+            tier: token.subscription_tier.clone(),
+            reason,
+            would_wait_seconds,
+        })
+    }
+
+    /// Filter and order the pool for one selection and look up its session
+    ///
+    /// With `peek` the session binding is read without refreshing its TTL.
+    /// Only `scheduler` is touched, so a dry run can pass a preview copy.
+    async fn plan_selection(
+        &self,
+        scheduler: &AccountScheduler,
+        quota_group: &str,
+        request_type: &str,
+        options: &GetTokenOptions,
+        peek: bool,
+    ) -> Result<SelectionPlan, GetTokenError> {
+        if self.is_shut_down() {
+            return Err(GetTokenError {
+                kind: GetTokenErrorKind::ShutDown,
+                ..GetTokenError::new("Token manager is shut down".to_string())
+            });
+        }
+
+        // Trying accounts under a limit on the whole group only burns them
+        let limit_scope = self.limit_scope(quota_group, request_type, options.model.as_deref());
+        if let Some(remaining) = self.rate_limit_tracker.group_reset_seconds(&limit_scope) {
+            let soonest = self.soonest_available(quota_group, request_type, &limit_scope);
+            let wait = soonest
+                .as_ref()
+                .map_or(remaining, |(wait, _)| remaining.max(*wait))
+                .max(1);
+            tracing::warn!(
+                "[TokenManager] {} is rate limited as a whole for {}s",
+                limit_scope,
+                wait
+            );
+            return Err(GetTokenError {
+                retry_after_seconds: Some(wait),
+                soonest_account: soonest.map(|(_, account_id)| account_id),
+                scope_group: Some(limit_scope.clone()),
+                ..GetTokenError::new(format!(
+                    "{} is rate limited for every account. Please wait {}s.",
+                    limit_scope, wait
+                ))
+            });
+        }
+
+        let session_id = options.session_id.as_deref();
+        let expiry_buffer = self.expiry_buffer_for(request_type);
+
+        // Take snapshot of tokens
+        let mut tokens_snapshot: Vec<Arc<ProxyToken>> =
+            self.tokens.iter().map(|e| e.value().clone()).collect();
+
+        if tokens_snapshot.is_empty() {
+            return Err(GetTokenError::new("Token pool is empty".to_string()));
+        }
+
+        let pool_size = tokens_snapshot.len();
+        let provider = provider_for(quota_group);
+        tokens_snapshot.retain(|t| t.serves(&provider));
+        if tokens_snapshot.is_empty() {
+            return Err(GetTokenError {
+                kind: GetTokenErrorKind::NoEligibleAccounts,
+                ..GetTokenError::new(format!(
+                    "None of the {} account(s) serves {}; check the accounts' providers",
+                    pool_size, provider
+                ))
+            });
+        }
+
+        tokens_snapshot.retain(|t| {
+            !options.excluded_accounts.contains(&t.account_id)
+                && options.required_tags.iter().all(|tag| t.has_tag(tag))
+                && !options.excluded_tags.iter().any(|tag| t.has_tag(tag))
+        });
+        if tokens_snapshot.is_empty() {
+            return Err(GetTokenError::new(format!(
+                "All {} account(s) are excluded",
+                pool_size
+            )));
+        }
+        let tenant = options.tenant.as_deref();
+        if let Some(policy) = tenant.and_then(|tenant| self.tenant_policy(tenant)) {
+            tokens_snapshot.retain(|t| policy.allows(t));
+            if tokens_snapshot.is_empty() {
+                return Err(GetTokenError {
+                    kind: GetTokenErrorKind::NoEligibleAccounts,
+                    ..GetTokenError::new(format!(
+                        "No eligible accounts for tenant {}",
+                        tenant.unwrap_or_default()
+                    ))
+                });
+            }
+        }
+        // Some request types may only use certain tiers or tagged accounts
+        let policy = self
+            .sticky_config
+            .load()
+            .default
+            .request_type_policies
+            .get(request_type)
+            .cloned();
+        if let Some(policy) = policy {
+            tokens_snapshot.retain(|t| allowed_for_request_type(&policy, t));
+            if tokens_snapshot.is_empty() {
+                return Err(GetTokenError {
+                    kind: GetTokenErrorKind::NoEligibleAccounts,
+                    ..GetTokenError::new(format!(
+                        "No eligible accounts for request_type {}",
+                        request_type
+                    ))
+                });
+            }
+        }
+
+        // Paused accounts are passed over; sessions bound to one fail over
+        let mut trace: Vec<AttemptInfo> = Vec::new();
+        if !self.paused.is_empty() {
+            let eligible = tokens_snapshot.len();
+            tokens_snapshot.retain(|t| {
+                let paused = self.paused.contains(&t.account_id);
+                if paused {
+                    trace.push(AttemptInfo::new(
+                        t,
+                        AttemptOutcome::Paused,
+                        self.log_identity(),
+                    ));
+                }
+                !paused
+            });
+            if tokens_snapshot.is_empty() {
+                return Err(GetTokenError {
+                    kind: GetTokenErrorKind::NoRecoverableAccounts,
+                    attempts: trace,
+                    ..GetTokenError::new(format!("All {} eligible account(s) are paused", eligible))
+                });
+            }
+        }
+
+        let preferred_account = options
+            .preferred_account
+            .as_deref()
+            .filter(|id| tokens_snapshot.iter().any(|t| t.account_id == *id));
+
+        // Skip accounts that need a refresh but are backing off after failures
+        let now = self.now();
+        let before_backoff = tokens_snapshot.len();
+        let mut min_backoff: Option<(u64, String)> = None;
+        tokens_snapshot.retain(|t| {
+            if !t.is_expired_at(now, expiry_buffer) {
+                return true;
+            }
+            match self
+                .refresh_coordinator
+                .backoff_remaining(&t.account_id, now)
+            {
+                Some(wait) => {
+                    if min_backoff.as_ref().is_none_or(|(m, _)| wait < *m) {
+                        min_backoff = Some((wait, t.account_id.clone()));
+                    }
+                    let error = RefreshError::BackingOff { retry_in: wait }.to_string();
+                    trace.push(AttemptInfo::new(
+                        t,
+                        AttemptOutcome::RefreshFailed { error },
+                        self.log_identity(),
+                    ));
+                    false
+                }
+                None => true,
+            }
+        });
+
+        if tokens_snapshot.is_empty() {
+            let message = format!(
+                "All {} account(s) are waiting to retry token refresh. Please wait {}s.",
+                before_backoff,
+                min_backoff.as_ref().map_or(0, |(wait, _)| *wait)
+            );
+            let message = with_paused_note(message, &trace);
+            let (retry_after_seconds, soonest_account) = min_backoff.unzip();
+            return Err(GetTokenError {
+                attempts: trace,
+                retry_after_seconds,
+                soonest_account,
+                ..GetTokenError::new(message)
+            });
+        }
+
+        // Sort by subscription tier priority
+        scheduler.sort_by_tier(&mut tokens_snapshot);
+
+        // Sessions stay bound per group; limits and rotation may be per model
+        let session_group = AccountScheduler::scope_group(quota_group, request_type, None);
+        let scheduling = self.sticky_config.load().resolve(&session_group).clone();
+        let model = options
+            .model
+            .as_deref()
+            .filter(|_| scheduling.scope_by_model);
+        let scope_group = AccountScheduler::scope_group(quota_group, request_type, model);
+
+        // Without a session ID the conversation's opening stands in for one
+        let fingerprint = match (session_id, &options.conversation) {
+            (None, Some(prefix)) if scheduling.fingerprint_sessions => Some(fingerprint_request(
+                &prefix.system_prompt,
+                &prefix.first_user_message,
+            )),
+            _ => None,
+        };
+        let session_id = session_id.or(fingerprint.as_deref());
+        // Tenants may reuse each other's session IDs without sharing bindings
+        let tenant_session = tenant
+            .zip(session_id)
+            .map(|(tenant, sid)| tenant_session_id(tenant, sid));
+        let session_id = tenant_session.as_deref().or(session_id);
+
+        // Get session binding if exists; a binding to an account filtered out
+        // of this request is ignored and replaced by the new pick
+        let binding = session_id.and_then(|sid| {
+            if peek {
+                self.session_manager.peek_binding(&session_group, sid)
+            } else {
+                self.session_manager.get_binding(&session_group, sid)
+            }
+        });
+        let bound_account = binding
+            .clone()
+            .filter(|id| tokens_snapshot.iter().any(|t| t.account_id == *id));
+        let binding_gone = binding.is_some() && bound_account.is_none();
+
+        // Draining accounts keep the session bound to them but take no new ones
+        if !self.draining.is_empty() {
+            let eligible = tokens_snapshot.len();
+            tokens_snapshot.retain(|t| {
+                let draining = self.draining.contains_key(&t.account_id)
+                    && bound_account.as_deref() != Some(t.account_id.as_str());
+                if draining {
+                    trace.push(AttemptInfo::new(
+                        t,
+                        AttemptOutcome::Draining,
+                        self.log_identity(),
+                    ));
+                }
+                !draining
+            });
+            if tokens_snapshot.is_empty() {
+                return Err(GetTokenError {
+                    kind: GetTokenErrorKind::NoRecoverableAccounts,
+                    attempts: trace,
+                    ..GetTokenError::new(format!(
+                        "All {} eligible account(s) are draining",
+                        eligible
+                    ))
+                });
+            }
+        }
+        let preferred_account =
+            preferred_account.filter(|id| tokens_snapshot.iter().any(|t| t.account_id == *id));
+
+        // Balance mode places new sessions on the account with the fewest bindings
+        let session_counts = if session_id.is_some() && scheduling.mode == SchedulingMode::Balance {
+            self.session_manager.bindings_per_account(&session_group)
+        } else {
+            HashMap::new()
+        };
+
+        Ok(SelectionPlan {
+            tokens: tokens_snapshot,
+            trace,
+            session_group,
+            scope_group,
+            scheduling,
+            session_id: session_id.map(str::to_string),
+            bound_account,
+            binding_gone,
+            preferred_account: preferred_account.map(str::to_string),
+            session_counts,
+        })
+    }
+
+    /// Trace outcome for an account passed over at its concurrency ceiling
+    fn busy_outcome(&self, token: &ProxyToken) -> AttemptOutcome {
+        AttemptOutcome::Busy {
+            in_flight: self.scheduler.in_flight_count(&token.account_id),
+            max_concurrent: token.max_concurrent.unwrap_or(0),
+        }
+    }
+
+    /// Build the error for a failed selection when every account is benched
+    ///
+    /// The wait is the soonest of the scheduler's rate-limit and breaker
+    /// estimate and what the trace says about each account.
+    fn all_limited_failure(
+        &self,
+        min_wait_seconds: Option<u64>,
+        attempts: Vec<AttemptInfo>,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+    ) -> GetTokenError {
+        let mut error = self.selection_failure(String::new(), attempts, tokens, scope_group);
+        let retry_after = min_wait_seconds
+            .into_iter()
+            .chain(error.retry_after_seconds)
+            .min()
+            .map(|wait| wait.max(1));
+        error.retry_after_seconds = retry_after;
+        let message = match retry_after {
+            Some(wait) => format!("All accounts are currently limited. Please wait {}s.", wait),
+            None => "No recoverable accounts: none will become available by waiting".to_string(),
+        };
+        error.message = with_paused_note(message, &error.attempts);
+        error.kind = if retry_after.is_some() {
+            GetTokenErrorKind::Unavailable
+        } else {
+            GetTokenErrorKind::NoRecoverableAccounts
+        };
+        error
+    }
+
+    /// Seconds until an account passed over in a selection may be usable again
+    ///
+    /// `None` if waiting will not bring it back, e.g. it was disabled,
+    /// removed, paused or is draining.
+    fn recovers_in(&self, attempt: &AttemptInfo, scope_group: &str, now: i64) -> Option<u64> {
+        match &attempt.outcome {
+            AttemptOutcome::RateLimited { remaining_seconds } => Some(*remaining_seconds),
+            AttemptOutcome::Throttled {
+                retry_in_seconds, ..
+            } => Some(*retry_in_seconds),
+            AttemptOutcome::RefreshFailed { .. } => Some(
+                self.refresh_coordinator
+                    .backoff_remaining(&attempt.account_id, now)
+                    .unwrap_or(0),
+            ),
+            AttemptOutcome::Skipped => Some(
+                self.scheduler
+                    .circuit_breaker()
+                    .remaining_at(scope_group, &attempt.account_id, now)
+                    .unwrap_or(0),
+            ),
+            AttemptOutcome::ProjectIdFailed { .. }
+            | AttemptOutcome::Busy { .. }
+            | AttemptOutcome::Probation => Some(0),
+            AttemptOutcome::DisabledMidFlight { .. }
+            | AttemptOutcome::Paused
+            | AttemptOutcome::Draining
+            | AttemptOutcome::ProviderNotServed { .. } => None,
+        }
+    }
+
+    /// Build the error for a failed selection and log its trace
+    ///
+    /// Accounts the loop never reached are added to the trace as rate
+    /// limited or skipped. The retry hint is the soonest any account in the
+    /// trace recovers; with none recovering the kind is
+    /// `NoRecoverableAccounts`.
+    fn selection_failure(
+        &self,
+        message: String,
+        mut attempts: Vec<AttemptInfo>,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+    ) -> GetTokenError {
+        for token in tokens {
+            if attempts.iter().any(|a| a.account_id == token.account_id) {
+                continue;
+            }
+            let throttled = self.scheduler.throttle_wait(token, scope_group);
+            let outcome = match self
+                .rate_limit_tracker
+                .get_reset_seconds(scope_group, &token.account_id)
+            {
+                Some(remaining_seconds) => AttemptOutcome::RateLimited { remaining_seconds },
+                None if throttled > 0 => AttemptOutcome::Throttled {
+                    max_rpm: token.max_rpm.unwrap_or(0),
+                    retry_in_seconds: throttled,
+                },
+                None if !self.scheduler.has_capacity(token) => self.busy_outcome(token),
+                None if !self
+                    .rate_limit_tracker
+                    .probation_allows(scope_group, &token.account_id) =>
+                {
+                    AttemptOutcome::Probation
+                }
+                None => AttemptOutcome::Skipped,
+            };
+            attempts.push(AttemptInfo::new(token, outcome, self.log_identity()));
+        }
+
+        let now = self.now();
+        let (retry_after_seconds, soonest_account) = attempts
+            .iter()
+            .filter_map(|a| Some((self.recovers_in(a, scope_group, now)?, a.account_id.clone())))
+            .min_by_key(|(wait, _)| *wait)
+            .map(|(wait, account_id)| (wait.max(1), account_id))
+            .unzip();
+        let kind = match retry_after_seconds {
+            Some(_) => GetTokenErrorKind::Unavailable,
+            None => GetTokenErrorKind::NoRecoverableAccounts,
+        };
+        let error = GetTokenError {
+            kind,
+            message: with_paused_note(message, &attempts),
+            attempts,
+            retry_after_seconds,
+            soonest_account,
+            scope_group: None,
+        };
+        tracing::warn!(
+            "[TokenManager] No account available in {}: {}",
+            scope_group,
+            error.summary()
+        );
+        error
+    }
+
+    /// Get a token from one specific account, bypassing scheduling
+    ///
+    /// Meant for probing an account from the admin UI: the token is refreshed
+    /// and its project_id resolved as usual, but no session binding is
+    /// created or changed and no other account is ever substituted. `model`
+    /// picks the rate limit to check when the group is scoped by model.
+    pub async fn get_token_for_account(
+        &self,
+        account_id: &str,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+    ) -> Result<SelectedToken, AccountTokenError> {
+        let mut token = self
+            .tokens
+            .get(account_id)
+            .map(|entry| ProxyToken::clone(entry.value()))
+            .ok_or_else(|| AccountTokenError::NotFound {
+                account_id: account_id.to_string(),
+            })?;
+
+        let provider = provider_for(quota_group);
+        if !token.serves(&provider) {
+            return Err(AccountTokenError::ProviderNotServed { provider });
+        }
+
+        let scope_group = self.limit_scope(quota_group, request_type, model);
+        if self
+            .rate_limit_tracker
+            .is_rate_limited(&scope_group, account_id)
+        {
+            let remaining = self
+                .rate_limit_tracker
+                .get_remaining_wait(&scope_group, account_id);
+            return Err(AccountTokenError::RateLimited {
+                remaining_seconds: remaining.max(1),
+            });
+        }
+
+        let in_flight = self
+            .scheduler
+            .try_acquire_in_flight(&token)
+            .ok_or_else(|| AccountTokenError::Busy {
+                in_flight: self.scheduler.in_flight_count(account_id),
+                max_concurrent: token.max_concurrent.unwrap_or(0),
+            })?;
+
+        let project_id = self
+            .prepare_token(&mut token, self.expiry_buffer_for(request_type))
+            .await?;
+        // Explicit requests still count toward max_rpm, though it never stops them
+        self.scheduler
+            .throttle()
+            .record_at(&scope_group, account_id, self.now());
+
+        tracing::info!(
+            "[TokenManager] Using account {} (id: {}) by explicit request",
+            self.display_identity(&token),
+            token.account_id
+        );
+        self.emit(TokenManagerEvent::AccountSelected {
+            account_id: token.account_id.clone(),
+            email: self.display_identity(&token),
+            scope_group: scope_group.clone(),
+            reason: SelectionReason::Explicit,
+            session_id: None,
+        });
+
+        Ok(SelectedToken {
+            display_name: self.display_identity(&token),
+            access_token: token.access_token,
+            project_id,
+            email: token.email,
+            in_flight,
+            account_id: token.account_id,
+            subscription_tier: token.subscription_tier,
+            expires_at: token.timestamp,
+            scope_group,
+            selected_reason: SelectionReason::Explicit,
+            provider,
+            base_url_override: token.base_url_override,
+        })
+    }
+
+    /// Make a chosen account ready for a request and return its project_id
+    ///
+    /// Refreshes the token if it expires within `expiry_buffer` seconds and
+    /// resolves a missing project_id. A permanent refresh error disables the
+    /// account and drops it from the pool.
+    async fn prepare_token(
+        &self,
+        token: &mut ProxyToken,
+        expiry_buffer: u64,
+    ) -> Result<String, AccountTokenError> {
+        if token.is_expired_at(self.now(), expiry_buffer) {
+            match self.refresh_token(token, expiry_buffer).await {
+                Ok(()) => self.store_refreshed_token(token),
+                Err(e) => {
+                    tracing::error!(
+                        "Token refresh failed for {}: {}",
+                        self.display_identity(token),
+                        e
+                    );
+
+                    match e.kind() {
+                        RefreshErrorKind::Permanent => {
+                            self.disable_after_refresh_error(token, &e).await
+                        }
+                        RefreshErrorKind::Temporary => {
+                            tracing::warn!(
+                                "Refresh failure for {} looks temporary",
+                                self.display_identity(token)
+                            );
+                        }
+                        RefreshErrorKind::Unknown => {}
+                    }
+
+                    return Err(AccountTokenError::Refresh(e));
+                }
+            }
+        }
+
+        match &token.project_id {
+            Some(pid) => Ok(pid.clone()),
+            None => self
+                .resolve_project_id(token)
+                .await
+                .map_err(AccountTokenError::ProjectId),
+        }
+    }
+
+    /// Refresh a token using OAuth
+    ///
+    /// Concurrent callers for the same account share one OAuth call through
+    /// the refresh coordinator. A token already refreshed by someone else is
+    /// reused only if it lasts beyond `expiry_buffer` seconds.
+    async fn refresh_token(
+        &self,
+        token: &mut ProxyToken,
+        expiry_buffer: u64,
+    ) -> Result<(), RefreshError> {
+        // Another request or the background refresher may already have
+        // refreshed the pooled entry
+        if let Some(entry) = self.tokens.get(&token.account_id) {
+            if !entry.is_expired_at(self.now(), expiry_buffer) {
+                token.access_token = entry.access_token.clone();
+                token.refresh_token = entry.refresh_token.clone();
+                token.expires_in = entry.expires_in;
+                token.timestamp = entry.timestamp;
+                return Ok(());
+            }
+            // Google may have rotated the refresh token since our snapshot
+            token.refresh_token = entry.refresh_token.clone();
+        }
+
+        let current = || {
+            self.tokens
+                .get(&token.account_id)
+                .map(|entry| entry.refresh_token.clone())
+        };
+        let response = match self
+            .refresh_coordinator
+            .refresh_token(token, expiry_buffer, current)
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                // Waiting out a backoff made no OAuth call
+                if !matches!(e, RefreshError::BackingOff { .. }) {
+                    self.emit(TokenManagerEvent::RefreshFailed {
+                        account_id: token.account_id.clone(),
+                        email: self.display_identity(token),
+                        error: e.to_string(),
+                    });
+                }
+                return Err(e);
+            }
+        };
+
+        token.access_token = response.access_token;
+        token.expires_in = response.expires_in;
+        token.timestamp = self.now() + response.expires_in;
+        if let Some(refresh_token) = response.refresh_token {
+            if refresh_token != token.refresh_token {
+                tracing::info!(
+                    "[TokenManager] Refresh token rotated for {}",
+                    self.display_identity(token)
+                );
+                token.refresh_token = refresh_token;
+            }
+        }
+        self.emit(TokenManagerEvent::TokenRefreshed {
+            account_id: token.account_id.clone(),
+            email: self.display_identity(token),
+            expires_at: token.timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Write a refreshed token back into the pool, unless the pool already
+    /// holds one that expires later
+    ///
+    /// Another request or the background refresher may have stored a newer
+    /// token while this one was being refreshed; `token` then takes the
+    /// pooled one instead of overwriting it.
+    fn store_refreshed_token(&self, token: &mut ProxyToken) {
+        let Some(mut entry) = self.tokens.get_mut(&token.account_id) else {
+            return;
+        };
+        if entry.timestamp > token.timestamp {
+            token.access_token = entry.access_token.clone();
+            token.refresh_token = entry.refresh_token.clone();
+            token.expires_in = entry.expires_in;
+            token.timestamp = entry.timestamp;
+            return;
+        }
+        let mut updated = ProxyToken::clone(&entry);
+        updated.access_token = token.access_token.clone();
+        updated.refresh_token = token.refresh_token.clone();
+        updated.expires_in = token.expires_in;
+        updated.timestamp = token.timestamp;
+        *entry = Arc::new(updated);
+    }
+
+    /// Replace a pooled account with an updated copy
+    ///
+    /// Pooled tokens are shared with the snapshots of in-flight requests, so
+    /// they are never mutated in place: `update` works on a copy that then
+    /// replaces the entry, and readers holding the old one keep a consistent
+    /// token. Returns false if the account is not loaded.
+    fn update_pooled(&self, account_id: &str, update: impl FnOnce(&mut ProxyToken)) -> bool {
+        match self.tokens.get_mut(account_id) {
+            Some(mut entry) => {
+                let mut token = ProxyToken::clone(&entry);
+                update(&mut token);
+                *entry = Arc::new(token);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Account IDs whose tokens are within the expiry buffer, not rate limited
+    /// and not backing off after failed refreshes
+    fn accounts_due_for_refresh(&self) -> Vec<String> {
+        let now = self.now();
+        self.tokens
+            .iter()
+            .filter(|e| e.value().is_expired_at(now, self.expiry_buffer()))
+            .filter(|e| !self.rate_limit_tracker.is_limited_in_any_group(e.key()))
+            .filter(|e| {
+                self.refresh_coordinator
+                    .backoff_remaining(e.key(), now)
+                    .is_none()
+            })
+            .map(|e| e.key().clone())
+            .collect()
+    }
+
+    /// Refresh one pooled account now
+    ///
+    /// Permanent failures disable the account exactly like `get_token` does.
+    async fn refresh_account(&self, account_id: &str) -> Result<(), String> {
+        let mut token = match self.tokens.get(account_id) {
+            Some(entry) => ProxyToken::clone(entry.value()),
+            None => return Err("Account not found".to_string()),
+        };
+
+        match self.refresh_token(&mut token, self.expiry_buffer()).await {
+            Ok(()) => {
+                self.store_refreshed_token(&mut token);
+                Ok(())
+            }
+            Err(e) => {
+                if e.kind() == RefreshErrorKind::Permanent {
+                    self.disable_after_refresh_error(&token, &e).await;
+                }
+                Err(e.to_string())
+            }
+        }
+    }
+
+    /// Refresh every token that is about to expire
+    ///
+    /// Returns the number of accounts refreshed. Permanent failures disable
+    /// the account exactly like `get_token` does.
+    pub async fn refresh_expiring_tokens(&self) -> usize {
+        let mut refreshed = 0;
+
+        for account_id in self.accounts_due_for_refresh() {
+            match self.refresh_account(&account_id).await {
+                Ok(()) => refreshed += 1,
+                Err(e) => {
+                    tracing::warn!(
+                        "[TokenManager] Background refresh failed for {}: {}",
+                        account_id,
+                        e
+                    );
+                }
+            }
+        }
+
+        if refreshed > 0 {
+            tracing::info!(
+                "[TokenManager] Background refresh renewed {} token(s)",
+                refreshed
+            );
+        }
+        refreshed
+    }
+
+    /// Refresh expiring tokens and resolve missing project_ids ahead of traffic
+    ///
+    /// Works on at most `concurrency` accounts at a time and reports every
+    /// account instead of stopping at the first failure. Permanent refresh
+    /// errors disable the account exactly like `get_token` does.
+    pub async fn warm_up(&self, concurrency: usize) -> WarmUpReport {
+        let semaphore = tokio::sync::Semaphore::new(concurrency.max(1));
+        let tokens: Vec<ProxyToken> = self
+            .tokens
+            .iter()
+            .map(|e| ProxyToken::clone(e.value()))
+            .collect();
+        let expiry_buffer = self.expiry_buffer();
+
+        let results = futures::future::join_all(tokens.into_iter().map(|mut token| {
+            let semaphore = &semaphore;
+            async move {
+                let _permit = semaphore
+                    .acquire()
+                    .await
+                    .expect("semaphore is never closed");
+                let refresh_due = token.is_expired_at(self.now(), expiry_buffer);
+                let project_missing = token.project_id.is_none();
+
+                let result = self.prepare_token(&mut token, expiry_buffer).await;
+
+                WarmUpResult {
+                    refreshed: refresh_due && !matches!(result, Err(AccountTokenError::Refresh(_))),
+                    project_id_resolved: project_missing && result.is_ok(),
+                    account_id: token.account_id,
+                    email: token.email,
+                    error: result.err().map(|e| e.to_string()),
+                }
+            }
+        }))
+        .await;
+
+        let mut report = WarmUpReport {
+            total: results.len(),
+            failed: results.iter().filter(|r| r.error.is_some()).count(),
+            accounts: results,
+            ..WarmUpReport::default()
+        };
+        report.warmed = report.total - report.failed;
+        report.accounts.sort_by(|a, b| a.email.cmp(&b.email));
+
+        tracing::info!(
+            "[TokenManager] Warmed up {}/{} account(s), {} failed",
+            report.warmed,
+            report.total,
+            report.failed
+        );
+        report
+    }
+
+    /// Spawn a periodic task that stops on `shutdown` and is watched by
+    /// `liveness`
+    fn spawn_task<F, Fut>(&self, name: &'static str, period: Duration, tick: F) -> BackgroundTask
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = bool> + Send + 'static,
+    {
+        let task = BackgroundTask::spawn_periodic(name, period, self.shutdown.subscribe(), tick);
+        self.tasks.register(&task);
+        task
+    }
+
+    /// Watch the accounts directory and apply file changes to the live pool
+    ///
+    /// Polls every 2 seconds and waits for a change to settle for 1 second
+    /// before applying it, since editors often write a file twice.
+    pub fn watch_accounts(self: &Arc<Self>) -> BackgroundTask {
+        self.watch_accounts_with(Duration::from_secs(2), Duration::from_secs(1))
+    }
+
+    /// Watch the accounts directory with a custom poll interval and debounce
+    pub fn watch_accounts_with(
+        self: &Arc<Self>,
+        poll_interval: Duration,
+        debounce: Duration,
+    ) -> BackgroundTask {
+        let manager = Arc::downgrade(self);
+        let watcher = Arc::new(tokio::sync::Mutex::new(AccountWatcher::new(
+            self.accounts_dir(),
+            debounce,
+        )));
+
+        self.spawn_task("account-watcher", poll_interval, move || {
+            let manager = manager.clone();
+            let watcher = watcher.clone();
+            async move {
+                match manager.upgrade() {
+                    Some(manager) => {
+                        watcher.lock().await.poll(&manager).await;
+                        true
+                    }
+                    None => false,
+                }
+            }
+        })
+    }
+
+    /// Start a background task that proactively refreshes expiring tokens every `interval`
+    ///
+    /// Refreshes share the per-account locks with `get_token`, so a request
+    /// racing the task waits for and reuses the refreshed token.
+    pub fn start_background_refresh(self: &Arc<Self>, interval: Duration) -> BackgroundTask {
+        let manager = Arc::downgrade(self);
+        self.spawn_task("token-refresher", interval, move || {
+            let manager = manager.clone();
+            async move {
+                match manager.upgrade() {
+                    Some(manager) => {
+                        manager.refresh_expiring_tokens().await;
+                        true
+                    }
+                    None => false,
+                }
+            }
+        })
+    }
+
+    /// Start a background task that retries temporarily disabled accounts every `interval`
+    ///
+    /// See [`TokenManager::revive_disabled_accounts`].
+    pub fn start_account_reviver(self: &Arc<Self>, interval: Duration) -> BackgroundTask {
+        let manager = Arc::downgrade(self);
+        self.spawn_task("account-reviver", interval, move || {
+            let manager = manager.clone();
+            async move {
+                match manager.upgrade() {
+                    Some(manager) => {
+                        manager.revive_disabled_accounts().await;
+                        true
+                    }
+                    None => false,
+                }
+            }
+        })
+    }
+
+    /// Resolve the project ID for an account that was loaded without one
+    ///
+    /// Concurrent callers share a single lookup, and the result stays cached
+    /// in memory even if saving it to the account file fails.
+    async fn resolve_project_id(&self, token: &ProxyToken) -> Result<String, String> {
+        let cell = self
+            .project_ids
+            .entry(token.account_id.clone())
+            .or_default()
+            .clone();
+
+        let project_id = cell
+            .get_or_try_init(|| async {
+                let proxy = self
+                    .refresh_coordinator
+                    .outbound_proxy_for(token.outbound_proxy.as_deref());
+                self.oauth_call(|| {
+                    self.oauth_client
+                        .fetch_project_id(&token.access_token, proxy.as_deref())
+                })
+                .await
+            })
+            .await?
+            .clone();
+
+        // Only the caller that fills in the pooled entry writes the file
+        let needs_save = match self.tokens.get_mut(&token.account_id) {
+            Some(mut entry) if entry.project_id.is_none() => {
+                let mut updated = ProxyToken::clone(&entry);
+                updated.project_id = Some(project_id.clone());
+                *entry = Arc::new(updated);
+                true
+            }
+            _ => false,
+        };
+        if needs_save {
+            if let Err(e) = self.save_project_id(&token.account_id, &project_id).await {
+                tracing::warn!(
+                    "Failed to save project_id for {}: {}",
+                    self.display_identity(token),
+                    e
+                );
+            }
+        }
+
+        Ok(project_id)
+    }
+
+    /// Save project_id to account file
+    async fn save_project_id(&self, account_id: &str, project_id: &str) -> Result<(), String> {
+        let entry = self.tokens.get(account_id).ok_or("Account not found")?;
+        let path = entry.account_path.clone();
+        drop(entry);
+
+        let project_id = project_id.to_string();
+        self.account_files
+            .update(&path, move |account| {
+                account.token.project_id = Some(project_id);
+            })
+            .await
+    }
+
+    /// Disable an account after a permanent refresh error
+    ///
+    /// The error decides whether the account may be revived later.
+    async fn disable_after_refresh_error(&self, token: &ProxyToken, error: &RefreshError) {
+        let reason = error.to_string();
+        let kind = RefreshCoordinator::disabled_kind(&reason);
+        tracing::error!(
+            "Disabling account due to permanent error ({:?}): {}",
+            kind,
+            self.display_identity(token)
+        );
+        if let Err(err) = self
+            .disable_account_as(&token.account_id, &reason, kind)
+            .await
+        {
+            tracing::warn!(
+                "Failed to mark {} disabled: {}",
+                self.display_identity(token),
+                err
+            );
+        }
+    }
+
+    /// Disable an account: mark its file disabled and drop it from the pool
+    ///
+    /// The disable is permanent: the account only comes back through
+    /// `enable_account`. The account's pool entry, session bindings, rate
+    /// limits and refresh state are removed even if the file can't be
+    /// updated. A loaded account whose file is gone is still disabled; an
+    /// id that is neither loaded nor has a file is an error.
+    pub async fn disable_account(&self, account_id: &str, reason: &str) -> Result<(), String> {
+        self.disable_account_as(account_id, reason, DisabledKind::Permanent)
+            .await
+    }
+
+    /// Disable an account with the given kind of disable
+    async fn disable_account_as(
+        &self,
+        account_id: &str,
+        reason: &str,
+        kind: DisabledKind,
+    ) -> Result<(), String> {
+        let existing = self
+            .tokens
+            .get(account_id)
+            .map(|entry| entry.account_path.clone());
+        let loaded = existing.is_some();
+        let path = match existing {
+            Some(path) => path,
+            None => self.account_file_path(account_id).await?,
+        };
+        if !loaded && !path.exists() {
+            return Err(format!("Account {} not found", account_id));
+        }
+        let providers = self
+            .tokens
+            .get(account_id)
+            .map(|entry| entry.providers.clone())
+            .unwrap_or_default();
+
+        self.remove_account(account_id);
+        let now = self.now();
+        self.recently_disabled
+            .retain(|_, at| now - *at < RECENT_DISABLE_WINDOW_SECONDS);
+        self.recently_disabled.insert(account_id.to_string(), now);
+        self.emit(TokenManagerEvent::AccountDisabled {
+            account_id: account_id.to_string(),
+            reason: redact_secrets(reason),
+            kind,
+        });
+        self.alerts.fire(AlertEvent::AccountDisabled {
+            account_id: account_id.to_string(),
+            reason: redact_secrets(reason),
+            kind,
+        });
+        for provider in &providers {
+            self.check_pool_health_as(provider, "chat", true);
+        }
+
+        if !path.exists() {
+            tracing::warn!(
+                "Account disabled: {} (file already gone: {:?})",
+                account_id,
+                path
+            );
+            return Ok(());
+        }
+
+        match self.mark_disabled(&path, reason, kind).await {
+            Ok(()) => {}
+            // Deleted while this disable was under way
+            Err(e) if !path.exists() => {
+                tracing::warn!(
+                    "Account disabled: {} (file removed meanwhile: {:?}): {}",
+                    account_id,
+                    path,
+                    e
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+
+        tracing::warn!("Account disabled: {} ({:?})", account_id, path);
+        if self.quarantine_disabled.load(Ordering::Relaxed) {
+            self.quarantine_account_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Mark an account file disabled as of now
+    ///
+    /// A file that no longer parses is replaced with just the disabled
+    /// fields, its old contents kept under `corrupted_original`.
+    async fn mark_disabled(
+        &self,
+        path: &std::path::Path,
+        reason: &str,
+        kind: DisabledKind,
+    ) -> Result<(), String> {
+        let reason = truncate_string(&redact_secrets(reason), 800);
+        let disabled_at = self.now();
+        let result = {
+            let reason = reason.clone();
+            self.account_files
+                .update(path, move |account| {
+                    account.disabled = true;
+                    account.disabled_at = Some(disabled_at);
+                    account.disabled_reason = Some(reason);
+                    account.disabled_kind = Some(kind);
+                })
+                .await
+        };
+        match result {
+            Err(e) if e.starts_with(INVALID_ACCOUNT_FILE) => {
+                tracing::warn!(
+                    "Replacing corrupted account file {:?} to disable it: {}",
+                    path,
+                    e
+                );
+                let mut fields = serde_json::Map::new();
+                fields.insert("disabled".to_string(), serde_json::Value::Bool(true));
+                fields.insert("disabled_at".to_string(), disabled_at.into());
+                fields.insert("disabled_reason".to_string(), reason.into());
+                fields.insert(
+                    "disabled_kind".to_string(),
+                    serde_json::to_value(kind).map_err(|e| e.to_string())?,
+                );
+                self.account_files.replace_corrupted(path, fields).await
+            }
+            other => other,
+        }
+    }
+
+    /// Seconds until some account in a scope group can take a request
+    ///
+    /// `Some(0)` if one can right now. Accounts wait out their group-wide
+    /// rate limit, open circuit and, with an expiring token, refresh
+    /// backoff, and all of them wait out a limit on the group as a whole.
+    /// `None` if no account serves the group's provider or every one that
+    /// does is paused.
+    pub fn next_available_in(&self, quota_group: &str, request_type: &str) -> Option<u64> {
+        let scope_group = AccountScheduler::scope_group(quota_group, request_type, None);
+        let (wait, _) = self.soonest_available(quota_group, request_type, &scope_group)?;
+        let group_wait = self
+            .rate_limit_tracker
+            .group_reset_seconds(&scope_group)
+            .unwrap_or(0);
+        Some(wait.max(group_wait))
+    }
+
+    /// Account that can take a request first, ignoring any limit on the
+    /// group as a whole, and the seconds until it can
+    fn soonest_available(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        scope_group: &str,
+    ) -> Option<(u64, String)> {
+        let provider = provider_for(quota_group);
+        let buffer = self.expiry_buffer_for(request_type);
+        let now = self.now();
+
+        self.tokens
+            .iter()
+            .filter(|e| !self.paused.contains(e.key()) && e.value().serves(&provider))
+            .map(|e| {
+                let token = e.value();
+                let limited = self
+                    .rate_limit_tracker
+                    .get_reset_seconds(scope_group, &token.account_id);
+                let open = self.scheduler.circuit_breaker().remaining_at(
+                    scope_group,
+                    &token.account_id,
+                    now,
+                );
+                let backoff = if token.is_expired_at(now, buffer) {
+                    self.refresh_coordinator
+                        .backoff_remaining(&token.account_id, now)
+                } else {
+                    None
+                };
+                let wait = [limited, open, backoff]
+                    .into_iter()
+                    .flatten()
+                    .max()
+                    .unwrap_or(0);
+                (wait, token.account_id.clone())
+            })
+            .min()
+    }
+
+    /// Set the seconds of rate limit beyond which an account doesn't count
+    /// toward [`readiness`](Self::readiness)
+    pub fn set_readiness_rate_limit_threshold(&self, seconds: u64) {
+        self.readiness_rate_limit_seconds
+            .store(seconds, Ordering::Relaxed);
+    }
+
+    /// Check whether a quota group can serve a request at all
+    ///
+    /// Not ready when no account serves the group, or every account is
+    /// paused, rate limited for longer than the readiness threshold, or
+    /// holds an expired token it keeps failing to refresh. Reads in-memory
+    /// state only, so it is cheap enough for a probe hit every second.
+    pub fn readiness(&self, quota_group: &str) -> Readiness {
+        let provider = provider_for(quota_group);
+        let tokens: Vec<Arc<ProxyToken>> = self
+            .tokens
+            .iter()
+            .map(|e| e.value().clone())
+            .filter(|t| t.serves(&provider))
+            .collect();
+        let not_ready = |reason: String| Readiness {
+            ready: false,
+            healthy_accounts: 0,
+            reason: Some(reason),
+        };
+        if self.is_shut_down() {
+            return not_ready("Token manager is shut down".to_string());
+        }
+        if tokens.is_empty() {
+            return not_ready(format!("No account serves {}", provider));
+        }
+
+        let threshold = self.readiness_rate_limit_seconds.load(Ordering::Relaxed);
+        if let Some(wait) = self
+            .rate_limit_tracker
+            .group_reset_seconds(quota_group)
+            .filter(|wait| *wait > threshold)
+        {
+            return not_ready(format!(
+                "{} is rate limited for every account for {}s",
+                quota_group, wait
+            ));
+        }
+
+        let now = self.now();
+        let (mut paused, mut limited, mut failing) = (0, 0, 0);
+        for token in &tokens {
+            if self.paused.contains(&token.account_id) {
+                paused += 1;
+            } else if self
+                .rate_limit_tracker
+                .get_reset_seconds(quota_group, &token.account_id)
+                .is_some_and(|wait| wait > threshold)
+            {
+                limited += 1;
+            } else if token.is_expired_at(now, 0)
+                && self
+                    .refresh_coordinator
+                    .failure(&token.account_id)
+                    .is_some()
+            {
+                failing += 1;
+            }
+        }
+        let healthy_accounts = tokens.len() - paused - limited - failing;
+        if healthy_accounts > 0 {
+            return Readiness {
+                ready: true,
+                healthy_accounts,
+                reason: None,
+            };
+        }
+
+        let total = tokens.len();
+        not_ready(match (paused, limited, failing) {
+            (_, 0, 0) => format!("All {} account(s) are paused", total),
+            (0, _, 0) => format!("All {} account(s) are rate limited for more than {}s", total, threshold),
+            (0, 0, _) => format!("All {} account(s) are failing token refresh", total),
+            _ => format!(
+                "No usable account: {} paused, {} rate limited for more than {}s, {} failing token refresh",
+                paused, limited, threshold, failing
+            ),
+        })
+    }
+
+    /// Check that no background task started by this manager died in a panic
+    ///
+    /// Tasks that were stopped or shut down normally don't count.
+    pub fn liveness(&self) -> Liveness {
+        let panicked_tasks = self.tasks.panicked();
+        Liveness {
+            alive: panicked_tasks.is_empty(),
+            panicked_tasks,
+        }
+    }
+
+    /// Count healthy and troubled accounts for one scope group
+    ///
+    /// Rate limits are those of the group as a whole, not of a single
+    /// model. Reads the current pool only; no scheduler state changes.
+    pub fn pool_health(&self, quota_group: &str, request_type: &str) -> PoolHealth {
+        let scope_group = AccountScheduler::scope_group(quota_group, request_type, None);
+        let provider = provider_for(quota_group);
+        let tokens: Vec<Arc<ProxyToken>> = self
+            .tokens
+            .iter()
+            .map(|e| e.value().clone())
+            .filter(|t| t.serves(&provider))
+            .collect();
+        let buffer = self.expiry_buffer_for(request_type);
+        let now = self.now();
+        let group_limited_seconds = self.rate_limit_tracker.group_reset_seconds(&scope_group);
+
+        let healthy = if group_limited_seconds.is_some() {
+            0
+        } else {
+            self.scheduler
+                .get_healthy_accounts(&tokens, &scope_group)
+                .into_iter()
+                .filter(|(t, _)| {
+                    !self.paused.contains(&t.account_id)
+                        && !t.is_expired_at(now, buffer)
+                        && t.project_id.is_some()
+                })
+                .count()
+        };
+
+        PoolHealth {
+            total: tokens.len(),
+            healthy,
+            rate_limited: self.scheduler.count_limited_accounts(&tokens, &scope_group),
+            expiring_soon: tokens
+                .iter()
+                .filter(|t| t.is_expired_at(now, buffer))
+                .count(),
+            missing_project_id: tokens.iter().filter(|t| t.project_id.is_none()).count(),
+            disabled_recently: self
+                .recently_disabled
+                .iter()
+                .filter(|e| now - *e.value() < RECENT_DISABLE_WINDOW_SECONDS)
+                .count(),
+            group_limited_seconds,
+            quota_remaining: tokens
+                .iter()
+                .filter_map(|t| {
+                    self.scheduler
+                        .quota()
+                        .remaining_at(&scope_group, &t.account_id, now)
+                })
+                .reduce(u64::saturating_add),
+        }
+    }
+
+    /// Describe every loaded account, best tier first
+    pub fn list_accounts(&self) -> Vec<AccountStatus> {
+        let now = self.now();
+        let mut accounts: Vec<(usize, AccountStatus)> = self
+            .tokens
+            .iter()
+            .map(|entry| {
+                let token = entry.value();
+                (
+                    self.scheduler.tier_priority(token),
+                    self.account_status(token, now),
+                )
+            })
+            .collect();
+
+        accounts.sort_by(|(a_tier, a), (b_tier, b)| {
+            a_tier.cmp(b_tier).then_with(|| a.email.cmp(&b.email))
+        });
+        accounts.into_iter().map(|(_, status)| status).collect()
+    }
+
+    /// Describe one account as of `now`
+    fn account_status(&self, token: &ProxyToken, now: i64) -> AccountStatus {
+        let rate_limits = self
+            .rate_limit_tracker
+            .active_limits_for_account(&token.account_id)
+            .into_iter()
+            .map(|(scope_group, remaining_seconds)| ScopeRateLimit {
+                scope_group,
+                remaining_seconds,
+            })
+            .collect();
+
+        AccountStatus {
+            account_id: token.account_id.clone(),
+            email: token.email.clone(),
+            subscription_tier: token.subscription_tier.clone(),
+            has_project_id: token.project_id.is_some(),
+            token_expires_at: token.timestamp,
+            token_expired: token.is_expired_at(now, self.expiry_buffer()),
+            refresh_failure: self.refresh_coordinator.failure(&token.account_id),
+            rate_limits,
+            circuit_breakers: self
+                .scheduler
+                .circuit_breaker()
+                .states_for_account_at(&token.account_id, now),
+            quotas: self
+                .scheduler
+                .quota()
+                .for_account_at(&token.account_id, now),
+            session_bindings: self.session_manager.bindings_for_account(&token.account_id),
+            in_flight: self.scheduler.in_flight_count(&token.account_id),
+            max_concurrent: token.max_concurrent,
+            max_rpm: token.max_rpm,
+            tags: token.tags.clone(),
+            paused: self.paused.contains(&token.account_id),
+            draining: self.draining.contains_key(&token.account_id),
+        }
+    }
+
+    // ===== Metrics =====
+
+    /// Choose how accounts are named in logs, events and error messages
+    ///
+    /// Defaults to `LogIdentity::Email`. Covers the `email` field of
+    /// selection and refresh events too; metric labels never carry an
+    /// email either way (see `set_metrics_account_label`).
+    pub fn set_log_identity(&self, identity: LogIdentity) {
+        self.log_identity.store(identity.as_u8(), Ordering::Relaxed);
+    }
+
+    /// How accounts are named in logs, events and error messages
+    pub fn log_identity(&self) -> LogIdentity {
+        LogIdentity::from_u8(self.log_identity.load(Ordering::Relaxed))
+    }
+
+    /// Name an account in a log line or event as `set_log_identity` asks
+    pub fn display_identity(&self, token: &ProxyToken) -> String {
+        self.log_identity().display(&token.account_id, &token.email)
+    }
+
+    /// Name an account read from its file as `set_log_identity` asks
+    fn display_account(&self, account: &AccountFile) -> String {
+        self.log_identity().display(&account.id, &account.email)
+    }
+
+    /// How often sessions landed back on their bound account, per scope group
+    ///
+    /// Counts every request of a bound session since the start or the last
+    /// `reset_stickiness`: hits, misses by cause, CacheFirst waits and
+    /// forced rotations. Also part of `metrics_snapshot`.
+    pub fn stickiness_report(&self) -> StickinessReport {
+        StickinessReport {
+            scope_groups: self.metrics.stickiness(),
+        }
+    }
+
+    /// Zero the stickiness counters
+    pub fn reset_stickiness(&self) {
+        self.metrics.reset_stickiness();
+    }
+
+    /// Why a session's first pick is not its bound account
+    fn sticky_miss(&self, scope_group: &str, bound: &str) -> StickyOutcome {
+        if !self.tokens.contains_key(bound) {
+            StickyOutcome::MissAccountGone
+        } else if self.rate_limit_tracker.is_rate_limited(scope_group, bound) {
+            StickyOutcome::MissRateLimited
+        } else {
+            StickyOutcome::MissOther
+        }
+    }
+
+    /// Choose what the `account` label of metric series holds
+    pub fn set_metrics_account_label(&self, label: AccountLabel) {
+        self.metrics.set_account_label(label);
+    }
+
+    /// Copy the current metrics
+    ///
+    /// Accounts that left the pool are dropped from the per-account series.
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        let now = self.now();
+        let emails: HashMap<String, String> = self
+            .tokens
+            .iter()
+            .map(|e| (e.key().clone(), e.value().email.clone()))
+            .collect();
+        let healthy_accounts = self
+            .tokens
+            .iter()
+            .filter(|e| !self.rate_limit_tracker.is_limited_in_any_group(e.key()))
+            .filter(|e| {
+                self.scheduler
+                    .circuit_breaker()
+                    .states_for_account_at(e.key(), now)
+                    .is_empty()
+            })
+            .count();
+
+        let label = self.metrics.account_label();
+        self.metrics.snapshot(
+            |account_id| match label {
+                AccountLabel::AccountId => account_id.to_string(),
+                AccountLabel::EmailHash => self
+                    .metrics
+                    .email_label(account_id, emails.get(account_id).map(String::as_str)),
+            },
+            healthy_accounts,
+            self.session_manager.len(),
+            self.scheduler.throttle().counts_at(now),
+        )
+    }
+
+    /// Render the current metrics in the Prometheus text format
+    pub fn metrics_text(&self) -> String {
+        self.metrics_snapshot().to_prometheus()
+    }
+
+    /// Get the number of loaded accounts
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Check if no accounts are loaded
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    // ===== Rate Limit Management =====
+
+    /// Scope group that limits for a request are tracked in
+    ///
+    /// `model` only narrows the scope if the group is scoped by model.
+    fn limit_scope(&self, quota_group: &str, request_type: &str, model: Option<&str>) -> String {
+        let session_group = AccountScheduler::scope_group(quota_group, request_type, None);
+        let by_model = self
+            .sticky_config
+            .load()
+            .resolve(&session_group)
+            .scope_by_model;
+        match model.filter(|_| by_model) {
+            Some(model) => AccountScheduler::scope_group(quota_group, request_type, Some(model)),
+            None => session_group,
+        }
+    }
+
+    /// Mark an account as rate limited
+    ///
+    /// An error matching one of the configured `group_limit_signatures`
+    /// limits the whole scope group instead of the account.
+    #[allow(clippy::too_many_arguments)]
+    pub fn mark_rate_limited(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+        account_id: &str,
+        status: u16,
+        retry_after_header: Option<&str>,
+        error_body: &str,
     ) {
-        let scope_group = AccountScheduler::scope_group(quota_group, request_type);
-        self.rate_limit_tracker.parse_from_error(
+        let scope_group = self.limit_scope(quota_group, request_type, model);
+        let group_wide = self
+            .sticky_config
+            .load()
+            .default
+            .group_limit_signatures
+            .iter()
+            .any(|signature| signature.matches(status, error_body));
+        if group_wide {
+            let limited = self.rate_limit_tracker.parse_group_from_error(
+                &scope_group,
+                status,
+                retry_after_header,
+                error_body,
+            );
+            if let Some(info) = limited {
+                tracing::warn!(
+                    "[TokenManager] Error on account {} limits all of {} for {}s",
+                    account_id,
+                    scope_group,
+                    info.retry_after_sec
+                );
+                self.emit(TokenManagerEvent::GroupRateLimited {
+                    scope_group,
+                    remaining_seconds: info.retry_after_sec,
+                    status: Some(status),
+                });
+                self.check_pool_health(quota_group, request_type);
+            }
+            return;
+        }
+
+        let limited = self.rate_limit_tracker.parse_from_error(
             &scope_group,
             account_id,
             status,
             retry_after_header,
             error_body,
         );
+        if let Some(info) = limited {
+            self.unbind_after_limit(
+                quota_group,
+                request_type,
+                &scope_group,
+                account_id,
+                info.retry_after_sec,
+            );
+            self.emit_rate_limited(account_id, scope_group, info.retry_after_sec, Some(status));
+            self.check_pool_health(quota_group, request_type);
+        }
+    }
+
+    /// Unbind the sessions of a rate limited account if they would only wait
+    ///
+    /// With `unbind_on_long_limit`, a cooldown longer than `max_wait_seconds`
+    /// drops the account's bindings in the session group, so their next
+    /// requests pick a new account right away. A limit of a single model
+    /// leaves the bindings alone, since the sessions may be using others.
+    fn unbind_after_limit(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        scope_group: &str,
+        account_id: &str,
+        cooldown: u64,
+    ) {
+        let session_group = AccountScheduler::scope_group(quota_group, request_type, None);
+        if scope_group != session_group {
+            return;
+        }
+        let unbind = {
+            let policy = self.sticky_config.load();
+            let config = policy.resolve(&session_group);
+            config.unbind_on_long_limit && cooldown > config.max_wait_seconds
+        };
+        if !unbind {
+            return;
+        }
+        let unbound = self
+            .session_manager
+            .remove_bindings_for_account_in_group(account_id, &session_group);
+        if unbound > 0 {
+            tracing::info!(
+                "[TokenManager] Unbound {} session(s) from account {} in {}: limited for {}s",
+                unbound,
+                account_id,
+                session_group,
+                cooldown
+            );
+        }
+    }
+
+    /// Rate limit a whole scope group for `seconds`
+    ///
+    /// Token selection in the group fails right away with the wait until the
+    /// limit lifts, without trying any account.
+    pub fn mark_group_limited(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+        seconds: u64,
+    ) {
+        let scope_group = self.limit_scope(quota_group, request_type, model);
+        self.rate_limit_tracker
+            .mark_group_limited(&scope_group, seconds);
+        tracing::warn!(
+            "[TokenManager] Rate limited all of {} for {}s",
+            scope_group,
+            seconds
+        );
+        self.emit(TokenManagerEvent::GroupRateLimited {
+            scope_group,
+            remaining_seconds: seconds,
+            status: None,
+        });
+    }
+
+    /// Record the quota an upstream response reported for an account
+    ///
+    /// Call this after each response that carries quota headers or usage
+    /// metadata; it replaces the account's previous observation in the
+    /// scope group. The observation is dropped once `reset_at` passes.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_quota_observation(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+        account_id: &str,
+        remaining: Option<u64>,
+        limit: Option<u64>,
+        reset_at: Option<i64>,
+    ) {
+        let scope_group = self.limit_scope(quota_group, request_type, model);
+        let observation = QuotaObservation {
+            remaining,
+            limit,
+            reset_at,
+            observed_at: self.now(),
+        };
+        self.scheduler
+            .quota()
+            .record(&scope_group, account_id, observation);
+    }
+
+    /// Lift the rate limit on a whole scope group ahead of time
+    ///
+    /// Returns whether the group was limited. Limits of single accounts stay.
+    pub fn clear_group_limit(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+    ) -> bool {
+        let scope_group = self.limit_scope(quota_group, request_type, model);
+        let cleared = self.rate_limit_tracker.clear_group(&scope_group);
+        if cleared {
+            tracing::info!(
+                "[TokenManager] Cleared the group rate limit of {}",
+                scope_group
+            );
+        }
+        cleared
+    }
+
+    fn emit_rate_limited(
+        &self,
+        account_id: &str,
+        scope_group: String,
+        remaining_seconds: u64,
+        status: Option<u16>,
+    ) {
+        self.usage.record_rate_limit(account_id);
+        self.emit(TokenManagerEvent::AccountRateLimited {
+            account_id: account_id.to_string(),
+            scope_group,
+            remaining_seconds,
+            status,
+        });
+    }
+
+    // ===== Request Feedback =====
+
+    /// Report how a request sent with an account turned out
+    ///
+    /// Successes close the account's circuit in the scope group and end its
+    /// probation after a rate limit, repeated upstream 5xx errors open the
+    /// circuit, and `Unauthorized` drops the cached access token and refreshes
+    /// it right away. Rate limits in a row come back with growing cooldowns
+    /// until a success, and one without a known `retry_after` gets the
+    /// default 429 cooldown if the account isn't marked limited yet; any
+    /// other failure frees the probation trial.
+    pub async fn report_result(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+        account_id: &str,
+        outcome: RequestOutcome,
+    ) {
+        self.health.record(account_id, outcome);
+        let scope_group = self.limit_scope(quota_group, request_type, model);
+
+        match outcome {
+            RequestOutcome::Success { .. } => {
+                self.scheduler
+                    .circuit_breaker()
+                    .record_success(&scope_group, account_id);
+                if self
+                    .rate_limit_tracker
+                    .record_success(&scope_group, account_id)
+                {
+                    tracing::info!(
+                        "[TokenManager] Account {} passed probation in {}",
+                        account_id,
+                        scope_group
+                    );
+                }
+            }
+            RequestOutcome::Upstream5xx { .. } => {
+                self.rate_limit_tracker.end_trial(&scope_group, account_id);
+                let opened = self.scheduler.circuit_breaker().record_failure_at(
+                    &scope_group,
+                    account_id,
+                    self.now(),
+                );
+                if opened {
+                    tracing::warn!(
+                        "[TokenManager] Circuit opened for account {} in {} after repeated upstream errors",
+                        account_id,
+                        scope_group
+                    );
+                }
+            }
+            RequestOutcome::Unauthorized => {
+                self.rate_limit_tracker.end_trial(&scope_group, account_id);
+                // Make sure nobody else picks up the rejected token meanwhile
+                self.update_pooled(account_id, |entry| entry.timestamp = 0);
+                self.refresh_coordinator.invalidate(account_id);
+                if let Err(e) = self.refresh_account(account_id).await {
+                    tracing::warn!(
+                        "[TokenManager] Eager refresh failed for {}: {}",
+                        account_id,
+                        e
+                    );
+                }
+            }
+            RequestOutcome::RateLimited {
+                retry_after: Some(seconds),
+            } => {
+                let cooldown =
+                    self.rate_limit_tracker
+                        .mark_limited(&scope_group, account_id, seconds);
+                self.unbind_after_limit(
+                    quota_group,
+                    request_type,
+                    &scope_group,
+                    account_id,
+                    cooldown,
+                );
+                self.emit_rate_limited(account_id, scope_group, cooldown, None);
+                self.check_pool_health(quota_group, request_type);
+            }
+            RequestOutcome::RateLimited { retry_after: None } => {
+                // Unless the handler already marked the limit, apply the default one
+                let marked = self
+                    .rate_limit_tracker
+                    .is_rate_limited(&scope_group, account_id)
+                    || self
+                        .rate_limit_tracker
+                        .group_reset_seconds(&scope_group)
+                        .is_some();
+                if !marked {
+                    self.mark_rate_limited(
+                        quota_group,
+                        request_type,
+                        model,
+                        account_id,
+                        429,
+                        None,
+                        "",
+                    );
+                }
+            }
+        }
+    }
+
+    /// Get request statistics for an account
+    pub fn account_stats(&self, account_id: &str) -> Option<AccountStats> {
+        self.health.stats(account_id)
+    }
+
+    /// Get request statistics for every account that has reported an outcome
+    pub fn all_account_stats(&self) -> HashMap<String, AccountStats> {
+        self.health.snapshot()
+    }
+
+    /// Check if an account is rate limited
+    pub fn is_rate_limited(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+        account_id: &str,
+    ) -> bool {
+        let scope_group = self.limit_scope(quota_group, request_type, model);
+        self.rate_limit_tracker
+            .is_rate_limited(&scope_group, account_id)
+    }
+
+    /// Lift an account's rate limit ahead of time
+    ///
+    /// Meant for a cooldown that came from a misparsed error. Returns
+    /// whether a limit was recorded. CacheFirst requests waiting on the
+    /// account are woken and take it right away.
+    pub fn clear_rate_limit(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        model: Option<&str>,
+        account_id: &str,
+    ) -> bool {
+        let scope_group = self.limit_scope(quota_group, request_type, model);
+        let cleared = self.rate_limit_tracker.clear(&scope_group, account_id);
+        if cleared {
+            tracing::info!(
+                "[TokenManager] Cleared rate limit of {} in {}",
+                account_id,
+                scope_group
+            );
+        }
+        cleared
+    }
+
+    /// List every active rate limit, across all accounts and scope groups
+    pub fn rate_limit_snapshot(&self) -> Vec<RateLimitEntry> {
+        self.rate_limit_tracker.snapshot()
+    }
+
+    // ===== Scheduling Configuration =====
+
+    /// Get the default scheduling configuration
+    pub async fn get_sticky_config(&self) -> StickySessionConfig {
+        self.sticky_config.load().default.clone()
+    }
+
+    /// Update the default scheduling configuration
+    ///
+    /// Session TTL, session persistence, the session cap, the tier order and
+    /// circuit breaker settings are global and only taken from the default
+    /// entry.
+    pub async fn update_sticky_config(&self, new_config: StickySessionConfig) {
+        self.update_sticky_config_for(DEFAULT_GROUP, new_config)
+            .await;
+    }
+
+    /// Get the scheduling configuration in effect for a quota or scope group
+    pub async fn get_sticky_config_for(&self, group: &str) -> StickySessionConfig {
+        self.sticky_config.load().resolve(group).clone()
+    }
+
+    /// Set the scheduling configuration for a quota or scope group
+    /// (`"default"` updates the fallback entry)
+    pub async fn update_sticky_config_for(&self, group: &str, new_config: StickySessionConfig) {
+        let _update = self.sticky_config_update.lock().await;
+        let mut configs = GroupedStickyConfig::clone(&self.sticky_config.load());
+        if group == DEFAULT_GROUP {
+            self.session_manager.set_ttl(new_config.session_ttl_seconds);
+            self.session_manager
+                .set_max_bindings(new_config.max_session_bindings);
+            self.session_persistence
+                .store(new_config.persist_sessions, Ordering::Relaxed);
+            Self::configure_scheduler(&self.scheduler, &new_config);
+        }
+        tracing::debug!(
+            "Scheduling configuration for {} updated: {:?}",
+            group,
+            new_config
+        );
+        configs.set(group, new_config);
+        let configs = Arc::new(configs);
+        self.sticky_config.store(configs.clone());
+        // CacheFirst waiters re-check under the new configuration
+        self.rate_limit_tracker.wake_all();
+        self.emit(TokenManagerEvent::ConfigUpdated {
+            group: group.to_string(),
+        });
+
+        if let Err(e) = self.save_sticky_configs(&configs).await {
+            tracing::warn!(
+                "[TokenManager] Failed to save scheduling configuration: {}",
+                e
+            );
+        }
     }
 
-    /// Check if an account is rate limited
-    pub fn is_rate_limited(&self, quota_group: &str, request_type: &str, account_id: &str) -> bool {
-        let scope_group = AccountScheduler::scope_group(quota_group, request_type);
-        self.rate_limit_tracker.is_rate_limited(&scope_group, account_id)
+    /// Read the named OAuth clients, or none if the file is missing or corrupt
+    fn load_oauth_clients(data_dir: &std::path::Path) -> HashMap<String, OAuthClientCredentials> {
+        let path = data_dir.join(OAUTH_CLIENTS_FILE);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+            Err(e) => {
+                tracing::warn!("[TokenManager] Failed to read {:?}: {}", path, e);
+                return HashMap::new();
+            }
+        };
+
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            tracing::warn!("[TokenManager] Ignoring corrupt {:?}: {}", path, e);
+            HashMap::new()
+        })
     }
 
-    // ===== Scheduling Configuration =====
+    /// OAuth client an account's refresh token was issued to
+    ///
+    /// Inline credentials win over a named client; `None` means the global
+    /// client. A name missing from `oauth_clients.json` is an error, since
+    /// refreshing with the global client would fail and disable the account.
+    fn oauth_client_for(
+        &self,
+        account: &AccountFile,
+    ) -> Result<Option<OAuthClientCredentials>, String> {
+        if let Some(credentials) = account.oauth_credentials() {
+            return Ok(Some(credentials));
+        }
+        let Some(name) = &account.oauth_client else {
+            return Ok(None);
+        };
+        self.oauth_clients
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown OAuth client {:?} for account {}; add it to {}",
+                    name,
+                    self.display_account(account),
+                    OAUTH_CLIENTS_FILE
+                )
+            })
+    }
 
-    /// Get current scheduling configuration
-    pub async fn get_sticky_config(&self) -> StickySessionConfig {
-        self.sticky_config.read().await.clone()
+    /// Path of the persisted scheduling configuration
+    fn sticky_config_path(data_dir: &std::path::Path) -> PathBuf {
+        data_dir.join("scheduling.json")
     }
 
-    /// Update scheduling configuration
-    pub async fn update_sticky_config(&self, new_config: StickySessionConfig) {
-        let mut config = self.sticky_config.write().await;
-        *config = new_config;
-        tracing::debug!("Scheduling configuration updated: {:?}", *config);
+    /// Whether a scheduling configuration was saved by an earlier run
+    pub fn has_saved_sticky_config(&self) -> bool {
+        Self::sticky_config_path(&self.data_dir).exists()
+    }
+
+    /// Read the saved scheduling configuration, falling back to defaults
+    fn load_sticky_configs(data_dir: &std::path::Path) -> GroupedStickyConfig {
+        let path = Self::sticky_config_path(data_dir);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return GroupedStickyConfig::default()
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "[TokenManager] Failed to read {:?}, using defaults: {}",
+                    path,
+                    e
+                );
+                return GroupedStickyConfig::default();
+            }
+        };
+
+        GroupedStickyConfig::from_json(&content).unwrap_or_else(|e| {
+            tracing::warn!(
+                "[TokenManager] Ignoring corrupt {:?}, using defaults: {}",
+                path,
+                e
+            );
+            GroupedStickyConfig::default()
+        })
+    }
+
+    /// Write the scheduling configuration back to disk atomically
+    async fn save_sticky_configs(&self, configs: &GroupedStickyConfig) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(configs)
+            .map_err(|e| format!("Failed to serialize scheduling configuration: {}", e))?;
+        let path = Self::sticky_config_path(&self.data_dir);
+
+        tokio::task::spawn_blocking(move || {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create data directory: {}", e))?;
+            }
+            write_atomic(&path, &json)
+        })
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+    }
+
+    /// Get every scheduling configuration, keyed by group
+    pub async fn get_sticky_configs(&self) -> GroupedStickyConfig {
+        GroupedStickyConfig::clone(&self.sticky_config.load())
+    }
+
+    /// Apply the global scheduler settings of the default entry
+    fn configure_scheduler(scheduler: &AccountScheduler, config: &StickySessionConfig) {
+        scheduler.set_tier_order(TierOrder::new(
+            config.tier_order.clone(),
+            config.unknown_tier_position,
+        ));
+        scheduler.circuit_breaker().configure(
+            config.circuit_breaker_threshold,
+            config.circuit_breaker_window_seconds,
+            config.circuit_breaker_cooldown_seconds,
+        );
     }
 
     /// Clear all session bindings
     pub fn clear_all_sessions(&self) {
         self.session_manager.clear_all();
     }
+
+    /// Get the account a session is bound to
+    ///
+    /// The scope group is resolved as in `get_token`, so image_gen sessions
+    /// are addressed with `request_type = "image_gen"`. Looking a binding up
+    /// does not extend its idle TTL.
+    pub fn get_session_binding(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        session_id: &str,
+    ) -> Option<String> {
+        let session_group = AccountScheduler::scope_group(quota_group, request_type, None);
+        self.session_manager
+            .peek_binding(&session_group, session_id)
+    }
+
+    /// Drop one session's binding so its next request is scheduled afresh
+    ///
+    /// Returns the account the session was bound to, if any.
+    pub fn unbind_session(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        session_id: &str,
+    ) -> Option<String> {
+        let session_group = AccountScheduler::scope_group(quota_group, request_type, None);
+        let account_id = self
+            .session_manager
+            .take_binding(&session_group, session_id)?;
+        tracing::info!(
+            "[TokenManager] Unbound session {} in {} from account {}",
+            session_id,
+            session_group,
+            account_id
+        );
+        Some(account_id)
+    }
+
+    /// List live session bindings, ordered by scope group and session
+    pub fn list_sessions(&self) -> Vec<SessionInfo> {
+        self.session_manager
+            .snapshot()
+            .into_iter()
+            .map(|binding| SessionInfo {
+                scope_group: binding.quota_group,
+                session_id: binding.session_id,
+                account_id: binding.account_id,
+                last_used_at: binding.last_used_at,
+            })
+            .collect()
+    }
+
+    /// Clear the session bindings of one scope group, leaving other groups
+    /// bound
+    ///
+    /// The scope group is resolved as in `get_token`. With
+    /// `include_subscopes`, clearing `("gemini", "chat")` also clears
+    /// `gemini::image_gen`. Returns the number of bindings removed.
+    pub fn clear_sessions_for_group(
+        &self,
+        quota_group: &str,
+        request_type: &str,
+        include_subscopes: bool,
+    ) -> usize {
+        let scope_group = AccountScheduler::scope_group(quota_group, request_type, None);
+        let removed = self
+            .session_manager
+            .clear_group(&scope_group, include_subscopes);
+        tracing::info!(
+            "[TokenManager] Cleared {} session binding(s) in {}",
+            removed,
+            scope_group
+        );
+        removed
+    }
+
+    /// Remove session bindings idle longer than the configured TTL
+    ///
+    /// Returns the number of bindings removed.
+    pub fn evict_expired_sessions(&self) -> usize {
+        self.session_manager.evict_expired(self.now())
+    }
+
+    /// Start a background task that prunes expired session bindings every `interval`
+    ///
+    /// The task stops when the returned handle is stopped or dropped, or when
+    /// the manager is shut down or dropped.
+    pub fn start_session_sweeper(self: &Arc<Self>, interval: Duration) -> BackgroundTask {
+        let manager = Arc::downgrade(self);
+        self.spawn_task("session-sweeper", interval, move || {
+            let manager = manager.clone();
+            async move {
+                match manager.upgrade() {
+                    Some(manager) => {
+                        manager.evict_expired_sessions();
+                        true
+                    }
+                    None => false,
+                }
+            }
+        })
+    }
+
+    /// Path of the persisted session bindings
+    fn sessions_path(data_dir: &std::path::Path) -> PathBuf {
+        data_dir.join("sessions.json")
+    }
+
+    /// Load saved session bindings into `session_manager`
+    ///
+    /// Bindings idle past the TTL are dropped; bindings of accounts that no
+    /// longer exist are dropped by the next `load_accounts`.
+    fn restore_sessions(session_manager: &SessionManager, data_dir: &std::path::Path, now: i64) {
+        let path = Self::sessions_path(data_dir);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::warn!("[TokenManager] Failed to read {:?}: {}", path, e);
+                return;
+            }
+        };
+
+        match serde_json::from_str::<Vec<PersistedBinding>>(&content) {
+            Ok(bindings) => {
+                let restored = session_manager.restore(bindings, now);
+                tracing::info!("[TokenManager] Restored {} session binding(s)", restored);
+            }
+            Err(e) => tracing::warn!("[TokenManager] Ignoring corrupt {:?}: {}", path, e),
+        }
+    }
+
+    /// Write session bindings to disk if persistence is on and they changed
+    /// since the last save
+    ///
+    /// Returns `true` if the file was written.
+    pub async fn save_sessions(&self) -> Result<bool, String> {
+        if !self.session_persistence.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let revision = self.session_manager.revision();
+        if revision == self.saved_session_revision.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+
+        let json = serde_json::to_string(&self.session_manager.snapshot())
+            .map_err(|e| format!("Failed to serialize session bindings: {}", e))?;
+        let path = Self::sessions_path(&self.data_dir);
+        tokio::task::spawn_blocking(move || {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create data directory: {}", e))?;
+            }
+            write_atomic(&path, &json)
+        })
+        .await
+        .map_err(|e| format!("Task failed: {}", e))??;
+
+        self.saved_session_revision
+            .store(revision, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// Start a background task that saves changed session bindings every
+    /// `interval`, so a burst of changes costs one write
+    ///
+    /// Does nothing while session persistence is off. The task stops when
+    /// the returned handle is stopped or dropped, or when the manager is shut
+    /// down or dropped.
+    pub fn start_session_persister(self: &Arc<Self>, interval: Duration) -> BackgroundTask {
+        let manager = Arc::downgrade(self);
+        self.spawn_task("session-persister", interval, move || {
+            let manager = manager.clone();
+            async move {
+                match manager.upgrade() {
+                    Some(manager) => {
+                        if let Err(e) = manager.save_sessions().await {
+                            tracing::warn!("[TokenManager] Failed to save session bindings: {}", e);
+                        }
+                        true
+                    }
+                    None => false,
+                }
+            }
+        })
+    }
+
+    // ===== Usage Stats =====
+
+    /// Usage counters of one account, including what is not on disk yet
+    pub fn account_usage(&self, account_id: &str) -> Option<ProxyStats> {
+        self.usage.get(account_id)
+    }
+
+    /// Write every account's unsaved usage counters to its `proxy_stats`
+    ///
+    /// Meant for shutdown; returns the number of account files written.
+    pub async fn flush_stats(&self) -> usize {
+        self.write_stats(None).await
+    }
+
+    /// Write unsaved usage counters of accounts not written in the last
+    /// `min_interval` seconds
+    ///
+    /// A failed write is logged and retried on the next flush; it never
+    /// affects requests.
+    async fn write_stats(&self, min_interval: Option<i64>) -> usize {
+        let now = self.now();
+        let mut written = 0;
+        for pending in self.usage.pending(now, min_interval) {
+            let Some(path) = self
+                .tokens
+                .get(&pending.account_id)
+                .map(|t| t.account_path.clone())
+            else {
+                continue;
+            };
+            let stats = pending.stats;
+            let result = self
+                .account_files
+                .update(&path, move |account| account.proxy_stats = Some(stats))
+                .await;
+            match result {
+                Ok(()) => {
+                    self.usage
+                        .mark_flushed(&pending.account_id, pending.revision, now);
+                    written += 1;
+                }
+                Err(e) => tracing::warn!(
+                    "[TokenManager] Failed to write usage stats for {}: {}",
+                    pending.account_id,
+                    e
+                ),
+            }
+        }
+        written
+    }
+
+    /// Start a background task that writes changed usage counters every `interval`
+    ///
+    /// Each account file is written at most once a minute however often the
+    /// task runs.
+    pub fn start_stats_flusher(self: &Arc<Self>, interval: Duration) -> BackgroundTask {
+        let manager = Arc::downgrade(self);
+        self.spawn_task("stats-flusher", interval, move || {
+            let manager = manager.clone();
+            async move {
+                match manager.upgrade() {
+                    Some(manager) => {
+                        manager
+                            .write_stats(Some(STATS_FLUSH_INTERVAL_SECONDS))
+                            .await;
+                        true
+                    }
+                    None => false,
+                }
+            }
+        })
+    }
+
+    /// Whether `shutdown` has been called
+    pub fn is_shut_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Number of background tasks started by this manager still running
+    pub fn running_tasks(&self) -> usize {
+        self.shutdown.receiver_count()
+    }
+
+    /// Stop every background task and write unsaved state to disk
+    ///
+    /// Waits up to `timeout` for the tasks to exit, then saves session
+    /// bindings, refreshed tokens and usage counters whether or not they all
+    /// did. From then
+    /// on token selection fails with `GetTokenErrorKind::ShutDown`, and
+    /// requests waiting out a rate limit give up at once. Calling it again
+    /// only repeats the flush.
+    ///
+    /// Returns an error if a task outlived the timeout or the sessions
+    /// could not be saved.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), String> {
+        self.shutdown.send_replace(true);
+        let mut result = Ok(());
+
+        if tokio::time::timeout(timeout, self.shutdown.closed())
+            .await
+            .is_err()
+        {
+            let running = self.running_tasks();
+            tracing::warn!(
+                "[TokenManager] {} background task(s) still running after {:?}",
+                running,
+                timeout
+            );
+            result = Err(format!(
+                "{} background task(s) did not stop in time",
+                running
+            ));
+        }
+
+        if let Err(e) = self.save_sessions().await {
+            tracing::warn!("[TokenManager] Failed to save session bindings: {}", e);
+            result = result.and(Err(e));
+        }
+        let tokens_written = self.flush_token_writes().await;
+        let written = self.flush_stats().await;
+        tracing::info!(
+            "[TokenManager] Shut down; refreshed tokens written for {} and usage stats for {} account(s)",
+            tokens_written,
+            written
+        );
+
+        result
+    }
+}
+
+#[cfg(test)]
+impl TokenManager {
+    pub(super) fn bind_session_for_test(
+        &self,
+        scope_group: &str,
+        session_id: &str,
+        account_id: &str,
+    ) {
+        self.session_manager
+            .set_binding(scope_group, session_id, account_id);
+    }
+
+    pub(super) fn session_binding_for_test(
+        &self,
+        scope_group: &str,
+        session_id: &str,
+    ) -> Option<String> {
+        self.session_manager.get_binding(scope_group, session_id)
+    }
+
+    pub(super) fn set_drain_started_for_test(&self, account_id: &str, started_at: i64) {
+        self.draining.insert(account_id.to_string(), started_at);
+    }
+
+    pub(super) fn token_for_test(&self, account_id: &str) -> Option<ProxyToken> {
+        self.tokens
+            .get(account_id)
+            .map(|e| ProxyToken::clone(e.value()))
+    }
+
+    pub(super) fn insert_token_for_test(&self, token: ProxyToken) {
+        self.tokens
+            .insert(token.account_id.clone(), Arc::new(token));
+    }
+}
+
+/// The filtered pool and session state one selection runs on
+struct SelectionPlan {
+    /// Eligible accounts, best tier first
+    tokens: Vec<Arc<ProxyToken>>,
+    /// Accounts already passed over while filtering
+    trace: Vec<AttemptInfo>,
+    session_group: String,
+    scope_group: String,
+    scheduling: StickySessionConfig,
+    /// The client's session ID, or the conversation fingerprint standing in for it
+    session_id: Option<String>,
+    bound_account: Option<String>,
+    /// Whether the session was bound to an account this request cannot use
+    binding_gone: bool,
+    preferred_account: Option<String>,
+    /// Live bindings per account, filled in Balance mode only
+    session_counts: HashMap<String, usize>,
+}
+
+/// Check whether a request type's policy lets an account serve it
+///
+/// The account's subdirectory counts as one more tag.
+fn allowed_for_request_type(policy: &RequestTypePolicy, token: &ProxyToken) -> bool {
+    let tier = token.subscription_tier.as_deref();
+    policy.allows(tier, &token.tags)
+        || token
+            .source_dir
+            .as_ref()
+            .is_some_and(|dir| policy.allows(tier, std::slice::from_ref(dir)))
+}
+
+/// Mention accounts that were passed over only because they are paused
+fn with_paused_note(message: String, attempts: &[AttemptInfo]) -> String {
+    let paused = attempts
+        .iter()
+        .filter(|a| a.outcome == AttemptOutcome::Paused)
+        .count();
+    if paused == 0 {
+        return message;
+    }
+    format!("{} ({} paused account(s) skipped)", message, paused)
 }
 
 /// Truncate a string to a maximum length
@@ -545,39 +4952,420 @@ mod tests {
 
     #[tokio::test]
     async fn test_token_manager_creation() {
-        let tm = TokenManager::new(PathBuf::from("/tmp"));
+        let dir = tempfile::tempdir().unwrap();
+        let tm = TokenManager::new(dir.path().to_path_buf());
         assert!(tm.is_empty());
         assert_eq!(tm.len(), 0);
     }
 
     #[tokio::test]
     async fn test_sticky_config_update() {
-        let tm = TokenManager::new(PathBuf::from("/tmp"));
-        
+        let dir = tempfile::tempdir().unwrap();
+        let tm = TokenManager::new(dir.path().to_path_buf());
+
         let initial = tm.get_sticky_config().await;
         // Default is CacheFirst with 120 seconds from StickySessionConfig
         assert_eq!(initial.max_wait_seconds, 120);
-        
+
         let new_config = StickySessionConfig {
             mode: crate::proxy::sticky_config::SchedulingMode::Balance,
             max_wait_seconds: 60,
+            ..StickySessionConfig::default()
         };
-        
+
         tm.update_sticky_config(new_config.clone()).await;
-        
+
         let updated = tm.get_sticky_config().await;
         assert_eq!(updated.max_wait_seconds, 60);
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_readers_see_sticky_config_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let tm = Arc::new(TokenManager::new(dir.path().to_path_buf()));
+        let updated = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let tm = tm.clone();
+                let updated = updated.clone();
+                tokio::spawn(async move {
+                    loop {
+                        // Checked before reading: once set, the read must see the update
+                        let done = updated.load(Ordering::SeqCst);
+                        let max_wait = tm.get_sticky_config_for("claude").await.max_wait_seconds;
+                        assert!(max_wait == 120 || max_wait == 60, "{}", max_wait);
+                        if done {
+                            return max_wait;
+                        }
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+
+        tm.update_sticky_config(StickySessionConfig {
+            max_wait_seconds: 60,
+            ..StickySessionConfig::default()
+        })
+        .await;
+        updated.store(true, Ordering::SeqCst);
+
+        for reader in readers {
+            assert_eq!(reader.await.unwrap(), 60);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sticky_config_survives_restart() {
+        use crate::proxy::sticky_config::SchedulingMode;
+
+        let dir = tempfile::tempdir().unwrap();
+        let tm = TokenManager::new(dir.path().to_path_buf());
+        assert!(!tm.has_saved_sticky_config());
+
+        tm.update_sticky_config(StickySessionConfig {
+            mode: SchedulingMode::Balance,
+            max_wait_seconds: 45,
+            ..StickySessionConfig::default()
+        })
+        .await;
+        tm.update_sticky_config_for(
+            "claude",
+            StickySessionConfig {
+                max_wait_seconds: 300,
+                ..StickySessionConfig::default()
+            },
+        )
+        .await;
+        assert!(tm.has_saved_sticky_config());
+        assert!(!dir.path().join("scheduling.json.tmp").exists());
+
+        let restarted = TokenManager::new(dir.path().to_path_buf());
+        let config = restarted.get_sticky_config().await;
+        assert_eq!(config.mode, SchedulingMode::Balance);
+        assert_eq!(config.max_wait_seconds, 45);
+        assert_eq!(
+            restarted
+                .get_sticky_config_for("claude")
+                .await
+                .max_wait_seconds,
+            300
+        );
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_sticky_config_falls_back_to_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("scheduling.json"), "{ not json").unwrap();
+
+        let tm = TokenManager::new(dir.path().to_path_buf());
+        let config = tm.get_sticky_config().await;
+        assert_eq!(config.mode, StickySessionConfig::default().mode);
+        assert_eq!(config.max_wait_seconds, 120);
+    }
+
     #[tokio::test]
     async fn test_session_clearing() {
-        let tm = TokenManager::new(PathBuf::from("/tmp"));
-        
+        let dir = tempfile::tempdir().unwrap();
+        let tm = TokenManager::new(dir.path().to_path_buf());
+
         // This would normally be set during get_token
-        tm.session_manager.set_binding("claude", "session-1", "account-1");
+        tm.session_manager
+            .set_binding("claude", "session-1", "account-1");
         assert_eq!(tm.session_manager.len(), 1);
-        
+
         tm.clear_all_sessions();
         assert!(tm.session_manager.is_empty());
     }
+
+    fn test_token(account_id: &str, timestamp: i64) -> ProxyToken {
+        ProxyToken {
+            account_id: account_id.to_string(),
+            access_token: format!("access-{}", account_id),
+            refresh_token: format!("refresh-{}", account_id),
+            expires_in: 3600,
+            timestamp,
+            email: format!("{}@example.com", account_id),
+            account_path: PathBuf::from(format!("/tmp/{}.json", account_id)),
+            project_id: Some("project".to_string()),
+            subscription_tier: None,
+            proxy_weight: 1.0,
+            max_concurrent: None,
+            max_rpm: None,
+            tags: Vec::new(),
+            source_dir: None,
+            oauth_client: None,
+            outbound_proxy: None,
+            service_account: None,
+            providers: vec!["gemini".to_string(), "claude".to_string()],
+            base_url_override: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_accounts_due_for_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let tm = TokenManager::new(dir.path().to_path_buf());
+        let now = chrono::Utc::now().timestamp();
+
+        tm.tokens.insert(
+            "fresh".to_string(),
+            Arc::new(test_token("fresh", now + 3600)),
+        );
+        tm.tokens.insert(
+            "expiring".to_string(),
+            Arc::new(test_token("expiring", now + 60)),
+        );
+        tm.tokens.insert(
+            "limited".to_string(),
+            Arc::new(test_token("limited", now + 60)),
+        );
+        tm.rate_limit_tracker.mark_limited("claude", "limited", 60);
+
+        assert_eq!(tm.accounts_due_for_refresh(), vec!["expiring".to_string()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_pool_updates_replace_entries_for_readers() {
+        let dir = tempfile::tempdir().unwrap();
+        let tm = Arc::new(TokenManager::new(dir.path().to_path_buf()));
+        tm.insert_token_for_test(test_token("a", 0));
+
+        // A snapshot taken before an update keeps the old token
+        let snapshot = tm.tokens.get("a").unwrap().value().clone();
+        tm.store_refreshed_token(&mut ProxyToken {
+            access_token: "access-1".to_string(),
+            ..test_token("a", 1)
+        });
+        assert_eq!(
+            (snapshot.access_token.as_str(), snapshot.timestamp),
+            ("access-a", 0)
+        );
+        let current = tm.tokens.get("a").unwrap().value().clone();
+        assert_eq!(
+            (current.access_token.as_str(), current.timestamp),
+            ("access-1", 1)
+        );
+        assert!(!Arc::ptr_eq(&snapshot, &current));
+
+        // Readers racing a stream of updates never see a half-written token
+        let writer = {
+            let tm = tm.clone();
+            tokio::spawn(async move {
+                for n in 2..2000 {
+                    tm.store_refreshed_token(&mut ProxyToken {
+                        access_token: format!("access-{}", n),
+                        ..test_token("a", n)
+                    });
+                }
+            })
+        };
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let tm = tm.clone();
+                tokio::spawn(async move {
+                    for _ in 0..2000 {
+                        let token = tm.tokens.get("a").unwrap().value().clone();
+                        assert_eq!(token.access_token, format!("access-{}", token.timestamp));
+                    }
+                })
+            })
+            .collect();
+
+        writer.await.unwrap();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_interleaved_refreshes_keep_the_newest_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let tm = Arc::new(TokenManager::new(dir.path().to_path_buf()));
+        tm.insert_token_for_test(test_token("a", 0));
+
+        // Refreshes finishing out of order never roll the pool back
+        let writers: Vec<_> = (0..4)
+            .map(|w| {
+                let tm = tm.clone();
+                tokio::spawn(async move {
+                    for n in (0..500).rev().filter(|n| n % 4 == w) {
+                        let mut token = ProxyToken {
+                            access_token: format!("access-{}", n),
+                            ..test_token("a", n)
+                        };
+                        tm.store_refreshed_token(&mut token);
+                        assert!(token.timestamp >= n);
+                        assert_eq!(token.access_token, format!("access-{}", token.timestamp));
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        let current = tm.tokens.get("a").unwrap().value().clone();
+        assert_eq!(
+            (current.access_token.as_str(), current.timestamp),
+            ("access-499", 499)
+        );
+
+        // A stale refresh takes the pooled token instead
+        let mut stale = ProxyToken {
+            access_token: "access-old".to_string(),
+            ..test_token("a", 10)
+        };
+        tm.store_refreshed_token(&mut stale);
+        assert_eq!(
+            (stale.access_token.as_str(), stale.timestamp),
+            ("access-499", 499)
+        );
+        assert_eq!(tm.tokens.get("a").unwrap().access_token, "access-499");
+    }
+
+    #[tokio::test]
+    async fn test_background_refresh_stops() {
+        let dir = tempfile::tempdir().unwrap();
+        let tm = Arc::new(TokenManager::new(dir.path().to_path_buf()));
+        let now = chrono::Utc::now().timestamp();
+        tm.tokens.insert(
+            "fresh".to_string(),
+            Arc::new(test_token("fresh", now + 3600)),
+        );
+
+        let refresher = tm.start_background_refresh(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!refresher.is_finished());
+
+        refresher.stop().await;
+        assert_eq!(tm.tokens.get("fresh").unwrap().access_token, "access-fresh");
+    }
+
+    #[tokio::test]
+    async fn test_session_ttl_follows_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let tm = TokenManager::new(dir.path().to_path_buf());
+        assert_eq!(
+            tm.session_manager.ttl(),
+            StickySessionConfig::default().session_ttl_seconds
+        );
+
+        tm.update_sticky_config(StickySessionConfig {
+            session_ttl_seconds: 30,
+            ..StickySessionConfig::default()
+        })
+        .await;
+        assert_eq!(tm.session_manager.ttl(), 30);
+    }
+
+    #[tokio::test]
+    async fn test_session_sweeper_prunes_expired_bindings() {
+        let dir = tempfile::tempdir().unwrap();
+        let tm = Arc::new(TokenManager::new(dir.path().to_path_buf()));
+        let now = chrono::Utc::now().timestamp();
+        tm.session_manager.set_ttl(60);
+        tm.session_manager
+            .set_binding_at("claude", "stale", "account-1", now - 120);
+        tm.session_manager
+            .set_binding_at("claude", "fresh", "account-2", now);
+
+        let sweeper = tm.start_session_sweeper(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        sweeper.stop().await;
+
+        // Already pruned by the sweeper
+        assert_eq!(tm.session_manager.evict_expired(now), 0);
+        assert_eq!(tm.session_manager.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_project_id_and_token_saves() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = crate::proxy::token_manager::tests::write_account_file(&accounts, "a", None);
+
+        let tm = Arc::new(TokenManager::new(dir.path().to_path_buf()));
+        tm.load_accounts().await.unwrap();
+        tm.set_token_write_delay(Duration::ZERO);
+        let token = tm.token_for_test("a").unwrap();
+
+        let mut handles = Vec::new();
+        for _ in 0..10 {
+            let project_tm = tm.clone();
+            handles.push(tokio::spawn(async move {
+                project_tm.save_project_id("a", "project-new").await
+            }));
+
+            // A buffer longer than the token's lifetime refreshes every time
+            let refresh_tm = tm.clone();
+            let token = token.clone();
+            handles.push(tokio::spawn(async move {
+                refresh_tm
+                    .refresh_coordinator
+                    .refresh_with(&token, 7200, |_| async {
+                        Ok(TokenResponse {
+                            access_token: "token-new".to_string(),
+                            expires_in: 3600,
+                            refresh_token: None,
+                        })
+                    })
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+
+        let content: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(content["token"]["project_id"], "project-new");
+        assert_eq!(content["token"]["access_token"], "token-new");
+    }
+
+    #[tokio::test]
+    async fn test_accounts_backing_off_refresh_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        crate::proxy::token_manager::tests::write_account_file(&accounts, "a", Some("ULTRA"));
+        crate::proxy::token_manager::tests::write_account_file(&accounts, "b", Some("PRO"));
+
+        let tm = TokenManager::new(dir.path().to_path_buf());
+        tm.load_accounts().await.unwrap();
+
+        // "a" needs a refresh, but the last one failed moments ago
+        let now = chrono::Utc::now().timestamp();
+        tm.insert_token_for_test(ProxyToken {
+            timestamp: now - 10,
+            ..tm.token_for_test("a").unwrap()
+        });
+        tm.refresh_coordinator
+            .record_failure("a", "刷新请求失败: timeout", now);
+
+        for _ in 0..3 {
+            let selected = tm
+                .get_token("claude", "chat", None, true, None)
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, "b");
+        }
+        assert!(tm.accounts_due_for_refresh().is_empty());
+
+        let status = tm.list_accounts();
+        let failure = status[0].refresh_failure.as_ref().unwrap();
+        assert_eq!(status[0].account_id, "a");
+        assert_eq!(failure.failures, 1);
+        assert_eq!(failure.failing_since, now);
+
+        tm.remove_account("b");
+        let err = tm
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap_err();
+        assert!(err.contains("waiting to retry token refresh"), "{}", err);
+    }
 }
@@ -0,0 +1,221 @@
+//! Account File Encryption
+//!
+//! Account files may be stored encrypted so refresh tokens never sit on
+//! disk in plain JSON. An encrypted file is a single line:
+//!
+//! ```text
+//! APXENC1:<base64 nonce>:<base64 ciphertext and tag>
+//! ```
+//!
+//! The JSON is sealed with ChaCha20-Poly1305 under a 32-byte key, with a
+//! fresh random nonce on every write. Plaintext files keep working; a file
+//! that was encrypted is written back encrypted, and
+//! [`encrypt_account_file`] converts existing plaintext files.
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use super::storage::write_atomic;
+use super::types::AccountFile;
+
+/// Marker at the start of an encrypted account file
+pub const ENCRYPTED_HEADER: &str = "APXENC1";
+
+/// Environment variable holding the base64-encoded account key
+pub const ACCOUNT_KEY_ENV: &str = "ANTI_PROXY_ACCOUNT_KEY";
+
+/// Key for encrypted account files
+///
+/// `Debug` never shows the key material.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AccountKey([u8; 32]);
+
+impl std::fmt::Debug for AccountKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AccountKey(..)")
+    }
+}
+
+impl AccountKey {
+    /// Use 32 raw bytes as the key
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        <[u8; 32]>::try_from(bytes)
+            .map(Self)
+            .map_err(|_| format!("Account key must be 32 bytes, got {}", bytes.len()))
+    }
+
+    /// Decode a base64-encoded 32-byte key
+    pub fn from_base64(encoded: &str) -> Result<Self, String> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("Account key is not valid base64: {}", e))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Key from the `ANTI_PROXY_ACCOUNT_KEY` environment variable
+    ///
+    /// The variable is read once per process; an invalid value is logged
+    /// and ignored.
+    pub fn from_env() -> Option<Self> {
+        static KEY: OnceLock<Option<AccountKey>> = OnceLock::new();
+        KEY.get_or_init(|| {
+            let encoded = std::env::var(ACCOUNT_KEY_ENV).ok()?;
+            AccountKey::from_base64(&encoded)
+                .inspect_err(|e| {
+                    tracing::warn!("[TokenManager] Ignoring {}: {}", ACCOUNT_KEY_ENV, e)
+                })
+                .ok()
+        })
+        .clone()
+    }
+
+    fn cipher(&self) -> LessSafeKey {
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &self.0).expect("key is 32 bytes");
+        LessSafeKey::new(key)
+    }
+}
+
+/// Whether file contents are in the encrypted format
+pub fn is_encrypted(content: &str) -> bool {
+    content.trim_start().starts_with(ENCRYPTED_HEADER)
+}
+
+/// Seal account JSON into the encrypted file format
+pub fn encrypt(key: &AccountKey, plaintext: &str) -> Result<String, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "Failed to generate a nonce".to_string())?;
+
+    let mut data = plaintext.as_bytes().to_vec();
+    key.cipher()
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(ENCRYPTED_HEADER.as_bytes()),
+            &mut data,
+        )
+        .map_err(|_| "Failed to encrypt account file".to_string())?;
+
+    Ok(format!(
+        "{}:{}:{}\n",
+        ENCRYPTED_HEADER,
+        STANDARD.encode(nonce),
+        STANDARD.encode(data)
+    ))
+}
+
+/// Open an encrypted file's contents back into account JSON
+pub fn decrypt(key: &AccountKey, content: &str) -> Result<String, String> {
+    let malformed = || "Malformed encrypted account file".to_string();
+    let body = content
+        .trim()
+        .strip_prefix(ENCRYPTED_HEADER)
+        .and_then(|rest| rest.strip_prefix(':'))
+        .ok_or_else(malformed)?;
+    let (nonce, data) = body.split_once(':').ok_or_else(malformed)?;
+
+    let nonce: [u8; NONCE_LEN] = STANDARD
+        .decode(nonce)
+        .ok()
+        .and_then(|n| n.try_into().ok())
+        .ok_or_else(malformed)?;
+    let mut data = STANDARD.decode(data).map_err(|_| malformed())?;
+
+    let plaintext = key
+        .cipher()
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(ENCRYPTED_HEADER.as_bytes()),
+            &mut data,
+        )
+        .map_err(|_| "Failed to decrypt account file: wrong key or corrupted data".to_string())?;
+    String::from_utf8(plaintext.to_vec())
+        .map_err(|e| format!("Decrypted account file is not UTF-8: {}", e))
+}
+
+/// Account JSON from file contents as read from disk, and whether the file
+/// was encrypted
+pub(super) fn open_account_file(
+    key: Option<&AccountKey>,
+    content: String,
+) -> Result<(String, bool), String> {
+    if !is_encrypted(&content) {
+        return Ok((content, false));
+    }
+    let key = key
+        .ok_or_else(|| "Account file is encrypted but no account key is configured".to_string())?;
+    Ok((decrypt(key, &content)?, true))
+}
+
+/// Encrypt a plaintext account file in place
+///
+/// Returns `false` if the file was already encrypted. The file must hold a
+/// valid account, so a broken file is never sealed away.
+pub fn encrypt_account_file(path: &Path, key: &AccountKey) -> Result<bool, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    if is_encrypted(&content) {
+        return Ok(false);
+    }
+    AccountFile::from_json(&content)
+        .map_err(|e| format!("Invalid account file {:?}: {}", path, e))?;
+
+    write_atomic(path, &encrypt(key, &content)?)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> AccountKey {
+        AccountKey::from_bytes(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let sealed = encrypt(&key(1), r#"{"id": "a"}"#).unwrap();
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.contains("\"id\""));
+        assert_eq!(decrypt(&key(1), &sealed).unwrap(), r#"{"id": "a"}"#);
+
+        // Every write uses a fresh nonce
+        assert_ne!(sealed, encrypt(&key(1), r#"{"id": "a"}"#).unwrap());
+    }
+
+    #[test]
+    fn test_wrong_key_or_tampering_is_rejected() {
+        let sealed = encrypt(&key(1), r#"{"id": "a"}"#).unwrap();
+        assert!(decrypt(&key(2), &sealed).unwrap_err().contains("wrong key"));
+
+        let mut tampered = sealed.trim_end().to_string();
+        let last = tampered.pop().unwrap();
+        tampered.push(if last == 'A' { 'B' } else { 'A' });
+        assert!(decrypt(&key(1), &tampered).is_err());
+        assert!(decrypt(&key(1), "APXENC1:garbage").is_err());
+    }
+
+    #[test]
+    fn test_key_parsing() {
+        assert!(AccountKey::from_bytes(&[0; 16]).is_err());
+        let encoded = STANDARD.encode([7u8; 32]);
+        assert_eq!(AccountKey::from_base64(&encoded).unwrap(), key(7));
+        assert!(AccountKey::from_base64("not base64!").is_err());
+        assert_eq!(format!("{:?}", key(7)), "AccountKey(..)");
+    }
+
+    #[test]
+    fn test_plaintext_passes_through() {
+        let (json, encrypted) = open_account_file(None, r#"{"id": "a"}"#.to_string()).unwrap();
+        assert_eq!((json.as_str(), encrypted), (r#"{"id": "a"}"#, false));
+
+        let sealed = encrypt(&key(1), r#"{"id": "a"}"#).unwrap();
+        assert!(open_account_file(None, sealed)
+            .unwrap_err()
+            .contains("no account key"));
+    }
+}
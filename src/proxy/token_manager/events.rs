@@ -0,0 +1,144 @@
+//! Token Manager Events
+//!
+//! Typed notifications of what the token manager did, for the desktop app
+//! to follow without scraping logs. Events go out on a bounded broadcast
+//! channel: a subscriber that falls behind loses the oldest events
+//! (`RecvError::Lagged`) and never slows down token selection.
+//!
+//! Events never carry access or refresh tokens.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use super::types::{DisabledKind, SelectionReason};
+
+/// Events buffered per subscriber before the oldest are dropped
+pub const EVENT_BUFFER_SIZE: usize = 256;
+
+/// Something the token manager did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TokenManagerEvent {
+    /// An account was chosen for a request
+    AccountSelected {
+        account_id: String,
+        /// The account's email, or what the `LogIdentity` setting shows instead
+        email: String,
+        scope_group: String,
+        reason: SelectionReason,
+        /// Session the request belongs to, if it named one or was fingerprinted
+        session_id: Option<String>,
+    },
+    /// An account was rate limited in a scope group
+    AccountRateLimited {
+        account_id: String,
+        scope_group: String,
+        remaining_seconds: u64,
+        /// Upstream status that caused it; `None` when marked directly
+        status: Option<u16>,
+    },
+    /// A scope group was rate limited as a whole, e.g. by source IP
+    GroupRateLimited {
+        scope_group: String,
+        remaining_seconds: u64,
+        /// Upstream status that caused it; `None` when marked directly
+        status: Option<u16>,
+    },
+    /// An access token was refreshed through OAuth
+    TokenRefreshed {
+        account_id: String,
+        /// Named as in `AccountSelected`
+        email: String,
+        /// Unix timestamp at which the new token expires
+        expires_at: i64,
+    },
+    /// An OAuth refresh failed
+    RefreshFailed {
+        account_id: String,
+        /// Named as in `AccountSelected`
+        email: String,
+        error: String,
+    },
+    /// An account was disabled and dropped from the pool
+    AccountDisabled {
+        account_id: String,
+        reason: String,
+        kind: DisabledKind,
+    },
+    /// A session was bound to a new account
+    SessionBound {
+        scope_group: String,
+        session_id: String,
+        account_id: String,
+    },
+    /// A session binding was dropped: it expired, its account left or it
+    /// was unbound
+    SessionEvicted {
+        scope_group: String,
+        session_id: String,
+        account_id: String,
+    },
+    /// The scheduling configuration of a group changed
+    ConfigUpdated { group: String },
+}
+
+impl TokenManagerEvent {
+    /// Account the event is about, if it is about one
+    pub fn account_id(&self) -> Option<&str> {
+        match self {
+            Self::AccountSelected { account_id, .. }
+            | Self::AccountRateLimited { account_id, .. }
+            | Self::TokenRefreshed { account_id, .. }
+            | Self::RefreshFailed { account_id, .. }
+            | Self::AccountDisabled { account_id, .. }
+            | Self::SessionBound { account_id, .. }
+            | Self::SessionEvicted { account_id, .. } => Some(account_id),
+            Self::GroupRateLimited { .. } | Self::ConfigUpdated { .. } => None,
+        }
+    }
+
+    /// Scope group the event happened in, if it happened in one
+    pub fn scope_group(&self) -> Option<&str> {
+        match self {
+            Self::AccountSelected { scope_group, .. }
+            | Self::AccountRateLimited { scope_group, .. }
+            | Self::GroupRateLimited { scope_group, .. }
+            | Self::SessionBound { scope_group, .. }
+            | Self::SessionEvicted { scope_group, .. } => Some(scope_group),
+            Self::TokenRefreshed { .. }
+            | Self::RefreshFailed { .. }
+            | Self::AccountDisabled { .. }
+            | Self::ConfigUpdated { .. } => None,
+        }
+    }
+}
+
+/// Sending half of the event channel, shared by the manager's components
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<TokenManagerEvent>,
+}
+
+impl EventBus {
+    /// Create a channel buffering `capacity` events per subscriber
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// Subscribe to events sent from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<TokenManagerEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Send an event; without subscribers it is dropped
+    pub fn emit(&self, event: TokenManagerEvent) {
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(EVENT_BUFFER_SIZE)
+    }
+}
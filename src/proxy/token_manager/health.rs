@@ -0,0 +1,191 @@
+//! Account Health Tracking
+//!
+//! Collects per-account request outcomes reported by the proxy handlers.
+//! Keeping failing accounts out of rotation is the scheduler's circuit
+//! breaker's job; these stats are for status output.
+
+use std::collections::HashMap;
+
+use dashmap::DashMap;
+use serde::Serialize;
+
+use crate::proxy::clock::{SharedClock, SystemClock};
+
+/// How a request sent with a selected account turned out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOutcome {
+    /// Upstream answered successfully
+    Success { latency_ms: u64 },
+    /// Upstream failed with a 5xx status
+    Upstream5xx { status: u16 },
+    /// Upstream rejected the access token
+    Unauthorized,
+    /// Upstream rate limited the account
+    ///
+    /// `retry_after` (seconds), when known, marks the account as limited;
+    /// without it an account not limited yet gets the default 429
+    /// cooldown. Handlers that already call `mark_rate_limited` with the
+    /// error body should pass `None`.
+    RateLimited { retry_after: Option<u64> },
+}
+
+impl RequestOutcome {
+    /// Classify a failed upstream status, if it says anything about the account
+    pub fn from_error_status(status: u16) -> Option<Self> {
+        match status {
+            401 => Some(Self::Unauthorized),
+            429 => Some(Self::RateLimited { retry_after: None }),
+            500..=599 => Some(Self::Upstream5xx { status }),
+            _ => None,
+        }
+    }
+}
+
+/// Request counters for one account
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AccountStats {
+    pub successes: u64,
+    pub server_errors: u64,
+    pub unauthorized: u64,
+    pub rate_limited: u64,
+    /// Upstream 5xx errors since the last success
+    pub consecutive_server_errors: u32,
+    pub last_latency_ms: Option<u64>,
+    pub last_success_at: Option<i64>,
+    pub last_failure_at: Option<i64>,
+}
+
+/// Per-account outcome statistics
+pub struct HealthTracker {
+    stats: DashMap<String, AccountStats>,
+    clock: SharedClock,
+}
+
+impl HealthTracker {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock::shared())
+    }
+
+    /// Create an empty tracker reading the time from `clock`
+    pub fn with_clock(clock: SharedClock) -> Self {
+        Self {
+            stats: DashMap::new(),
+            clock,
+        }
+    }
+
+    /// Record a request outcome
+    pub fn record(&self, account_id: &str, outcome: RequestOutcome) {
+        self.record_at(account_id, outcome, self.clock.now())
+    }
+
+    /// Record a request outcome as of `now`
+    pub fn record_at(&self, account_id: &str, outcome: RequestOutcome, now: i64) {
+        let mut stats = self.stats.entry(account_id.to_string()).or_default();
+
+        match outcome {
+            RequestOutcome::Success { latency_ms } => {
+                stats.successes += 1;
+                stats.consecutive_server_errors = 0;
+                stats.last_latency_ms = Some(latency_ms);
+                stats.last_success_at = Some(now);
+            }
+            RequestOutcome::Upstream5xx { .. } => {
+                stats.server_errors += 1;
+                stats.consecutive_server_errors += 1;
+                stats.last_failure_at = Some(now);
+            }
+            RequestOutcome::Unauthorized => {
+                stats.unauthorized += 1;
+                stats.last_failure_at = Some(now);
+            }
+            RequestOutcome::RateLimited { .. } => {
+                stats.rate_limited += 1;
+                stats.last_failure_at = Some(now);
+            }
+        }
+    }
+
+    /// Get the stats for one account
+    pub fn stats(&self, account_id: &str) -> Option<AccountStats> {
+        self.stats.get(account_id).map(|s| s.clone())
+    }
+
+    /// Get the stats for every account that has reported an outcome
+    pub fn snapshot(&self) -> HashMap<String, AccountStats> {
+        self.stats
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect()
+    }
+
+    /// Forget an account's stats
+    pub fn remove(&self, account_id: &str) {
+        self.stats.remove(account_id);
+    }
+}
+
+impl Default for HealthTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let tracker = HealthTracker::new();
+        tracker.record("a", RequestOutcome::Success { latency_ms: 120 });
+        tracker.record("a", RequestOutcome::Upstream5xx { status: 503 });
+        tracker.record("a", RequestOutcome::Unauthorized);
+        tracker.record("a", RequestOutcome::RateLimited { retry_after: None });
+
+        let stats = tracker.stats("a").unwrap();
+        assert_eq!(stats.successes, 1);
+        assert_eq!(stats.server_errors, 1);
+        assert_eq!(stats.unauthorized, 1);
+        assert_eq!(stats.rate_limited, 1);
+        assert_eq!(stats.last_latency_ms, Some(120));
+        assert!(tracker.stats("b").is_none());
+    }
+
+    #[test]
+    fn test_success_resets_consecutive_server_errors() {
+        let tracker = HealthTracker::new();
+        let now = chrono::Utc::now().timestamp();
+
+        for _ in 0..3 {
+            tracker.record_at("a", RequestOutcome::Upstream5xx { status: 500 }, now);
+        }
+        let stats = tracker.stats("a").unwrap();
+        assert_eq!(stats.consecutive_server_errors, 3);
+        assert_eq!(stats.last_failure_at, Some(now));
+
+        tracker.record_at("a", RequestOutcome::Success { latency_ms: 10 }, now + 1);
+        let stats = tracker.stats("a").unwrap();
+        assert_eq!(stats.consecutive_server_errors, 0);
+        assert_eq!(stats.server_errors, 3);
+        assert_eq!(stats.last_success_at, Some(now + 1));
+    }
+
+    #[test]
+    fn test_classify_error_status() {
+        assert_eq!(
+            RequestOutcome::from_error_status(401),
+            Some(RequestOutcome::Unauthorized)
+        );
+        assert_eq!(
+            RequestOutcome::from_error_status(429),
+            Some(RequestOutcome::RateLimited { retry_after: None })
+        );
+        assert_eq!(
+            RequestOutcome::from_error_status(503),
+            Some(RequestOutcome::Upstream5xx { status: 503 })
+        );
+        assert_eq!(RequestOutcome::from_error_status(400), None);
+    }
+}
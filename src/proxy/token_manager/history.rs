@@ -0,0 +1,204 @@
+//! Event History
+//!
+//! The last events of the token manager, kept in memory so questions like
+//! "why did this session switch accounts?" can be answered without trawling
+//! logs. Recording holds a mutex only to push onto the buffer; once it
+//! holds `capacity` events the oldest one is dropped.
+//!
+//! Events never carry access or refresh tokens, so neither does the history.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use super::events::TokenManagerEvent;
+
+/// Events kept by default
+pub const HISTORY_CAPACITY: usize = 2000;
+
+/// An event with the time it happened
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecordedEvent {
+    /// Unix timestamp in milliseconds
+    pub at_ms: i64,
+    #[serde(flatten)]
+    pub event: TokenManagerEvent,
+}
+
+/// Which recorded events to return; empty fields match everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    pub account_id: Option<String>,
+    pub scope_group: Option<String>,
+}
+
+impl EventFilter {
+    /// Check whether an event concerns the filtered account and scope group
+    pub fn matches(&self, event: &TokenManagerEvent) -> bool {
+        let account_matches = self
+            .account_id
+            .as_deref()
+            .is_none_or(|id| event.account_id() == Some(id));
+        let scope_matches = self
+            .scope_group
+            .as_deref()
+            .is_none_or(|group| event.scope_group() == Some(group));
+        account_matches && scope_matches
+    }
+}
+
+/// Bounded buffer of the most recent events, oldest first
+pub struct EventHistory {
+    events: Mutex<VecDeque<RecordedEvent>>,
+    capacity: usize,
+}
+
+impl EventHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Record an event that happened at `at_ms`
+    pub fn record(&self, event: TokenManagerEvent, at_ms: i64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(RecordedEvent { at_ms, event });
+    }
+
+    /// The last `limit` events matching `filter`, oldest first
+    pub fn recent(&self, limit: usize, filter: &EventFilter) -> Vec<RecordedEvent> {
+        let events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        let mut recent: Vec<RecordedEvent> = events
+            .iter()
+            .rev()
+            .filter(|recorded| filter.matches(&recorded.event))
+            .take(limit)
+            .cloned()
+            .collect();
+        recent.reverse();
+        recent
+    }
+}
+
+impl Default for EventHistory {
+    fn default() -> Self {
+        Self::new(HISTORY_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limited(account_id: &str, scope_group: &str) -> TokenManagerEvent {
+        TokenManagerEvent::AccountRateLimited {
+            account_id: account_id.to_string(),
+            scope_group: scope_group.to_string(),
+            remaining_seconds: 60,
+            status: Some(429),
+        }
+    }
+
+    #[test]
+    fn test_oldest_events_are_evicted() {
+        let history = EventHistory::new(3);
+        for (i, account_id) in ["a", "b", "c", "d"].iter().enumerate() {
+            history.record(rate_limited(account_id, "claude"), i as i64);
+        }
+
+        let recent = history.recent(10, &EventFilter::default());
+        assert_eq!(
+            recent.iter().map(|r| r.at_ms).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(recent[0].event.account_id(), Some("b"));
+
+        // A limit keeps the newest events, still oldest first
+        let last_two = history.recent(2, &EventFilter::default());
+        assert_eq!(
+            last_two.iter().map(|r| r.at_ms).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn test_filter_by_account_and_scope_group() {
+        let history = EventHistory::new(10);
+        history.record(rate_limited("a", "claude"), 1);
+        history.record(rate_limited("b", "claude"), 2);
+        history.record(rate_limited("a", "gemini"), 3);
+        history.record(
+            TokenManagerEvent::ConfigUpdated {
+                group: "claude".to_string(),
+            },
+            4,
+        );
+
+        let by_account = EventFilter {
+            account_id: Some("a".to_string()),
+            ..EventFilter::default()
+        };
+        assert_eq!(
+            history
+                .recent(10, &by_account)
+                .iter()
+                .map(|r| r.at_ms)
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+
+        let by_scope = EventFilter {
+            scope_group: Some("claude".to_string()),
+            ..EventFilter::default()
+        };
+        assert_eq!(
+            history
+                .recent(10, &by_scope)
+                .iter()
+                .map(|r| r.at_ms)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        let both = EventFilter {
+            account_id: Some("a".to_string()),
+            scope_group: Some("gemini".to_string()),
+        };
+        assert_eq!(
+            history
+                .recent(10, &both)
+                .iter()
+                .map(|r| r.at_ms)
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn test_recorded_event_serialization() {
+        let recorded = RecordedEvent {
+            at_ms: 1_700_000_000_000,
+            event: rate_limited("a", "claude"),
+        };
+        assert_eq!(
+            serde_json::to_value(&recorded).unwrap(),
+            serde_json::json!({
+                "at_ms": 1_700_000_000_000i64,
+                "type": "account_rate_limited",
+                "account_id": "a",
+                "scope_group": "claude",
+                "remaining_seconds": 60,
+                "status": 429,
+            })
+        );
+    }
+}
@@ -0,0 +1,819 @@
+//! Token Manager Metrics
+//!
+//! Hand-rolled counters and a latency histogram, rendered in the Prometheus
+//! text exposition format. Counters are keyed by account id and fed from the
+//! manager's events; the account label is only chosen when a snapshot is
+//! taken, so switching to hashed emails never splits a series mid-flight.
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::Duration;
+
+use super::events::TokenManagerEvent;
+
+/// Upper bounds (seconds) of the get_token latency histogram buckets
+pub const GET_TOKEN_LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Scope groups with their own stickiness counters; model-scoped groups
+/// come from client requests, so later ones share [`OTHER_SCOPE_GROUPS`]
+const MAX_STICKINESS_SCOPE_GROUPS: usize = 256;
+
+/// Scope group that counts stickiness past [`MAX_STICKINESS_SCOPE_GROUPS`]
+const OTHER_SCOPE_GROUPS: &str = "other";
+
+/// What the `account` label of a series holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountLabel {
+    /// The account id
+    #[default]
+    AccountId,
+    /// The first 12 hex digits of the SHA-256 of the lowercased email,
+    /// so dashboards never show an address
+    EmailHash,
+}
+
+impl AccountLabel {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::EmailHash,
+            _ => Self::AccountId,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Self::AccountId => 0,
+            Self::EmailHash => 1,
+        }
+    }
+}
+
+/// Hash an email for the `EmailHash` label
+pub fn email_hash(email: &str) -> String {
+    let digest = Sha256::digest(email.to_lowercase().as_bytes());
+    digest
+        .iter()
+        .take(6)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Refresh results of one account
+#[derive(Debug, Default)]
+struct RefreshCounts {
+    successes: AtomicU64,
+    failures: AtomicU64,
+}
+
+/// What became of a session's binding in one selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum StickyOutcome {
+    /// The bound account served the request
+    Hit,
+    /// The bound account was rate limited and another one was used
+    MissRateLimited,
+    /// The bound account left the pool or could not serve the request
+    MissAccountGone,
+    /// The bound account was busy, throttled, on probation or had an open
+    /// circuit
+    MissOther,
+    /// The request waited for the bound account and it served the request
+    Waited { elapsed: Duration },
+    /// The client asked to rotate away from the bound account
+    ForcedRotation,
+}
+
+/// Stickiness counters of one scope group
+#[derive(Debug, Default)]
+struct StickinessCounters {
+    hits: AtomicU64,
+    misses_rate_limited: AtomicU64,
+    misses_account_gone: AtomicU64,
+    misses_other: AtomicU64,
+    waits: AtomicU64,
+    wait_micros: AtomicU64,
+    forced_rotations: AtomicU64,
+}
+
+/// Latency histogram with fixed buckets
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket (not cumulative); the last one is `+Inf`
+    buckets: [AtomicU64; GET_TOKEN_LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = GET_TOKEN_LATENCY_BUCKETS
+            .iter()
+            .position(|&le| seconds <= le)
+            .unwrap_or(GET_TOKEN_LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let buckets = GET_TOKEN_LATENCY_BUCKETS
+            .iter()
+            .copied()
+            .chain(std::iter::once(f64::INFINITY))
+            .zip(&self.buckets)
+            .map(|(le, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                HistogramBucket {
+                    le,
+                    count: cumulative,
+                }
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            sum_seconds: self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+            count: self.count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Live counters of one TokenManager
+#[derive(Debug, Default)]
+pub(super) struct Metrics {
+    /// (account_id, scope_group) -> selections
+    selections: DashMap<(String, String), AtomicU64>,
+    /// (account_id, scope_group) -> rate limits recorded
+    rate_limit_hits: DashMap<(String, String), AtomicU64>,
+    /// account_id -> refresh results
+    refreshes: DashMap<String, RefreshCounts>,
+    get_token_latency: Histogram,
+    account_label: AtomicU8,
+    /// scope_group -> what became of session bindings
+    stickiness: DashMap<String, StickinessCounters>,
+    /// account_id -> `EmailHash` label, hashed on first use
+    email_hashes: DashMap<String, String>,
+}
+
+impl Metrics {
+    /// Count what an event says happened
+    pub(super) fn record(&self, event: &TokenManagerEvent) {
+        match event {
+            TokenManagerEvent::AccountSelected {
+                account_id,
+                scope_group,
+                ..
+            } => {
+                Self::increment(&self.selections, account_id, scope_group);
+            }
+            TokenManagerEvent::AccountRateLimited {
+                account_id,
+                scope_group,
+                ..
+            } => {
+                Self::increment(&self.rate_limit_hits, account_id, scope_group);
+            }
+            TokenManagerEvent::TokenRefreshed { account_id, .. } => {
+                self.refreshes
+                    .entry(account_id.clone())
+                    .or_default()
+                    .successes
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            TokenManagerEvent::RefreshFailed { account_id, .. } => {
+                self.refreshes
+                    .entry(account_id.clone())
+                    .or_default()
+                    .failures
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    fn increment(
+        counters: &DashMap<(String, String), AtomicU64>,
+        account_id: &str,
+        scope_group: &str,
+    ) {
+        counters
+            .entry((account_id.to_string(), scope_group.to_string()))
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count what became of a session's binding in a scope group
+    pub(super) fn record_sticky(&self, scope_group: &str, outcome: StickyOutcome) {
+        let scope_group = if self.stickiness.len() < MAX_STICKINESS_SCOPE_GROUPS
+            || self.stickiness.contains_key(scope_group)
+        {
+            scope_group
+        } else {
+            OTHER_SCOPE_GROUPS
+        };
+        let counters = self.stickiness.entry(scope_group.to_string()).or_default();
+        let counter = match outcome {
+            StickyOutcome::Hit => &counters.hits,
+            StickyOutcome::MissRateLimited => &counters.misses_rate_limited,
+            StickyOutcome::MissAccountGone => &counters.misses_account_gone,
+            StickyOutcome::MissOther => &counters.misses_other,
+            StickyOutcome::Waited { elapsed } => {
+                counters
+                    .wait_micros
+                    .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+                &counters.waits
+            }
+            StickyOutcome::ForcedRotation => &counters.forced_rotations,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Copy the stickiness counters, sorted by scope group
+    pub(super) fn stickiness(&self) -> Vec<StickinessCount> {
+        let mut counts: Vec<StickinessCount> = self
+            .stickiness
+            .iter()
+            .map(|entry| {
+                let c = entry.value();
+                StickinessCount {
+                    scope_group: entry.key().clone(),
+                    hits: c.hits.load(Ordering::Relaxed),
+                    misses_rate_limited: c.misses_rate_limited.load(Ordering::Relaxed),
+                    misses_account_gone: c.misses_account_gone.load(Ordering::Relaxed),
+                    misses_other: c.misses_other.load(Ordering::Relaxed),
+                    waits: c.waits.load(Ordering::Relaxed),
+                    wait_seconds: c.wait_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+                    forced_rotations: c.forced_rotations.load(Ordering::Relaxed),
+                }
+            })
+            .collect();
+        counts.sort_by(|a, b| a.scope_group.cmp(&b.scope_group));
+        counts
+    }
+
+    /// The `EmailHash` label of an account, hashing `email` the first time
+    ///
+    /// Accounts never seen with an email are labelled by their hashed id.
+    pub(super) fn email_label(&self, account_id: &str, email: Option<&str>) -> String {
+        if let Some(hash) = self.email_hashes.get(account_id) {
+            return hash.clone();
+        }
+        match email {
+            Some(email) => self
+                .email_hashes
+                .entry(account_id.to_string())
+                .or_insert_with(|| email_hash(email))
+                .clone(),
+            None => email_hash(account_id),
+        }
+    }
+
+    /// Drop every series of an account that left the pool
+    pub(super) fn remove_account(&self, account_id: &str) {
+        self.selections.retain(|(id, _), _| id != account_id);
+        self.rate_limit_hits.retain(|(id, _), _| id != account_id);
+        self.refreshes.remove(account_id);
+        self.email_hashes.remove(account_id);
+    }
+
+    /// Zero the stickiness counters
+    pub(super) fn reset_stickiness(&self) {
+        self.stickiness.clear();
+    }
+
+    /// Record how long one get_token call took
+    pub(super) fn observe_get_token(&self, elapsed: Duration) {
+        self.get_token_latency.observe(elapsed);
+    }
+
+    pub(super) fn set_account_label(&self, label: AccountLabel) {
+        self.account_label.store(label.as_u8(), Ordering::Relaxed);
+    }
+
+    pub(super) fn account_label(&self) -> AccountLabel {
+        AccountLabel::from_u8(self.account_label.load(Ordering::Relaxed))
+    }
+
+    /// Copy the counters, labelling accounts with `label_for(account_id)`
+    pub(super) fn snapshot(
+        &self,
+        label_for: impl Fn(&str) -> String,
+        healthy_accounts: usize,
+        session_bindings: usize,
+        requests_last_minute: Vec<(String, String, u64)>,
+    ) -> MetricsSnapshot {
+        let scoped = |counters: &DashMap<(String, String), AtomicU64>| {
+            let mut counts: Vec<ScopedCount> = counters
+                .iter()
+                .map(|entry| ScopedCount {
+                    account: label_for(&entry.key().0),
+                    scope_group: entry.key().1.clone(),
+                    count: entry.value().load(Ordering::Relaxed),
+                })
+                .collect();
+            counts.sort_by(|a, b| (&a.account, &a.scope_group).cmp(&(&b.account, &b.scope_group)));
+            counts
+        };
+
+        let mut refreshes: Vec<RefreshCount> = self
+            .refreshes
+            .iter()
+            .map(|entry| RefreshCount {
+                account: label_for(entry.key()),
+                successes: entry.value().successes.load(Ordering::Relaxed),
+                failures: entry.value().failures.load(Ordering::Relaxed),
+            })
+            .collect();
+        refreshes.sort_by(|a, b| a.account.cmp(&b.account));
+
+        let mut requests_last_minute: Vec<ScopedCount> = requests_last_minute
+            .into_iter()
+            .map(|(account_id, scope_group, count)| ScopedCount {
+                account: label_for(&account_id),
+                scope_group,
+                count,
+            })
+            .collect();
+        requests_last_minute
+            .sort_by(|a, b| (&a.account, &a.scope_group).cmp(&(&b.account, &b.scope_group)));
+
+        MetricsSnapshot {
+            selections: scoped(&self.selections),
+            rate_limit_hits: scoped(&self.rate_limit_hits),
+            refreshes,
+            healthy_accounts,
+            session_bindings,
+            requests_last_minute,
+            get_token_latency: self.get_token_latency.snapshot(),
+            stickiness: self.stickiness(),
+        }
+    }
+}
+
+/// A counter of one account in one scope group
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScopedCount {
+    pub account: String,
+    pub scope_group: String,
+    pub count: u64,
+}
+
+/// Refresh results of one account
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RefreshCount {
+    pub account: String,
+    pub successes: u64,
+    pub failures: u64,
+}
+
+/// What became of session bindings in one scope group
+///
+/// Only requests whose session was bound count; a session's first request
+/// binds it and counts nowhere.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StickinessCount {
+    pub scope_group: String,
+    /// Requests served by their bound account without waiting
+    pub hits: u64,
+    /// Requests moved off a rate limited bound account
+    pub misses_rate_limited: u64,
+    /// Requests whose bound account left the pool or was paused
+    pub misses_account_gone: u64,
+    /// Requests moved off a busy, throttled or failing bound account
+    pub misses_other: u64,
+    /// Requests that waited for their bound account (CacheFirst)
+    pub waits: u64,
+    /// Seconds spent in those waits
+    pub wait_seconds: f64,
+    /// Requests that asked to rotate away from their bound account
+    pub forced_rotations: u64,
+}
+
+impl StickinessCount {
+    /// Share of bound requests that stayed on their account, waiting or not
+    ///
+    /// `None` before any bound request was counted.
+    pub fn hit_rate(&self) -> Option<f64> {
+        let stayed = self.hits + self.waits;
+        let total = stayed
+            + self.misses_rate_limited
+            + self.misses_account_gone
+            + self.misses_other
+            + self.forced_rotations;
+        (total > 0).then(|| stayed as f64 / total as f64)
+    }
+}
+
+/// How often sessions landed back on their bound account, per scope group
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StickinessReport {
+    pub scope_groups: Vec<StickinessCount>,
+}
+
+impl StickinessReport {
+    /// The counters of one scope group, if any request there was counted
+    pub fn scope_group(&self, scope_group: &str) -> Option<&StickinessCount> {
+        self.scope_groups
+            .iter()
+            .find(|c| c.scope_group == scope_group)
+    }
+}
+
+/// One cumulative histogram bucket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistogramBucket {
+    /// Upper bound in seconds (`inf` for the last bucket)
+    pub le: f64,
+    /// Observations at or below `le`
+    pub count: u64,
+}
+
+/// Copy of a latency histogram
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<HistogramBucket>,
+    pub sum_seconds: f64,
+    pub count: u64,
+}
+
+/// Point-in-time copy of the manager's metrics
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    pub selections: Vec<ScopedCount>,
+    pub rate_limit_hits: Vec<ScopedCount>,
+    pub refreshes: Vec<RefreshCount>,
+    /// Loaded accounts not rate limited anywhere and without an open circuit
+    pub healthy_accounts: usize,
+    /// Live session bindings
+    pub session_bindings: usize,
+    /// Requests per account and scope group over the last minute, as
+    /// counted for `max_rpm`
+    pub requests_last_minute: Vec<ScopedCount>,
+    pub get_token_latency: HistogramSnapshot,
+    /// What became of session bindings per scope group
+    pub stickiness: Vec<StickinessCount>,
+}
+
+impl MetricsSnapshot {
+    /// Render in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "antiproxy_account_selections_total",
+            "counter",
+            "Requests each account was selected for.",
+        );
+        for c in &self.selections {
+            sample(
+                &mut out,
+                "antiproxy_account_selections_total",
+                &[("account", &c.account), ("scope_group", &c.scope_group)],
+                c.count,
+            );
+        }
+
+        header(
+            &mut out,
+            "antiproxy_rate_limit_hits_total",
+            "counter",
+            "Rate limits recorded per account and scope group.",
+        );
+        for c in &self.rate_limit_hits {
+            sample(
+                &mut out,
+                "antiproxy_rate_limit_hits_total",
+                &[("account", &c.account), ("scope_group", &c.scope_group)],
+                c.count,
+            );
+        }
+
+        header(
+            &mut out,
+            "antiproxy_token_refreshes_total",
+            "counter",
+            "OAuth token refreshes by result.",
+        );
+        for r in &self.refreshes {
+            sample(
+                &mut out,
+                "antiproxy_token_refreshes_total",
+                &[("account", &r.account), ("result", "success")],
+                r.successes,
+            );
+            sample(
+                &mut out,
+                "antiproxy_token_refreshes_total",
+                &[("account", &r.account), ("result", "failure")],
+                r.failures,
+            );
+        }
+
+        header(
+            &mut out,
+            "antiproxy_healthy_accounts",
+            "gauge",
+            "Accounts not rate limited anywhere and without an open circuit.",
+        );
+        sample(
+            &mut out,
+            "antiproxy_healthy_accounts",
+            &[],
+            self.healthy_accounts as u64,
+        );
+
+        header(
+            &mut out,
+            "antiproxy_session_bindings",
+            "gauge",
+            "Live session bindings.",
+        );
+        sample(
+            &mut out,
+            "antiproxy_session_bindings",
+            &[],
+            self.session_bindings as u64,
+        );
+
+        header(
+            &mut out,
+            "antiproxy_requests_last_minute",
+            "gauge",
+            "Requests per account and scope group over the last minute.",
+        );
+        for c in &self.requests_last_minute {
+            sample(
+                &mut out,
+                "antiproxy_requests_last_minute",
+                &[("account", &c.account), ("scope_group", &c.scope_group)],
+                c.count,
+            );
+        }
+
+        header(
+            &mut out,
+            "antiproxy_sticky_selections_total",
+            "counter",
+            "Requests of bound sessions by what became of the binding.",
+        );
+        for c in &self.stickiness {
+            for (outcome, count) in [
+                ("hit", c.hits),
+                ("miss_rate_limited", c.misses_rate_limited),
+                ("miss_account_gone", c.misses_account_gone),
+                ("miss_other", c.misses_other),
+                ("waited", c.waits),
+                ("forced_rotation", c.forced_rotations),
+            ] {
+                sample(
+                    &mut out,
+                    "antiproxy_sticky_selections_total",
+                    &[("scope_group", &c.scope_group), ("outcome", outcome)],
+                    count,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "antiproxy_sticky_wait_seconds_total",
+            "counter",
+            "Seconds bound sessions spent waiting for their account.",
+        );
+        for c in &self.stickiness {
+            sample(
+                &mut out,
+                "antiproxy_sticky_wait_seconds_total",
+                &[("scope_group", &c.scope_group)],
+                c.wait_seconds,
+            );
+        }
+
+        let histogram = &self.get_token_latency;
+        header(
+            &mut out,
+            "antiproxy_get_token_duration_seconds",
+            "histogram",
+            "Time spent in get_token.",
+        );
+        for bucket in &histogram.buckets {
+            let le = if bucket.le.is_infinite() {
+                "+Inf".to_string()
+            } else {
+                bucket.le.to_string()
+            };
+            sample(
+                &mut out,
+                "antiproxy_get_token_duration_seconds_bucket",
+                &[("le", &le)],
+                bucket.count,
+            );
+        }
+        let _ = writeln!(
+            out,
+            "antiproxy_get_token_duration_seconds_sum {}",
+            histogram.sum_seconds
+        );
+        sample(
+            &mut out,
+            "antiproxy_get_token_duration_seconds_count",
+            &[],
+            histogram.count,
+        );
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+            .collect();
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+/// Escape a label value per the exposition format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prometheus_text_is_stable() {
+        let metrics = Metrics::default();
+        let selected = |account_id: &str, scope_group: &str| TokenManagerEvent::AccountSelected {
+            account_id: account_id.to_string(),
+            email: format!("{}@test.com", account_id),
+            scope_group: scope_group.to_string(),
+            reason: super::super::types::SelectionReason::Scheduled,
+            session_id: None,
+        };
+        metrics.record(&selected("b", "claude"));
+        metrics.record(&selected("a", "gemini::image_gen"));
+        metrics.record(&selected("a", "claude"));
+        metrics.record(&selected("a", "claude"));
+        metrics.record(&TokenManagerEvent::AccountRateLimited {
+            account_id: "a".to_string(),
+            scope_group: "claude".to_string(),
+            remaining_seconds: 60,
+            status: Some(429),
+        });
+        metrics.record(&TokenManagerEvent::TokenRefreshed {
+            account_id: "a".to_string(),
+            email: "a@test.com".to_string(),
+            expires_at: 0,
+        });
+        metrics.record(&TokenManagerEvent::RefreshFailed {
+            account_id: "b".to_string(),
+            email: "b@test.com".to_string(),
+            error: "boom".to_string(),
+        });
+        metrics.record_sticky("claude", StickyOutcome::Hit);
+        metrics.record_sticky(
+            "claude",
+            StickyOutcome::Waited {
+                elapsed: Duration::from_millis(12_500),
+            },
+        );
+        metrics.observe_get_token(Duration::from_millis(3));
+        metrics.observe_get_token(Duration::from_millis(40));
+        metrics.observe_get_token(Duration::from_secs(30));
+
+        let text = metrics
+            .snapshot(
+                |id| format!("acc\"{}", id),
+                1,
+                2,
+                vec![("a".to_string(), "claude".to_string(), 4)],
+            )
+            .to_prometheus();
+        let expected = r#"# HELP antiproxy_account_selections_total Requests each account was selected for.
+# TYPE antiproxy_account_selections_total counter
+antiproxy_account_selections_total{account="acc\"a",scope_group="claude"} 2
+antiproxy_account_selections_total{account="acc\"a",scope_group="gemini::image_gen"} 1
+antiproxy_account_selections_total{account="acc\"b",scope_group="claude"} 1
+# HELP antiproxy_rate_limit_hits_total Rate limits recorded per account and scope group.
+# TYPE antiproxy_rate_limit_hits_total counter
+antiproxy_rate_limit_hits_total{account="acc\"a",scope_group="claude"} 1
+# HELP antiproxy_token_refreshes_total OAuth token refreshes by result.
+# TYPE antiproxy_token_refreshes_total counter
+antiproxy_token_refreshes_total{account="acc\"a",result="success"} 1
+antiproxy_token_refreshes_total{account="acc\"a",result="failure"} 0
+antiproxy_token_refreshes_total{account="acc\"b",result="success"} 0
+antiproxy_token_refreshes_total{account="acc\"b",result="failure"} 1
+# HELP antiproxy_healthy_accounts Accounts not rate limited anywhere and without an open circuit.
+# TYPE antiproxy_healthy_accounts gauge
+antiproxy_healthy_accounts 1
+# HELP antiproxy_session_bindings Live session bindings.
+# TYPE antiproxy_session_bindings gauge
+antiproxy_session_bindings 2
+# HELP antiproxy_requests_last_minute Requests per account and scope group over the last minute.
+# TYPE antiproxy_requests_last_minute gauge
+antiproxy_requests_last_minute{account="acc\"a",scope_group="claude"} 4
+# HELP antiproxy_sticky_selections_total Requests of bound sessions by what became of the binding.
+# TYPE antiproxy_sticky_selections_total counter
+antiproxy_sticky_selections_total{scope_group="claude",outcome="hit"} 1
+antiproxy_sticky_selections_total{scope_group="claude",outcome="miss_rate_limited"} 0
+antiproxy_sticky_selections_total{scope_group="claude",outcome="miss_account_gone"} 0
+antiproxy_sticky_selections_total{scope_group="claude",outcome="miss_other"} 0
+antiproxy_sticky_selections_total{scope_group="claude",outcome="waited"} 1
+antiproxy_sticky_selections_total{scope_group="claude",outcome="forced_rotation"} 0
+# HELP antiproxy_sticky_wait_seconds_total Seconds bound sessions spent waiting for their account.
+# TYPE antiproxy_sticky_wait_seconds_total counter
+antiproxy_sticky_wait_seconds_total{scope_group="claude"} 12.5
+# HELP antiproxy_get_token_duration_seconds Time spent in get_token.
+# TYPE antiproxy_get_token_duration_seconds histogram
+antiproxy_get_token_duration_seconds_bucket{le="0.005"} 1
+antiproxy_get_token_duration_seconds_bucket{le="0.01"} 1
+antiproxy_get_token_duration_seconds_bucket{le="0.025"} 1
+antiproxy_get_token_duration_seconds_bucket{le="0.05"} 2
+antiproxy_get_token_duration_seconds_bucket{le="0.1"} 2
+antiproxy_get_token_duration_seconds_bucket{le="0.25"} 2
+antiproxy_get_token_duration_seconds_bucket{le="0.5"} 2
+antiproxy_get_token_duration_seconds_bucket{le="1"} 2
+antiproxy_get_token_duration_seconds_bucket{le="2.5"} 2
+antiproxy_get_token_duration_seconds_bucket{le="5"} 2
+antiproxy_get_token_duration_seconds_bucket{le="10"} 2
+antiproxy_get_token_duration_seconds_bucket{le="+Inf"} 3
+antiproxy_get_token_duration_seconds_sum 30.043
+antiproxy_get_token_duration_seconds_count 3
+"#;
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn test_email_hash_is_short_and_case_insensitive() {
+        let hash = email_hash("User@Example.com");
+        assert_eq!(hash.len(), 12);
+        assert_eq!(hash, email_hash("user@example.com"));
+        assert_ne!(hash, email_hash("other@example.com"));
+    }
+
+    #[test]
+    fn test_stickiness_scope_groups_are_bounded() {
+        let metrics = Metrics::default();
+        for i in 0..MAX_STICKINESS_SCOPE_GROUPS + 10 {
+            metrics.record_sticky(&format!("gemini::model-{}", i), StickyOutcome::Hit);
+        }
+        let counts = metrics.stickiness();
+        assert_eq!(counts.len(), MAX_STICKINESS_SCOPE_GROUPS + 1);
+        let other = counts
+            .iter()
+            .find(|c| c.scope_group == OTHER_SCOPE_GROUPS)
+            .unwrap();
+        assert_eq!(other.hits, 10);
+    }
+
+    #[test]
+    fn test_removed_account_loses_its_series() {
+        let metrics = Metrics::default();
+        metrics.record(&TokenManagerEvent::AccountSelected {
+            account_id: "a".to_string(),
+            email: "a@test.com".to_string(),
+            scope_group: "claude".to_string(),
+            reason: super::super::types::SelectionReason::Scheduled,
+            session_id: None,
+        });
+        metrics.record(&TokenManagerEvent::RefreshFailed {
+            account_id: "a".to_string(),
+            email: "a@test.com".to_string(),
+            error: "boom".to_string(),
+        });
+
+        // The first hash sticks even once the email is no longer known
+        let label = metrics.email_label("a", Some("a@test.com"));
+        assert_eq!(label, email_hash("a@test.com"));
+        assert_eq!(metrics.email_label("a", None), label);
+
+        metrics.remove_account("a");
+        let snapshot = metrics.snapshot(|id| id.to_string(), 0, 0, Vec::new());
+        assert!(snapshot.selections.is_empty());
+        assert!(snapshot.refreshes.is_empty());
+        assert_eq!(metrics.email_label("a", None), email_hash("a"));
+    }
+}
@@ -0,0 +1,187 @@
+//! Account File Schema Migrations
+//!
+//! Account files carry a `schema_version`. Files written before versioning
+//! (version 0) come in a few shapes depending on the desktop app release;
+//! [`migrate`] upgrades them in memory to the current shape before the
+//! fields are parsed:
+//!
+//! - token fields at the top level instead of under `token`
+//! - `expires_at` instead of `expiry_timestamp`
+//! - no `expiry_timestamp` at all, derived from `expires_in` and the time
+//!   the file was last written
+//! - `subscription_tier` at the top level instead of under `quota`
+//!
+//! Files from a newer version than this build knows are loaded as they are.
+
+use serde_json::{Map, Value};
+
+/// Schema version written by this build
+pub const CURRENT_SCHEMA_VERSION: u64 = 1;
+
+/// Token fields older files kept at the top level
+const LEGACY_TOKEN_FIELDS: [&str; 6] = [
+    "access_token",
+    "refresh_token",
+    "expires_in",
+    "expiry_timestamp",
+    "expires_at",
+    "project_id",
+];
+
+/// What [`migrate`] did to an account file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Migration {
+    /// Already at the current version
+    Current,
+    /// Upgraded from an older version
+    Upgraded { from: u64 },
+    /// Written by a newer version; left as it is
+    Newer { version: u64 },
+}
+
+/// Upgrade a parsed account file to the current schema
+///
+/// `written_at` is the file's modification time (Unix seconds), used to
+/// work out when a token with only `expires_in` expires.
+pub fn migrate(account: &mut Value, written_at: i64) -> Migration {
+    let Some(object) = account.as_object_mut() else {
+        // Not an object; parsing will report it
+        return Migration::Current;
+    };
+    let version = object
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    if version > CURRENT_SCHEMA_VERSION {
+        return Migration::Newer { version };
+    }
+    if version == CURRENT_SCHEMA_VERSION {
+        return Migration::Current;
+    }
+
+    upgrade_v0(object, written_at);
+    object.insert(
+        "schema_version".to_string(),
+        Value::from(CURRENT_SCHEMA_VERSION),
+    );
+    Migration::Upgraded { from: version }
+}
+
+/// Version 0: the shapes written before `schema_version` existed
+fn upgrade_v0(object: &mut Map<String, Value>, written_at: i64) {
+    let (mut token, had_token) = match object.remove("token") {
+        Some(Value::Object(token)) => (token, true),
+        Some(other) => {
+            // Leave a malformed section for the parser to report
+            object.insert("token".to_string(), other);
+            return;
+        }
+        None => (Map::new(), false),
+    };
+    for field in LEGACY_TOKEN_FIELDS {
+        if let Some(value) = object.remove(field) {
+            token.entry(field).or_insert(value);
+        }
+    }
+
+    if let Some(expires_at) = token.remove("expires_at") {
+        token.entry("expiry_timestamp").or_insert(expires_at);
+    }
+    if !token.contains_key("expiry_timestamp") {
+        if let Some(expires_in) = token.get("expires_in").and_then(Value::as_i64) {
+            token.insert(
+                "expiry_timestamp".to_string(),
+                Value::from(written_at + expires_in),
+            );
+        }
+    }
+    // Without any token fields, leave the parser to report the missing section
+    if had_token || !token.is_empty() {
+        object.insert("token".to_string(), Value::Object(token));
+    }
+
+    if let Some(tier) = object.remove("subscription_tier") {
+        let quota = object
+            .entry("quota")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Some(quota) = quota.as_object_mut() {
+            quota.entry("subscription_tier").or_insert(tier);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::token_manager::types::AccountFile;
+    use serde_json::json;
+
+    const WRITTEN_AT: i64 = 1_700_000_000;
+
+    fn parse(mut value: Value) -> (AccountFile, Migration) {
+        let migration = migrate(&mut value, WRITTEN_AT);
+        (
+            AccountFile::from_json(&value.to_string()).unwrap(),
+            migration,
+        )
+    }
+
+    #[test]
+    fn test_flat_legacy_shape() {
+        let (account, migration) = parse(json!({
+            "id": "a",
+            "email": "a@test.com",
+            "access_token": "token-a",
+            "refresh_token": "refresh-a",
+            "expires_in": 3600,
+            "expires_at": WRITTEN_AT + 600,
+            "subscription_tier": "PRO",
+        }));
+        assert_eq!(migration, Migration::Upgraded { from: 0 });
+        assert_eq!(account.schema_version, Some(CURRENT_SCHEMA_VERSION));
+        assert_eq!(account.token.refresh_token, "refresh-a");
+        assert_eq!(account.token.expiry_timestamp, WRITTEN_AT + 600);
+        assert_eq!(
+            account.quota.unwrap().subscription_tier.as_deref(),
+            Some("PRO")
+        );
+        assert!(account.extra.is_empty());
+    }
+
+    #[test]
+    fn test_missing_expiry_is_derived_from_write_time() {
+        let (account, _) = parse(json!({
+            "id": "a",
+            "email": "a@test.com",
+            "token": {
+                "access_token": "token-a",
+                "refresh_token": "refresh-a",
+                "expires_in": 3599,
+                "project_id": "project-a",
+            },
+            "quota": { "subscription_tier": "ULTRA" },
+        }));
+        assert_eq!(account.token.expiry_timestamp, WRITTEN_AT + 3599);
+        assert_eq!(account.token.project_id.as_deref(), Some("project-a"));
+        assert_eq!(
+            account.quota.unwrap().subscription_tier.as_deref(),
+            Some("ULTRA")
+        );
+    }
+
+    #[test]
+    fn test_current_and_newer_versions_are_left_alone() {
+        let mut current = json!({ "schema_version": CURRENT_SCHEMA_VERSION, "expires_at": 1 });
+        let before = current.clone();
+        assert_eq!(migrate(&mut current, WRITTEN_AT), Migration::Current);
+        assert_eq!(current, before);
+
+        let mut newer = json!({ "schema_version": 99, "token": { "expires_at": 1 } });
+        let before = newer.clone();
+        assert_eq!(
+            migrate(&mut newer, WRITTEN_AT),
+            Migration::Newer { version: 99 }
+        );
+        assert_eq!(newer, before);
+    }
+}
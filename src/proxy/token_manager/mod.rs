@@ -1,24 +1,89 @@
 //! Token Manager Module
-//! 
+//!
 //! Manages OAuth tokens, account rotation, and intelligent scheduling for multi-provider AI proxy.
-//! 
+//!
 //! # Architecture
-//! 
+//!
+//! - `alerts`: Deduplicated alerts to a hook or webhook when the pool degrades
+//! - `breaker`: Per-scope circuit breaker for accounts failing with upstream 5xx
 //! - `core`: TokenManager struct and initialization
-//! - `scheduling`: Account selection algorithms (sticky sessions, round-robin, health-based)
+//! - `crypto`: Optional encryption of account files at rest
+//! - `events`: Typed event stream for subscribers such as the desktop app
+//! - `health`: Per-account request outcome stats and 5xx cooldowns
+//! - `history`: Bounded history of recent events for debugging
+//! - `metrics`: Counters and latency histogram in the Prometheus text format
+//! - `migrations`: Upgrades of older account file shapes
+//! - `oauth_client`: Injectable Google OAuth / project discovery client
+//! - `quota`: Latest quota observations reported by upstream responses
+//! - `redact`: Masking of access and refresh tokens in logs and errors
 //! - `refresh`: OAuth token refresh with concurrent protection
+//! - `scheduling`: Account selection algorithms (sticky sessions, round-robin, health-based)
+//! - `service_account`: Signed JWT assertions for service account accounts
 //! - `session`: Session fingerprinting and sticky account binding
+//! - `storage`: Locked read-modify-write of account files
+//! - `tasks`: Background task handles
+//! - `throttle`: Client-side `max_rpm` request windows
 //! - `types`: Shared data structures
+//! - `usage`: Per-account usage counters written back into account files
+//! - `watcher`: Incremental hot-reload of the accounts directory
+//! - `write_behind`: Debounced, coalesced writes of refreshed tokens
 
+mod alerts;
+mod breaker;
 mod core;
-mod scheduling;
+mod crypto;
+mod events;
+mod health;
+mod history;
+mod metrics;
+mod migrations;
+mod oauth_client;
+mod quota;
+mod redact;
 mod refresh;
+mod scheduling;
+mod service_account;
 mod session;
+mod storage;
+mod tasks;
+mod throttle;
 mod types;
+mod usage;
+mod watcher;
+mod write_behind;
 
 #[cfg(test)]
 mod tests;
 
 // Re-export public API
-pub use core::TokenManager;
-pub use types::{ProxyToken, SelectedToken};
+pub use alerts::{AlertEvent, AlertHook, ALERT_COOLDOWN_SECONDS, WEBHOOK_RETRIES};
+pub use breaker::{BreakerState, ScopeBreaker};
+pub use core::{SelectionHook, TokenManager, DEFAULT_READINESS_RATE_LIMIT_SECONDS};
+pub use crypto::{encrypt_account_file, AccountKey, ACCOUNT_KEY_ENV, ENCRYPTED_HEADER};
+pub use events::{TokenManagerEvent, EVENT_BUFFER_SIZE};
+pub use health::{AccountStats, RequestOutcome};
+pub use history::{EventFilter, RecordedEvent, HISTORY_CAPACITY};
+pub use metrics::{
+    AccountLabel, HistogramBucket, HistogramSnapshot, MetricsSnapshot, RefreshCount, ScopedCount,
+    StickinessCount, StickinessReport,
+};
+pub use migrations::CURRENT_SCHEMA_VERSION;
+pub use oauth_client::{GoogleOAuthClient, OAuthClient, OAuthError, OAuthErrorKind};
+pub use quota::{QuotaObservation, ScopeQuota};
+pub use redact::{mask_secret, redact_secrets, LogIdentity};
+pub use refresh::{
+    OAuthCallPolicy, RefreshError, RefreshErrorKind, RefreshFailure, TokenResponse,
+    DEFAULT_OAUTH_RETRIES, DEFAULT_OAUTH_TIMEOUT_SECONDS,
+};
+pub use session::{fingerprint_request, tenant_session_id};
+pub use tasks::{BackgroundTask, TaskStatus};
+pub use types::{
+    provider_for, AccountConflict, AccountFile, AccountStatus, AccountTokenError, AddAccountError,
+    AttemptInfo, AttemptOutcome, AuthType, ConflictField, ConversationPrefix, DisabledKind,
+    GetTokenError, GetTokenErrorKind, GetTokenOptions, InFlightGuard, Liveness, LoadReport,
+    OAuthClientCredentials, PendingAccount, PoolHealth, ProxyStats, ProxyToken, QuotaSection,
+    Readiness, RetireOptions, RetireReport, RetiredAccountFile, ScopeRateLimit, SelectedToken,
+    SelectionPreview, SelectionReason, ServiceAccountKey, SessionInfo, TenantPolicy, TierOrder,
+    TokenSection, WarmUpReport, WarmUpResult, DEFAULT_PROVIDERS, DEFAULT_SERVICE_ACCOUNT_TOKEN_URI,
+};
+pub use write_behind::DEFAULT_TOKEN_WRITE_DELAY;
@@ -0,0 +1,160 @@
+//! OAuth Client Abstraction
+//!
+//! The token manager talks to Google only through [`OAuthClient`], so tests
+//! can drive refresh, project discovery and device logins without network
+//! access.
+//!
+//! Calls that run under the OAuth call policy fail with an [`OAuthError`],
+//! whose kind (connect, timeout or HTTP status) decides whether they are
+//! retried.
+
+use async_trait::async_trait;
+
+use super::refresh::TokenResponse;
+use super::types::OAuthClientCredentials;
+use crate::modules::oauth::{self, DeviceCode, DeviceTokenPoll, OAuthEndpoints};
+pub use crate::modules::oauth::{OAuthError, OAuthErrorKind};
+
+/// The Google endpoints the token manager depends on
+#[async_trait]
+pub trait OAuthClient: Send + Sync {
+    /// Exchange a refresh token for a new access token
+    ///
+    /// `client` is the OAuth client the refresh token was issued to, or
+    /// `None` for the global one. `proxy` is the outbound proxy URL to send
+    /// the call through, or `None` for the app's upstream proxy setting.
+    async fn refresh(
+        &self,
+        refresh_token: &str,
+        client: Option<&OAuthClientCredentials>,
+        proxy: Option<&str>,
+    ) -> Result<TokenResponse, OAuthError>;
+
+    /// Exchange a service account's signed JWT assertion at `token_uri` for
+    /// an access token, through `proxy` when set
+    async fn exchange_assertion(
+        &self,
+        _token_uri: &str,
+        _assertion: &str,
+        _proxy: Option<&str>,
+    ) -> Result<TokenResponse, OAuthError> {
+        Err("Service accounts are not supported by this OAuth client"
+            .to_string()
+            .into())
+    }
+
+    /// Resolve the Cloud Code project for an access token, through `proxy`
+    /// when set
+    async fn fetch_project_id(
+        &self,
+        access_token: &str,
+        proxy: Option<&str>,
+    ) -> Result<String, OAuthError>;
+
+    /// Start a device authorization for a new account
+    async fn request_device_code(&self) -> Result<DeviceCode, String> {
+        Err("Device authorization is not supported by this OAuth client".to_string())
+    }
+
+    /// Poll a device authorization once
+    async fn poll_device_token(&self, _device_code: &str) -> Result<DeviceTokenPoll, String> {
+        Err("Device authorization is not supported by this OAuth client".to_string())
+    }
+
+    /// Look up the email of the account an access token belongs to
+    async fn fetch_email(&self, _access_token: &str) -> Result<String, String> {
+        Err("Userinfo is not supported by this OAuth client".to_string())
+    }
+
+    /// Revoke a token at Google, through `proxy` when set
+    ///
+    /// A token that is already revoked counts as revoked.
+    async fn revoke(&self, _token: &str, _proxy: Option<&str>) -> Result<(), OAuthError> {
+        Err("Revocation is not supported by this OAuth client"
+            .to_string()
+            .into())
+    }
+}
+
+/// Default client backed by the real Google APIs
+pub struct GoogleOAuthClient {
+    endpoints: OAuthEndpoints,
+}
+
+impl GoogleOAuthClient {
+    pub fn new() -> Self {
+        Self::with_endpoints(OAuthEndpoints::default())
+    }
+
+    /// Create a client sending OAuth calls to other endpoints, e.g. a mock
+    /// server
+    pub fn with_endpoints(endpoints: OAuthEndpoints) -> Self {
+        Self { endpoints }
+    }
+}
+
+impl Default for GoogleOAuthClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl OAuthClient for GoogleOAuthClient {
+    async fn refresh(
+        &self,
+        refresh_token: &str,
+        client: Option<&OAuthClientCredentials>,
+        proxy: Option<&str>,
+    ) -> Result<TokenResponse, OAuthError> {
+        let client = client.map(|c| (c.client_id.as_str(), c.client_secret.as_deref()));
+        oauth::refresh_access_token_with(&self.endpoints, client, proxy, refresh_token)
+            .await
+            .map(|response| TokenResponse {
+                access_token: response.access_token,
+                expires_in: response.expires_in,
+                refresh_token: response.refresh_token,
+            })
+    }
+
+    async fn exchange_assertion(
+        &self,
+        token_uri: &str,
+        assertion: &str,
+        proxy: Option<&str>,
+    ) -> Result<TokenResponse, OAuthError> {
+        oauth::exchange_jwt_assertion(token_uri, proxy, assertion)
+            .await
+            .map(|response| TokenResponse {
+                access_token: response.access_token,
+                expires_in: response.expires_in,
+                refresh_token: response.refresh_token,
+            })
+    }
+
+    async fn fetch_project_id(
+        &self,
+        access_token: &str,
+        proxy: Option<&str>,
+    ) -> Result<String, OAuthError> {
+        crate::proxy::project_resolver::fetch_project_id_via(access_token, proxy).await
+    }
+
+    async fn request_device_code(&self) -> Result<DeviceCode, String> {
+        oauth::request_device_code(&self.endpoints).await
+    }
+
+    async fn poll_device_token(&self, device_code: &str) -> Result<DeviceTokenPoll, String> {
+        oauth::poll_device_token(&self.endpoints, device_code).await
+    }
+
+    async fn fetch_email(&self, access_token: &str) -> Result<String, String> {
+        oauth::get_user_info_from(&self.endpoints.userinfo_url, access_token)
+            .await
+            .map(|info| info.email)
+    }
+
+    async fn revoke(&self, token: &str, proxy: Option<&str>) -> Result<(), OAuthError> {
+        oauth::revoke_token_with(&self.endpoints, proxy, token).await
+    }
+}
@@ -0,0 +1,183 @@
+//! Observed Quota
+//!
+//! Upstream responses can tell how much of an account's quota is left, e.g.
+//! through rate-limit headers or usage metadata. The proxy layer reports
+//! what it saw after each response, and the latest observation per account
+//! and scope group is kept here. With `prefer_most_remaining_quota` the
+//! scheduler leans on it to pick the account with the most headroom instead
+//! of finding limits through 429s.
+//!
+//! An observation only holds until its `reset_at`; after that the quota has
+//! been refilled and the observation is ignored.
+
+use std::collections::HashSet;
+
+use dashmap::DashMap;
+use serde::Serialize;
+
+/// What an upstream response said about an account's quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct QuotaObservation {
+    /// Requests or units left until the reset
+    pub remaining: Option<u64>,
+    /// Size of the quota
+    pub limit: Option<u64>,
+    /// Unix timestamp at which the quota is refilled
+    pub reset_at: Option<i64>,
+    /// Unix timestamp of the response that reported it
+    pub observed_at: i64,
+}
+
+impl QuotaObservation {
+    /// Check whether the quota has been refilled since the observation
+    pub fn is_stale_at(&self, now: i64) -> bool {
+        self.reset_at.is_some_and(|reset_at| reset_at <= now)
+    }
+}
+
+/// The latest quota observation of an account in one scope group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScopeQuota {
+    pub scope_group: String,
+    #[serde(flatten)]
+    pub observation: QuotaObservation,
+}
+
+/// Latest quota observations, keyed by `scope_group::account_id`
+pub struct QuotaTracker {
+    observations: DashMap<String, QuotaObservation>,
+}
+
+impl QuotaTracker {
+    pub fn new() -> Self {
+        Self {
+            observations: DashMap::new(),
+        }
+    }
+
+    fn key(scope_group: &str, account_id: &str) -> String {
+        format!("{}::{}", scope_group, account_id)
+    }
+
+    /// Replace an account's observation in a scope group
+    pub fn record(&self, scope_group: &str, account_id: &str, observation: QuotaObservation) {
+        self.observations
+            .insert(Self::key(scope_group, account_id), observation);
+    }
+
+    /// An account's observation in a scope group, unless the quota has been
+    /// refilled since
+    pub fn get_at(
+        &self,
+        scope_group: &str,
+        account_id: &str,
+        now: i64,
+    ) -> Option<QuotaObservation> {
+        self.observations
+            .get(&Self::key(scope_group, account_id))
+            .map(|o| *o)
+            .filter(|o| !o.is_stale_at(now))
+    }
+
+    /// Quota an account has left in a scope group, if a live observation says
+    pub fn remaining_at(&self, scope_group: &str, account_id: &str, now: i64) -> Option<u64> {
+        self.get_at(scope_group, account_id, now)?.remaining
+    }
+
+    /// List the account's live observations, sorted by scope group
+    pub fn for_account_at(&self, account_id: &str, now: i64) -> Vec<ScopeQuota> {
+        let suffix = format!("::{}", account_id);
+        let mut quotas: Vec<ScopeQuota> = self
+            .observations
+            .iter()
+            .filter(|entry| !entry.value().is_stale_at(now))
+            .filter_map(|entry| {
+                let scope_group = entry.key().strip_suffix(&suffix)?;
+                Some(ScopeQuota {
+                    scope_group: scope_group.to_string(),
+                    observation: *entry.value(),
+                })
+            })
+            .collect();
+        quotas.sort_by(|a, b| a.scope_group.cmp(&b.scope_group));
+        quotas
+    }
+
+    /// Forget every observation of an account
+    pub fn remove_account(&self, account_id: &str) {
+        let suffix = format!("::{}", account_id);
+        self.observations.retain(|key, _| !key.ends_with(&suffix));
+    }
+
+    /// Forget the observations of accounts outside `account_ids` and the
+    /// ones whose quota has been refilled
+    pub fn cleanup(&self, account_ids: &HashSet<String>, now: i64) {
+        self.observations.retain(|key, observation| {
+            !observation.is_stale_at(now)
+                && key
+                    .rsplit_once("::")
+                    .is_some_and(|(_, account_id)| account_ids.contains(account_id))
+        });
+    }
+}
+
+impl Default for QuotaTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn observed(remaining: u64, reset_at: Option<i64>) -> QuotaObservation {
+        QuotaObservation {
+            remaining: Some(remaining),
+            limit: Some(1000),
+            reset_at,
+            observed_at: NOW,
+        }
+    }
+
+    #[test]
+    fn test_observation_is_ignored_after_reset() {
+        let tracker = QuotaTracker::new();
+        tracker.record("gemini", "a", observed(10, Some(NOW + 60)));
+        tracker.record("gemini", "b", observed(20, None));
+
+        assert_eq!(tracker.remaining_at("gemini", "a", NOW), Some(10));
+        assert_eq!(tracker.remaining_at("gemini", "a", NOW + 60), None);
+        assert_eq!(tracker.remaining_at("claude", "a", NOW), None);
+        // Without a reset time the observation holds until replaced
+        assert_eq!(tracker.remaining_at("gemini", "b", NOW + 86_400), Some(20));
+
+        tracker.record("gemini", "a", observed(5, Some(NOW + 120)));
+        assert_eq!(tracker.remaining_at("gemini", "a", NOW + 60), Some(5));
+    }
+
+    #[test]
+    fn test_observations_per_account_and_cleanup() {
+        let tracker = QuotaTracker::new();
+        tracker.record("gemini", "a", observed(10, Some(NOW + 60)));
+        tracker.record("claude", "a", observed(30, None));
+        tracker.record("gemini::image_gen", "a", observed(1, Some(NOW - 1)));
+        tracker.record("gemini", "b", observed(20, None));
+
+        let scopes: Vec<String> = tracker
+            .for_account_at("a", NOW)
+            .into_iter()
+            .map(|q| q.scope_group)
+            .collect();
+        assert_eq!(scopes, vec!["claude", "gemini"]);
+
+        tracker.cleanup(&HashSet::from(["a".to_string()]), NOW + 60);
+        assert_eq!(tracker.for_account_at("a", NOW).len(), 1);
+        assert!(tracker.for_account_at("b", NOW).is_empty());
+
+        tracker.remove_account("a");
+        assert!(tracker.for_account_at("a", NOW).is_empty());
+    }
+}
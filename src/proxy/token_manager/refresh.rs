@@ -1,35 +1,357 @@
 //! OAuth Token Refresh Module
-//! 
+//!
 //! Handles token refresh with concurrent protection to prevent
 //! multiple simultaneous refreshes for the same account.
 
 use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 
-use super::types::ProxyToken;
+use super::oauth_client::{GoogleOAuthClient, OAuthClient, OAuthError, OAuthErrorKind};
+use super::redact::redact_secrets;
+use super::service_account::build_assertion;
+use super::storage::AccountFileStore;
+use super::types::{DisabledKind, ProxyToken};
+use super::write_behind::TokenWriter;
+use crate::proxy::clock::{SharedClock, SystemClock};
+
+/// OAuth error codes that will never succeed on retry
+///
+/// See RFC 6749 section 5.2 and Google's OAuth error reference.
+const PERMANENT_ERROR_CODES: &[&str] = &[
+    "invalid_grant",
+    "invalid_client",
+    "unauthorized_client",
+    "access_denied",
+    "deleted_client",
+    "disabled_client",
+    "org_internal",
+];
+
+/// OAuth error codes worth retrying later
+const TEMPORARY_ERROR_CODES: &[&str] = &[
+    "temporarily_unavailable",
+    "server_error",
+    "internal_failure",
+    "rate_limit_exceeded",
+];
+
+/// Permanent OAuth error codes Google has also returned during outages
+///
+/// Accounts disabled for these are retried after a cooldown.
+const REVIVABLE_ERROR_CODES: &[&str] = &["invalid_grant"];
+
+/// Free-text signs of an account that is gone for good
+///
+/// A service account key Google rejects, or one that cannot sign at all,
+/// won't start working later either.
+const PERMANENT_ERROR_PHRASES: &[&str] = &[
+    "account has been deleted",
+    "account has been disabled",
+    "account is disabled",
+    "invalid jwt signature",
+    "invalid service account private key",
+];
+
+/// Free-text signs of a transient failure (network, throttling, 5xx)
+const TEMPORARY_ERROR_MARKERS: &[&str] = &[
+    "刷新请求失败",
+    "timeout",
+    "timed out",
+    "connection",
+    "network",
+    "temporarily",
+    "rate limit",
+    "rate_limit",
+    "internal error",
+    "502",
+    "503",
+    "504",
+];
+
+/// How a failed token refresh should be treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshErrorKind {
+    /// The grant or client is dead; the account should be disabled
+    Permanent,
+    /// Transient failure; retry after a backoff
+    Temporary,
+    /// Not recognized
+    Unknown,
+}
+
+/// Default seconds an OAuth or project lookup call may take
+pub const DEFAULT_OAUTH_TIMEOUT_SECONDS: u64 = 15;
+
+/// Default retries of an OAuth call that got no answer or a 5xx
+pub const DEFAULT_OAUTH_RETRIES: u32 = 2;
+
+/// Default delay before the first retry; it doubles on every retry
+const DEFAULT_OAUTH_RETRY_BACKOFF_MS: u64 = 500;
+
+/// Timeout and retries of the HTTP calls behind refreshes and project lookups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OAuthCallPolicy {
+    /// Longest a single attempt may take
+    pub timeout: Duration,
+    /// Attempts after the first one, for calls that got no answer or a 5xx
+    pub retries: u32,
+    /// Delay before the first retry, doubled on each further one and
+    /// stretched by up to 100% of random jitter
+    pub retry_backoff: Duration,
+}
+
+impl Default for OAuthCallPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(DEFAULT_OAUTH_TIMEOUT_SECONDS),
+            retries: DEFAULT_OAUTH_RETRIES,
+            retry_backoff: Duration::from_millis(DEFAULT_OAUTH_RETRY_BACKOFF_MS),
+        }
+    }
+}
+
+impl OAuthCallPolicy {
+    /// Run `call` under the timeout, retrying it while it fails retryably
+    ///
+    /// A call that runs out of time fails with an [`OAuthErrorKind::Timeout`]
+    /// error.
+    pub async fn run<T, F, Fut>(&self, mut call: F) -> Result<T, OAuthError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, OAuthError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let error = match tokio::time::timeout(self.timeout, call()).await {
+                Ok(Ok(value)) => return Ok(value),
+                Ok(Err(error)) => error,
+                Err(_) => OAuthError::new(
+                    OAuthErrorKind::Timeout,
+                    format!("OAuth call timed out after {}ms", self.timeout.as_millis()),
+                ),
+            };
+            if attempt >= self.retries || !Self::is_retryable(&error) {
+                return Err(error);
+            }
+            attempt += 1;
+            let backoff = self
+                .retry_backoff
+                .saturating_mul(1 << (attempt - 1).min(16));
+            let jitter = backoff.mul_f64(rand::random::<f64>());
+            tracing::debug!(
+                "OAuth call failed (attempt {}/{}), retrying in {:?}: {}",
+                attempt,
+                self.retries + 1,
+                backoff + jitter,
+                redact_secrets(&error.message)
+            );
+            tokio::time::sleep(backoff + jitter).await;
+        }
+    }
+
+    /// Whether a failed call is worth retrying right away: it got no answer
+    /// (connect error or timeout) or Google answered with a 5xx
+    pub fn is_retryable(error: &OAuthError) -> bool {
+        matches!(
+            error.kind,
+            OAuthErrorKind::Connect | OAuthErrorKind::Timeout | OAuthErrorKind::Status(500..=599)
+        )
+    }
+}
+
+/// Backoff after consecutive temporary refresh failures, capped at the last step
+const REFRESH_BACKOFF_SECONDS: &[i64] = &[5, 30, 120, 600];
+
+/// Consecutive refresh failures of one account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RefreshFailure {
+    /// Failures since the last successful refresh
+    pub failures: u32,
+    /// Unix timestamp of the first failure in the streak
+    pub failing_since: i64,
+    /// Unix timestamp of the most recent failure
+    pub last_failed_at: i64,
+    /// Unix timestamp before which no refresh is attempted
+    pub retry_at: i64,
+    pub last_error: String,
+}
 
 /// OAuth token response from Google
 #[derive(Debug, Clone)]
 pub struct TokenResponse {
     pub access_token: String,
     pub expires_in: i64,
+    /// Set when Google rotated the refresh token
+    pub refresh_token: Option<String>,
+}
+
+/// Why a refresh did not produce a token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefreshError {
+    /// Still waiting out the backoff after earlier failures
+    BackingOff { retry_in: u64 },
+    /// The OAuth call failed
+    Failed {
+        message: String,
+        kind: RefreshErrorKind,
+    },
+}
+
+impl RefreshError {
+    /// How the failure should be treated
+    pub fn kind(&self) -> RefreshErrorKind {
+        match self {
+            Self::BackingOff { .. } => RefreshErrorKind::Temporary,
+            Self::Failed { kind, .. } => *kind,
+        }
+    }
+}
+
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BackingOff { retry_in } => write!(
+                f,
+                "Token refresh is backing off after repeated failures, retry in {}s",
+                retry_in
+            ),
+            Self::Failed { message, .. } => f.write_str(message),
+        }
+    }
+}
+
+/// The newest token obtained for an account
+#[derive(Debug, Clone)]
+struct RefreshedToken {
+    response: TokenResponse,
+    expires_at: i64,
 }
 
 /// Token refresh coordinator with per-account locking
+///
+/// Refreshes are single-flight: the first caller for an account performs the
+/// OAuth call while later callers wait on the account's lock and then reuse
+/// the token it stored.
 pub struct RefreshCoordinator {
     /// Per-account refresh locks to prevent concurrent refreshes
     refresh_locks: Arc<DashMap<String, Arc<Mutex<()>>>>,
+    /// Per-account streaks of temporary refresh failures
+    failures: Arc<DashMap<String, RefreshFailure>>,
+    /// Freshest token obtained per account
+    latest: Arc<DashMap<String, RefreshedToken>>,
+    /// Writes refreshed tokens back into the account files
+    writer: Arc<TokenWriter>,
+    /// Performs the OAuth refresh calls
+    client: Arc<dyn OAuthClient>,
+    /// OAuth refresh calls currently waiting on Google
+    in_progress: Arc<AtomicUsize>,
+    /// Source of the current time for cached tokens and backoff
+    clock: SharedClock,
+    /// Outbound proxy for accounts without one of their own
+    outbound_proxy: std::sync::RwLock<Option<String>>,
+    /// Timeout and retries of each OAuth call
+    call_policy: std::sync::RwLock<OAuthCallPolicy>,
+}
+
+/// Counts one refresh call as in progress until dropped
+struct InProgress<'a>(&'a AtomicUsize);
+
+impl<'a> InProgress<'a> {
+    fn start(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for InProgress<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl RefreshCoordinator {
-    /// Create a new refresh coordinator
+    /// Create a new refresh coordinator talking to Google
     pub fn new() -> Self {
+        Self::with_client(
+            Arc::new(GoogleOAuthClient::new()),
+            Arc::new(AccountFileStore::new()),
+        )
+    }
+
+    /// Create a refresh coordinator with its own OAuth client and a shared file store
+    pub fn with_client(client: Arc<dyn OAuthClient>, files: Arc<AccountFileStore>) -> Self {
+        Self::with_client_and_clock(client, files, SystemClock::shared())
+    }
+
+    /// Create a refresh coordinator with its own OAuth client, a shared file
+    /// store and `clock` for the current time
+    pub fn with_client_and_clock(
+        client: Arc<dyn OAuthClient>,
+        files: Arc<AccountFileStore>,
+        clock: SharedClock,
+    ) -> Self {
         Self {
             refresh_locks: Arc::new(DashMap::new()),
+            failures: Arc::new(DashMap::new()),
+            latest: Arc::new(DashMap::new()),
+            writer: Arc::new(TokenWriter::new(files)),
+            client,
+            in_progress: Arc::new(AtomicUsize::new(0)),
+            clock,
+            outbound_proxy: std::sync::RwLock::new(None),
+            call_policy: std::sync::RwLock::new(OAuthCallPolicy::default()),
         }
     }
 
+    /// Set the timeout and retries of each OAuth call
+    pub fn set_call_policy(&self, policy: OAuthCallPolicy) {
+        *self.call_policy.write().unwrap_or_else(|e| e.into_inner()) = policy;
+    }
+
+    /// Timeout and retries of each OAuth call
+    pub fn call_policy(&self) -> OAuthCallPolicy {
+        *self.call_policy.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Set how long a refreshed token waits before it is written to its file
+    pub fn set_token_write_delay(&self, delay: Duration) {
+        self.writer.set_delay(delay);
+    }
+
+    /// Write every refreshed token still waiting; returns the files written
+    pub async fn flush_token_writes(&self) -> usize {
+        self.writer.flush().await
+    }
+
+    /// Set the outbound proxy for accounts without one of their own
+    pub fn set_outbound_proxy(&self, proxy: Option<String>) {
+        *self
+            .outbound_proxy
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = proxy;
+    }
+
+    /// Outbound proxy for an account: its own, else the default one
+    pub fn outbound_proxy_for(&self, account_proxy: Option<&str>) -> Option<String> {
+        account_proxy.map(str::to_string).or_else(|| {
+            self.outbound_proxy
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone()
+        })
+    }
+
+    /// Whether any account's refresh call is in progress right now
+    pub fn is_refreshing(&self) -> bool {
+        self.in_progress.load(Ordering::SeqCst) > 0
+    }
+
     /// Get or create a refresh lock for an account
     pub fn get_lock(&self, account_id: &str) -> Arc<Mutex<()>> {
         self.refresh_locks
@@ -38,75 +360,310 @@ impl RefreshCoordinator {
             .clone()
     }
 
+    /// Drop the refresh lock and all refresh state for an account that left the pool
+    pub fn remove_lock(&self, account_id: &str) {
+        self.refresh_locks.remove(account_id);
+        self.failures.remove(account_id);
+        self.latest.remove(account_id);
+        self.writer.discard(account_id);
+    }
+
+    /// Drop refresh state for every account not in `retained_account_ids`
+    ///
+    /// Locks held by an in-flight refresh are kept so the refresh can finish
+    /// under them; the next cleanup drops them.
+    pub fn cleanup(&self, retained_account_ids: &HashSet<String>) {
+        self.refresh_locks
+            .retain(|id, lock| retained_account_ids.contains(id) || Arc::strong_count(lock) > 1);
+        self.failures
+            .retain(|id, _| retained_account_ids.contains(id));
+        self.latest
+            .retain(|id, _| retained_account_ids.contains(id));
+        self.writer.retain(retained_account_ids);
+    }
+
+    /// Forget the cached token for an account, e.g. after upstream rejected it
+    pub fn invalidate(&self, account_id: &str) {
+        self.latest.remove(account_id);
+    }
+
+    /// Record a failed refresh and start the next backoff step
+    pub fn record_failure(&self, account_id: &str, error: &str, now: i64) -> RefreshFailure {
+        let mut entry = self
+            .failures
+            .entry(account_id.to_string())
+            .or_insert_with(|| RefreshFailure {
+                failures: 0,
+                failing_since: now,
+                last_failed_at: now,
+                retry_at: now,
+                last_error: String::new(),
+            });
+
+        entry.failures += 1;
+        let step = (entry.failures as usize - 1).min(REFRESH_BACKOFF_SECONDS.len() - 1);
+        entry.last_failed_at = now;
+        entry.retry_at = now + REFRESH_BACKOFF_SECONDS[step];
+        entry.last_error = error.to_string();
+        entry.clone()
+    }
+
+    /// Forget an account's failure streak after a successful refresh
+    pub fn record_success(&self, account_id: &str) {
+        self.failures.remove(account_id);
+    }
+
+    /// Get an account's current failure streak, if any
+    pub fn failure(&self, account_id: &str) -> Option<RefreshFailure> {
+        self.failures.get(account_id).map(|f| f.clone())
+    }
+
+    /// Seconds until an account may try to refresh again, if it is backing off
+    pub fn backoff_remaining(&self, account_id: &str, now: i64) -> Option<u64> {
+        self.failures
+            .get(account_id)
+            .filter(|f| f.retry_at > now)
+            .map(|f| (f.retry_at - now) as u64)
+    }
+
     /// Refresh a token, respecting the lock to prevent concurrent refreshes
-    /// 
-    /// Returns the new token response if refresh was successful,
-    /// or an error message if refresh failed.
-    pub async fn refresh_token(
+    ///
+    /// Callers that waited on another caller's refresh get the token it
+    /// obtained, with `expires_in` counting from now, unless it expires
+    /// within `buffer_secs`.
+    ///
+    /// `token` may be a stale snapshot. Unless this coordinator has seen a
+    /// rotated refresh token, `current` is asked for the one the pool holds
+    /// once the lock is taken.
+    ///
+    /// Service accounts have no refresh token; a fresh JWT assertion signed
+    /// with their key is exchanged instead.
+    pub async fn refresh_token<C>(
+        &self,
+        token: &ProxyToken,
+        buffer_secs: u64,
+        current: C,
+    ) -> Result<TokenResponse, RefreshError>
+    where
+        C: FnOnce() -> Option<String>,
+    {
+        let client = self.client.clone();
+        let credentials = token.oauth_client.clone();
+        let proxy = self.outbound_proxy_for(token.outbound_proxy.as_deref());
+        let policy = self.call_policy();
+        let snapshot = token.refresh_token.clone();
+        if let Some(key) = token.service_account.clone() {
+            let clock = self.clock.clone();
+            return self
+                .refresh_with(token, buffer_secs, |_| async move {
+                    let assertion = build_assertion(&key, clock.now())?;
+                    policy
+                        .run(|| {
+                            client.exchange_assertion(&key.token_uri, &assertion, proxy.as_deref())
+                        })
+                        .await
+                })
+                .await;
+        }
+        // The timeout runs under the account's refresh lock, so a hung call
+        // holds it no longer than the policy allows
+        self.refresh_with(token, buffer_secs, |candidate| async move {
+            let refresh_token = if candidate != snapshot {
+                candidate
+            } else {
+                current().unwrap_or(candidate)
+            };
+            policy
+                .run(|| client.refresh(&refresh_token, credentials.as_ref(), proxy.as_deref()))
+                .await
+        })
+        .await
+    }
+
+    /// Refresh a token through `refresh`, which receives the refresh token
+    ///
+    /// Failures that got no answer or a 5xx are temporary; the others are
+    /// classified by their message.
+    pub async fn refresh_with<F, Fut>(
         &self,
         token: &ProxyToken,
-    ) -> Result<TokenResponse, String> {
-        // Acquire lock for this account
+        buffer_secs: u64,
+        refresh: F,
+    ) -> Result<TokenResponse, RefreshError>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<TokenResponse, OAuthError>>,
+    {
         let lock = self.get_lock(&token.account_id);
         let _guard = lock.lock().await;
 
-        // Check if token still needs refresh (might have been refreshed by another request)
-        if !token.is_expired() {
-            return Err("Token no longer needs refresh".to_string());
+        let now = self.clock.now();
+        if let Some(cached) = self.cached_token(&token.account_id, now, buffer_secs) {
+            return Ok(cached);
         }
 
-        // Call OAuth refresh
-        crate::modules::oauth::refresh_access_token(&token.refresh_token)
+        if let Some(retry_in) = self.backoff_remaining(&token.account_id, now) {
+            return Err(RefreshError::BackingOff { retry_in });
+        }
+
+        let refresh_token = self
+            .latest
+            .get(&token.account_id)
+            .and_then(|t| t.response.refresh_token.clone())
+            .unwrap_or_else(|| token.refresh_token.clone());
+
+        let in_progress = InProgress::start(&self.in_progress);
+        let result = refresh(refresh_token).await;
+        drop(in_progress);
+        let response = match result {
+            Ok(response) => response,
+            Err(error) => {
+                // Error bodies may echo the refresh token that was sent
+                let message = redact_secrets(&error.message);
+                let kind = if OAuthCallPolicy::is_retryable(&error) {
+                    RefreshErrorKind::Temporary
+                } else {
+                    Self::classify_error(&message)
+                };
+                if kind != RefreshErrorKind::Permanent {
+                    let failure = self.record_failure(&token.account_id, &message, now);
+                    tracing::debug!(
+                        "Refresh for {} failed {} time(s), next attempt in {}s",
+                        token.account_id,
+                        failure.failures,
+                        failure.retry_at - now
+                    );
+                }
+                return Err(RefreshError::Failed { message, kind });
+            }
+        };
+        self.record_success(&token.account_id);
+
+        // Cached before saving, so a failed save still leaves the new token
+        // for the next caller while the writer retries the file
+        self.latest.insert(
+            token.account_id.clone(),
+            RefreshedToken {
+                response: response.clone(),
+                expires_at: now + response.expires_in,
+            },
+        );
+        self.writer
+            .save(&token.account_id, &token.account_path, &response, now)
             .await
-            .map(|response| TokenResponse {
-                access_token: response.access_token,
-                expires_in: response.expires_in,
-            })
-            .map_err(|e| e.to_string())
+            .map_err(|e| RefreshError::Failed {
+                message: format!("Failed to save refreshed token: {}", e),
+                kind: RefreshErrorKind::Temporary,
+            })?;
+        Ok(response)
     }
 
-    /// Update a token in storage after refresh
-    pub async fn save_refreshed_token(
-        token: &ProxyToken,
-        response: &TokenResponse,
-    ) -> Result<(), String> {
-        use tokio::task::spawn_blocking;
-
-        let path = token.account_path.clone();
-        let content_str = {
-            let path_clone = path.clone();
-            spawn_blocking(move || std::fs::read_to_string(&path_clone))
-                .await
-                .map_err(|e| format!("Task failed: {}", e))?
-                .map_err(|e| format!("Failed to read file: {}", e))?
+    /// The stored token for an account if it is still outside the expiry buffer
+    fn cached_token(&self, account_id: &str, now: i64, buffer_secs: u64) -> Option<TokenResponse> {
+        let cached = self.latest.get(account_id)?;
+        if cached.expires_at - buffer_secs as i64 <= now {
+            return None;
+        }
+        Some(TokenResponse {
+            expires_in: cached.expires_at - now,
+            ..cached.response.clone()
+        })
+    }
+
+    /// Which kind of disable a refresh error calls for
+    ///
+    /// Only dead clients and deleted or disabled Google accounts disable an
+    /// account permanently; anything else, including `invalid_grant`, leaves
+    /// it eligible for revival after a cooldown.
+    pub fn disabled_kind(error: &str) -> DisabledKind {
+        let text = error.to_lowercase();
+        if PERMANENT_ERROR_PHRASES
+            .iter()
+            .any(|phrase| text.contains(phrase))
+        {
+            return DisabledKind::Permanent;
+        }
+
+        let code = match parse_oauth_error(error) {
+            Some((code, _)) => Some(code),
+            None => PERMANENT_ERROR_CODES
+                .iter()
+                .find(|code| text.contains(*code))
+                .map(|code| code.to_string()),
         };
+        match code {
+            Some(code)
+                if PERMANENT_ERROR_CODES.contains(&code.as_str())
+                    && !REVIVABLE_ERROR_CODES.contains(&code.as_str()) =>
+            {
+                DisabledKind::Permanent
+            }
+            _ => DisabledKind::Temporary,
+        }
+    }
 
-        let mut content: serde_json::Value = serde_json::from_str(&content_str)
-            .map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    /// Classify a refresh error by whether retrying can ever succeed
+    ///
+    /// The OAuth error JSON (`error` + `error_description`) is used when the
+    /// message carries one; otherwise the raw text is matched.
+    pub fn classify_error(error: &str) -> RefreshErrorKind {
+        if let Some((code, description)) = parse_oauth_error(error) {
+            return classify_oauth_error(&code, &description);
+        }
 
-        let now = chrono::Utc::now().timestamp();
+        let text = error.to_lowercase();
+        if PERMANENT_ERROR_CODES.iter().any(|code| text.contains(code)) {
+            // Google reports refresh throttling as invalid_grant too
+            if text.contains("rate limit") {
+                return RefreshErrorKind::Temporary;
+            }
+            return RefreshErrorKind::Permanent;
+        }
+        if PERMANENT_ERROR_PHRASES
+            .iter()
+            .any(|phrase| text.contains(phrase))
+        {
+            return RefreshErrorKind::Permanent;
+        }
+        if TEMPORARY_ERROR_MARKERS
+            .iter()
+            .any(|marker| text.contains(marker))
+        {
+            return RefreshErrorKind::Temporary;
+        }
+        RefreshErrorKind::Unknown
+    }
+}
 
-        content["token"]["access_token"] = serde_json::Value::String(response.access_token.clone());
-        content["token"]["expires_in"] = serde_json::Value::Number(response.expires_in.into());
-        content["token"]["expiry_timestamp"] = serde_json::Value::Number((now + response.expires_in).into());
+/// Extract `error` and `error_description` from an OAuth error JSON body
+/// embedded anywhere in the message
+fn parse_oauth_error(error: &str) -> Option<(String, String)> {
+    let start = error.find('{')?;
+    let value: serde_json::Value = serde_json::Deserializer::from_str(&error[start..])
+        .into_iter()
+        .next()?
+        .ok()?;
 
-        let json_str = serde_json::to_string_pretty(&content)
-            .map_err(|e| format!("Failed to serialize JSON: {}", e))?;
-        
-        let path_clone = path.clone();
-        spawn_blocking(move || std::fs::write(&path_clone, json_str))
-            .await
-            .map_err(|e| format!("Task failed: {}", e))?
-            .map_err(|e| format!("Failed to write file: {}", e))?;
+    let code = value.get("error")?.as_str()?.to_lowercase();
+    let description = value
+        .get("error_description")
+        .and_then(|d| d.as_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    Some((code, description))
+}
 
-        tracing::debug!("Saved refreshed token for account {}", token.account_id);
-        Ok(())
+fn classify_oauth_error(code: &str, description: &str) -> RefreshErrorKind {
+    if code == "invalid_grant" && description.contains("rate limit") {
+        return RefreshErrorKind::Temporary;
     }
-
-    /// Check if a refresh error indicates the account should be disabled
-    pub fn is_permanent_error(error: &str) -> bool {
-        error.contains("\"invalid_grant\"") || error.contains("invalid_grant")
+    if PERMANENT_ERROR_CODES.contains(&code) {
+        return RefreshErrorKind::Permanent;
     }
+    if TEMPORARY_ERROR_CODES.contains(&code) {
+        return RefreshErrorKind::Temporary;
+    }
+    RefreshErrorKind::Unknown
 }
 
 impl Default for RefreshCoordinator {
@@ -118,6 +675,7 @@ impl Default for RefreshCoordinator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proxy::token_manager::types::DEFAULT_EXPIRY_BUFFER_SECONDS;
     use std::path::PathBuf;
 
     fn create_test_token() -> ProxyToken {
@@ -132,30 +690,469 @@ mod tests {
             account_path: PathBuf::from("/tmp/test-account.json"),
             project_id: Some("project-123".to_string()),
             subscription_tier: Some("PRO".to_string()),
+            proxy_weight: 1.0,
+            max_concurrent: None,
+            max_rpm: None,
+            tags: Vec::new(),
+            source_dir: None,
+            oauth_client: None,
+            outbound_proxy: None,
+            service_account: None,
+            providers: vec!["gemini".to_string(), "claude".to_string()],
+            base_url_override: None,
         }
     }
 
+    #[tokio::test]
+    async fn test_call_policy_retries_only_retryable_errors() {
+        let policy = OAuthCallPolicy {
+            timeout: Duration::from_millis(100),
+            retries: 2,
+            retry_backoff: Duration::from_millis(1),
+        };
+        let attempts = AtomicUsize::new(0);
+
+        // Connect errors and 5xx are retried until one succeeds
+        let result = policy
+            .run(|| async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(OAuthError::new(
+                        OAuthErrorKind::Connect,
+                        "刷新请求失败: error sending request",
+                    )),
+                    1 => Err(OAuthError::new(
+                        OAuthErrorKind::Status(503),
+                        "刷新失败 (503 Service Unavailable): ",
+                    )),
+                    _ => Ok("token"),
+                }
+            })
+            .await;
+        assert_eq!(result, Ok("token"));
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+
+        // OAuth errors are answered for good, whatever numbers their body holds
+        let result: Result<(), OAuthError> = policy
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(OAuthError::new(
+                    OAuthErrorKind::Status(400),
+                    r#"刷新失败 (400 Bad Request): {"error": "invalid_grant", "error_description": "500 retries"}"#,
+                ))
+            })
+            .await;
+        assert_eq!(result.unwrap_err().kind, OAuthErrorKind::Status(400));
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        // Errors without a kind are not retried either
+        let result: Result<(), OAuthError> = policy
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err("请求失败: timed out 503".to_string().into())
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        // Hung calls time out on every attempt
+        let result: Result<(), OAuthError> = policy
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                Ok(())
+            })
+            .await;
+        let error = result.unwrap_err();
+        assert_eq!(error.kind, OAuthErrorKind::Timeout);
+        assert!(error.message.contains("timed out"), "{}", error);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
     #[test]
     fn test_lock_creation() {
         let coordinator = RefreshCoordinator::new();
-        
+
         let lock1 = coordinator.get_lock("account-1");
         let lock2 = coordinator.get_lock("account-1");
         let lock3 = coordinator.get_lock("account-2");
 
         // Same account should get the same lock (via Arc pointer comparison)
         assert!(Arc::ptr_eq(&lock1, &lock2));
-        
+
         // Different accounts get different locks
         assert!(!Arc::ptr_eq(&lock1, &lock3));
     }
 
+    #[tokio::test]
+    async fn test_cleanup_drops_departed_accounts_but_not_held_locks() {
+        let coordinator = RefreshCoordinator::new();
+        let lock_a = coordinator.get_lock("account-a");
+        coordinator.get_lock("account-b");
+        coordinator.get_lock("account-c");
+        coordinator.record_failure("account-c", "timeout", 1_700_000_000);
+        assert_eq!(coordinator.refresh_locks.len(), 3);
+
+        let retained: HashSet<String> = ["account-a", "account-b"].map(String::from).into();
+        coordinator.cleanup(&retained);
+        assert_eq!(coordinator.refresh_locks.len(), 2);
+        assert!(coordinator.failure("account-c").is_none());
+        assert!(Arc::ptr_eq(&lock_a, &coordinator.get_lock("account-a")));
+
+        // account-b leaves mid-refresh: its lock stays until the refresh is done
+        let lock_b = coordinator.get_lock("account-b");
+        let guard = lock_b.lock().await;
+        let retained: HashSet<String> = ["account-a"].map(String::from).into();
+        coordinator.cleanup(&retained);
+        assert_eq!(coordinator.refresh_locks.len(), 2);
+        assert!(Arc::ptr_eq(&lock_b, &coordinator.get_lock("account-b")));
+
+        drop(guard);
+        drop(lock_b);
+        coordinator.cleanup(&retained);
+        assert_eq!(coordinator.refresh_locks.len(), 1);
+    }
+
     #[test]
     fn test_permanent_error_detection() {
-        assert!(RefreshCoordinator::is_permanent_error("Error: \"invalid_grant\""));
-        assert!(RefreshCoordinator::is_permanent_error("invalid_grant: token revoked"));
-        assert!(!RefreshCoordinator::is_permanent_error("temporary network error"));
-        assert!(!RefreshCoordinator::is_permanent_error("rate limit exceeded"));
+        assert_eq!(
+            RefreshCoordinator::classify_error("Error: \"invalid_grant\""),
+            RefreshErrorKind::Permanent
+        );
+        assert_eq!(
+            RefreshCoordinator::classify_error("invalid_grant: token revoked"),
+            RefreshErrorKind::Permanent
+        );
+        assert_ne!(
+            RefreshCoordinator::classify_error("temporary network error"),
+            RefreshErrorKind::Permanent
+        );
+        assert_ne!(
+            RefreshCoordinator::classify_error("rate limit exceeded"),
+            RefreshErrorKind::Permanent
+        );
+    }
+
+    #[test]
+    fn test_classify_real_world_errors() {
+        use RefreshErrorKind::*;
+
+        let cases: &[(&str, RefreshErrorKind)] = &[
+            (
+                r#"刷新失败: {"error": "invalid_grant", "error_description": "Token has been expired or revoked."}"#,
+                Permanent,
+            ),
+            (
+                r#"刷新失败: {"error": "invalid_grant", "error_description": "Bad Request"}"#,
+                Permanent,
+            ),
+            (
+                r#"刷新失败: {"error": "invalid_grant", "error_description": "rate limit exceeded"}"#,
+                Temporary,
+            ),
+            (
+                r#"刷新失败: {"error": "unauthorized_client", "error_description": "Unauthorized"}"#,
+                Permanent,
+            ),
+            (
+                r#"刷新失败: {"error": "invalid_client", "error_description": "The OAuth client was not found."}"#,
+                Permanent,
+            ),
+            (
+                r#"刷新失败: {"error": "deleted_client", "error_description": "The OAuth client was deleted."}"#,
+                Permanent,
+            ),
+            (r#"刷新失败: {"error": "access_denied"}"#, Permanent),
+            (
+                r#"刷新失败: {"error": "temporarily_unavailable", "error_description": "Try again later"}"#,
+                Temporary,
+            ),
+            (r#"刷新失败: {"error": "internal_failure"}"#, Temporary),
+            (r#"刷新失败: {"error": "something_new"}"#, Unknown),
+            ("invalid_grant: Token revoked", Permanent),
+            ("Account has been deleted", Permanent),
+            (
+                "刷新请求失败: error sending request for url (https://oauth2.googleapis.com/token)",
+                Temporary,
+            ),
+            ("operation timed out", Temporary),
+            ("刷新失败: <html>502 Bad Gateway</html>", Temporary),
+            ("rate_limit_exceeded", Temporary),
+            ("刷新失败: ", Unknown),
+        ];
+
+        for (error, expected) in cases {
+            assert_eq!(
+                RefreshCoordinator::classify_error(error),
+                *expected,
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_disabled_kind_of_permanent_errors() {
+        use DisabledKind::*;
+
+        let cases: &[(&str, DisabledKind)] = &[
+            (
+                r#"刷新失败: {"error": "invalid_grant", "error_description": "Token has been expired or revoked."}"#,
+                Temporary,
+            ),
+            ("invalid_grant: Token revoked", Temporary),
+            (
+                r#"刷新失败: {"error": "invalid_client", "error_description": "The OAuth client was not found."}"#,
+                Permanent,
+            ),
+            (r#"刷新失败: {"error": "unauthorized_client"}"#, Permanent),
+            (r#"刷新失败: {"error": "access_denied"}"#, Permanent),
+            (
+                r#"刷新失败: {"error": "invalid_grant", "error_description": "Account has been deleted"}"#,
+                Permanent,
+            ),
+            ("Account has been disabled", Permanent),
+            (
+                r#"刷新失败 (400 Bad Request): {"error": "invalid_grant", "error_description": "Invalid JWT Signature."}"#,
+                Permanent,
+            ),
+            (
+                "Invalid service account private key: InvalidEncoding",
+                Permanent,
+            ),
+            ("operation timed out", Temporary),
+        ];
+
+        for (error, expected) in cases {
+            assert_eq!(
+                RefreshCoordinator::disabled_kind(error),
+                *expected,
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_failure_backoff_grows_and_caps() {
+        let coordinator = RefreshCoordinator::new();
+        let now = 1_700_000_000;
+
+        let expected = [5, 30, 120, 600, 600];
+        for (i, delay) in expected.iter().enumerate() {
+            let failure = coordinator.record_failure("account-1", "timeout", now);
+            assert_eq!(failure.failures, i as u32 + 1);
+            assert_eq!(failure.retry_at, now + delay);
+        }
+
+        assert_eq!(coordinator.backoff_remaining("account-1", now), Some(600));
+        assert_eq!(coordinator.backoff_remaining("account-1", now + 600), None);
+        assert_eq!(coordinator.backoff_remaining("account-2", now), None);
+    }
+
+    #[test]
+    fn test_success_clears_failures() {
+        let coordinator = RefreshCoordinator::new();
+        let now = 1_700_000_000;
+
+        coordinator.record_failure("account-1", "timeout", now);
+        coordinator.record_failure("account-1", "503", now + 10);
+        let failure = coordinator.failure("account-1").unwrap();
+        assert_eq!(failure.failing_since, now);
+        assert_eq!(failure.last_error, "503");
+
+        coordinator.record_success("account-1");
+        assert!(coordinator.failure("account-1").is_none());
+
+        // A new streak starts from the first step again
+        let failure = coordinator.record_failure("account-1", "timeout", now + 100);
+        assert_eq!(failure.retry_at, now + 105);
+    }
+
+    #[tokio::test]
+    async fn test_save_rotated_refresh_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let path =
+            crate::proxy::token_manager::tests::write_account_file(dir.path(), "a", Some("PRO"));
+        let before: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+
+        let token = ProxyToken {
+            account_path: path.clone(),
+            ..create_test_token()
+        };
+        let writer = Arc::new(TokenWriter::new(Arc::new(AccountFileStore::new())));
+        writer.set_delay(Duration::ZERO);
+        writer
+            .save(
+                &token.account_id,
+                &token.account_path,
+                &TokenResponse {
+                    access_token: "new-access".to_string(),
+                    expires_in: 3599,
+                    refresh_token: Some("new-refresh".to_string()),
+                },
+                chrono::Utc::now().timestamp(),
+            )
+            .await
+            .unwrap();
+
+        let after: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(after["token"]["refresh_token"], "new-refresh");
+        assert_eq!(after["token"]["access_token"], "new-access");
+        assert_eq!(after["token"]["expires_in"], 3599);
+        assert_eq!(after["token"]["project_id"], before["token"]["project_id"]);
+        assert_eq!(after["email"], before["email"]);
+        assert_eq!(after["quota"], before["quota"]);
+
+        // Without a rotated token the stored one is kept
+        writer
+            .save(
+                &token.account_id,
+                &token.account_path,
+                &TokenResponse {
+                    access_token: "newer-access".to_string(),
+                    expires_in: 3599,
+                    refresh_token: None,
+                },
+                chrono::Utc::now().timestamp(),
+            )
+            .await
+            .unwrap();
+        let after: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(after["token"]["refresh_token"], "new-refresh");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_refreshes_share_one_oauth_call() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path =
+            crate::proxy::token_manager::tests::write_account_file(dir.path(), "a", Some("PRO"));
+        let token = ProxyToken {
+            account_path: path,
+            ..create_test_token()
+        };
+
+        let coordinator = Arc::new(RefreshCoordinator::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut handles = Vec::new();
+        for _ in 0..10 {
+            let coordinator = coordinator.clone();
+            let calls = calls.clone();
+            let token = token.clone();
+            handles.push(tokio::spawn(async move {
+                coordinator
+                    .refresh_with(&token, DEFAULT_EXPIRY_BUFFER_SECONDS, |_| async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        Ok(TokenResponse {
+                            access_token: "new-access".to_string(),
+                            expires_in: 3600,
+                            refresh_token: None,
+                        })
+                    })
+                    .await
+            }));
+        }
+
+        for handle in handles {
+            let response = handle.await.unwrap().unwrap();
+            assert_eq!(response.access_token, "new-access");
+            assert!(response.expires_in > 3500);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Once invalidated, the next caller refreshes again
+        coordinator.invalidate("test-account");
+        let response = coordinator
+            .refresh_with(&token, DEFAULT_EXPIRY_BUFFER_SECONDS, |_| async {
+                Ok(TokenResponse {
+                    access_token: "newer-access".to_string(),
+                    expires_in: 3600,
+                    refresh_token: None,
+                })
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.access_token, "newer-access");
+
+        // A caller needing a longer buffer doesn't accept the cached token
+        let response = coordinator
+            .refresh_with(&token, 4000, |_| async {
+                Ok(TokenResponse {
+                    access_token: "longest-access".to_string(),
+                    expires_in: 7200,
+                    refresh_token: None,
+                })
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.access_token, "longest-access");
+    }
+
+    #[tokio::test]
+    async fn test_failed_save_is_an_error_but_keeps_the_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let token = ProxyToken {
+            account_path: dir.path().join("missing.json"),
+            ..create_test_token()
+        };
+        let coordinator = RefreshCoordinator::new();
+
+        // A rotated refresh token is written at once, so the missing file fails the refresh
+        let err = coordinator
+            .refresh_with(&token, DEFAULT_EXPIRY_BUFFER_SECONDS, |_| async {
+                Ok(TokenResponse {
+                    access_token: "new-access".to_string(),
+                    expires_in: 3600,
+                    refresh_token: Some("new-refresh".to_string()),
+                })
+            })
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Failed to save refreshed token"),
+            "{}",
+            err
+        );
+        assert_eq!(err.kind(), RefreshErrorKind::Temporary);
+
+        let response = coordinator
+            .refresh_with(&token, DEFAULT_EXPIRY_BUFFER_SECONDS, |_| async {
+                panic!("the refreshed token must be reused")
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.access_token, "new-access");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_failure_backs_off() {
+        let coordinator = RefreshCoordinator::new();
+        let token = create_test_token();
+
+        let err = coordinator
+            .refresh_with(&token, DEFAULT_EXPIRY_BUFFER_SECONDS, |_| async {
+                Err(OAuthError::new(
+                    OAuthErrorKind::Timeout,
+                    "刷新请求失败: timeout",
+                ))
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), RefreshErrorKind::Temporary);
+
+        let err = coordinator
+            .refresh_with(&token, DEFAULT_EXPIRY_BUFFER_SECONDS, |_| async {
+                panic!("must not be called while backing off")
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RefreshError::BackingOff { .. }));
     }
 
     #[test]
@@ -1,18 +1,30 @@
 //! Account Scheduling Algorithms
-//! 
+//!
 //! Implements intelligent account selection based on:
 //! - Subscription tier prioritization (ULTRA > PRO > FREE)
-//! - Rate limit avoidance
-//! - Session stickiness
-//! - Round-robin load balancing
+//! - Rate limit avoidance and per-scope circuit breaking on upstream 5xx
+//! - Probation after a rate limit expires: one trial request at a time until
+//!   one succeeds
+//! - Session stickiness, spreading new sessions by live binding count in Balance mode
+//!   and optionally failing over within the bound account's tier
+//! - Round-robin load balancing, weighted within a tier by `proxy_weight`
+//! - Least-recently-used selection
+//! - Least-connections selection based on in-flight requests
+//! - Client-side `max_rpm` throttling per account and scope group
+//! - Optionally, within a tier, the accounts with the most quota left as
+//!   last reported upstream
 
-use std::collections::HashSet;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use dashmap::DashMap;
 
-use super::types::ProxyToken;
+use super::breaker::CircuitBreaker;
+use super::quota::QuotaTracker;
+use super::throttle::RequestThrottle;
+use super::types::{InFlightGuard, ProxyToken, TierOrder};
+use crate::proxy::clock::SharedClock;
 use crate::proxy::rate_limit::RateLimitTracker;
 use crate::proxy::sticky_config::{SchedulingMode, StickySessionConfig};
 
@@ -20,95 +32,639 @@ use crate::proxy::sticky_config::{SchedulingMode, StickySessionConfig};
 #[derive(Debug, Clone)]
 pub enum SchedulingDecision {
     /// Use this account immediately
-    UseAccount(ProxyToken),
+    UseAccount(Arc<ProxyToken>),
     /// Wait for rate limit to clear, then use account
-    WaitAndUse { token: ProxyToken, wait_seconds: u64 },
-    /// All accounts are unavailable
-    AllUnavailable { min_wait_seconds: u64 },
+    WaitAndUse {
+        token: Arc<ProxyToken>,
+        wait_seconds: u64,
+    },
+    /// All accounts are unavailable; `None` when no account has a known
+    /// rate-limit reset or breaker cooldown to wait for
+    AllUnavailable { min_wait_seconds: Option<u64> },
+    /// Every otherwise usable account is at its concurrency ceiling
+    AllBusy,
 }
 
 /// Account scheduler with multiple selection strategies
 pub struct AccountScheduler {
-    /// Round-robin index per quota group
-    round_robin_index: Arc<DashMap<String, Arc<AtomicUsize>>>,
+    /// Tier priority and id of the account each scope group's last
+    /// round-robin pass started from
+    round_robin_cursor: Arc<DashMap<String, (usize, String)>>,
+    /// Smooth weighted round-robin state, keyed by `scope_group::account_id`
+    weighted_current: Mutex<HashMap<String, f64>>,
+    /// Last selection stamp (milliseconds) per account_id
+    last_selected_at: Arc<DashMap<String, i64>>,
+    /// Monotonic source for selection stamps so two picks never share a value
+    selection_clock: AtomicI64,
+    /// In-flight request counters per account_id
+    in_flight: Arc<DashMap<String, Arc<AtomicUsize>>>,
     /// Rate limit tracker reference
     rate_limit_tracker: Arc<RateLimitTracker>,
+    /// Per-scope circuit breakers for accounts failing with upstream 5xx
+    circuit_breaker: CircuitBreaker,
+    /// Requests per account and scope group over the last minute
+    throttle: Arc<RequestThrottle>,
+    /// Latest quota reported upstream per account and scope group
+    quota: Arc<QuotaTracker>,
+    /// Ranking of subscription tiers
+    tier_order: RwLock<TierOrder>,
+    /// Bumped on every sort so each account of a tier takes its turn in front
+    tier_rotation: AtomicUsize,
+    /// Source of the current time for breakers, throttles and selection stamps
+    clock: SharedClock,
 }
 
 impl AccountScheduler {
-    /// Create a new account scheduler
+    /// Create a new account scheduler on the system clock
+    #[cfg(test)]
     pub fn new(rate_limit_tracker: Arc<RateLimitTracker>) -> Self {
+        Self::with_clock(
+            rate_limit_tracker,
+            crate::proxy::clock::SystemClock::shared(),
+        )
+    }
+
+    /// Create a new account scheduler reading the time from `clock`
+    pub fn with_clock(rate_limit_tracker: Arc<RateLimitTracker>, clock: SharedClock) -> Self {
         Self {
-            round_robin_index: Arc::new(DashMap::new()),
+            round_robin_cursor: Arc::new(DashMap::new()),
+            weighted_current: Mutex::new(HashMap::new()),
+            last_selected_at: Arc::new(DashMap::new()),
+            selection_clock: AtomicI64::new(0),
+            in_flight: Arc::new(DashMap::new()),
             rate_limit_tracker,
+            circuit_breaker: CircuitBreaker::new(),
+            throttle: Arc::new(RequestThrottle::new()),
+            quota: Arc::new(QuotaTracker::new()),
+            tier_order: RwLock::new(TierOrder::default()),
+            tier_rotation: AtomicUsize::new(0),
+            clock,
+        }
+    }
+
+    /// Detached copy of the scheduling state for a dry run
+    ///
+    /// Round-robin cursors, weighted totals, selection stamps, breakers and
+    /// the tier rotation are copied, so selecting on the copy peeks at what
+    /// the next real selection would pick without advancing any of them.
+    /// In-flight counters, request throttles, quota observations and rate
+    /// limits are shared;
+    /// selection only reads them.
+    pub fn preview(&self) -> Self {
+        Self {
+            round_robin_cursor: Arc::new(DashMap::clone(&self.round_robin_cursor)),
+            weighted_current: Mutex::new(
+                self.weighted_current
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone(),
+            ),
+            last_selected_at: Arc::new(DashMap::clone(&self.last_selected_at)),
+            selection_clock: AtomicI64::new(self.selection_clock.load(Ordering::SeqCst)),
+            in_flight: self.in_flight.clone(),
+            rate_limit_tracker: self.rate_limit_tracker.clone(),
+            circuit_breaker: self.circuit_breaker.clone(),
+            throttle: self.throttle.clone(),
+            quota: self.quota.clone(),
+            tier_order: RwLock::new(
+                self.tier_order
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone(),
+            ),
+            tier_rotation: AtomicUsize::new(self.tier_rotation.load(Ordering::Relaxed)),
+            clock: self.clock.clone(),
         }
     }
 
-    /// Generate scope group key from quota group and request type
-    pub fn scope_group(quota_group: &str, request_type: &str) -> String {
-        if request_type == "image_gen" {
+    /// Replace the tier ranking used by later selections
+    pub fn set_tier_order(&self, tier_order: TierOrder) {
+        *self.tier_order.write().unwrap_or_else(|e| e.into_inner()) = tier_order;
+    }
+
+    /// Get an account's tier priority (lower is better)
+    pub fn tier_priority(&self, token: &ProxyToken) -> usize {
+        self.tier_order
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .priority(token.subscription_tier.as_deref())
+    }
+
+    /// Circuit breakers consulted by every selection strategy
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.circuit_breaker
+    }
+
+    /// Check whether an account can take new requests in a scope group
+    fn is_available(&self, scope_group: &str, account_id: &str) -> bool {
+        !self
+            .rate_limit_tracker
+            .is_rate_limited(scope_group, account_id)
+            && self
+                .circuit_breaker
+                .allows_at(scope_group, account_id, self.clock.now())
+    }
+
+    /// Request throttles consulted by every selection strategy
+    pub fn throttle(&self) -> &RequestThrottle {
+        &self.throttle
+    }
+
+    /// Quota observations consulted by `prefer_most_remaining_quota`
+    pub fn quota(&self) -> &QuotaTracker {
+        &self.quota
+    }
+
+    /// Check whether an account is below its `max_concurrent` ceiling
+    pub fn has_capacity(&self, token: &ProxyToken) -> bool {
+        token
+            .max_concurrent
+            .is_none_or(|max| self.in_flight_count(&token.account_id) < max)
+    }
+
+    /// Check whether an account can take one more request in flight in a scope group
+    ///
+    /// Beyond its `max_concurrent` ceiling, an account on probation after a
+    /// rate limit takes no request while its trial request is out.
+    fn has_room(&self, token: &ProxyToken, scope_group: &str) -> bool {
+        self.has_capacity(token)
+            && self
+                .rate_limit_tracker
+                .probation_allows(scope_group, &token.account_id)
+    }
+
+    /// Check whether an account's last minute in a scope group is below its `max_rpm`
+    fn within_rpm(&self, token: &ProxyToken, scope_group: &str) -> bool {
+        self.throttle_wait(token, scope_group) == 0
+    }
+
+    /// Seconds until an account's `max_rpm` lets it take another request
+    pub fn throttle_wait(&self, token: &ProxyToken, scope_group: &str) -> u64 {
+        self.throttle.wait_at(
+            scope_group,
+            &token.account_id,
+            token.max_rpm,
+            self.clock.now(),
+        )
+    }
+
+    /// Check whether an account can take a new request in a scope group right now
+    pub fn can_take(&self, token: &ProxyToken, scope_group: &str) -> bool {
+        self.is_available(scope_group, &token.account_id)
+            && self.has_room(token, scope_group)
+            && self.within_rpm(token, scope_group)
+    }
+
+    /// Check whether some account that was not attempted is held back only
+    /// by its concurrency ceiling or a probation trial in flight
+    pub fn any_busy(
+        &self,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+        attempted: &HashSet<String>,
+    ) -> bool {
+        tokens.iter().any(|t| {
+            !attempted.contains(&t.account_id)
+                && self.is_available(scope_group, &t.account_id)
+                && self.within_rpm(t, scope_group)
+                && !self.has_room(t, scope_group)
+        })
+    }
+
+    /// Generate scope group key from quota group, request type and model
+    ///
+    /// Pass `model` only for groups scoped by model; it narrows the key to
+    /// e.g. "gemini::gemini-2.5-pro" so quotas of different models are
+    /// tracked apart.
+    pub fn scope_group(quota_group: &str, request_type: &str, model: Option<&str>) -> String {
+        let scope_group = if request_type == "image_gen" {
             format!("{}::image_gen", quota_group)
         } else {
             quota_group.to_string()
+        };
+        match model {
+            Some(model) => format!("{}::{}", scope_group, model),
+            None => scope_group,
         }
     }
 
-    /// Sort tokens by subscription tier priority (ULTRA first, FREE last)
-    pub fn sort_by_tier(tokens: &mut [ProxyToken]) {
-        tokens.sort_by(|a, b| a.tier_priority().cmp(&b.tier_priority()));
+    /// Sort tokens by subscription tier priority, best tier first
+    ///
+    /// Accounts of one tier are rotated by one place on every call, so
+    /// lookups that favor earlier positions (tie-breaks, the first eligible
+    /// account) do not keep landing on the same account.
+    pub fn sort_by_tier(&self, tokens: &mut [Arc<ProxyToken>]) {
+        let priorities: Vec<usize> = {
+            let tier_order = self.tier_order.read().unwrap_or_else(|e| e.into_inner());
+            tokens.sort_by_cached_key(|t| {
+                (
+                    tier_order.priority(t.subscription_tier.as_deref()),
+                    t.account_id.clone(),
+                )
+            });
+            tokens
+                .iter()
+                .map(|t| tier_order.priority(t.subscription_tier.as_deref()))
+                .collect()
+        };
+
+        let rotation = self.tier_rotation.fetch_add(1, Ordering::Relaxed);
+        let mut start = 0;
+        while start < tokens.len() {
+            let end = start
+                + priorities[start..]
+                    .iter()
+                    .take_while(|p| **p == priorities[start])
+                    .count();
+            let len = end - start;
+            tokens[start..end].rotate_left(rotation % len);
+            start = end;
+        }
     }
 
-    /// Get the next round-robin index for a quota group
-    fn get_next_index(&self, scope_group: &str, total: usize) -> usize {
-        let counter = self
-            .round_robin_index
-            .entry(scope_group.to_string())
-            .or_insert_with(|| Arc::new(AtomicUsize::new(0)))
-            .clone();
-        
-        counter.fetch_add(1, Ordering::SeqCst) % total
+    /// Drop scheduling state for every account not in `retained_account_ids`
+    ///
+    /// Call this after accounts left the pool. A round-robin cursor anchored
+    /// at a departed account is dropped too, so its scope group's next pass
+    /// starts over from the best tier. In-flight counters still held by a
+    /// request are kept.
+    pub fn cleanup(&self, retained_account_ids: &HashSet<String>) {
+        self.round_robin_cursor
+            .retain(|_, (_, account_id)| retained_account_ids.contains(account_id));
+        self.weighted_current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| {
+                key.rsplit_once("::")
+                    .is_some_and(|(_, account_id)| retained_account_ids.contains(account_id))
+            });
+        self.last_selected_at
+            .retain(|account_id, _| retained_account_ids.contains(account_id));
+        self.throttle.cleanup(retained_account_ids);
+        self.quota.cleanup(retained_account_ids, self.clock.now());
+        self.in_flight.retain(|account_id, counter| {
+            retained_account_ids.contains(account_id) || Arc::strong_count(counter) > 1
+        });
     }
 
     /// Select an account using round-robin with rate limit avoidance
+    ///
+    /// The rotation picks the tier, so a tier's share of traffic follows its
+    /// number of accounts. Within that tier the account is chosen by smooth
+    /// weighted round-robin on `proxy_weight`. Zero-weight accounts are only
+    /// used when no other account is available.
+    ///
+    /// Accounts take their turn ordered by tier, then account id, each pass
+    /// starting after the account the previous one started from. Accounts joining or
+    /// leaving the pool, or filtered out of `tokens`, don't shift the turn of
+    /// the others.
     pub fn select_round_robin(
         &self,
-        tokens: &[ProxyToken],
+        tokens: &[Arc<ProxyToken>],
         scope_group: &str,
         attempted: &HashSet<String>,
-    ) -> Option<ProxyToken> {
+    ) -> Option<Arc<ProxyToken>> {
         let total = tokens.len();
         if total == 0 {
             return None;
         }
 
-        let start_idx = self.get_next_index(scope_group, total);
-        
-        for offset in 0..total {
-            let idx = (start_idx + offset) % total;
-            let candidate = &tokens[idx];
-            
-            // Skip already attempted accounts
-            if attempted.contains(&candidate.account_id) {
-                continue;
-            }
-            
-            // Skip rate-limited accounts
-            if self.rate_limit_tracker.is_rate_limited(scope_group, &candidate.account_id) {
-                continue;
+        let mut order: Vec<(usize, &Arc<ProxyToken>)> =
+            tokens.iter().map(|t| (self.tier_priority(t), t)).collect();
+        order.sort_by(|(a_tier, a), (b_tier, b)| {
+            (a_tier, &a.account_id).cmp(&(b_tier, &b.account_id))
+        });
+        // Held until the pass is done so concurrent passes take distinct turns
+        let mut cursor = self
+            .round_robin_cursor
+            .entry(scope_group.to_string())
+            .or_default();
+        let start_idx = order.partition_point(|(tier, t)| {
+            (*tier, t.account_id.as_str()) <= (cursor.0, cursor.1.as_str())
+        });
+
+        for allow_zero_weight in [false, true] {
+            let eligible = |t: &ProxyToken| {
+                // Skip already attempted, rate-limited accounts and open circuits
+                !attempted.contains(&t.account_id)
+                    && (allow_zero_weight || t.proxy_weight > 0.0)
+                    && self.can_take(t, scope_group)
+            };
+
+            for offset in 0..total {
+                let (tier, candidate) = order[(start_idx + offset) % total];
+                if !eligible(candidate) {
+                    continue;
+                }
+                *cursor = (tier, candidate.account_id.clone());
+
+                let peers: Vec<&Arc<ProxyToken>> = tokens
+                    .iter()
+                    .filter(|t| self.tier_priority(t) == tier && eligible(t))
+                    .collect();
+                return Some(self.pick_weighted(&peers, scope_group).clone());
             }
-            
-            return Some(candidate.clone());
         }
-        
+
         None
     }
 
+    /// Smooth weighted round-robin over non-empty `peers`
+    ///
+    /// Every peer gains its weight, the one with the highest running total
+    /// wins and pays back the sum of all weights. Ties go to the earlier peer.
+    fn pick_weighted<'a>(
+        &self,
+        peers: &[&'a Arc<ProxyToken>],
+        scope_group: &str,
+    ) -> &'a Arc<ProxyToken> {
+        if peers.len() == 1 {
+            return peers[0];
+        }
+
+        // Zero-weight fallback peers rotate evenly
+        let weight = |t: &ProxyToken| {
+            if t.proxy_weight > 0.0 {
+                t.proxy_weight
+            } else {
+                1.0
+            }
+        };
+        let total_weight: f64 = peers.iter().map(|t| weight(t)).sum();
+
+        let mut current = self
+            .weighted_current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let mut best: Option<(&'a Arc<ProxyToken>, f64)> = None;
+        for peer in peers {
+            let value = current
+                .entry(format!("{}::{}", scope_group, peer.account_id))
+                .or_insert(0.0);
+            *value += weight(peer);
+            if best.is_none_or(|(_, v)| *value > v) {
+                best = Some((peer, *value));
+            }
+        }
+
+        let (chosen, _) = best.expect("peers is not empty");
+        if let Some(value) = current.get_mut(&format!("{}::{}", scope_group, chosen.account_id)) {
+            *value -= total_weight;
+        }
+        chosen
+    }
+
+    /// Record that an account was just handed out
+    ///
+    /// Stamps are wall-clock milliseconds, bumped by one when two selections
+    /// land in the same millisecond so LRU ordering stays strict.
+    pub fn record_selection(&self, account_id: &str) {
+        let now = self.clock.now_millis();
+        let previous = self
+            .selection_clock
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or(now);
+        let stamp = now.max(previous + 1);
+        self.last_selected_at.insert(account_id.to_string(), stamp);
+    }
+
+    /// Get the last selection stamp for an account, if it was ever selected
+    pub fn last_selected_at(&self, account_id: &str) -> Option<i64> {
+        self.last_selected_at.get(account_id).map(|v| *v)
+    }
+
+    /// Select the healthy account that has gone longest without being selected
+    ///
+    /// Accounts that were never selected come first; ties are broken by tier
+    /// and then by position in `tokens`. The stamp is updated immediately so
+    /// concurrent callers spread across the pool.
+    pub fn select_least_recently_used(
+        &self,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+        attempted: &HashSet<String>,
+    ) -> Option<Arc<ProxyToken>> {
+        let candidate = tokens
+            .iter()
+            .filter(|t| !attempted.contains(&t.account_id))
+            .filter(|t| self.can_take(t, scope_group))
+            .min_by_key(|t| {
+                (
+                    self.last_selected_at(&t.account_id).unwrap_or(i64::MIN),
+                    self.tier_priority(t),
+                )
+            })?;
+
+        self.record_selection(&candidate.account_id);
+        Some(candidate.clone())
+    }
+
+    /// Take an in-flight slot for an account, released when the guard drops
+    pub fn acquire_in_flight(&self, account_id: &str) -> InFlightGuard {
+        InFlightGuard::acquire(self.in_flight_counter(account_id))
+    }
+
+    /// Take an in-flight slot unless the account is at its `max_concurrent` ceiling
+    ///
+    /// The check and the increment are one atomic step, so concurrent
+    /// callers can never push an account past its ceiling.
+    pub fn try_acquire_in_flight(&self, token: &ProxyToken) -> Option<InFlightGuard> {
+        match token.max_concurrent {
+            Some(max) => InFlightGuard::try_acquire(self.in_flight_counter(&token.account_id), max),
+            None => Some(self.acquire_in_flight(&token.account_id)),
+        }
+    }
+
+    fn in_flight_counter(&self, account_id: &str) -> Arc<AtomicUsize> {
+        self.in_flight
+            .entry(account_id.to_string())
+            .or_insert_with(|| Arc::new(AtomicUsize::new(0)))
+            .clone()
+    }
+
+    /// Get the number of requests currently in flight on an account
+    pub fn in_flight_count(&self, account_id: &str) -> usize {
+        self.in_flight
+            .get(account_id)
+            .map(|c| c.load(Ordering::SeqCst))
+            .unwrap_or(0)
+    }
+
+    /// Select the healthy account with the fewest in-flight requests
+    ///
+    /// Ties are broken by tier, then by least recent selection so idle pools
+    /// still spread load instead of always picking the first account.
+    pub fn select_least_connections(
+        &self,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+        attempted: &HashSet<String>,
+    ) -> Option<Arc<ProxyToken>> {
+        let candidate = tokens
+            .iter()
+            .filter(|t| !attempted.contains(&t.account_id))
+            .filter(|t| self.can_take(t, scope_group))
+            .min_by_key(|t| {
+                (
+                    self.in_flight_count(&t.account_id),
+                    self.tier_priority(t),
+                    self.last_selected_at(&t.account_id).unwrap_or(i64::MIN),
+                )
+            })?;
+
+        Some(candidate.clone())
+    }
+
+    /// Select the healthy account carrying the fewest live session bindings
+    ///
+    /// `session_counts` maps account_id to its bindings in the scope group;
+    /// missing accounts have none. Ties fall back to round-robin among the
+    /// least-bound accounts.
+    pub fn select_least_bound(
+        &self,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+        session_counts: &HashMap<String, usize>,
+        attempted: &HashSet<String>,
+    ) -> Option<Arc<ProxyToken>> {
+        let count = |t: &ProxyToken| session_counts.get(&t.account_id).copied().unwrap_or(0);
+
+        let candidates: Vec<&Arc<ProxyToken>> = tokens
+            .iter()
+            .filter(|t| !attempted.contains(&t.account_id))
+            .filter(|t| self.can_take(t, scope_group))
+            .collect();
+        let fewest = candidates.iter().map(|t| count(t)).min()?;
+
+        let least_bound: Vec<Arc<ProxyToken>> = candidates
+            .into_iter()
+            .filter(|t| count(t) == fewest)
+            .cloned()
+            .collect();
+        self.select_round_robin(&least_bound, scope_group, attempted)
+    }
+
+    /// Narrow each tier to its usable accounts with the most quota left
+    ///
+    /// Accounts without a live quota observation rank above every observed
+    /// one: nothing says they are running low. Tiers are kept, so the mode
+    /// still decides between them and among accounts tied on quota.
+    pub fn most_remaining_quota(
+        &self,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+        attempted: &HashSet<String>,
+    ) -> Vec<Arc<ProxyToken>> {
+        let now = self.clock.now();
+        let candidates: Vec<(usize, u64, &Arc<ProxyToken>)> = tokens
+            .iter()
+            .filter(|t| !attempted.contains(&t.account_id) && self.can_take(t, scope_group))
+            .map(|t| {
+                let remaining = self
+                    .quota
+                    .remaining_at(scope_group, &t.account_id, now)
+                    .unwrap_or(u64::MAX);
+                (self.tier_priority(t), remaining, t)
+            })
+            .collect();
+
+        let mut best: HashMap<usize, u64> = HashMap::new();
+        for (tier, remaining, _) in &candidates {
+            let most = best.entry(*tier).or_insert(*remaining);
+            *most = (*most).max(*remaining);
+        }
+        candidates
+            .into_iter()
+            .filter(|(tier, remaining, _)| best.get(tier) == Some(remaining))
+            .map(|(_, _, t)| t.clone())
+            .collect()
+    }
+
+    /// Select a fresh (non-sticky) account using the configured mode
+    ///
+    /// With `prefer_most_remaining_quota`, the mode only picks among each
+    /// tier's accounts with the most quota left.
+    pub fn select_next(
+        &self,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+        scheduling: &StickySessionConfig,
+        attempted: &HashSet<String>,
+    ) -> Option<Arc<ProxyToken>> {
+        let roomiest;
+        let tokens = if scheduling.prefer_most_remaining_quota {
+            roomiest = self.most_remaining_quota(tokens, scope_group, attempted);
+            roomiest.as_slice()
+        } else {
+            tokens
+        };
+        match scheduling.mode {
+            SchedulingMode::LeastRecentlyUsed => {
+                self.select_least_recently_used(tokens, scope_group, attempted)
+            }
+            SchedulingMode::LeastConnections => {
+                self.select_least_connections(tokens, scope_group, attempted)
+            }
+            _ => self.select_round_robin(tokens, scope_group, attempted),
+        }
+    }
+
+    /// Select an account with `select_next`, steering clear of `avoid`
+    ///
+    /// `avoid` lists accounts most recent first. When nothing else is left,
+    /// the oldest entries are allowed back one at a time, so the account
+    /// used longest ago is picked before the one just left.
+    pub fn select_next_avoiding(
+        &self,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+        scheduling: &StickySessionConfig,
+        attempted: &HashSet<String>,
+        avoid: &[String],
+    ) -> Option<Arc<ProxyToken>> {
+        for keep in (1..=avoid.len()).rev() {
+            let mut excluded = attempted.clone();
+            excluded.extend(avoid[..keep].iter().cloned());
+            if let Some(token) = self.select_next(tokens, scope_group, scheduling, &excluded) {
+                return Some(token);
+            }
+        }
+        self.select_next(tokens, scope_group, scheduling, attempted)
+    }
+
+    /// Select a new account for a session with the configured mode
+    ///
+    /// Balance mode spreads sessions by how many bindings each account
+    /// already carries.
+    fn select_for_session(
+        &self,
+        tokens: &[Arc<ProxyToken>],
+        scope_group: &str,
+        session_counts: &HashMap<String, usize>,
+        scheduling: &StickySessionConfig,
+        attempted: &HashSet<String>,
+    ) -> Option<Arc<ProxyToken>> {
+        match scheduling.mode {
+            SchedulingMode::Balance => {
+                self.select_least_bound(tokens, scope_group, session_counts, attempted)
+            }
+            _ => self.select_next(tokens, scope_group, scheduling, attempted),
+        }
+    }
+
     /// Select account with sticky session support
+    ///
+    /// With `prefer_same_tier_on_failover`, a session leaving its bound
+    /// account first tries accounts of the same or a better tier and only
+    /// drops to lower tiers when none of those is available.
     pub fn select_with_session(
         &self,
-        tokens: &[ProxyToken],
+        tokens: &[Arc<ProxyToken>],
         scope_group: &str,
         bound_account_id: Option<&str>,
+        session_counts: &HashMap<String, usize>,
         scheduling: &StickySessionConfig,
         attempted: &HashSet<String>,
     ) -> SchedulingDecision {
@@ -135,56 +691,127 @@ impl AccountScheduler {
                         // Switch to a different account
                         tracing::debug!(
                             "Session bound account {} is rate limited for {}s, switching",
-                            bound_id, remaining_wait
+                            bound_id,
+                            remaining_wait
                         );
                     }
                 }
+            } else if !self.is_available(scope_group, bound_id) {
+                tracing::debug!(
+                    "Session bound account {} has an open circuit after upstream errors, switching",
+                    bound_id
+                );
             } else if !attempted.contains(bound_id) {
                 // Bound account is available and not previously attempted
                 if let Some(token) = tokens.iter().find(|t| t.account_id == bound_id) {
-                    return SchedulingDecision::UseAccount(token.clone());
+                    if !self.within_rpm(token, scope_group) {
+                        tracing::debug!(
+                            "Session bound account {} is at its max_rpm, switching",
+                            bound_id
+                        );
+                    } else if !self
+                        .rate_limit_tracker
+                        .probation_allows(scope_group, bound_id)
+                    {
+                        tracing::debug!(
+                            "Session bound account {} is on probation with a trial request out, switching",
+                            bound_id
+                        );
+                    } else if self.has_capacity(token) {
+                        return SchedulingDecision::UseAccount(token.clone());
+                    } else {
+                        tracing::debug!(
+                            "Session bound account {} is at its concurrency limit, switching",
+                            bound_id
+                        );
+                    }
                 }
             }
         }
 
-        // Fall back to round-robin selection
-        match self.select_round_robin(tokens, scope_group, attempted) {
-            Some(token) => SchedulingDecision::UseAccount(token),
-            None => {
-                // Calculate minimum wait time across all accounts
-                let min_wait = tokens
-                    .iter()
-                    .filter_map(|t| {
-                        self.rate_limit_tracker
-                            .get_reset_seconds(scope_group, &t.account_id)
-                    })
-                    .min()
-                    .unwrap_or(60);
-
-                SchedulingDecision::AllUnavailable {
-                    min_wait_seconds: min_wait,
-                }
+        let bound_tier = bound_account_id
+            .filter(|_| scheduling.prefer_same_tier_on_failover)
+            .and_then(|bound_id| tokens.iter().find(|t| t.account_id == bound_id))
+            .map(|t| self.tier_priority(t));
+        if let Some(bound_tier) = bound_tier {
+            let same_or_better: Vec<Arc<ProxyToken>> = tokens
+                .iter()
+                .filter(|t| self.tier_priority(t) <= bound_tier)
+                .cloned()
+                .collect();
+            if let Some(token) = self.select_for_session(
+                &same_or_better,
+                scope_group,
+                session_counts,
+                scheduling,
+                attempted,
+            ) {
+                return SchedulingDecision::UseAccount(token);
             }
+            tracing::debug!(
+                "No account at or above the bound account's tier is available, widening"
+            );
         }
+
+        // Fall back to the mode's selection strategy over the whole pool
+        let selected =
+            self.select_for_session(tokens, scope_group, session_counts, scheduling, attempted);
+
+        match selected {
+            Some(token) => SchedulingDecision::UseAccount(token),
+            None if self.any_busy(tokens, scope_group, attempted) => SchedulingDecision::AllBusy,
+            None => SchedulingDecision::AllUnavailable {
+                min_wait_seconds: self.min_wait(tokens, scope_group),
+            },
+        }
+    }
+
+    /// Seconds until the first account's rate limit, open circuit or full
+    /// `max_rpm` window clears
+    ///
+    /// `None` if no account is rate limited, throttled or has an open circuit.
+    pub fn min_wait(&self, tokens: &[Arc<ProxyToken>], scope_group: &str) -> Option<u64> {
+        let now = self.clock.now();
+        tokens
+            .iter()
+            .filter_map(|t| {
+                let limited = self
+                    .rate_limit_tracker
+                    .get_reset_seconds(scope_group, &t.account_id);
+                let open = self
+                    .circuit_breaker
+                    .remaining_at(scope_group, &t.account_id, now);
+                let throttled = Some(self.throttle_wait(t, scope_group)).filter(|&w| w > 0);
+                limited.max(open).max(throttled)
+            })
+            .min()
     }
 
-    /// Get all healthy (non-rate-limited) accounts
+    /// Get all healthy (non-rate-limited) accounts with their in-flight counts
     pub fn get_healthy_accounts<'a>(
         &self,
-        tokens: &'a [ProxyToken],
+        tokens: &'a [Arc<ProxyToken>],
         scope_group: &str,
-    ) -> Vec<&'a ProxyToken> {
+    ) -> Vec<(&'a Arc<ProxyToken>, usize)> {
         tokens
             .iter()
-            .filter(|t| !self.rate_limit_tracker.is_rate_limited(scope_group, &t.account_id))
+            .filter(|t| {
+                !self
+                    .rate_limit_tracker
+                    .is_rate_limited(scope_group, &t.account_id)
+            })
+            .map(|t| (t, self.in_flight_count(&t.account_id)))
             .collect()
     }
 
     /// Get the count of rate-limited accounts
-    pub fn count_limited_accounts(&self, tokens: &[ProxyToken], scope_group: &str) -> usize {
+    pub fn count_limited_accounts(&self, tokens: &[Arc<ProxyToken>], scope_group: &str) -> usize {
         tokens
             .iter()
-            .filter(|t| self.rate_limit_tracker.is_rate_limited(scope_group, &t.account_id))
+            .filter(|t| {
+                self.rate_limit_tracker
+                    .is_rate_limited(scope_group, &t.account_id)
+            })
             .count()
     }
 }
@@ -194,10 +821,10 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
 
-    fn create_test_tokens() -> Vec<ProxyToken> {
+    fn create_test_tokens() -> Vec<Arc<ProxyToken>> {
         let now = chrono::Utc::now().timestamp() + 3600;
         vec![
-            ProxyToken {
+            Arc::new(ProxyToken {
                 account_id: "ultra-1".to_string(),
                 access_token: "token".to_string(),
                 refresh_token: "refresh".to_string(),
@@ -207,21 +834,31 @@ mod tests {
                 account_path: PathBuf::from("/tmp/ultra.json"),
                 project_id: Some("proj".to_string()),
                 subscription_tier: Some("ULTRA".to_string()),
-            },
-            ProxyToken {
+                proxy_weight: 1.0,
+                max_concurrent: None,
+                max_rpm: None,
+                tags: Vec::new(),
+                source_dir: None,
+                oauth_client: None,
+                outbound_proxy: None,
+                service_account: None,
+                providers: vec!["gemini".to_string(), "claude".to_string()],
+                base_url_override: None,
+            }),
+            Arc::new(ProxyToken {
                 account_id: "pro-1".to_string(),
                 subscription_tier: Some("PRO".to_string()),
                 email: "pro@example.com".to_string(),
                 account_path: PathBuf::from("/tmp/pro.json"),
                 ..create_base_token(now)
-            },
-            ProxyToken {
+            }),
+            Arc::new(ProxyToken {
                 account_id: "free-1".to_string(),
                 subscription_tier: Some("FREE".to_string()),
                 email: "free@example.com".to_string(),
                 account_path: PathBuf::from("/tmp/free.json"),
                 ..create_base_token(now)
-            },
+            }),
         ]
     }
 
@@ -236,6 +873,16 @@ mod tests {
             account_path: PathBuf::from("/tmp/base.json"),
             project_id: Some("proj".to_string()),
             subscription_tier: None,
+            proxy_weight: 1.0,
+            max_concurrent: None,
+            max_rpm: None,
+            tags: Vec::new(),
+            source_dir: None,
+            oauth_client: None,
+            outbound_proxy: None,
+            service_account: None,
+            providers: vec!["gemini".to_string(), "claude".to_string()],
+            base_url_override: None,
         }
     }
 
@@ -244,24 +891,68 @@ mod tests {
         let mut tokens = create_test_tokens();
         // Shuffle order
         tokens.reverse();
-        
-        AccountScheduler::sort_by_tier(&mut tokens);
-        
+
+        let scheduler = AccountScheduler::new(Arc::new(RateLimitTracker::new()));
+        scheduler.sort_by_tier(&mut tokens);
+
         assert_eq!(tokens[0].subscription_tier.as_deref(), Some("ULTRA"));
         assert_eq!(tokens[1].subscription_tier.as_deref(), Some("PRO"));
         assert_eq!(tokens[2].subscription_tier.as_deref(), Some("FREE"));
     }
 
+    #[test]
+    fn test_tier_sorting_rotates_within_tier() {
+        let mut tokens = create_test_tokens();
+        for id in ["ultra2", "ultra3"] {
+            let mut token = (*tokens[0]).clone();
+            token.account_id = id.to_string();
+            tokens.push(Arc::new(token));
+        }
+        let scheduler = AccountScheduler::new(Arc::new(RateLimitTracker::new()));
+
+        let mut leaders = Vec::new();
+        for _ in 0..3 {
+            let mut sorted = tokens.clone();
+            scheduler.sort_by_tier(&mut sorted);
+            let tiers: Vec<_> = sorted
+                .iter()
+                .map(|t| t.subscription_tier.as_deref())
+                .collect();
+            assert_eq!(
+                tiers,
+                vec![
+                    Some("ULTRA"),
+                    Some("ULTRA"),
+                    Some("ULTRA"),
+                    Some("PRO"),
+                    Some("FREE")
+                ]
+            );
+            leaders.push(sorted[0].account_id.clone());
+        }
+        leaders.sort();
+        leaders.dedup();
+        assert_eq!(leaders.len(), 3);
+    }
+
     #[test]
     fn test_scope_group_generation() {
         assert_eq!(
-            AccountScheduler::scope_group("claude", "chat"),
+            AccountScheduler::scope_group("claude", "chat", None),
             "claude"
         );
         assert_eq!(
-            AccountScheduler::scope_group("claude", "image_gen"),
+            AccountScheduler::scope_group("claude", "image_gen", None),
             "claude::image_gen"
         );
+        assert_eq!(
+            AccountScheduler::scope_group("gemini", "chat", Some("gemini-2.5-pro")),
+            "gemini::gemini-2.5-pro"
+        );
+        assert_eq!(
+            AccountScheduler::scope_group("gemini", "image_gen", Some("gemini-3-pro-image")),
+            "gemini::image_gen::gemini-3-pro-image"
+        );
     }
 
     #[test]
@@ -284,12 +975,66 @@ mod tests {
         assert!(third.is_some());
     }
 
+    #[test]
+    fn test_round_robin_stays_fair_when_the_pool_changes() {
+        let scheduler = AccountScheduler::new(Arc::new(RateLimitTracker::new()));
+        let now = chrono::Utc::now().timestamp() + 3600;
+        // Turns go by tier, then id: a, d, b, e, c
+        let tokens: Vec<Arc<ProxyToken>> = [
+            ("a", "ULTRA"),
+            ("b", "PRO"),
+            ("c", "FREE"),
+            ("d", "ULTRA"),
+            ("e", "PRO"),
+        ]
+        .iter()
+        .map(|(id, tier)| {
+            Arc::new(ProxyToken {
+                account_id: id.to_string(),
+                subscription_tier: Some(tier.to_string()),
+                ..create_base_token(now)
+            })
+        })
+        .collect();
+        let without_c: Vec<Arc<ProxyToken>> = tokens
+            .iter()
+            .filter(|t| t.account_id != "c")
+            .cloned()
+            .collect();
+        let attempted = HashSet::new();
+        let pick = |pool: &[Arc<ProxyToken>]| {
+            scheduler
+                .select_round_robin(pool, "claude", &attempted)
+                .unwrap()
+                .account_id
+                .clone()
+        };
+
+        let mut picks: Vec<String> = (0..3).map(|_| pick(&tokens)).collect();
+        picks.extend((0..2).map(|_| pick(&without_c)));
+        picks.extend((0..2 * tokens.len()).map(|_| pick(&tokens)));
+
+        // Every window of 2×pool_size picks after the change covers every account
+        for window in picks[5..].windows(2 * tokens.len()) {
+            for token in &tokens {
+                assert!(
+                    window.contains(&token.account_id),
+                    "{} starved in {:?}",
+                    token.account_id,
+                    picks
+                );
+            }
+        }
+        // c's turn passes while it is gone; nobody else's turn moves
+        assert_eq!(picks[..5], ["a", "d", "b", "e", "a"]);
+    }
+
     #[test]
     fn test_skip_attempted_accounts() {
         let tracker = Arc::new(RateLimitTracker::new());
         let scheduler = AccountScheduler::new(tracker);
         let tokens = create_test_tokens();
-        
+
         let mut attempted = HashSet::new();
         attempted.insert("ultra-1".to_string());
         attempted.insert("pro-1".to_string());
@@ -304,7 +1049,7 @@ mod tests {
         let tracker = Arc::new(RateLimitTracker::new());
         let scheduler = AccountScheduler::new(tracker);
         let tokens = create_test_tokens();
-        
+
         let mut attempted = HashSet::new();
         for token in &tokens {
             attempted.insert(token.account_id.clone());
@@ -326,6 +1071,7 @@ mod tests {
             &tokens,
             "claude",
             Some("ultra-1"),
+            &HashMap::new(),
             &config,
             &attempted,
         );
@@ -342,13 +1088,105 @@ mod tests {
     fn test_empty_token_pool() {
         let tracker = Arc::new(RateLimitTracker::new());
         let scheduler = AccountScheduler::new(tracker);
-        let tokens: Vec<ProxyToken> = vec![];
+        let tokens: Vec<Arc<ProxyToken>> = vec![];
         let attempted = HashSet::new();
 
         let selected = scheduler.select_round_robin(&tokens, "claude", &attempted);
         assert!(selected.is_none());
     }
 
+    #[test]
+    fn test_least_recently_used_prefers_unselected_then_oldest() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker);
+        let tokens = create_test_tokens();
+        let attempted = HashSet::new();
+
+        // Never-selected accounts are taken in tier order
+        let picks: Vec<String> = (0..3)
+            .filter_map(|_| scheduler.select_least_recently_used(&tokens, "claude", &attempted))
+            .map(|t| t.account_id.clone())
+            .collect();
+        assert_eq!(picks, vec!["ultra-1", "pro-1", "free-1"]);
+
+        // Then the oldest selection comes around again
+        let next = scheduler
+            .select_least_recently_used(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(next.account_id, "ultra-1");
+    }
+
+    #[test]
+    fn test_least_recently_used_skips_limited_and_attempted() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker.clone());
+        let tokens = create_test_tokens();
+
+        tracker.mark_limited("claude", "ultra-1", 60);
+        let mut attempted = HashSet::new();
+        attempted.insert("pro-1".to_string());
+
+        let selected = scheduler
+            .select_least_recently_used(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "free-1");
+
+        attempted.insert("free-1".to_string());
+        assert!(scheduler
+            .select_least_recently_used(&tokens, "claude", &attempted)
+            .is_none());
+    }
+
+    #[test]
+    fn test_least_recently_used_with_changing_pool() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker);
+        let attempted = HashSet::new();
+        let mut tokens = create_test_tokens();
+
+        for _ in 0..3 {
+            scheduler.select_least_recently_used(&tokens, "claude", &attempted);
+        }
+
+        // A new account joins the pool: it has never served, so it goes first
+        tokens.push(Arc::new(ProxyToken {
+            account_id: "free-2".to_string(),
+            subscription_tier: Some("FREE".to_string()),
+            ..create_base_token(chrono::Utc::now().timestamp() + 3600)
+        }));
+        let selected = scheduler
+            .select_least_recently_used(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "free-2");
+
+        // The least recently used account leaves the pool: the next oldest wins
+        tokens.retain(|t| t.account_id != "ultra-1");
+        let selected = scheduler
+            .select_least_recently_used(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "pro-1");
+    }
+
+    #[test]
+    fn test_select_next_routes_by_mode() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker);
+        let tokens = create_test_tokens();
+        let attempted = HashSet::new();
+        let config = StickySessionConfig {
+            mode: SchedulingMode::LeastRecentlyUsed,
+            ..StickySessionConfig::default()
+        };
+
+        scheduler.record_selection("ultra-1");
+        scheduler.record_selection("pro-1");
+
+        let selected = scheduler
+            .select_next(&tokens, "claude", &config, &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "free-1");
+    }
+
     #[test]
     fn test_healthy_accounts_count() {
         let tracker = Arc::new(RateLimitTracker::new());
@@ -368,4 +1206,461 @@ mod tests {
         let limited = scheduler.count_limited_accounts(&tokens, "claude");
         assert_eq!(limited, 1);
     }
+
+    #[test]
+    fn test_least_connections_picks_idlest_account() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker);
+        let tokens = create_test_tokens();
+        let attempted = HashSet::new();
+
+        let _a = scheduler.acquire_in_flight("ultra-1");
+        let _b = scheduler.acquire_in_flight("ultra-1");
+        let _c = scheduler.acquire_in_flight("pro-1");
+
+        let selected = scheduler
+            .select_least_connections(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "free-1");
+
+        let healthy = scheduler.get_healthy_accounts(&tokens, "claude");
+        let counts: Vec<(&str, usize)> = healthy
+            .iter()
+            .map(|(t, n)| (t.account_id.as_str(), *n))
+            .collect();
+        assert_eq!(counts, vec![("ultra-1", 2), ("pro-1", 1), ("free-1", 0)]);
+    }
+
+    #[test]
+    fn test_least_connections_counts_drop_with_guards() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker);
+        let tokens = create_test_tokens();
+        let attempted = HashSet::new();
+
+        let guard = scheduler.acquire_in_flight("ultra-1");
+        let selected = scheduler
+            .select_least_connections(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "pro-1");
+
+        drop(guard);
+        assert_eq!(scheduler.in_flight_count("ultra-1"), 0);
+        let selected = scheduler
+            .select_least_connections(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "ultra-1");
+    }
+
+    #[test]
+    fn test_saturated_accounts_are_skipped() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker);
+        let mut tokens = create_test_tokens();
+        for token in &mut tokens {
+            Arc::make_mut(token).max_concurrent = Some(1);
+        }
+        let attempted = HashSet::new();
+
+        let _ultra = scheduler.try_acquire_in_flight(&tokens[0]).unwrap();
+        assert!(scheduler.try_acquire_in_flight(&tokens[0]).is_none());
+
+        let selected = scheduler
+            .select_round_robin(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "pro-1");
+        assert!(scheduler.any_busy(&tokens, "claude", &attempted));
+
+        let _pro = scheduler.try_acquire_in_flight(&tokens[1]).unwrap();
+        let _free = scheduler.try_acquire_in_flight(&tokens[2]).unwrap();
+        let decision = scheduler.select_with_session(
+            &tokens,
+            "claude",
+            Some("ultra-1"),
+            &HashMap::new(),
+            &StickySessionConfig::default(),
+            &attempted,
+        );
+        assert!(matches!(decision, SchedulingDecision::AllBusy));
+    }
+
+    #[test]
+    fn test_cleanup_drops_departed_accounts() {
+        let scheduler = AccountScheduler::new(Arc::new(RateLimitTracker::new()));
+        let now = chrono::Utc::now().timestamp() + 3600;
+        let tokens: Vec<Arc<ProxyToken>> = ["a", "b", "c"]
+            .iter()
+            .map(|id| {
+                Arc::new(ProxyToken {
+                    account_id: id.to_string(),
+                    subscription_tier: Some("PRO".to_string()),
+                    ..create_base_token(now)
+                })
+            })
+            .collect();
+        let attempted = HashSet::new();
+        for scope_group in ["claude", "gemini::image_gen"] {
+            for _ in 0..3 {
+                let token = scheduler
+                    .select_round_robin(&tokens, scope_group, &attempted)
+                    .unwrap();
+                scheduler.record_selection(&token.account_id);
+            }
+        }
+        // This group's cursor stays on the retained account
+        let token = scheduler
+            .select_round_robin(&tokens, "gemini", &attempted)
+            .unwrap();
+        assert_eq!(token.account_id, "a");
+        let _in_flight = scheduler.acquire_in_flight("c");
+        scheduler.acquire_in_flight("b");
+
+        let retained: HashSet<String> = ["a"].map(String::from).into();
+        scheduler.cleanup(&retained);
+
+        let cursors: Vec<String> = scheduler
+            .round_robin_cursor
+            .iter()
+            .map(|e| e.key().clone())
+            .collect();
+        assert_eq!(cursors, vec!["gemini"]);
+        let mut weighted: Vec<String> = scheduler
+            .weighted_current
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        weighted.sort();
+        assert_eq!(
+            weighted,
+            vec!["claude::a", "gemini::a", "gemini::image_gen::a"]
+        );
+        assert!(scheduler.last_selected_at("a").is_some());
+        assert!(scheduler.last_selected_at("b").is_none());
+        // A request still running on a departed account keeps its counter
+        assert_eq!(scheduler.in_flight_count("c"), 1);
+        assert!(!scheduler.in_flight.contains_key("b"));
+    }
+
+    #[test]
+    fn test_balance_mode_picks_least_bound_account() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker.clone());
+        let tokens = create_test_tokens();
+        let attempted = HashSet::new();
+        let config = StickySessionConfig {
+            mode: SchedulingMode::Balance,
+            ..StickySessionConfig::default()
+        };
+        let counts = HashMap::from([("ultra-1".to_string(), 4), ("pro-1".to_string(), 2)]);
+
+        // New session: fewest bindings wins regardless of tier
+        match scheduler.select_with_session(&tokens, "claude", None, &counts, &config, &attempted) {
+            SchedulingDecision::UseAccount(token) => assert_eq!(token.account_id, "free-1"),
+            other => panic!("unexpected decision: {:?}", other),
+        }
+
+        // A limited account is skipped even if it is the least bound
+        tracker.parse_from_error("claude", "free-1", 429, Some("60"), "");
+        let selected = scheduler
+            .select_least_bound(&tokens, "claude", &counts, &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "pro-1");
+
+        // Ties rotate among the least-bound accounts
+        let tied = HashMap::from([("free-1".to_string(), 1)]);
+        let picks: HashSet<String> = (0..2)
+            .map(|_| {
+                scheduler
+                    .select_least_bound(&tokens, "gemini", &tied, &attempted)
+                    .unwrap()
+                    .account_id
+                    .clone()
+            })
+            .collect();
+        assert_eq!(
+            picks,
+            HashSet::from(["ultra-1".to_string(), "pro-1".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_weighted_round_robin_ratio() {
+        let scheduler = AccountScheduler::new(Arc::new(RateLimitTracker::new()));
+        let now = chrono::Utc::now().timestamp() + 3600;
+        let tokens = vec![
+            Arc::new(ProxyToken {
+                account_id: "heavy".to_string(),
+                subscription_tier: Some("PRO".to_string()),
+                proxy_weight: 3.0,
+                ..create_base_token(now)
+            }),
+            Arc::new(ProxyToken {
+                account_id: "light".to_string(),
+                subscription_tier: Some("PRO".to_string()),
+                proxy_weight: 1.0,
+                ..create_base_token(now)
+            }),
+        ];
+        let attempted = HashSet::new();
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for _ in 0..100 {
+            let token = scheduler
+                .select_round_robin(&tokens, "claude", &attempted)
+                .unwrap();
+            *counts.entry(token.account_id.clone()).or_default() += 1;
+        }
+        assert_eq!(counts["heavy"], 75);
+        assert_eq!(counts["light"], 25);
+    }
+
+    #[test]
+    fn test_weights_do_not_cross_tiers() {
+        let scheduler = AccountScheduler::new(Arc::new(RateLimitTracker::new()));
+        let now = chrono::Utc::now().timestamp() + 3600;
+        let tokens = vec![
+            Arc::new(ProxyToken {
+                account_id: "ultra".to_string(),
+                subscription_tier: Some("ULTRA".to_string()),
+                proxy_weight: 1.0,
+                ..create_base_token(now)
+            }),
+            Arc::new(ProxyToken {
+                account_id: "pro".to_string(),
+                subscription_tier: Some("PRO".to_string()),
+                proxy_weight: 9.0,
+                ..create_base_token(now)
+            }),
+        ];
+        let attempted = HashSet::new();
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for _ in 0..10 {
+            let token = scheduler
+                .select_round_robin(&tokens, "claude", &attempted)
+                .unwrap();
+            *counts.entry(token.account_id.clone()).or_default() += 1;
+        }
+        assert_eq!(counts["ultra"], 5);
+        assert_eq!(counts["pro"], 5);
+    }
+
+    #[test]
+    fn test_zero_weight_is_last_resort() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker.clone());
+        let now = chrono::Utc::now().timestamp() + 3600;
+        let tokens = vec![
+            Arc::new(ProxyToken {
+                account_id: "shared".to_string(),
+                subscription_tier: Some("ULTRA".to_string()),
+                proxy_weight: 0.0,
+                ..create_base_token(now)
+            }),
+            Arc::new(ProxyToken {
+                account_id: "dedicated".to_string(),
+                subscription_tier: Some("PRO".to_string()),
+                ..create_base_token(now)
+            }),
+        ];
+        let attempted = HashSet::new();
+
+        for _ in 0..4 {
+            let token = scheduler
+                .select_round_robin(&tokens, "claude", &attempted)
+                .unwrap();
+            assert_eq!(token.account_id, "dedicated");
+        }
+
+        tracker.mark_limited("claude", "dedicated", 60);
+        let token = scheduler
+            .select_round_robin(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(token.account_id, "shared");
+    }
+
+    #[test]
+    fn test_prefers_most_remaining_quota_within_tier() {
+        use super::super::quota::QuotaObservation;
+
+        let scheduler = AccountScheduler::new(Arc::new(RateLimitTracker::new()));
+        let mut tokens = create_test_tokens();
+        for id in ["pro-2", "pro-3"] {
+            let mut token = (*tokens[1]).clone();
+            token.account_id = id.to_string();
+            tokens.push(Arc::new(token));
+        }
+        let now = chrono::Utc::now().timestamp();
+        let observe = |account_id: &str, remaining: u64, reset_at: i64| {
+            let observation = QuotaObservation {
+                remaining: Some(remaining),
+                limit: Some(100),
+                reset_at: Some(reset_at),
+                observed_at: now,
+            };
+            scheduler.quota().record("claude", account_id, observation);
+        };
+        observe("ultra-1", 1, now + 600);
+        observe("pro-1", 10, now + 600);
+        observe("pro-2", 50, now + 600);
+        observe("pro-3", 5, now + 600);
+        let config = StickySessionConfig {
+            mode: SchedulingMode::PerformanceFirst,
+            prefer_most_remaining_quota: true,
+            ..StickySessionConfig::default()
+        };
+        let picks = |attempted: &HashSet<String>| -> HashSet<String> {
+            (0..6)
+                .map(|_| {
+                    scheduler
+                        .select_next(&tokens, "claude", &config, attempted)
+                        .unwrap()
+                        .account_id
+                        .clone()
+                })
+                .collect()
+        };
+
+        // Every tier keeps its turn; within PRO the roomiest account wins and
+        // the unobserved FREE account counts as roomy
+        let expected: HashSet<String> = ["ultra-1", "pro-2", "free-1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(picks(&HashSet::new()), expected);
+
+        let attempted = HashSet::from(["pro-2".to_string()]);
+        assert!(picks(&attempted).contains("pro-1"));
+
+        // An observation past its reset no longer counts
+        observe("pro-3", 5, now - 1);
+        assert!(picks(&HashSet::new()).contains("pro-3"));
+        assert!(!picks(&HashSet::new()).contains("pro-2"));
+    }
+
+    #[test]
+    fn test_open_circuit_account_is_skipped() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker);
+        let tokens = create_test_tokens();
+        let attempted = HashSet::new();
+
+        let now = chrono::Utc::now().timestamp();
+        for _ in 0..3 {
+            scheduler
+                .circuit_breaker()
+                .record_failure_at("claude", "ultra-1", now);
+        }
+
+        let selected = scheduler
+            .select_least_connections(&tokens, "claude", &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "pro-1");
+
+        // A sticky account with an open circuit is switched away from, not waited on
+        let config = StickySessionConfig::default();
+        match scheduler.select_with_session(
+            &tokens,
+            "claude",
+            Some("ultra-1"),
+            &HashMap::new(),
+            &config,
+            &attempted,
+        ) {
+            SchedulingDecision::UseAccount(token) => assert_ne!(token.account_id, "ultra-1"),
+            other => panic!("unexpected decision: {:?}", other),
+        }
+
+        // The circuit is per scope group
+        let selected = scheduler
+            .select_least_connections(&tokens, "gemini", &attempted)
+            .unwrap();
+        assert_eq!(selected.account_id, "ultra-1");
+    }
+
+    #[test]
+    fn test_all_unavailable_without_known_wait() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker.clone());
+        let tokens = create_test_tokens();
+        let config = StickySessionConfig::default();
+
+        // Every account was tried and none has a reset to wait for
+        let attempted: HashSet<String> = tokens.iter().map(|t| t.account_id.clone()).collect();
+        match scheduler.select_with_session(
+            &tokens,
+            "claude",
+            None,
+            &HashMap::new(),
+            &config,
+            &attempted,
+        ) {
+            SchedulingDecision::AllUnavailable { min_wait_seconds } => {
+                assert_eq!(min_wait_seconds, None)
+            }
+            other => panic!("expected AllUnavailable, got {:?}", other),
+        }
+
+        tracker.mark_limited("claude", "pro-1", 45);
+        let wait = scheduler.min_wait(&tokens, "claude");
+        assert!(matches!(wait, Some(44..=45)), "{:?}", wait);
+    }
+
+    #[test]
+    fn test_failover_prefers_same_or_better_tier() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        let scheduler = AccountScheduler::new(tracker.clone());
+        let tokens = create_test_tokens();
+        let attempted = HashSet::new();
+        let config = StickySessionConfig {
+            mode: SchedulingMode::PerformanceFirst,
+            prefer_same_tier_on_failover: true,
+            ..StickySessionConfig::default()
+        };
+        let select = || match scheduler.select_with_session(
+            &tokens,
+            "claude",
+            Some("pro-1"),
+            &HashMap::new(),
+            &config,
+            &attempted,
+        ) {
+            SchedulingDecision::UseAccount(token) => token.account_id.clone(),
+            other => panic!("unexpected decision: {:?}", other),
+        };
+
+        tracker.parse_from_error("claude", "pro-1", 429, Some("60"), "");
+        for _ in 0..3 {
+            assert_eq!(select(), "ultra-1");
+        }
+
+        // Nothing at PRO or above is left, so the session drops a tier
+        tracker.parse_from_error("claude", "ultra-1", 429, Some("60"), "");
+        assert_eq!(select(), "free-1");
+
+        // Without the option, round-robin may pick any tier
+        let config = StickySessionConfig {
+            prefer_same_tier_on_failover: false,
+            ..config
+        };
+        tracker.clear("claude", "ultra-1");
+        let picks: HashSet<String> = (0..3)
+            .map(|_| {
+                match scheduler.select_with_session(
+                    &tokens,
+                    "claude",
+                    Some("pro-1"),
+                    &HashMap::new(),
+                    &config,
+                    &attempted,
+                ) {
+                    SchedulingDecision::UseAccount(token) => token.account_id.clone(),
+                    other => panic!("unexpected decision: {:?}", other),
+                }
+            })
+            .collect();
+        assert!(picks.contains("free-1"));
+    }
 }
@@ -1,22 +1,134 @@
 //! Session Management for Sticky Account Binding
-//! 
+//!
 //! Manages the mapping between client sessions and accounts to maintain
 //! cache coherence and consistent behavior across requests.
+//!
+//! Bindings expire after an idle TTL: expired entries are treated as absent
+//! on lookup (and removed lazily), and [`SessionManager::evict_expired`]
+//! prunes the rest.
+//!
+//! A reverse index from account to session keys lets an account's bindings
+//! be dropped without a full scan and lets Balance mode count how many
+//! sessions each account carries.
+//!
+//! [`SessionManager::snapshot`] and [`SessionManager::restore`] carry live
+//! bindings across restarts; a revision counter tells the persister when
+//! there is something new to write.
+//!
+//! Each binding remembers the last few accounts the session was bound to
+//! before, so a forced rotation can move on to an account the session has
+//! not just left instead of bouncing back.
+//!
+//! The number of bindings is capped. Going over the cap evicts the least
+//! recently used tenth in one pass, so the map stays bounded even when a
+//! client invents a new session ID for every request.
 
 use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
+use super::events::{EventBus, TokenManagerEvent};
+use crate::proxy::clock::{SharedClock, SystemClock};
+
+/// Default cap on the number of session bindings
+pub const DEFAULT_MAX_SESSION_BINDINGS: usize = 50_000;
+
+/// Previous accounts remembered per session binding
+pub const ROTATION_HISTORY_LEN: usize = 4;
+
+/// A session's bound account and when the binding was last used
+#[derive(Debug, Clone)]
+struct SessionBinding {
+    quota_group: String,
+    account_id: String,
+    last_used_at: i64,
+    /// Accounts the session was bound to before, most recent first
+    history: VecDeque<String>,
+}
+
+/// Derive a session ID from the opening of a conversation
+///
+/// Clients that send no session ID repeat the same system prompt and first
+/// user message on every turn, so hashing them identifies the conversation.
+/// Trailing whitespace is ignored. Unrelated conversations with the same
+/// opening share an ID, which only costs some load spreading.
+pub fn fingerprint_request(system_prompt: &str, first_user_message: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(system_prompt.trim_end().as_bytes());
+    hasher.update([0u8]);
+    hasher.update(first_user_message.trim_end().as_bytes());
+    let digest = hasher.finalize();
+    let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("fp-{}", hex)
+}
+
+/// Session ID under which a tenant's session is bound
+///
+/// Tenants sending the same session ID get separate bindings.
+pub fn tenant_session_id(tenant: &str, session_id: &str) -> String {
+    format!("{}::{}", tenant, session_id)
+}
+
+/// A live binding as written to disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistedBinding {
+    pub quota_group: String,
+    pub session_id: String,
+    pub account_id: String,
+    /// Unix timestamp of the binding's last use
+    pub last_used_at: i64,
+}
+
 /// Session fingerprint to account binding manager
 pub struct SessionManager {
-    /// Maps (quota_group::session_id) -> account_id
-    bindings: Arc<DashMap<String, String>>,
+    /// Maps (quota_group::session_id) -> binding
+    bindings: Arc<DashMap<String, SessionBinding>>,
+    /// Maps account_id -> session keys bound to it
+    ///
+    /// Entries may briefly outlive their binding under concurrent updates;
+    /// readers check each key against `bindings`.
+    by_account: DashMap<String, HashSet<String>>,
+    /// Idle seconds after which a binding expires (0 disables expiry)
+    ttl_seconds: AtomicU64,
+    /// Where binding and eviction events are sent
+    events: EventBus,
+    /// Bumped on every change to the bindings, including last-use updates
+    revision: AtomicU64,
+    /// Most bindings kept before the least recently used are evicted
+    /// (0 disables the cap)
+    max_bindings: AtomicUsize,
+    /// Set while one caller trims the map, so others don't pile on
+    trimming: AtomicBool,
+    /// Source of the current time for last uses and expiry
+    clock: SharedClock,
 }
 
 impl SessionManager {
     /// Create a new session manager
     pub fn new() -> Self {
+        Self::with_events(EventBus::default())
+    }
+
+    /// Create a new session manager that reports bindings on `events`
+    pub fn with_events(events: EventBus) -> Self {
+        Self::with_events_and_clock(events, SystemClock::shared())
+    }
+
+    /// Create a new session manager that reports bindings on `events` and
+    /// reads the time from `clock`
+    pub fn with_events_and_clock(events: EventBus, clock: SharedClock) -> Self {
         Self {
             bindings: Arc::new(DashMap::new()),
+            by_account: DashMap::new(),
+            ttl_seconds: AtomicU64::new(0),
+            events,
+            revision: AtomicU64::new(0),
+            max_bindings: AtomicUsize::new(DEFAULT_MAX_SESSION_BINDINGS),
+            trimming: AtomicBool::new(false),
+            clock,
         }
     }
 
@@ -25,37 +137,510 @@ impl SessionManager {
         format!("{}::{}", quota_group, session_id)
     }
 
+    /// Set the idle TTL for bindings (0 disables expiry)
+    pub fn set_ttl(&self, ttl_seconds: u64) {
+        self.ttl_seconds.store(ttl_seconds, Ordering::Relaxed);
+    }
+
+    /// Get the idle TTL for bindings
+    pub fn ttl(&self) -> u64 {
+        self.ttl_seconds.load(Ordering::Relaxed)
+    }
+
+    /// Set the most bindings kept (0 disables the cap)
+    ///
+    /// Lowering the cap takes effect on the next new binding.
+    pub fn set_max_bindings(&self, max_bindings: usize) {
+        self.max_bindings.store(max_bindings, Ordering::Relaxed);
+    }
+
+    /// Get the most bindings kept
+    pub fn max_bindings(&self) -> usize {
+        self.max_bindings.load(Ordering::Relaxed)
+    }
+
+    /// Counter that changes whenever the bindings do
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Relaxed)
+    }
+
+    fn touch(&self) {
+        self.revision.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn is_expired(&self, binding: &SessionBinding, now: i64) -> bool {
+        let ttl = self.ttl();
+        ttl > 0 && now - binding.last_used_at >= ttl as i64
+    }
+
+    fn index(&self, account_id: &str, key: &str) {
+        self.by_account
+            .entry(account_id.to_string())
+            .or_default()
+            .insert(key.to_string());
+    }
+
+    fn unindex(&self, account_id: &str, key: &str) {
+        if let Some(mut keys) = self.by_account.get_mut(account_id) {
+            keys.remove(key);
+        }
+        self.by_account
+            .remove_if(account_id, |_, keys| keys.is_empty());
+    }
+
+    /// Recover the session ID from a binding's key
+    fn session_id_of<'a>(key: &'a str, binding: &SessionBinding) -> &'a str {
+        key.strip_prefix(&binding.quota_group)
+            .and_then(|rest| rest.strip_prefix("::"))
+            .unwrap_or(key)
+    }
+
+    /// Report a binding that was dropped
+    fn emit_evicted(&self, key: &str, binding: &SessionBinding) {
+        self.events.emit(TokenManagerEvent::SessionEvicted {
+            scope_group: binding.quota_group.clone(),
+            session_id: Self::session_id_of(key, binding).to_string(),
+            account_id: binding.account_id.clone(),
+        });
+    }
+
+    /// Check whether `key` is a live binding of `account_id` in a quota group
+    fn is_live_binding(&self, key: &str, account_id: &str, quota_group: &str, now: i64) -> bool {
+        self.bindings.get(key).is_some_and(|b| {
+            b.account_id == account_id && b.quota_group == quota_group && !self.is_expired(&b, now)
+        })
+    }
+
     /// Get the bound account for a session
     pub fn get_binding(&self, quota_group: &str, session_id: &str) -> Option<String> {
+        self.get_binding_at(quota_group, session_id, self.clock.now())
+    }
+
+    /// Get the bound account for a session as of `now`, refreshing its last use
+    ///
+    /// An expired binding is removed and reported as absent.
+    pub fn get_binding_at(&self, quota_group: &str, session_id: &str, now: i64) -> Option<String> {
         let key = Self::session_key(quota_group, session_id);
-        self.bindings.get(&key).map(|v| v.clone())
+
+        if let Some(mut entry) = self.bindings.get_mut(&key) {
+            if !self.is_expired(&entry, now) {
+                entry.last_used_at = now;
+                self.touch();
+                return Some(entry.account_id.clone());
+            }
+        }
+
+        if let Some((key, binding)) = self
+            .bindings
+            .remove_if(&key, |_, b| self.is_expired(b, now))
+        {
+            self.unindex(&binding.account_id, &key);
+            self.touch();
+            self.emit_evicted(&key, &binding);
+        }
+        None
+    }
+
+    /// Get the bound account for a session without counting it as a use
+    pub fn peek_binding(&self, quota_group: &str, session_id: &str) -> Option<String> {
+        let now = self.clock.now();
+        self.bindings
+            .get(&Self::session_key(quota_group, session_id))
+            .filter(|b| !self.is_expired(b, now))
+            .map(|b| b.account_id.clone())
     }
 
     /// Bind a session to an account
     pub fn set_binding(&self, quota_group: &str, session_id: &str, account_id: &str) {
+        self.set_binding_at(quota_group, session_id, account_id, self.clock.now());
+    }
+
+    /// Bind a session to an account, recording `now` as its last use
+    pub fn set_binding_at(&self, quota_group: &str, session_id: &str, account_id: &str, now: i64) {
         let key = Self::session_key(quota_group, session_id);
-        self.bindings.insert(key, account_id.to_string());
+        let mut binding = SessionBinding {
+            quota_group: quota_group.to_string(),
+            account_id: account_id.to_string(),
+            last_used_at: now,
+            history: VecDeque::new(),
+        };
+        let previous = match self.bindings.entry(key.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(mut entry) => {
+                if !self.is_expired(entry.get(), now) {
+                    binding.history = Self::next_history(entry.get(), account_id);
+                }
+                Some(entry.insert(binding))
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(binding);
+                None
+            }
+        };
+
+        if let Some(previous) = &previous {
+            if previous.account_id != account_id {
+                self.unindex(&previous.account_id, &key);
+            }
+        }
+        self.index(account_id, &key);
+        self.touch();
+        if previous.is_none() {
+            self.enforce_cap();
+        }
+
+        if previous.is_none_or(|p| p.account_id != account_id) {
+            self.events.emit(TokenManagerEvent::SessionBound {
+                scope_group: quota_group.to_string(),
+                session_id: session_id.to_string(),
+                account_id: account_id.to_string(),
+            });
+        }
+    }
+
+    /// History of a binding about to point at `account_id`
+    fn next_history(previous: &SessionBinding, account_id: &str) -> VecDeque<String> {
+        if previous.account_id == account_id {
+            return previous.history.clone();
+        }
+        std::iter::once(&previous.account_id)
+            .chain(&previous.history)
+            .filter(|id| *id != account_id)
+            .take(ROTATION_HISTORY_LEN)
+            .cloned()
+            .collect()
+    }
+
+    /// Accounts a session used recently: the bound account first, then the
+    /// ones it was bound to before, most recent first
+    ///
+    /// Does not count as a use of the binding. Empty without a live binding.
+    pub fn recent_accounts(&self, quota_group: &str, session_id: &str) -> Vec<String> {
+        let now = self.clock.now();
+        self.bindings
+            .get(&Self::session_key(quota_group, session_id))
+            .filter(|b| !self.is_expired(b, now))
+            .map(|b| {
+                std::iter::once(&b.account_id)
+                    .chain(&b.history)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Evict the least recently used bindings once the map is over the cap
+    ///
+    /// Trims down to nine tenths of the cap so the cost of the scan is spread
+    /// over many insertions. Concurrent inserts may overshoot the cap by a
+    /// few entries while a trim is running.
+    fn enforce_cap(&self) {
+        let cap = self.max_bindings();
+        if cap == 0 || self.bindings.len() <= cap {
+            return;
+        }
+        if self
+            .trimming
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        let mut by_recency: Vec<(i64, String)> = self
+            .bindings
+            .iter()
+            .map(|b| (b.last_used_at, b.key().clone()))
+            .collect();
+        let excess = by_recency.len().saturating_sub(cap - cap / 10);
+        if excess > 0 && excess < by_recency.len() {
+            by_recency.select_nth_unstable(excess);
+        }
+
+        let mut removed = 0;
+        for (last_used_at, key) in by_recency.into_iter().take(excess) {
+            // Skip bindings used again since the scan
+            if let Some((key, binding)) = self
+                .bindings
+                .remove_if(&key, |_, b| b.last_used_at == last_used_at)
+            {
+                self.unindex(&binding.account_id, &key);
+                self.emit_evicted(&key, &binding);
+                removed += 1;
+            }
+        }
+        self.trimming.store(false, Ordering::Release);
+
+        tracing::warn!(
+            "[SessionManager] Over {} session bindings, evicted {} least recently used",
+            cap,
+            removed
+        );
     }
 
     /// Remove a session binding
     pub fn remove_binding(&self, quota_group: &str, session_id: &str) -> bool {
+        self.take_binding(quota_group, session_id).is_some()
+    }
+
+    /// Remove a session binding, returning the account it was bound to
+    ///
+    /// An expired binding is removed too but reported as absent.
+    pub fn take_binding(&self, quota_group: &str, session_id: &str) -> Option<String> {
         let key = Self::session_key(quota_group, session_id);
-        self.bindings.remove(&key).is_some()
+        let (key, binding) = self.bindings.remove(&key)?;
+        self.unindex(&binding.account_id, &key);
+        self.touch();
+        self.emit_evicted(&key, &binding);
+
+        let now = self.clock.now();
+        (!self.is_expired(&binding, now)).then_some(binding.account_id)
+    }
+
+    /// Remove every binding that points at an account
+    ///
+    /// Returns the number of bindings removed.
+    pub fn remove_bindings_for_account(&self, account_id: &str) -> usize {
+        let Some((_, keys)) = self.by_account.remove(account_id) else {
+            return 0;
+        };
+
+        let removed = keys
+            .iter()
+            .filter_map(|key| {
+                self.bindings
+                    .remove_if(key.as_str(), |_, b| b.account_id == account_id)
+            })
+            .inspect(|(key, binding)| self.emit_evicted(key, binding))
+            .count();
+        if removed > 0 {
+            self.touch();
+        }
+        removed
+    }
+
+    /// Remove the bindings of one quota group that point at an account
+    ///
+    /// Returns the number of bindings removed.
+    pub fn remove_bindings_for_account_in_group(
+        &self,
+        account_id: &str,
+        quota_group: &str,
+    ) -> usize {
+        let keys: Vec<String> = match self.by_account.get(account_id) {
+            Some(keys) => keys.iter().cloned().collect(),
+            None => return 0,
+        };
+
+        let mut removed = 0;
+        for key in keys {
+            if let Some((key, binding)) = self.bindings.remove_if(&key, |_, b| {
+                b.account_id == account_id && b.quota_group == quota_group
+            }) {
+                self.unindex(&binding.account_id, &key);
+                self.emit_evicted(&key, &binding);
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            self.touch();
+        }
+        removed
+    }
+
+    /// Remove every binding that points at an account outside `account_ids`
+    ///
+    /// Returns the number of bindings removed.
+    pub fn retain_accounts(&self, account_ids: &HashSet<String>) -> usize {
+        let departed: Vec<String> = self
+            .by_account
+            .iter()
+            .filter(|e| !account_ids.contains(e.key()))
+            .map(|e| e.key().clone())
+            .collect();
+
+        departed
+            .iter()
+            .map(|account_id| self.remove_bindings_for_account(account_id))
+            .sum()
+    }
+
+    /// Remove every binding that has been idle longer than the TTL
+    ///
+    /// Returns the number of bindings removed.
+    pub fn evict_expired(&self, now: i64) -> usize {
+        let expired: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|b| self.is_expired(b.value(), now))
+            .map(|b| b.key().clone())
+            .collect();
+
+        let mut removed = 0;
+        for key in expired {
+            if let Some((key, binding)) = self
+                .bindings
+                .remove_if(&key, |_, b| self.is_expired(b, now))
+            {
+                self.unindex(&binding.account_id, &key);
+                self.emit_evicted(&key, &binding);
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            self.touch();
+            tracing::debug!(
+                "[SessionManager] Evicted {} expired session bindings",
+                removed
+            );
+        }
+        removed
+    }
+
+    /// Remove every binding in a scope group
+    ///
+    /// With `include_subscopes`, bindings in groups nested under it go too,
+    /// e.g. `gemini::image_gen` when clearing `gemini`. Returns the number
+    /// of bindings removed.
+    pub fn clear_group(&self, scope_group: &str, include_subscopes: bool) -> usize {
+        let nested_prefix = format!("{}::", scope_group);
+        let in_group = |binding: &SessionBinding| {
+            binding.quota_group == scope_group
+                || (include_subscopes && binding.quota_group.starts_with(&nested_prefix))
+        };
+
+        let keys: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|b| in_group(b.value()))
+            .map(|b| b.key().clone())
+            .collect();
+
+        let mut removed = 0;
+        for key in keys {
+            if let Some((key, binding)) = self.bindings.remove_if(&key, |_, b| in_group(b)) {
+                self.unindex(&binding.account_id, &key);
+                self.emit_evicted(&key, &binding);
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            self.touch();
+        }
+        removed
     }
 
     /// Clear all session bindings
     pub fn clear_all(&self) {
         self.bindings.clear();
+        self.by_account.clear();
+        self.touch();
+    }
+
+    /// Copy out every live binding, ordered by quota group and session
+    pub fn snapshot(&self) -> Vec<PersistedBinding> {
+        let now = self.clock.now();
+        let mut bindings: Vec<PersistedBinding> = self
+            .bindings
+            .iter()
+            .filter(|entry| !self.is_expired(entry.value(), now))
+            .map(|entry| {
+                let binding = entry.value();
+                PersistedBinding {
+                    quota_group: binding.quota_group.clone(),
+                    session_id: Self::session_id_of(entry.key(), binding).to_string(),
+                    account_id: binding.account_id.clone(),
+                    last_used_at: binding.last_used_at,
+                }
+            })
+            .collect();
+        bindings
+            .sort_by(|a, b| (&a.quota_group, &a.session_id).cmp(&(&b.quota_group, &b.session_id)));
+        bindings
+    }
+
+    /// Restore bindings from a snapshot as of `now`
+    ///
+    /// Bindings already expired under the current TTL are dropped, and
+    /// existing bindings win over restored ones. No events are sent: the
+    /// bindings were reported when they were first made.
+    ///
+    /// Returns the number of bindings restored.
+    pub fn restore(&self, bindings: Vec<PersistedBinding>, now: i64) -> usize {
+        let mut restored = 0;
+        for persisted in bindings {
+            let binding = SessionBinding {
+                quota_group: persisted.quota_group,
+                account_id: persisted.account_id,
+                last_used_at: persisted.last_used_at,
+                history: VecDeque::new(),
+            };
+            if self.is_expired(&binding, now) {
+                continue;
+            }
+
+            let key = Self::session_key(&binding.quota_group, &persisted.session_id);
+            let account_id = binding.account_id.clone();
+            if let dashmap::mapref::entry::Entry::Vacant(entry) = self.bindings.entry(key.clone()) {
+                entry.insert(binding);
+                self.index(&account_id, &key);
+                restored += 1;
+            }
+        }
+        self.enforce_cap();
+        restored
     }
 
-    /// Get the number of active bindings
+    /// Count live bindings per account in a quota group
+    ///
+    /// Accounts without bindings in the group are left out.
+    pub fn bindings_per_account(&self, quota_group: &str) -> HashMap<String, usize> {
+        let now = self.clock.now();
+        self.by_account
+            .iter()
+            .filter_map(|entry| {
+                let count = entry
+                    .value()
+                    .iter()
+                    .filter(|key| self.is_live_binding(key, entry.key(), quota_group, now))
+                    .count();
+                (count > 0).then(|| (entry.key().clone(), count))
+            })
+            .collect()
+    }
+
+    /// Count an account's live bindings per quota group
+    pub fn bindings_for_account(&self, account_id: &str) -> BTreeMap<String, usize> {
+        let now = self.clock.now();
+        let mut counts = BTreeMap::new();
+        let Some(keys) = self.by_account.get(account_id) else {
+            return counts;
+        };
+
+        for key in keys.iter() {
+            if let Some(binding) = self.bindings.get(key) {
+                if binding.account_id == account_id && !self.is_expired(&binding, now) {
+                    *counts.entry(binding.quota_group.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Get the number of live (non-expired) bindings
     pub fn len(&self) -> usize {
-        self.bindings.len()
+        let now = self.clock.now();
+        self.bindings
+            .iter()
+            .filter(|b| !self.is_expired(b.value(), now))
+            .count()
     }
 
-    /// Check if there are no bindings
+    /// Check if there are no live bindings
     pub fn is_empty(&self) -> bool {
-        self.bindings.is_empty()
+        self.len() == 0
     }
 }
 
@@ -72,53 +657,210 @@ mod tests {
     #[test]
     fn test_session_binding() {
         let manager = SessionManager::new();
-        
+
         // Initially empty
         assert!(manager.is_empty());
-        
+
         // Set binding
         manager.set_binding("claude", "session-123", "account-456");
         assert_eq!(manager.len(), 1);
-        
+
         // Get binding
         let bound = manager.get_binding("claude", "session-123");
         assert_eq!(bound, Some("account-456".to_string()));
-        
+
         // Non-existent binding
         let none = manager.get_binding("gemini", "session-123");
         assert!(none.is_none());
     }
 
+    #[test]
+    fn test_rebinding_keeps_recent_accounts() {
+        let manager = SessionManager::new();
+        manager.set_binding("claude", "s", "a");
+        manager.set_binding("claude", "s", "b");
+        manager.set_binding("claude", "s", "b");
+        manager.set_binding("claude", "s", "c");
+        assert_eq!(manager.recent_accounts("claude", "s"), vec!["c", "b", "a"]);
+
+        // Returning to an account moves it to the front without duplicates
+        manager.set_binding("claude", "s", "a");
+        assert_eq!(manager.recent_accounts("claude", "s"), vec!["a", "c", "b"]);
+
+        for id in ["d", "e", "f", "g"] {
+            manager.set_binding("claude", "s", id);
+        }
+        assert_eq!(
+            manager.recent_accounts("claude", "s").len(),
+            ROTATION_HISTORY_LEN + 1
+        );
+        assert!(manager.recent_accounts("claude", "other").is_empty());
+    }
+
     #[test]
     fn test_remove_binding() {
         let manager = SessionManager::new();
-        
+
         manager.set_binding("claude", "session-123", "account-456");
         assert_eq!(manager.len(), 1);
-        
+
         // Remove binding
         let removed = manager.remove_binding("claude", "session-123");
         assert!(removed);
         assert!(manager.is_empty());
-        
+
         // Remove non-existent
         let not_removed = manager.remove_binding("claude", "session-123");
         assert!(!not_removed);
     }
 
+    #[test]
+    fn test_take_binding_reports_account() {
+        let manager = SessionManager::new();
+        manager.set_ttl(60);
+        let now = chrono::Utc::now().timestamp();
+
+        manager.set_binding("claude", "session-1", "account-1");
+        manager.set_binding_at("claude", "stale", "account-1", now - 120);
+        assert_eq!(
+            manager.peek_binding("claude", "session-1"),
+            Some("account-1".to_string())
+        );
+        assert_eq!(manager.peek_binding("claude", "stale"), None);
+
+        assert_eq!(
+            manager.take_binding("claude", "session-1"),
+            Some("account-1".to_string())
+        );
+        assert_eq!(manager.take_binding("claude", "session-1"), None);
+        assert_eq!(manager.take_binding("claude", "stale"), None);
+        assert!(manager.bindings_for_account("account-1").is_empty());
+    }
+
+    #[test]
+    fn test_binding_events() {
+        let events = EventBus::default();
+        let mut receiver = events.subscribe();
+        let manager = SessionManager::with_events(events);
+        manager.set_ttl(60);
+
+        manager.set_binding_at("claude", "stale", "account-1", 0);
+        manager.set_binding_at("claude", "stale", "account-1", 10);
+        manager.set_binding_at("gemini::image_gen", "fresh", "account-2", 100);
+        manager.evict_expired(100);
+        manager.remove_bindings_for_account("account-2");
+
+        let event = |bound: bool, scope_group: &str, session_id: &str, account_id: &str| {
+            let (scope_group, session_id, account_id) = (
+                scope_group.to_string(),
+                session_id.to_string(),
+                account_id.to_string(),
+            );
+            if bound {
+                TokenManagerEvent::SessionBound {
+                    scope_group,
+                    session_id,
+                    account_id,
+                }
+            } else {
+                TokenManagerEvent::SessionEvicted {
+                    scope_group,
+                    session_id,
+                    account_id,
+                }
+            }
+        };
+        let received: Vec<TokenManagerEvent> =
+            std::iter::from_fn(|| receiver.try_recv().ok()).collect();
+        assert_eq!(
+            received,
+            vec![
+                event(true, "claude", "stale", "account-1"),
+                event(true, "gemini::image_gen", "fresh", "account-2"),
+                event(false, "claude", "stale", "account-1"),
+                event(false, "gemini::image_gen", "fresh", "account-2"),
+            ]
+        );
+    }
+
     #[test]
     fn test_clear_all() {
         let manager = SessionManager::new();
-        
+
         manager.set_binding("claude", "session-1", "account-1");
         manager.set_binding("claude", "session-2", "account-2");
         manager.set_binding("gemini", "session-3", "account-3");
         assert_eq!(manager.len(), 3);
-        
+
         manager.clear_all();
         assert!(manager.is_empty());
     }
 
+    #[test]
+    fn test_clear_group() {
+        let manager = SessionManager::new();
+
+        manager.set_binding("claude", "session-1", "account-1");
+        manager.set_binding("gemini", "session-2", "account-1");
+        manager.set_binding("gemini::image_gen", "session-3", "account-2");
+        // Session IDs never widen the match
+        manager.set_binding("gemini", "image_gen::session-4", "account-2");
+
+        assert_eq!(manager.clear_group("gemini", false), 2);
+        assert_eq!(
+            manager.get_binding("gemini::image_gen", "session-3"),
+            Some("account-2".to_string())
+        );
+        assert_eq!(
+            manager.get_binding("claude", "session-1"),
+            Some("account-1".to_string())
+        );
+
+        manager.set_binding("gemini", "session-2", "account-1");
+        assert_eq!(manager.clear_group("gemini", true), 2);
+        assert_eq!(manager.len(), 1);
+        assert!(manager.bindings_for_account("account-2").is_empty());
+        assert_eq!(manager.clear_group("gemini", true), 0);
+    }
+
+    #[test]
+    fn test_binding_count_is_capped() {
+        let manager = SessionManager::new();
+        let cap = 1_000;
+        manager.set_max_bindings(cap);
+        let start = chrono::Utc::now().timestamp();
+
+        for i in 0..3 * cap {
+            let now = start + i as i64;
+            manager.set_binding_at("claude", &format!("session-{}", i), "account-1", now);
+            // The first sessions stay in use throughout
+            if i % 100 == 99 {
+                for j in 0..10 {
+                    manager.get_binding_at("claude", &format!("session-{}", j), now);
+                }
+            }
+            assert!(manager.bindings.len() <= cap);
+        }
+
+        for j in 0..10 {
+            assert!(manager
+                .get_binding("claude", &format!("session-{}", j))
+                .is_some());
+        }
+        // The most recent insertions survive, the stale middle is gone
+        assert!(manager
+            .get_binding("claude", &format!("session-{}", 3 * cap - 1))
+            .is_some());
+        assert!(manager.get_binding("claude", "session-500").is_none());
+        assert_eq!(
+            manager
+                .bindings_for_account("account-1")
+                .values()
+                .sum::<usize>(),
+            manager.bindings.len()
+        );
+    }
+
     #[test]
     fn test_session_key_format() {
         let key = SessionManager::session_key("claude", "session-abc");
@@ -128,21 +870,307 @@ mod tests {
     #[test]
     fn test_overwrite_binding() {
         let manager = SessionManager::new();
-        
+
         manager.set_binding("claude", "session-1", "account-old");
         assert_eq!(
             manager.get_binding("claude", "session-1"),
             Some("account-old".to_string())
         );
-        
+
         // Overwrite with new account
         manager.set_binding("claude", "session-1", "account-new");
         assert_eq!(
             manager.get_binding("claude", "session-1"),
             Some("account-new".to_string())
         );
-        
+
         // Should still be just 1 binding
         assert_eq!(manager.len(), 1);
     }
+
+    #[test]
+    fn test_remove_bindings_for_account() {
+        let manager = SessionManager::new();
+
+        manager.set_binding("claude", "session-1", "account-1");
+        manager.set_binding("gemini", "session-2", "account-1");
+        manager.set_binding("claude", "session-3", "account-2");
+
+        assert_eq!(manager.remove_bindings_for_account("account-1"), 2);
+        assert_eq!(manager.len(), 1);
+        assert_eq!(
+            manager.get_binding("claude", "session-3"),
+            Some("account-2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_remove_bindings_for_account_in_group() {
+        let manager = SessionManager::new();
+
+        manager.set_binding("claude", "session-1", "account-1");
+        manager.set_binding("claude", "session-2", "account-1");
+        manager.set_binding("gemini", "session-3", "account-1");
+        manager.set_binding("claude", "session-4", "account-2");
+
+        assert_eq!(
+            manager.remove_bindings_for_account_in_group("account-1", "claude"),
+            2
+        );
+        assert_eq!(manager.get_binding("claude", "session-1"), None);
+        assert_eq!(
+            manager.get_binding("gemini", "session-3"),
+            Some("account-1".to_string())
+        );
+        assert_eq!(
+            manager.get_binding("claude", "session-4"),
+            Some("account-2".to_string())
+        );
+
+        // The remaining binding is still indexed under the account
+        assert_eq!(manager.remove_bindings_for_account("account-1"), 1);
+        assert_eq!(
+            manager.remove_bindings_for_account_in_group("account-1", "claude"),
+            0
+        );
+    }
+
+    #[test]
+    fn test_expired_binding_is_absent_and_removed() {
+        let manager = SessionManager::new();
+        manager.set_ttl(60);
+        let now = chrono::Utc::now().timestamp();
+
+        manager.set_binding_at("claude", "stale", "account-1", now - 120);
+        manager.set_binding_at("claude", "fresh", "account-2", now - 10);
+
+        // len() only counts live bindings
+        assert_eq!(manager.len(), 1);
+
+        assert_eq!(manager.get_binding_at("claude", "stale", now), None);
+        assert_eq!(
+            manager.get_binding_at("claude", "fresh", now),
+            Some("account-2".to_string())
+        );
+
+        // The stale entry was removed lazily, nothing left to evict
+        assert_eq!(manager.evict_expired(now), 0);
+    }
+
+    #[test]
+    fn test_lookup_refreshes_last_used() {
+        let manager = SessionManager::new();
+        manager.set_ttl(60);
+        let start = chrono::Utc::now().timestamp();
+
+        manager.set_binding_at("claude", "session-1", "account-1", start);
+        assert!(manager
+            .get_binding_at("claude", "session-1", start + 50)
+            .is_some());
+
+        // 90s after creation, but only 40s after the last use
+        assert!(manager
+            .get_binding_at("claude", "session-1", start + 90)
+            .is_some());
+        assert!(manager
+            .get_binding_at("claude", "session-1", start + 151)
+            .is_none());
+    }
+
+    #[test]
+    fn test_evict_expired() {
+        let manager = SessionManager::new();
+        manager.set_ttl(300);
+        let now = chrono::Utc::now().timestamp();
+
+        manager.set_binding_at("claude", "session-1", "account-1", now - 600);
+        manager.set_binding_at("gemini", "session-2", "account-2", now - 301);
+        manager.set_binding_at("claude", "session-3", "account-3", now);
+
+        assert_eq!(manager.evict_expired(now), 2);
+        assert_eq!(manager.len(), 1);
+        assert_eq!(manager.evict_expired(now), 0);
+    }
+
+    #[test]
+    fn test_zero_ttl_never_expires() {
+        let manager = SessionManager::new();
+        manager.set_ttl(0);
+        let now = chrono::Utc::now().timestamp();
+
+        manager.set_binding_at("claude", "session-1", "account-1", now - 86_400 * 30);
+
+        assert_eq!(manager.evict_expired(now), 0);
+        assert_eq!(
+            manager.get_binding_at("claude", "session-1", now),
+            Some("account-1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_bindings_per_account_follows_updates() {
+        let manager = SessionManager::new();
+
+        manager.set_binding("claude", "session-1", "account-1");
+        manager.set_binding("claude", "session-2", "account-1");
+        manager.set_binding("claude", "session-3", "account-2");
+        assert_eq!(
+            manager.bindings_per_account("claude"),
+            HashMap::from([("account-1".to_string(), 2), ("account-2".to_string(), 1)])
+        );
+
+        // Rebinding moves the session between accounts
+        manager.set_binding("claude", "session-2", "account-2");
+        assert_eq!(
+            manager.bindings_per_account("claude"),
+            HashMap::from([("account-1".to_string(), 1), ("account-2".to_string(), 2)])
+        );
+
+        manager.remove_binding("claude", "session-1");
+        assert_eq!(
+            manager.bindings_per_account("claude"),
+            HashMap::from([("account-2".to_string(), 2)])
+        );
+
+        manager.clear_all();
+        assert!(manager.bindings_per_account("claude").is_empty());
+    }
+
+    #[test]
+    fn test_bindings_are_counted_per_quota_group() {
+        let manager = SessionManager::new();
+
+        manager.set_binding("gemini", "session-1", "account-1");
+        manager.set_binding("gemini::image_gen", "session-2", "account-1");
+        manager.set_binding("gemini::image_gen", "session-3", "account-1");
+
+        assert_eq!(
+            manager.bindings_per_account("gemini"),
+            HashMap::from([("account-1".to_string(), 1)])
+        );
+        assert_eq!(
+            manager.bindings_for_account("account-1"),
+            BTreeMap::from([
+                ("gemini".to_string(), 1),
+                ("gemini::image_gen".to_string(), 2)
+            ])
+        );
+
+        assert_eq!(manager.remove_bindings_for_account("account-1"), 3);
+        assert!(manager.bindings_for_account("account-1").is_empty());
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn test_expired_bindings_are_not_counted() {
+        let manager = SessionManager::new();
+        manager.set_ttl(60);
+        let now = chrono::Utc::now().timestamp();
+
+        manager.set_binding_at("claude", "stale", "account-1", now - 120);
+        manager.set_binding_at("claude", "fresh", "account-1", now);
+        assert_eq!(
+            manager.bindings_per_account("claude"),
+            HashMap::from([("account-1".to_string(), 1)])
+        );
+
+        assert_eq!(manager.evict_expired(now), 1);
+        assert_eq!(manager.remove_bindings_for_account("account-1"), 1);
+    }
+
+    #[test]
+    fn test_fingerprint_request() {
+        let id = fingerprint_request("You are helpful.", "Summarize this file");
+        assert!(id.starts_with("fp-"));
+        assert_eq!(id.len(), 19);
+
+        assert_eq!(
+            fingerprint_request("You are helpful.\n", "Summarize this file  \n"),
+            id
+        );
+        assert_ne!(
+            fingerprint_request("You are helpful.", "Summarize that file"),
+            id
+        );
+        // The boundary between the two parts matters
+        assert_ne!(
+            fingerprint_request("You are helpful.Summarize", " this file"),
+            id
+        );
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let manager = SessionManager::new();
+        manager.set_ttl(60);
+        let now = chrono::Utc::now().timestamp();
+
+        manager.set_binding_at("gemini::image_gen", "session-1", "account-1", now - 30);
+        manager.set_binding_at("claude", "session-2", "account-2", now - 50);
+        manager.set_binding_at("claude", "stale", "account-2", now - 120);
+
+        let snapshot = manager.snapshot();
+        assert_eq!(
+            snapshot,
+            vec![
+                PersistedBinding {
+                    quota_group: "claude".to_string(),
+                    session_id: "session-2".to_string(),
+                    account_id: "account-2".to_string(),
+                    last_used_at: now - 50,
+                },
+                PersistedBinding {
+                    quota_group: "gemini::image_gen".to_string(),
+                    session_id: "session-1".to_string(),
+                    account_id: "account-1".to_string(),
+                    last_used_at: now - 30,
+                },
+            ]
+        );
+
+        let restored = SessionManager::new();
+        restored.set_ttl(60);
+        restored.set_binding_at("claude", "session-2", "account-3", now);
+        // The live binding wins over the restored one
+        assert_eq!(restored.restore(snapshot.clone(), now), 1);
+        assert_eq!(
+            restored.get_binding_at("gemini::image_gen", "session-1", now),
+            Some("account-1".to_string())
+        );
+        assert_eq!(
+            restored.get_binding_at("claude", "session-2", now),
+            Some("account-3".to_string())
+        );
+        assert_eq!(restored.bindings_for_account("account-1").len(), 1);
+
+        // Restored late, both bindings have idled past the TTL
+        let late = SessionManager::new();
+        late.set_ttl(60);
+        assert_eq!(late.restore(snapshot, now + 40), 0);
+        assert!(late.is_empty());
+    }
+
+    #[test]
+    fn test_revision_tracks_changes() {
+        let manager = SessionManager::new();
+        let start = manager.revision();
+
+        manager.set_binding("claude", "session-1", "account-1");
+        let bound = manager.revision();
+        assert!(bound > start);
+
+        manager.get_binding("claude", "session-1");
+        assert!(manager.revision() > bound);
+
+        let looked_up = manager.revision();
+        manager.get_binding("claude", "missing");
+        assert_eq!(manager.revision(), looked_up);
+
+        assert_eq!(
+            manager.retain_accounts(&HashSet::from(["account-2".to_string()])),
+            1
+        );
+        assert!(manager.revision() > looked_up);
+        assert!(manager.is_empty());
+    }
 }
@@ -0,0 +1,511 @@
+//! Account File Storage
+//!
+//! Every change to an account file is a read-modify-write of the whole JSON
+//! document. Token refreshes, project_id saves and account disabling can
+//! race on the same file, so each cycle runs under a per-path write lock,
+//! and the new contents replace the file atomically so a crash mid-write
+//! never leaves a truncated account behind. A file that was read encrypted
+//! is written back encrypted (see `crypto`).
+//!
+//! Account files may be grouped in subdirectories of `accounts/`, e.g.
+//! `accounts/gemini/*.json`, up to [`ACCOUNT_DIR_MAX_DEPTH`] levels deep.
+//! `accounts/disabled/` is the exception: it holds quarantined disabled
+//! accounts and is never searched for the pool.
+
+use dashmap::DashMap;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::crypto::{encrypt, open_account_file, AccountKey};
+use super::migrations::{migrate, Migration};
+use super::types::AccountFile;
+
+/// Most subdirectory levels below `accounts/` searched for account files
+pub const ACCOUNT_DIR_MAX_DEPTH: usize = 3;
+
+/// Subdirectory of `accounts/` that disabled account files are moved to
+pub const DISABLED_ACCOUNTS_DIR: &str = "disabled";
+
+/// Start of the error for an account file that is not valid JSON or not an
+/// account
+pub const INVALID_ACCOUNT_FILE: &str = "Invalid account file";
+
+/// Serializes read-modify-write cycles on account files
+pub struct AccountFileStore {
+    /// Per-path write locks
+    write_locks: DashMap<PathBuf, Arc<Mutex<()>>>,
+    /// Key for encrypted account files
+    key: Option<AccountKey>,
+}
+
+impl AccountFileStore {
+    /// Create a new store for plaintext account files
+    pub fn new() -> Self {
+        Self::with_key(None)
+    }
+
+    /// Create a new store that can also read and write encrypted files
+    pub fn with_key(key: Option<AccountKey>) -> Self {
+        Self {
+            write_locks: DashMap::new(),
+            key,
+        }
+    }
+
+    /// Read and parse an account file, decrypting and migrating it as
+    /// needed
+    ///
+    /// The migration is reported but not written back; any `update` of the
+    /// file writes the upgraded shape.
+    pub async fn read(&self, path: &Path) -> Result<(AccountFile, Migration), String> {
+        let path = path.to_path_buf();
+        let key = self.key.clone();
+        tokio::task::spawn_blocking(move || {
+            read_account(&path, key.as_ref()).map(|(account, _, migration)| (account, migration))
+        })
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+    }
+
+    /// Get or create the write lock for a file
+    fn lock_for(&self, path: &Path) -> Arc<Mutex<()>> {
+        self.write_locks
+            .entry(path.to_path_buf())
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone()
+    }
+
+    /// Move an account file to `to`, creating its directory
+    ///
+    /// Holds the file's write lock so no update is lost mid-move. An
+    /// existing file at `to` is never replaced.
+    pub async fn relocate(&self, from: &Path, to: &Path) -> Result<(), String> {
+        let lock = self.lock_for(from);
+        let _guard = lock.lock().await;
+
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        tokio::task::spawn_blocking(move || move_account_file(&from, &to))
+            .await
+            .map_err(|e| format!("Task failed: {}", e))?
+    }
+
+    /// Write a new account file, encrypted when a key is set
+    ///
+    /// An existing file at `path` is never replaced.
+    pub async fn create(&self, path: &Path, account: &AccountFile) -> Result<(), String> {
+        let lock = self.lock_for(path);
+        let _guard = lock.lock().await;
+
+        let path = path.to_path_buf();
+        let key = self.key.clone();
+        let json_str = account.to_json()?;
+        tokio::task::spawn_blocking(move || {
+            if path.exists() {
+                return Err(format!("Account file {:?} already exists", path));
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+            }
+            match key.as_ref() {
+                Some(key) => write_atomic(&path, &encrypt(key, &json_str)?),
+                None => write_atomic(&path, &json_str),
+            }
+        })
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+    }
+
+    /// Read an account file, apply `mutate` to it and write it back
+    ///
+    /// The whole cycle holds the file's write lock, so concurrent updates
+    /// to different fields never clobber each other.
+    pub async fn update<F>(&self, path: &Path, mutate: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut AccountFile) + Send + 'static,
+    {
+        let lock = self.lock_for(path);
+        let _guard = lock.lock().await;
+
+        let path = path.to_path_buf();
+        let key = self.key.clone();
+        tokio::task::spawn_blocking(move || {
+            let (mut account, encrypted, _) = read_account(&path, key.as_ref())?;
+
+            mutate(&mut account);
+
+            let json_str = account.to_json()?;
+            match key.as_ref().filter(|_| encrypted) {
+                Some(key) => write_atomic(&path, &encrypt(key, &json_str)?),
+                None => write_atomic(&path, &json_str),
+            }
+        })
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+    }
+
+    /// Replace an unparseable account file with `fields`, keeping its raw
+    /// contents under `corrupted_original`
+    ///
+    /// Holds the file's write lock. A file that parses by then is left
+    /// alone and reported as an error, so a good account is never replaced.
+    pub async fn replace_corrupted(
+        &self,
+        path: &Path,
+        fields: Map<String, Value>,
+    ) -> Result<(), String> {
+        let lock = self.lock_for(path);
+        let _guard = lock.lock().await;
+
+        let path = path.to_path_buf();
+        let key = self.key.clone();
+        tokio::task::spawn_blocking(move || {
+            if read_account(&path, key.as_ref()).is_ok() {
+                return Err(format!("Account file {:?} is no longer corrupted", path));
+            }
+            let original = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read file: {}", e))?;
+
+            let mut document = fields;
+            document.insert("corrupted_original".to_string(), Value::String(original));
+            let json_str = serde_json::to_string_pretty(&document).map_err(|e| e.to_string())?;
+            write_atomic(&path, &json_str)
+        })
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+    }
+}
+
+/// Read an account file, whether it was encrypted and how it was migrated
+fn read_account(
+    path: &Path,
+    key: Option<&AccountKey>,
+) -> Result<(AccountFile, bool, Migration), String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let (json, encrypted) =
+        open_account_file(key, content).map_err(|e| format!("{:?}: {}", path, e))?;
+    let invalid = |e: String| format!("{} {:?}: {}", INVALID_ACCOUNT_FILE, path, e);
+
+    let mut value: serde_json::Value =
+        serde_json::from_str(&json).map_err(|e| invalid(e.to_string()))?;
+    let written_at = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let migration = migrate(&mut value, written_at);
+    if let Migration::Newer { version } = migration {
+        tracing::warn!(
+            "[TokenManager] Account file {:?} has schema version {}, newer than this build; loading it as is",
+            path,
+            version
+        );
+    }
+
+    let account = AccountFile::from_value(value).map_err(invalid)?;
+    Ok((account, encrypted, migration))
+}
+
+/// Replace a file's contents via a temp file and rename
+pub(super) fn write_atomic(path: &Path, contents: &str) -> Result<(), String> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    std::fs::write(&temp_path, contents).map_err(|e| format!("Failed to write file: {}", e))?;
+    std::fs::rename(&temp_path, path).map_err(|e| {
+        let _ = std::fs::remove_file(&temp_path);
+        format!("Failed to replace file: {}", e)
+    })
+}
+
+/// Rename an account file within the accounts directory
+///
+/// Both paths live under `accounts/`, so the rename never crosses a volume.
+/// Windows refuses to rename onto an existing file while Unix silently
+/// replaces it; an existing target is an error on both.
+fn move_account_file(from: &Path, to: &Path) -> Result<(), String> {
+    if to.exists() {
+        return Err(format!("Cannot move {:?}: {:?} already exists", from, to));
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+    }
+    std::fs::rename(from, to).map_err(|e| format!("Failed to move {:?} to {:?}: {}", from, to, e))
+}
+
+/// Whether a directory entry is an account file rather than a backup or an
+/// editor artifact
+///
+/// Only `*.json` files count; `*.bak.json` copies, `*~` backups and hidden
+/// files such as Emacs lock files (`.#a.json`) are ignored.
+pub(super) fn is_account_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    name.ends_with(".json")
+        && !name.ends_with(".bak.json")
+        && !name.ends_with('~')
+        && !name.starts_with('.')
+}
+
+/// Find the account files under `dir` and its subdirectories
+///
+/// Symlinked files are followed but symlinked directories are not, so a
+/// link back up the tree cannot loop. A `disabled` directory directly under
+/// `dir` is left out. Unreadable subdirectories are logged
+/// and skipped; only an unreadable `dir` is an error.
+pub(super) fn find_account_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![(dir.to_path_buf(), 0)];
+    while let Some((current, depth)) = pending.pop() {
+        let read_dir = match std::fs::read_dir(&current) {
+            Ok(read_dir) => read_dir,
+            Err(e) if depth == 0 => return Err(e),
+            Err(e) => {
+                tracing::warn!(
+                    "[TokenManager] Skipping unreadable directory {:?}: {}",
+                    current,
+                    e
+                );
+                continue;
+            }
+        };
+
+        for entry in read_dir.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                let name = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or_default();
+                let hidden = name.starts_with('.');
+                let quarantine = depth == 0 && name == DISABLED_ACCOUNTS_DIR;
+                if depth < ACCOUNT_DIR_MAX_DEPTH && !hidden && !quarantine {
+                    pending.push((path, depth + 1));
+                }
+            } else if is_account_file(&path) && (file_type.is_file() || path.is_file()) {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Name of the subdirectory of `accounts_dir` holding an account file
+///
+/// `None` for files directly in `accounts_dir`; for deeper files, the
+/// immediate parent directory.
+pub(super) fn source_dir_of(accounts_dir: &Path, path: &Path) -> Option<String> {
+    let parent = path.parent()?;
+    if parent == accounts_dir || !parent.starts_with(accounts_dir) {
+        return None;
+    }
+    parent.file_name().map(|n| n.to_string_lossy().into_owned())
+}
+
+impl Default for AccountFileStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_account_files_walks_subdirectories() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let deep = root.join("a").join("b").join("c");
+        std::fs::create_dir_all(deep.join("d")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join(DISABLED_ACCOUNTS_DIR)).unwrap();
+        std::fs::create_dir_all(root.join("a").join(DISABLED_ACCOUNTS_DIR)).unwrap();
+        for path in [
+            root.join("top.json"),
+            root.join("a").join("one.json"),
+            deep.join("three.json"),
+            deep.join("d").join("too-deep.json"),
+            root.join(".git").join("hidden.json"),
+            root.join(DISABLED_ACCOUNTS_DIR).join("quarantined.json"),
+            // Only the top-level `disabled` is the quarantine
+            root.join("a")
+                .join(DISABLED_ACCOUNTS_DIR)
+                .join("nested.json"),
+        ] {
+            std::fs::write(path, "{}").unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root, root.join("a").join("loop")).unwrap();
+
+        let mut names: Vec<String> = find_account_files(root)
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["nested.json", "one.json", "three.json", "top.json"]
+        );
+        assert!(find_account_files(&root.join("missing")).is_err());
+
+        assert_eq!(source_dir_of(root, &root.join("top.json")), None);
+        assert_eq!(
+            source_dir_of(root, &deep.join("three.json")),
+            Some("c".to_string())
+        );
+    }
+
+    #[test]
+    fn test_backups_and_editor_artifacts_are_not_account_files() {
+        assert!(is_account_file(Path::new("/data/accounts/a.json")));
+        for name in [
+            "a.bak.json",
+            "a.json~",
+            ".#a.json",
+            "a.json.tmp",
+            "notes.txt",
+        ] {
+            assert!(
+                !is_account_file(&Path::new("/data/accounts").join(name)),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relocate_never_replaces_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("a.json");
+        let to = dir.path().join(DISABLED_ACCOUNTS_DIR).join("a.json");
+        std::fs::write(&from, "first").unwrap();
+
+        let store = AccountFileStore::new();
+        store.relocate(&from, &to).await.unwrap();
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "first");
+
+        std::fs::write(&from, "second").unwrap();
+        assert!(store
+            .relocate(&from, &to)
+            .await
+            .unwrap_err()
+            .contains("already exists"));
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "first");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_updates_keep_every_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = crate::proxy::token_manager::tests::write_account_file(dir.path(), "a", None);
+
+        let store = Arc::new(AccountFileStore::new());
+        let mut handles = Vec::new();
+        for i in 0..20 {
+            let store = store.clone();
+            let path = path.clone();
+            handles.push(tokio::spawn(async move {
+                store
+                    .update(&path, move |account| {
+                        account
+                            .extra
+                            .insert(format!("field_{}", i), serde_json::json!(i));
+                    })
+                    .await
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+
+        let content: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        for i in 0..20 {
+            assert_eq!(content[format!("field_{}", i)], i);
+        }
+        assert_eq!(content["id"], "a");
+    }
+
+    #[tokio::test]
+    async fn test_update_rejects_malformed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.json");
+        std::fs::write(&path, r#"{"id": "a"}"#).unwrap();
+
+        let store = AccountFileStore::new();
+        let err = store.update(&path, |_| {}).await.unwrap_err();
+        assert!(err.contains("missing field `email`"), "{}", err);
+        // The file is left alone
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), r#"{"id": "a"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_update_leaves_no_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = crate::proxy::token_manager::tests::write_account_file(dir.path(), "a", None);
+
+        let store = AccountFileStore::new();
+        store
+            .update(&path, |account| {
+                account.token.project_id = Some("p".to_string())
+            })
+            .await
+            .unwrap();
+
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec![std::ffi::OsString::from("a.json")]);
+    }
+
+    #[tokio::test]
+    async fn test_replace_corrupted_keeps_the_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.json");
+        std::fs::write(&path, "{\"id\": \"a\", \"tok").unwrap();
+
+        let store = AccountFileStore::new();
+        let err = store.update(&path, |_| {}).await.unwrap_err();
+        assert!(err.starts_with(INVALID_ACCOUNT_FILE), "{}", err);
+
+        let mut fields = Map::new();
+        fields.insert("disabled".to_string(), Value::Bool(true));
+        store
+            .replace_corrupted(&path, fields.clone())
+            .await
+            .unwrap();
+        let content: Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(content["disabled"], true);
+        assert_eq!(content["corrupted_original"], "{\"id\": \"a\", \"tok");
+
+        // A valid account is never replaced
+        let good = crate::proxy::token_manager::tests::write_account_file(dir.path(), "b", None);
+        let before = std::fs::read_to_string(&good).unwrap();
+        assert!(store.replace_corrupted(&good, fields).await.is_err());
+        assert_eq!(std::fs::read_to_string(&good).unwrap(), before);
+    }
+
+    #[tokio::test]
+    async fn test_update_missing_file() {
+        let store = AccountFileStore::new();
+        let err = store
+            .update(Path::new("/nonexistent/account.json"), |_| {})
+            .await
+            .unwrap_err();
+        assert!(err.starts_with("Failed to read file"));
+    }
+}
@@ -0,0 +1,269 @@
+//! Background Task Handles
+//!
+//! Periodic maintenance loops (session sweeping, proactive refresh, ...) are
+//! spawned through [`BackgroundTask::spawn_periodic`] so they share one stop
+//! mechanism. Each task publishes whether it is running, stopped or died in
+//! a panic, which [`TaskRegistry`] collects for liveness checks.
+
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::FutureExt;
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+/// What became of a background task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Running,
+    /// Exited normally: stopped, shut down or its owner went away
+    Stopped,
+    /// A tick panicked; the task is gone
+    Panicked,
+}
+
+/// Status a task shares with whoever watches it
+#[derive(Debug)]
+pub(crate) struct TaskState(AtomicU8);
+
+impl TaskState {
+    fn new() -> Self {
+        Self(AtomicU8::new(TaskStatus::Running as u8))
+    }
+
+    fn set(&self, status: TaskStatus) {
+        self.0.store(status as u8, Ordering::Relaxed);
+    }
+
+    pub(crate) fn status(&self) -> TaskStatus {
+        match self.0.load(Ordering::Relaxed) {
+            0 => TaskStatus::Running,
+            1 => TaskStatus::Stopped,
+            _ => TaskStatus::Panicked,
+        }
+    }
+}
+
+/// Handle to a periodic background task
+///
+/// Dropping the handle stops the task at its next await point; call
+/// [`BackgroundTask::stop`] to stop it and wait for it to finish.
+pub struct BackgroundTask {
+    name: &'static str,
+    stop_tx: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+    state: Arc<TaskState>,
+}
+
+impl BackgroundTask {
+    /// Run `tick` every `period` until it returns `false`, the task is
+    /// stopped or `shutdown` turns `true`
+    ///
+    /// The first tick fires one full period after spawning. The task holds
+    /// `shutdown` until it exits, so its sender can wait for every task it
+    /// signalled with `watch::Sender::closed`; dropping the sender stops the
+    /// task too.
+    pub(crate) fn spawn_periodic<F, Fut>(
+        name: &'static str,
+        period: Duration,
+        mut shutdown: watch::Receiver<bool>,
+        mut tick: F,
+    ) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        let (stop_tx, mut stop_rx) = watch::channel(false);
+        let state = Arc::new(TaskState::new());
+        let task_state = state.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately
+            interval.tick().await;
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        // A panic ends the task but is recorded, so liveness
+                        // checks can tell it from a normal stop
+                        match AssertUnwindSafe(tick()).catch_unwind().await {
+                            Ok(true) => {}
+                            Ok(false) => break,
+                            Err(_) => {
+                                tracing::error!("[TokenManager] Background task '{}' panicked", name);
+                                task_state.set(TaskStatus::Panicked);
+                                return;
+                            }
+                        }
+                    }
+                    _ = stop_rx.changed() => break,
+                    _ = async { shutdown.wait_for(|stop| *stop).await.is_ok() } => break,
+                }
+            }
+
+            task_state.set(TaskStatus::Stopped);
+            tracing::debug!("[TokenManager] Background task '{}' stopped", name);
+        });
+
+        Self {
+            name,
+            stop_tx,
+            handle,
+            state,
+        }
+    }
+
+    /// Name of the task, for logging
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Whether the task has already exited
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Whether the task is running, stopped or died in a panic
+    pub fn status(&self) -> TaskStatus {
+        self.state.status()
+    }
+
+    /// Signal the task to stop and wait for it to exit
+    pub async fn stop(self) {
+        let _ = self.stop_tx.send(true);
+        if let Err(e) = self.handle.await {
+            tracing::warn!(
+                "[TokenManager] Background task '{}' failed: {}",
+                self.name,
+                e
+            );
+        }
+    }
+}
+
+/// Status of every background task a token manager started
+#[derive(Default)]
+pub(crate) struct TaskRegistry {
+    tasks: Mutex<Vec<(&'static str, Arc<TaskState>)>>,
+}
+
+impl TaskRegistry {
+    /// Watch a task; tasks that stopped normally are forgotten
+    pub(crate) fn register(&self, task: &BackgroundTask) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|(_, state)| state.status() != TaskStatus::Stopped);
+        tasks.push((task.name, task.state.clone()));
+    }
+
+    /// Names of the tasks that died in a panic
+    pub(crate) fn panicked(&self) -> Vec<String> {
+        let tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks
+            .iter()
+            .filter(|(_, state)| state.status() == TaskStatus::Panicked)
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_periodic_task_runs_and_stops() {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counter = ticks.clone();
+
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let task = BackgroundTask::spawn_periodic(
+            "test",
+            Duration::from_millis(10),
+            shutdown_rx,
+            move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    true
+                }
+            },
+        );
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        task.stop().await;
+
+        let seen = ticks.load(Ordering::SeqCst);
+        assert!(seen >= 2);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), seen);
+    }
+
+    #[tokio::test]
+    async fn test_periodic_task_stops_on_shutdown_signal() {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let task = BackgroundTask::spawn_periodic(
+            "test",
+            Duration::from_secs(3600),
+            shutdown_rx,
+            || async { true },
+        );
+        assert_eq!(shutdown_tx.receiver_count(), 1);
+
+        shutdown_tx.send_replace(true);
+        tokio::time::timeout(Duration::from_secs(1), shutdown_tx.closed())
+            .await
+            .expect("task should release the shutdown receiver");
+        assert!(task.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_periodic_task_exits_when_tick_returns_false() {
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let task = BackgroundTask::spawn_periodic(
+            "test",
+            Duration::from_millis(5),
+            shutdown_rx,
+            || async { false },
+        );
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(task.is_finished());
+        assert_eq!(task.status(), TaskStatus::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_is_reported() {
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let registry = TaskRegistry::default();
+        let healthy = BackgroundTask::spawn_periodic(
+            "healthy",
+            Duration::from_millis(5),
+            shutdown_rx.clone(),
+            || async { true },
+        );
+        let broken = BackgroundTask::spawn_periodic(
+            "broken",
+            Duration::from_millis(5),
+            shutdown_rx,
+            || async { panic!("tick failed") },
+        );
+        registry.register(&healthy);
+        registry.register(&broken);
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(healthy.status(), TaskStatus::Running);
+        assert_eq!(broken.status(), TaskStatus::Panicked);
+        assert_eq!(registry.panicked(), vec!["broken".to_string()]);
+    }
+}
@@ -1,15 +1,19 @@
 //! Integration Tests for Token Manager
-//! 
+//!
 //! Comprehensive tests covering account selection, session stickiness,
 //! rate limit handling, and token refresh scenarios.
 
 use super::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Helper to create a test token
-fn create_test_token(id: &str, email: &str, tier: Option<&str>) -> types::ProxyToken {
+fn create_test_token(
+    id: &str,
+    email: &str,
+    tier: Option<&str>,
+) -> std::sync::Arc<types::ProxyToken> {
     let now = chrono::Utc::now().timestamp() + 3600;
-    types::ProxyToken {
+    std::sync::Arc::new(types::ProxyToken {
         account_id: id.to_string(),
         access_token: format!("token-{}", id),
         refresh_token: format!("refresh-{}", id),
@@ -19,7 +23,40 @@ fn create_test_token(id: &str, email: &str, tier: Option<&str>) -> types::ProxyT
         account_path: PathBuf::from(format!("/tmp/{}.json", id)),
         project_id: Some(format!("project-{}", id)),
         subscription_tier: tier.map(String::from),
+        proxy_weight: 1.0,
+        max_concurrent: None,
+        max_rpm: None,
+        tags: Vec::new(),
+        source_dir: None,
+        oauth_client: None,
+        outbound_proxy: None,
+        service_account: None,
+        providers: vec!["gemini".to_string(), "claude".to_string()],
+        base_url_override: None,
+    })
+}
+
+/// Helper to write an account JSON file in the format the desktop app uses
+pub(super) fn write_account_file(dir: &Path, id: &str, tier: Option<&str>) -> PathBuf {
+    let expiry = chrono::Utc::now().timestamp() + 3600;
+    let mut account = serde_json::json!({
+        "id": id,
+        "email": format!("{}@test.com", id),
+        "token": {
+            "access_token": format!("token-{}", id),
+            "refresh_token": format!("refresh-{}", id),
+            "expires_in": 3600,
+            "expiry_timestamp": expiry,
+            "project_id": format!("project-{}", id),
+        },
+    });
+    if let Some(tier) = tier {
+        account["quota"] = serde_json::json!({ "subscription_tier": tier });
     }
+
+    let path = dir.join(format!("{}.json", id));
+    std::fs::write(&path, serde_json::to_string_pretty(&account).unwrap()).unwrap();
+    path
 }
 
 #[cfg(test)]
@@ -29,11 +66,11 @@ mod session_tests {
     #[test]
     fn test_session_isolation_between_groups() {
         let manager = SessionManager::new();
-        
+
         // Bind session to different accounts in different groups
         manager.set_binding("claude", "session-1", "claude-account");
         manager.set_binding("gemini", "session-1", "gemini-account");
-        
+
         // Same session ID, different groups, different accounts
         assert_eq!(
             manager.get_binding("claude", "session-1"),
@@ -48,14 +85,14 @@ mod session_tests {
     #[test]
     fn test_session_rebinding_after_rotation() {
         let manager = SessionManager::new();
-        
+
         // Initial binding
         manager.set_binding("claude", "session-1", "account-A");
-        
+
         // Simulate rotation - remove and rebind
         manager.remove_binding("claude", "session-1");
         manager.set_binding("claude", "session-1", "account-B");
-        
+
         assert_eq!(
             manager.get_binding("claude", "session-1"),
             Some("account-B".to_string())
@@ -79,9 +116,10 @@ mod scheduling_tests {
             create_test_token("pro-1", "pro@test.com", Some("PRO")),
             create_test_token("unknown-1", "unknown@test.com", None),
         ];
-        
-        AccountScheduler::sort_by_tier(&mut tokens);
-        
+
+        let scheduler = AccountScheduler::new(Arc::new(RateLimitTracker::new()));
+        scheduler.sort_by_tier(&mut tokens);
+
         // Order should be: ULTRA, PRO, FREE, Unknown
         assert_eq!(tokens[0].account_id, "ultra-1");
         assert_eq!(tokens[1].account_id, "pro-1");
@@ -93,23 +131,23 @@ mod scheduling_tests {
     fn test_round_robin_fairness() {
         let tracker = Arc::new(RateLimitTracker::new());
         let scheduler = AccountScheduler::new(tracker);
-        
+
         let tokens = vec![
             create_test_token("a", "a@test.com", Some("PRO")),
             create_test_token("b", "b@test.com", Some("PRO")),
             create_test_token("c", "c@test.com", Some("PRO")),
         ];
-        
+
         let attempted = HashSet::new();
         let mut selected_ids = Vec::new();
-        
+
         // Select 6 times to see round-robin behavior
         for _ in 0..6 {
             if let Some(token) = scheduler.select_round_robin(&tokens, "test", &attempted) {
                 selected_ids.push(token.account_id.clone());
             }
         }
-        
+
         // Each account should be selected at least once
         assert!(selected_ids.contains(&"a".to_string()));
         assert!(selected_ids.contains(&"b".to_string()));
@@ -120,17 +158,17 @@ mod scheduling_tests {
     fn test_rate_limit_avoidance() {
         let tracker = Arc::new(RateLimitTracker::new());
         let scheduler = AccountScheduler::new(tracker.clone());
-        
+
         let tokens = vec![
             create_test_token("healthy", "healthy@test.com", Some("PRO")),
             create_test_token("limited", "limited@test.com", Some("PRO")),
         ];
-        
+
         // Mark one account as rate limited
         tracker.mark_limited("test", "limited", 60);
-        
+
         let attempted = HashSet::new();
-        
+
         // Should always select the healthy account
         for _ in 0..5 {
             let token = scheduler.select_round_robin(&tokens, "test", &attempted);
@@ -149,34 +187,51 @@ mod refresh_tests {
     #[test]
     fn test_refresh_lock_per_account() {
         let coordinator = RefreshCoordinator::new();
-        
+
         let lock_a1 = coordinator.get_lock("account-a");
         let lock_a2 = coordinator.get_lock("account-a");
         let lock_b = coordinator.get_lock("account-b");
-        
+
         // Same account gets same lock
         assert!(Arc::ptr_eq(&lock_a1, &lock_a2));
-        
+
         // Different accounts get different locks
         assert!(!Arc::ptr_eq(&lock_a1, &lock_b));
     }
 
     #[test]
     fn test_permanent_error_detection() {
+        use refresh::RefreshErrorKind::Permanent;
+
         // These should be detected as permanent
-        assert!(RefreshCoordinator::is_permanent_error(r#"{"error": "invalid_grant"}"#));
-        assert!(RefreshCoordinator::is_permanent_error("invalid_grant: Token revoked"));
-        
+        assert_eq!(
+            RefreshCoordinator::classify_error(r#"{"error": "invalid_grant"}"#),
+            Permanent
+        );
+        assert_eq!(
+            RefreshCoordinator::classify_error("invalid_grant: Token revoked"),
+            Permanent
+        );
+
         // These should NOT be detected as permanent
-        assert!(!RefreshCoordinator::is_permanent_error("rate_limit_exceeded"));
-        assert!(!RefreshCoordinator::is_permanent_error("temporary_unavailable"));
-        assert!(!RefreshCoordinator::is_permanent_error("network_timeout"));
+        assert_ne!(
+            RefreshCoordinator::classify_error("rate_limit_exceeded"),
+            Permanent
+        );
+        assert_ne!(
+            RefreshCoordinator::classify_error("temporary_unavailable"),
+            Permanent
+        );
+        assert_ne!(
+            RefreshCoordinator::classify_error("network_timeout"),
+            Permanent
+        );
     }
 
     #[test]
     fn test_token_expiry_buffer() {
         let now = chrono::Utc::now().timestamp();
-        
+
         // Token expires in 4 minutes (within 5-min buffer) - should be considered expired
         let near_expiry = types::ProxyToken {
             account_id: "test".to_string(),
@@ -188,16 +243,26 @@ mod refresh_tests {
             account_path: PathBuf::from("/tmp/test.json"),
             project_id: None,
             subscription_tier: None,
+            proxy_weight: 1.0,
+            max_concurrent: None,
+            max_rpm: None,
+            tags: Vec::new(),
+            source_dir: None,
+            oauth_client: None,
+            outbound_proxy: None,
+            service_account: None,
+            providers: vec!["gemini".to_string(), "claude".to_string()],
+            base_url_override: None,
         };
-        
+
         assert!(near_expiry.is_expired()); // Within 5-min buffer
-        
+
         // Token expires in 10 minutes - should NOT be considered expired
         let healthy = types::ProxyToken {
             timestamp: now + 600, // 10 minutes from now
             ..near_expiry
         };
-        
+
         assert!(!healthy.is_expired());
     }
 }
@@ -205,12 +270,18 @@ mod refresh_tests {
 #[cfg(test)]
 mod integration_tests {
     use super::*;
+    use crate::proxy::sticky_config::{SchedulingMode, StickySessionConfig};
     use crate::proxy::token_manager::core::TokenManager;
+    use crate::proxy::token_manager::history::EventFilter;
+    use crate::proxy::token_manager::types::{
+        GetTokenOptions, PoolHealth, Readiness, SelectionReason,
+    };
 
     #[tokio::test]
     async fn test_manager_initialization() {
-        let manager = TokenManager::new(PathBuf::from("/tmp/antiproxy-test"));
-        
+        let dir = tempfile::tempdir().unwrap();
+        let manager = TokenManager::new(dir.path().to_path_buf());
+
         assert!(manager.is_empty());
         assert_eq!(manager.len(), 0);
     }
@@ -218,20 +289,24 @@ mod integration_tests {
     #[tokio::test]
     async fn test_scheduling_config_persistence() {
         use crate::proxy::sticky_config::{SchedulingMode, StickySessionConfig};
-        
-        let manager = TokenManager::new(PathBuf::from("/tmp/antiproxy-test"));
-        
+
+        let dir = tempfile::tempdir().unwrap();
+        let manager = TokenManager::new(dir.path().to_path_buf());
+
         // Default config from StickySessionConfig is CacheFirst with 120s
         let config = manager.get_sticky_config().await;
         assert_eq!(config.mode, SchedulingMode::CacheFirst);
         assert_eq!(config.max_wait_seconds, 120);
-        
+
         // Update config to Balance mode with different wait time
-        manager.update_sticky_config(StickySessionConfig {
-            mode: SchedulingMode::Balance,
-            max_wait_seconds: 60,
-        }).await;
-        
+        manager
+            .update_sticky_config(StickySessionConfig {
+                mode: SchedulingMode::Balance,
+                max_wait_seconds: 60,
+                ..StickySessionConfig::default()
+            })
+            .await;
+
         let updated = manager.get_sticky_config().await;
         assert_eq!(updated.mode, SchedulingMode::Balance);
         assert_eq!(updated.max_wait_seconds, 60);
@@ -239,38 +314,5673 @@ mod integration_tests {
 
     #[tokio::test]
     async fn test_rate_limit_tracking() {
-        let manager = TokenManager::new(PathBuf::from("/tmp/antiproxy-test"));
-        
+        let dir = tempfile::tempdir().unwrap();
+        let manager = TokenManager::new(dir.path().to_path_buf());
+
         // Initially not rate limited
-        assert!(!manager.is_rate_limited("claude", "chat", "account-1"));
-        
+        assert!(!manager.is_rate_limited("claude", "chat", None, "account-1"));
+
         // Mark as rate limited
         manager.mark_rate_limited(
             "claude",
             "chat",
+            None,
             "account-1",
             429,
             Some("60"),
             r#"{"error": "rate_limit_exceeded"}"#,
         );
-        
+
         // Now should be rate limited
-        assert!(manager.is_rate_limited("claude", "chat", "account-1"));
-        
+        assert!(manager.is_rate_limited("claude", "chat", None, "account-1"));
+
         // Different group should not be affected
-        assert!(!manager.is_rate_limited("gemini", "chat", "account-1"));
+        assert!(!manager.is_rate_limited("gemini", "chat", None, "account-1"));
     }
 
     #[tokio::test]
     async fn test_session_management() {
-        let manager = TokenManager::new(PathBuf::from("/tmp/antiproxy-test"));
-        
+        let dir = tempfile::tempdir().unwrap();
+        let manager = TokenManager::new(dir.path().to_path_buf());
+
         // Test clear_all_sessions (public API)
         // Sessions are managed internally during get_token calls,
         // but we can verify the clear functionality works
         manager.clear_all_sessions();
-        
+
         // Verify manager is still functional after clearing
         assert!(manager.is_empty());
     }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_load_many_account_files_concurrently() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for i in 0..50 {
+            let path = write_account_file(&accounts, &format!("acc-{:02}", i), Some("PRO"));
+            if i % 5 == 0 {
+                let mut account: serde_json::Value =
+                    serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+                account["disabled"] = serde_json::json!(true);
+                std::fs::write(&path, account.to_string()).unwrap();
+            }
+        }
+        std::fs::write(accounts.join("broken.json"), "{").unwrap();
+
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(report.added, 40);
+        assert_eq!(report.skipped_disabled, 10);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(manager.len(), 40);
+        for i in 0..50 {
+            let id = format!("acc-{:02}", i);
+            assert_eq!(manager.token_for_test(&id).is_some(), i % 5 != 0, "{}", id);
+        }
+
+        // A second pass finds nothing new
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!((report.added, report.kept), (0, 40));
+    }
+
+    #[tokio::test]
+    async fn test_load_reports_unreadable_account_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "good", Some("PRO"));
+        let disabled = write_account_file(&accounts, "off", None);
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&disabled).unwrap()).unwrap();
+        account["disabled"] = serde_json::json!(true);
+        std::fs::write(&disabled, account.to_string()).unwrap();
+
+        let truncated = write_account_file(&accounts, "truncated", None);
+        let content = std::fs::read_to_string(&truncated).unwrap();
+        std::fs::write(&truncated, &content[..content.len() / 2]).unwrap();
+        let no_refresh = write_account_file(&accounts, "no-refresh", None);
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&no_refresh).unwrap()).unwrap();
+        account["token"]
+            .as_object_mut()
+            .unwrap()
+            .remove("refresh_token");
+        std::fs::write(&no_refresh, account.to_string()).unwrap();
+        std::fs::write(accounts.join("binary.json"), [0x7b, 0xff, 0xfe, 0x7d]).unwrap();
+
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(report.added, 1);
+        assert_eq!(report.skipped_disabled, 1);
+        assert_eq!(report.failed.len(), 3);
+
+        let reason = |name: &str| {
+            report
+                .failed
+                .iter()
+                .find(|(path, _)| path.file_name().unwrap() == name)
+                .map(|(_, reason)| reason.clone())
+                .unwrap()
+        };
+        assert!(
+            reason("truncated.json").contains("EOF while parsing"),
+            "{}",
+            reason("truncated.json")
+        );
+        assert!(reason("truncated.json").contains("line"));
+        let missing = reason("no-refresh.json");
+        assert!(
+            missing.contains("`token`") && missing.contains("missing field `refresh_token`"),
+            "{}",
+            missing
+        );
+        assert!(
+            reason("binary.json").contains("UTF-8"),
+            "{}",
+            reason("binary.json")
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["failed"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_load_skips_duplicate_accounts() {
+        use crate::proxy::token_manager::types::ConflictField;
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let live = write_account_file(&accounts, "a", Some("PRO"));
+        // A copy kept as a backup carries the same id and an older mtime
+        let copy = accounts.join("a-copy.json");
+        std::fs::copy(&live, &copy).unwrap();
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(&copy)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+        // Backups the ignore rule recognizes are never looked at
+        std::fs::copy(&live, accounts.join("a.bak.json")).unwrap();
+        std::fs::copy(&live, accounts.join("a.json~")).unwrap();
+
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(report.added, 1);
+        assert_eq!(manager.len(), 1);
+        assert_eq!(manager.token_for_test("a").unwrap().account_path, live);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].field, ConflictField::Id);
+        assert_eq!(report.conflicts[0].kept, live);
+        assert_eq!(report.conflicts[0].skipped, copy);
+
+        // A different id under the same email is a conflict too
+        let other = write_account_file(&accounts, "b", None);
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&other).unwrap()).unwrap();
+        account["email"] = serde_json::json!("A@test.com");
+        std::fs::write(&other, account.to_string()).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&other)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(manager.len(), 1);
+        assert_eq!(report.conflicts.len(), 2);
+        assert!(report
+            .conflicts
+            .iter()
+            .any(|c| c.field == ConflictField::Email && c.skipped == other));
+    }
+
+    #[tokio::test]
+    async fn test_load_nested_account_directories() {
+        use crate::proxy::sticky_config::RequestTypePolicy;
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(accounts.join("gemini")).unwrap();
+        std::fs::create_dir_all(accounts.join("claude").join("team-a")).unwrap();
+        write_account_file(&accounts, "top", Some("PRO"));
+        write_account_file(&accounts.join("gemini"), "g", Some("PRO"));
+        write_account_file(&accounts.join("claude").join("team-a"), "c", Some("PRO"));
+
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        assert_eq!(manager.load_accounts().await.unwrap().added, 3);
+        assert_eq!(manager.token_for_test("top").unwrap().source_dir, None);
+        assert_eq!(
+            manager.token_for_test("g").unwrap().source_dir.as_deref(),
+            Some("gemini")
+        );
+        assert_eq!(
+            manager.token_for_test("c").unwrap().source_dir.as_deref(),
+            Some("team-a")
+        );
+
+        // The directory name works as a tag in request-type policies
+        let mut config = StickySessionConfig::default();
+        config.request_type_policies.insert(
+            "image_gen".to_string(),
+            RequestTypePolicy {
+                allowed_tags: vec!["gemini".to_string()],
+                ..RequestTypePolicy::default()
+            },
+        );
+        manager.update_sticky_config(config).await;
+        for _ in 0..3 {
+            let token = manager
+                .get_token("gemini", "image_gen", None, false, None)
+                .await
+                .unwrap();
+            assert_eq!(token.account_id, "g");
+        }
+
+        // Nested accounts can be disabled and enabled by id
+        manager.disable_account("c", "test").await.unwrap();
+        assert!(manager.token_for_test("c").is_none());
+        manager.enable_account("c", false).await.unwrap();
+        assert_eq!(
+            manager.token_for_test("c").unwrap().source_dir.as_deref(),
+            Some("team-a")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_legacy_account_files_are_migrated() {
+        use crate::proxy::token_manager::CURRENT_SCHEMA_VERSION;
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let expiry = chrono::Utc::now().timestamp() + 3600;
+        // Early desktop releases kept the token fields at the top level
+        let flat = accounts.join("flat.json");
+        std::fs::write(
+            &flat,
+            serde_json::json!({
+                "id": "flat",
+                "email": "flat@test.com",
+                "access_token": "token-flat",
+                "refresh_token": "refresh-flat",
+                "expires_in": 3600,
+                "expires_at": expiry,
+                "subscription_tier": "ULTRA",
+            })
+            .to_string(),
+        )
+        .unwrap();
+        // Later ones nested them but wrote no expiry_timestamp
+        let no_expiry = accounts.join("no-expiry.json");
+        std::fs::write(
+            &no_expiry,
+            serde_json::json!({
+                "id": "no-expiry",
+                "email": "no-expiry@test.com",
+                "token": {
+                    "access_token": "token-no-expiry",
+                    "refresh_token": "refresh-no-expiry",
+                    "expires_in": 3600,
+                },
+            })
+            .to_string(),
+        )
+        .unwrap();
+        // A file from a future release still loads
+        let future = write_account_file(&accounts, "future", None);
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&future).unwrap()).unwrap();
+        account["schema_version"] = serde_json::json!(CURRENT_SCHEMA_VERSION + 1);
+        std::fs::write(&future, account.to_string()).unwrap();
+
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(report.added, 3, "{:?}", report.failed);
+
+        let token = manager.token_for_test("flat").unwrap();
+        assert_eq!(token.refresh_token, "refresh-flat");
+        assert_eq!(token.timestamp, expiry);
+        assert_eq!(token.subscription_tier.as_deref(), Some("ULTRA"));
+        let token = manager.token_for_test("no-expiry").unwrap();
+        assert!((token.timestamp - expiry).abs() <= 5);
+
+        // Without the flag the files are left as they are
+        assert!(!std::fs::read_to_string(&flat)
+            .unwrap()
+            .contains("schema_version"));
+
+        manager.set_upgrade_account_files(true);
+        manager.load_accounts().await.unwrap();
+        let upgraded: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&flat).unwrap()).unwrap();
+        assert_eq!(upgraded["schema_version"], CURRENT_SCHEMA_VERSION);
+        assert_eq!(upgraded["token"]["expiry_timestamp"], expiry);
+        assert!(
+            upgraded.get("access_token").is_none() && upgraded["token"].get("expires_at").is_none()
+        );
+        let future_file: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&future).unwrap()).unwrap();
+        assert_eq!(future_file["schema_version"], CURRENT_SCHEMA_VERSION + 1);
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_live_state_for_unchanged_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+        write_account_file(&accounts, "b", Some("PRO"));
+        write_account_file(&accounts, "c", Some("FREE"));
+
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        let first = manager.load_accounts().await.unwrap();
+        assert_eq!(first.added, 3);
+        assert_eq!(first.active(), 3);
+
+        manager.bind_session_for_test("claude", "session-a", "a");
+        manager.bind_session_for_test("claude", "session-b", "b");
+
+        // A refresh that has not reached disk yet
+        let mut refreshed = manager.token_for_test("a").unwrap();
+        refreshed.access_token = "refreshed-a".to_string();
+        refreshed.timestamp += 1800;
+        manager.insert_token_for_test(refreshed);
+
+        std::fs::remove_file(accounts.join("b.json")).unwrap();
+        write_account_file(&accounts, "c", Some("ULTRA"));
+        write_account_file(&accounts, "d", None);
+
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(
+            report,
+            LoadReport {
+                added: 1,
+                removed: 1,
+                updated: 1,
+                kept: 1,
+                ..LoadReport::default()
+            }
+        );
+        assert_eq!(manager.len(), 3);
+
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-a"),
+            Some("a".to_string())
+        );
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-b"),
+            None
+        );
+        assert_eq!(
+            manager.token_for_test("a").unwrap().access_token,
+            "refreshed-a"
+        );
+    }
+
+    /// Load a manager from a temp dir holding one PRO account per id
+    async fn manager_with_accounts(ids: &[&str]) -> (tempfile::TempDir, TokenManager) {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for id in ids {
+            write_account_file(&accounts, id, Some("PRO"));
+        }
+
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        manager.load_accounts().await.unwrap();
+        (dir, manager)
+    }
+
+    #[tokio::test]
+    async fn test_reloaded_weight_changes_selection() {
+        let (dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        let path = dir.path().join("accounts").join("a.json");
+
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        account["proxy_weight"] = serde_json::json!(0.0);
+        std::fs::write(&path, account.to_string()).unwrap();
+
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(report.updated, 1);
+        assert_eq!(manager.token_for_test("a").unwrap().proxy_weight, 0.0);
+
+        for _ in 0..4 {
+            let selected = manager
+                .get_token("claude", "chat", None, true, None)
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, "b");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_per_group_scheduling_config() {
+        use crate::proxy::sticky_config::{SchedulingMode, StickySessionConfig};
+
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager
+            .update_sticky_config_for(
+                "gemini",
+                StickySessionConfig {
+                    mode: SchedulingMode::Balance,
+                    ..StickySessionConfig::default()
+                },
+            )
+            .await;
+        assert_eq!(
+            manager.get_sticky_config().await.mode,
+            SchedulingMode::CacheFirst
+        );
+        assert_eq!(
+            manager
+                .get_sticky_config_for("gemini::image_gen")
+                .await
+                .mode,
+            SchedulingMode::Balance
+        );
+
+        // CacheFirst would wait out the limit on the bound account; Balance switches
+        manager.bind_session_for_test("gemini", "session-1", "a");
+        manager.mark_rate_limited("gemini", "chat", None, "a", 429, Some("3"), "");
+        let started = std::time::Instant::now();
+        let selected = manager
+            .get_token("gemini", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_rotation_rebinds_session() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+
+        let rotated = manager
+            .get_token("claude", "chat", None, true, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(rotated.account_id, "b");
+        assert_eq!(rotated.selected_reason, SelectionReason::ForcedRotation);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("b".to_string())
+        );
+
+        let next = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(next.account_id, "b");
+        assert_eq!(next.selected_reason, SelectionReason::StickyHit);
+    }
+
+    #[tokio::test]
+    async fn test_forced_rotation_leaves_bound_account() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+
+        // Whatever the round-robin position, rotation never returns to "a"
+        for _ in 0..4 {
+            manager.bind_session_for_test("claude", "session-1", "a");
+            let rotated = manager
+                .get_token("claude", "chat", None, true, Some("session-1"))
+                .await
+                .unwrap();
+            assert_eq!(rotated.account_id, "b");
+        }
+
+        // With nothing else healthy, the bound account is still served
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("60"), "");
+        let rotated = manager
+            .get_token("claude", "chat", None, true, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(rotated.account_id, "a");
+    }
+
+    #[tokio::test]
+    async fn test_repeated_rotation_cycles_through_pool() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b", "c"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            let rotated = manager
+                .get_token("claude", "chat", None, true, Some("session-1"))
+                .await
+                .unwrap();
+            seen.push(rotated.account_id.clone());
+        }
+
+        // Every account once, ending on the one left longest ago
+        assert_ne!(seen[0], "a");
+        assert_ne!(seen[0], seen[1]);
+        assert_eq!(seen[2], "a");
+    }
+
+    #[tokio::test]
+    async fn test_selected_token_carries_metadata() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+
+        let selected = manager
+            .get_token("gemini", "image_gen", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.subscription_tier.as_deref(), Some("PRO"));
+        assert_eq!(selected.scope_group, "gemini::image_gen");
+        assert_eq!(selected.selected_reason, SelectionReason::Scheduled);
+
+        assert_eq!(
+            selected.expires_at,
+            manager.token_for_test("a").unwrap().timestamp
+        );
+    }
+
+    #[tokio::test]
+    async fn test_balance_mode_spreads_new_sessions() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b", "c"]).await;
+        manager
+            .update_sticky_config(StickySessionConfig {
+                mode: SchedulingMode::Balance,
+                ..StickySessionConfig::default()
+            })
+            .await;
+        // Existing load on "a" is taken into account
+        manager.bind_session_for_test("claude", "existing-1", "a");
+        manager.bind_session_for_test("claude", "existing-2", "a");
+
+        for i in 0..30 {
+            let session = format!("session-{}", i);
+            manager
+                .get_token("claude", "chat", None, false, Some(&session))
+                .await
+                .unwrap();
+        }
+
+        let counts: Vec<usize> = manager
+            .list_accounts()
+            .iter()
+            .map(|a| a.session_bindings.get("claude").copied().unwrap_or(0))
+            .collect();
+        assert_eq!(counts.iter().sum::<usize>(), 32);
+        let spread = counts.iter().max().unwrap() - counts.iter().min().unwrap();
+        assert!(spread <= 1, "uneven spread: {:?}", counts);
+
+        // A removed account's sessions stop counting
+        assert!(manager.remove_account("b"));
+        let remaining: usize = manager
+            .list_accounts()
+            .iter()
+            .map(|a| a.session_bindings.get("claude").copied().unwrap_or(0))
+            .sum();
+        assert_eq!(remaining, 32 - counts[1]);
+    }
+
+    #[tokio::test]
+    async fn test_excluded_accounts_are_never_selected() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+
+        let options = GetTokenOptions {
+            session_id: Some("session-1".to_string()),
+            excluded_accounts: vec!["a".to_string()],
+            ..GetTokenOptions::default()
+        };
+        for _ in 0..3 {
+            let selected = manager
+                .get_token_with_options("claude", "chat", &options)
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, "b");
+        }
+
+        let options = GetTokenOptions {
+            excluded_accounts: vec!["a".to_string(), "b".to_string()],
+            ..GetTokenOptions::default()
+        };
+        let err = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("excluded"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_preferred_account_keeps_existing_binding() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+
+        let mut options = GetTokenOptions {
+            session_id: Some("session-1".to_string()),
+            preferred_account: Some("b".to_string()),
+            ..GetTokenOptions::default()
+        };
+        let selected = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+        assert_eq!(selected.selected_reason, SelectionReason::Preferred);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("a".to_string())
+        );
+
+        options.rebind_session = true;
+        manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("b".to_string())
+        );
+
+        // An excluded preference is ignored
+        options.excluded_accounts = vec!["b".to_string()];
+        let selected = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "a");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_preferred_account_follows_mode() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager
+            .update_sticky_config(StickySessionConfig {
+                mode: SchedulingMode::Balance,
+                ..StickySessionConfig::default()
+            })
+            .await;
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+
+        let options = GetTokenOptions {
+            preferred_account: Some("a".to_string()),
+            ..GetTokenOptions::default()
+        };
+        let selected = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+    }
+
+    #[tokio::test]
+    async fn test_pool_health_counts_per_scope_group() {
+        let (dir, manager) = manager_with_accounts(&["a", "c", "d", "e"]).await;
+        assert_eq!(
+            manager.pool_health("claude", "chat"),
+            PoolHealth {
+                total: 4,
+                healthy: 4,
+                ..PoolHealth::default()
+            }
+        );
+
+        let path = write_account_file(&dir.path().join("accounts"), "b", Some("PRO"));
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        account["token"]["expiry_timestamp"] =
+            serde_json::json!(chrono::Utc::now().timestamp() - 60);
+        account["token"]
+            .as_object_mut()
+            .unwrap()
+            .remove("project_id");
+        std::fs::write(&path, account.to_string()).unwrap();
+        manager.load_accounts().await.unwrap();
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        manager
+            .disable_account("e", "paused by admin")
+            .await
+            .unwrap();
+
+        let health = manager.pool_health("claude", "chat");
+        assert_eq!(
+            health,
+            PoolHealth {
+                total: 4,
+                healthy: 2,
+                rate_limited: 1,
+                expiring_soon: 1,
+                missing_project_id: 1,
+                disabled_recently: 1,
+                group_limited_seconds: None,
+                quota_remaining: None,
+            }
+        );
+        // Rate limits are per scope group
+        assert_eq!(manager.pool_health("gemini", "chat").healthy, 3);
+
+        // Counting is read-only
+        assert_eq!(manager.pool_health("claude", "chat"), health);
+        assert!(manager.is_rate_limited("claude", "chat", None, "a"));
+    }
+
+    #[tokio::test]
+    async fn test_readiness_follows_rate_limits_and_pool_size() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        assert_eq!(
+            manager.readiness("claude"),
+            Readiness {
+                ready: true,
+                healthy_accounts: 2,
+                reason: None
+            }
+        );
+
+        // Short limits don't make the group unready
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("60"), "");
+        assert!(manager.readiness("claude").ready);
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("3600"), "");
+        assert_eq!(manager.readiness("claude").healthy_accounts, 1);
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("3600"), "");
+        let readiness = manager.readiness("claude");
+        assert!(!readiness.ready);
+        assert_eq!(readiness.healthy_accounts, 0);
+        assert!(readiness.reason.unwrap().contains("rate limited"));
+        // Other groups are unaffected
+        assert!(manager.readiness("gemini").ready);
+
+        manager.set_readiness_rate_limit_threshold(7200);
+        assert!(manager.readiness("claude").ready);
+
+        assert!(manager.remove_account("a"));
+        assert!(manager.remove_account("b"));
+        let readiness = manager.readiness("gemini");
+        assert!(!readiness.ready);
+        assert_eq!(
+            readiness.reason.as_deref(),
+            Some("No account serves gemini")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_liveness_ignores_stopped_tasks() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        let manager = std::sync::Arc::new(manager);
+        let refresher = manager.start_background_refresh(std::time::Duration::from_secs(3600));
+        assert!(manager.liveness().alive);
+
+        refresher.stop().await;
+        manager
+            .shutdown(std::time::Duration::from_secs(1))
+            .await
+            .unwrap();
+        let liveness = manager.liveness();
+        assert!(liveness.alive);
+        assert!(liveness.panicked_tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_selection_hook_fires_when_account_changes() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        let prefer = |id: &str| GetTokenOptions {
+            preferred_account: Some(id.to_string()),
+            ..GetTokenOptions::default()
+        };
+
+        // Without a hook, selections just work
+        drop(
+            manager
+                .get_token_with_options("claude", "chat", &prefer("a"))
+                .await
+                .unwrap(),
+        );
+
+        let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        manager.set_selection_hook(Box::new(move |selected| {
+            recorded.lock().unwrap().push(selected.account_id.clone());
+        }));
+
+        for id in ["a", "a", "b", "b", "a"] {
+            let selected = manager
+                .get_token_with_options("claude", "chat", &prefer(id))
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, id);
+        }
+        assert_eq!(*calls.lock().unwrap(), vec!["a", "b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_disable_account_purges_in_memory_state() {
+        let (dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.bind_session_for_test("gemini", "session-2", "a");
+        manager.bind_session_for_test("claude", "session-3", "b");
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+
+        manager
+            .disable_account("a", "paused by admin")
+            .await
+            .unwrap();
+
+        assert!(manager.token_for_test("a").is_none());
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            None
+        );
+        assert_eq!(
+            manager.session_binding_for_test("gemini", "session-2"),
+            None
+        );
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-3"),
+            Some("b".to_string())
+        );
+        assert!(!manager.is_rate_limited("claude", "chat", None, "a"));
+
+        let path = dir.path().join("accounts").join("a.json");
+        let account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(account["disabled"], true);
+        assert_eq!(account["disabled_reason"], "paused by admin");
+    }
+
+    #[tokio::test]
+    async fn test_disable_account_redacts_reason() {
+        let (dir, manager) = manager_with_accounts(&["a"]).await;
+        let reason =
+            r#"refresh failed: {"error":"invalid_grant","refresh_token":"1//0gLeakedSecret"}"#;
+
+        manager.disable_account("a", reason).await.unwrap();
+
+        let path = dir.path().join("accounts").join("a.json");
+        let account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let written = account["disabled_reason"].as_str().unwrap();
+        assert!(!written.contains("LeakedSecret"));
+        assert!(written.contains("invalid_grant"));
+    }
+
+    #[tokio::test]
+    async fn test_disable_account_without_file_still_cleans_up() {
+        let (dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        std::fs::remove_file(dir.path().join("accounts").join("a.json")).unwrap();
+
+        manager.disable_account("a", "gone").await.unwrap();
+
+        assert!(manager.token_for_test("a").is_none());
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            None
+        );
+        assert!(!manager.is_rate_limited("claude", "chat", None, "a"));
+        assert_eq!(manager.len(), 1);
+        assert!(!dir.path().join("accounts").join("a.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_disable_account_refuses_ids_outside_the_accounts_dir() {
+        let (dir, manager) = manager_with_accounts(&["a"]).await;
+        let outside = dir.path().join("x.json");
+        std::fs::write(&outside, r#"{"id": "x"}"#).unwrap();
+
+        for id in ["../x", "..\\x", ".."] {
+            let err = manager.disable_account(id, "escape").await.unwrap_err();
+            assert!(err.contains("Invalid account id"), "{}", err);
+        }
+        assert_eq!(std::fs::read_to_string(&outside).unwrap(), r#"{"id": "x"}"#);
+        assert_eq!(manager.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disabling_an_unknown_account_changes_nothing() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        let fired = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = fired.clone();
+        manager.set_alert_hook(1, Box::new(move |event| sink.lock().unwrap().push(event)));
+        let mut events = manager.subscribe();
+
+        let err = manager.disable_account("typo", "gone").await.unwrap_err();
+
+        assert!(err.contains("not found"), "{}", err);
+        assert!(fired.lock().unwrap().is_empty());
+        assert!(events.try_recv().is_err());
+        assert_eq!(manager.pool_health("claude", "chat").disabled_recently, 0);
+        assert_eq!(manager.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disabling_the_last_account_alerts_on_the_empty_pool() {
+        use crate::proxy::token_manager::AlertEvent;
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        let fired = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = fired.clone();
+        manager.set_alert_hook(1, Box::new(move |event| sink.lock().unwrap().push(event)));
+
+        manager.disable_account("a", "gone").await.unwrap();
+
+        let fired = fired.lock().unwrap();
+        assert!(fired.iter().any(|event| matches!(
+            event,
+            AlertEvent::PoolHealthBelowThreshold { scope_group, healthy: 0, total: 0 } if scope_group == "claude"
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_disable_account_with_corrupted_file_keeps_its_contents() {
+        let (dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        let path = dir.path().join("accounts").join("a.json");
+        std::fs::write(&path, "{\"id\": \"a\", \"email\": ").unwrap();
+
+        manager.disable_account("a", "broken").await.unwrap();
+
+        assert!(manager.token_for_test("a").is_none());
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            None
+        );
+        let account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(account["disabled"], true);
+        assert_eq!(account["disabled_reason"], "broken");
+        assert_eq!(account["disabled_kind"], "permanent");
+        assert!(account["disabled_at"].is_i64());
+        assert_eq!(account["corrupted_original"], "{\"id\": \"a\", \"email\": ");
+    }
+
+    #[tokio::test]
+    async fn test_cache_first_wait_rechecks_extended_limit() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        let manager = std::sync::Arc::new(manager);
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("2"), "");
+
+        // The limit gets extended while get_token sleeps on it
+        let extender = manager.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            extender.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        });
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+
+        // A wait that ends in a rotation is a miss, not a wait
+        let report = manager.stickiness_report();
+        let counts = report.scope_group("claude").unwrap();
+        assert_eq!((counts.waits, counts.misses_rate_limited), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_cache_first_waiter_wakes_when_limit_clears() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        let manager = std::sync::Arc::new(manager);
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("100"), "");
+
+        let waiter = manager.clone();
+        let started = std::time::Instant::now();
+        let handle = tokio::spawn(async move {
+            waiter
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!handle.is_finished());
+
+        assert!(manager.clear_rate_limit("claude", "chat", None, "a"));
+        let selected = tokio::time::timeout(std::time::Duration::from_secs(1), handle)
+            .await
+            .expect("waiter was not woken")
+            .unwrap()
+            .unwrap();
+        assert_eq!(selected.account_id, "a");
+        assert_eq!(selected.selected_reason, SelectionReason::WaitedForSticky);
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_single_account_takes_the_fast_path() {
+        let (dir, manager) = manager_with_accounts(&["a"]).await;
+
+        let first = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(
+            (first.account_id.as_str(), first.selected_reason),
+            ("a", SelectionReason::Scheduled)
+        );
+        drop(first);
+        // The session is still bound, so the next request is a sticky hit
+        let second = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(second.selected_reason, SelectionReason::StickyHit);
+        assert_eq!(
+            manager
+                .session_binding_for_test("claude", "session-1")
+                .as_deref(),
+            Some("a")
+        );
+        drop(second);
+        drop(
+            manager
+                .get_token("claude", "chat", None, false, None)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(manager.single_account_selections(), 3);
+
+        // Options that steer the pick go through the full selection
+        let options = GetTokenOptions {
+            force_rotate: true,
+            ..GetTokenOptions::default()
+        };
+        drop(
+            manager
+                .get_token_with_options("claude", "chat", &options)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(manager.single_account_selections(), 3);
+
+        write_account_file(&dir.path().join("accounts"), "b", Some("PRO"));
+        manager.load_accounts().await.unwrap();
+        drop(
+            manager
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(manager.single_account_selections(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_single_account() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        let manager = std::sync::Arc::new(manager);
+        drop(
+            manager
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+                .unwrap(),
+        );
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("100"), "");
+
+        let err = manager
+            .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            crate::proxy::token_manager::GetTokenErrorKind::Unavailable
+        );
+        assert!(
+            err.retry_after_seconds
+                .is_some_and(|wait| (99..=100).contains(&wait)),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.scope_group.as_deref(), Some("claude"));
+        assert_eq!(err.attempts.len(), 1);
+
+        // A CacheFirst session bound by the fast path waits for its account
+        let waiter = manager.clone();
+        let handle = tokio::spawn(async move {
+            waiter
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!handle.is_finished());
+        assert!(manager.clear_rate_limit("claude", "chat", None, "a"));
+        let selected = tokio::time::timeout(std::time::Duration::from_secs(1), handle)
+            .await
+            .expect("waiter was not woken")
+            .unwrap()
+            .unwrap();
+        assert_eq!(selected.selected_reason, SelectionReason::WaitedForSticky);
+        assert_eq!(manager.single_account_selections(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stickiness_counters_follow_the_session() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        let manager = std::sync::Arc::new(manager);
+        let session = |force_rotate| GetTokenOptions {
+            force_rotate,
+            session_id: Some("session-1".to_string()),
+            ..GetTokenOptions::default()
+        };
+        let counts =
+            |manager: &TokenManager| manager.stickiness_report().scope_group("claude").cloned();
+
+        // Binding the session is neither a hit nor a miss
+        let bound = manager
+            .get_token_with_options("claude", "chat", &session(false))
+            .await
+            .unwrap()
+            .account_id
+            .clone();
+        assert_eq!(counts(&manager), None);
+        drop(
+            manager
+                .get_token_with_options("claude", "chat", &session(false))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(counts(&manager).unwrap().hits, 1);
+
+        // A short limit is waited out on the bound account
+        manager.mark_rate_limited("claude", "chat", None, &bound, 429, Some("100"), "");
+        let waiter = manager.clone();
+        let handle = tokio::spawn(async move {
+            waiter
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(manager.clear_rate_limit("claude", "chat", None, &bound));
+        let selected = tokio::time::timeout(std::time::Duration::from_secs(1), handle)
+            .await
+            .expect("waiter was not woken")
+            .unwrap()
+            .unwrap();
+        assert_eq!(selected.selected_reason, SelectionReason::WaitedForSticky);
+        drop(selected);
+        assert_eq!(counts(&manager).unwrap().waits, 1);
+
+        // A limit longer than the CacheFirst wait moves the session
+        manager.mark_rate_limited("claude", "chat", None, &bound, 429, Some("600"), "");
+        let moved = manager
+            .get_token_with_options("claude", "chat", &session(false))
+            .await
+            .unwrap()
+            .account_id
+            .clone();
+        assert_ne!(moved, bound);
+        assert_eq!(counts(&manager).unwrap().misses_rate_limited, 1);
+        manager.clear_rate_limit("claude", "chat", None, &bound);
+
+        drop(
+            manager
+                .get_token_with_options("claude", "chat", &session(true))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(counts(&manager).unwrap().forced_rotations, 1);
+
+        let bound = manager
+            .session_binding_for_test("claude", "session-1")
+            .unwrap();
+        manager.pause_account(&bound);
+        drop(
+            manager
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+                .unwrap(),
+        );
+        let report = counts(&manager).unwrap();
+        assert_eq!(
+            (
+                report.hits,
+                report.misses_rate_limited,
+                report.misses_account_gone,
+                report.misses_other
+            ),
+            (1, 1, 1, 0)
+        );
+        assert_eq!((report.waits, report.forced_rotations), (1, 1));
+        // The ~50ms wait counts even though it is under a second
+        assert!(
+            report.wait_seconds > 0.0 && report.wait_seconds < 1.0,
+            "{}",
+            report.wait_seconds
+        );
+        assert_eq!(report.hit_rate(), Some(0.4));
+        assert_eq!(manager.metrics_snapshot().stickiness, vec![report]);
+        assert!(manager.metrics_text().contains(
+            "antiproxy_sticky_selections_total{scope_group=\"claude\",outcome=\"hit\"} 1"
+        ));
+
+        manager.reset_stickiness();
+        assert!(manager.stickiness_report().scope_groups.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cache_first_waiter_wakes_on_config_change() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        let manager = std::sync::Arc::new(manager);
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("100"), "");
+
+        let waiter = manager.clone();
+        let handle = tokio::spawn(async move {
+            waiter
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        // Switching to Balance stops the wait; the request moves on to b
+        manager
+            .update_sticky_config(StickySessionConfig {
+                mode: SchedulingMode::Balance,
+                ..manager.get_sticky_config().await
+            })
+            .await;
+        let selected = tokio::time::timeout(std::time::Duration::from_secs(1), handle)
+            .await
+            .expect("waiter was not woken")
+            .unwrap()
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+    }
+
+    #[tokio::test]
+    async fn test_wait_over_budget_rotates_immediately() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("30"), "");
+
+        let started = std::time::Instant::now();
+        let selected = manager
+            .get_token_with_budget(
+                "claude",
+                "chat",
+                None,
+                false,
+                Some("session-1"),
+                Some(std::time::Duration::from_millis(100)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(selected.account_id, "b");
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_report_result_cools_down_failing_account() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+
+        for _ in 0..3 {
+            manager
+                .report_result(
+                    "claude",
+                    "chat",
+                    None,
+                    "a",
+                    RequestOutcome::Upstream5xx { status: 503 },
+                )
+                .await;
+        }
+        manager
+            .report_result(
+                "claude",
+                "chat",
+                None,
+                "b",
+                RequestOutcome::Success { latency_ms: 80 },
+            )
+            .await;
+
+        for _ in 0..4 {
+            let selected = manager
+                .get_token("claude", "chat", None, true, None)
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, "b");
+        }
+        let status = manager
+            .list_accounts()
+            .into_iter()
+            .find(|a| a.account_id == "a")
+            .unwrap();
+        assert_eq!(status.circuit_breakers.len(), 1);
+        assert_eq!(status.circuit_breakers[0].scope_group, "claude");
+        assert!(matches!(
+            status.circuit_breakers[0].state,
+            BreakerState::Open { .. }
+        ));
+
+        // Other scope groups still use the account
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..4 {
+            seen.insert(
+                manager
+                    .get_token("gemini", "chat", None, true, None)
+                    .await
+                    .unwrap()
+                    .account_id,
+            );
+        }
+        assert!(seen.contains("a"));
+
+        // A success clears the cooldown
+        manager
+            .report_result(
+                "claude",
+                "chat",
+                None,
+                "a",
+                RequestOutcome::Success { latency_ms: 95 },
+            )
+            .await;
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..4 {
+            seen.insert(
+                manager
+                    .get_token("claude", "chat", None, true, None)
+                    .await
+                    .unwrap()
+                    .account_id,
+            );
+        }
+        assert!(seen.contains("a"));
+
+        let stats = manager.all_account_stats();
+        assert_eq!(stats["a"].server_errors, 3);
+        assert_eq!(stats["a"].successes, 1);
+        assert_eq!(
+            manager.account_stats("b").unwrap().last_latency_ms,
+            Some(80)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_report_rate_limited_with_retry_after() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+
+        manager
+            .report_result(
+                "claude",
+                "chat",
+                None,
+                "a",
+                RequestOutcome::RateLimited {
+                    retry_after: Some(60),
+                },
+            )
+            .await;
+
+        assert!(manager.is_rate_limited("claude", "chat", None, "a"));
+        assert_eq!(manager.account_stats("a").unwrap().rate_limited, 1);
+    }
+
+    #[tokio::test]
+    async fn test_add_account_keeps_existing_sessions() {
+        let (dir, manager) = manager_with_accounts(&["a"]).await;
+        manager.bind_session_for_test("claude", "session-a", "a");
+
+        let path = write_account_file(&dir.path().join("accounts"), "b", Some("ULTRA"));
+        let added = manager.add_account(path).await.unwrap();
+
+        assert_eq!(added.account_id, "b");
+        assert_eq!(added.subscription_tier.as_deref(), Some("ULTRA"));
+        assert_eq!(manager.len(), 2);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-a"),
+            Some("a".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_disabled_account_fails() {
+        let (dir, manager) = manager_with_accounts(&[]).await;
+        let path = write_account_file(&dir.path().join("accounts"), "a", None);
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        account["disabled"] = serde_json::Value::Bool(true);
+        std::fs::write(&path, account.to_string()).unwrap();
+
+        assert!(manager.add_account(path).await.is_err());
+        assert!(manager.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_removed_account_session_rebinds() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+
+        let first = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        let other = if first.account_id == "a" { "b" } else { "a" };
+        manager.mark_rate_limited(
+            "claude",
+            "chat",
+            None,
+            &first.account_id,
+            429,
+            Some("60"),
+            "",
+        );
+
+        assert!(manager.remove_account(&first.account_id));
+        assert!(!manager.remove_account(&first.account_id));
+        assert_eq!(manager.len(), 1);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            None
+        );
+        assert!(!manager.is_rate_limited("claude", "chat", None, &first.account_id));
+
+        let next = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(next.account_id, other);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some(other.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_accounts_reports_status_without_secrets() {
+        let (dir, manager) = manager_with_accounts(&["b"]).await;
+        let path = write_account_file(&dir.path().join("accounts"), "a", Some("ULTRA"));
+        manager.add_account(path).await.unwrap();
+        manager.mark_rate_limited("claude", "image_gen", None, "b", 429, Some("60"), "");
+
+        let accounts = manager.list_accounts();
+        assert_eq!(accounts.len(), 2);
+
+        assert_eq!(accounts[0].account_id, "a");
+        assert_eq!(accounts[0].subscription_tier.as_deref(), Some("ULTRA"));
+        assert!(accounts[0].has_project_id);
+        assert!(!accounts[0].token_expired);
+        assert!(accounts[0].rate_limits.is_empty());
+
+        assert_eq!(accounts[1].account_id, "b");
+        assert_eq!(accounts[1].rate_limits.len(), 1);
+        assert_eq!(accounts[1].rate_limits[0].scope_group, "claude::image_gen");
+
+        let json = serde_json::to_string(&accounts).unwrap();
+        assert!(!json.contains("token-a") && !json.contains("refresh-a"));
+    }
+
+    #[tokio::test]
+    async fn test_log_identity_names_accounts_in_events() {
+        use crate::proxy::token_manager::LogIdentity;
+
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        assert_eq!(manager.log_identity(), LogIdentity::Email);
+        let mut events = manager.subscribe();
+
+        let mut names = Vec::new();
+        for identity in [
+            LogIdentity::Email,
+            LogIdentity::AccountId,
+            LogIdentity::HashedEmail,
+        ] {
+            manager.set_log_identity(identity);
+            let selected = manager
+                .get_token("claude", "chat", None, false, None)
+                .await
+                .unwrap();
+            // Handlers log the selected account under this name
+            assert_eq!(selected.display_name, identity.display("a", "a@test.com"));
+            drop(selected);
+            let token = manager.token_for_test("a").unwrap();
+            assert_eq!(
+                manager.display_identity(&token),
+                identity.display("a", "a@test.com")
+            );
+            while let Ok(event) = events.try_recv() {
+                if let TokenManagerEvent::AccountSelected {
+                    account_id, email, ..
+                } = event
+                {
+                    assert_eq!(account_id, "a");
+                    names.push(email);
+                }
+            }
+        }
+
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0], "a@test.com");
+        assert_eq!(names[1], "a");
+        assert_eq!(names[2].len(), 8);
+        assert!(names[2].chars().all(|c| c.is_ascii_hexdigit()));
+        assert!(!names[2].contains("test.com"));
+    }
+
+    #[tokio::test]
+    async fn test_events_for_select_then_rate_limit() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        let mut events = manager.subscribe();
+
+        manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        let selected = |reason| TokenManagerEvent::AccountSelected {
+            account_id: "a".to_string(),
+            email: "a@test.com".to_string(),
+            scope_group: "claude".to_string(),
+            reason,
+            session_id: Some("session-1".to_string()),
+        };
+        assert_eq!(
+            received,
+            vec![
+                TokenManagerEvent::SessionBound {
+                    scope_group: "claude".to_string(),
+                    session_id: "session-1".to_string(),
+                    account_id: "a".to_string(),
+                },
+                selected(SelectionReason::Scheduled),
+                selected(SelectionReason::StickyHit),
+                TokenManagerEvent::AccountRateLimited {
+                    account_id: "a".to_string(),
+                    scope_group: "claude".to_string(),
+                    remaining_seconds: 60,
+                    status: Some(429),
+                },
+            ]
+        );
+
+        let json = serde_json::to_string(&received).unwrap();
+        assert!(json.contains(r#""type":"account_selected""#));
+        assert!(!json.contains("token-a") && !json.contains("refresh-a"));
+    }
+
+    #[tokio::test]
+    async fn test_recent_events_keep_selections_and_limits() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        manager
+            .get_token("gemini", "chat", None, false, None)
+            .await
+            .unwrap();
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+
+        let all = manager.recent_events(10, &EventFilter::default());
+        let types: Vec<&str> = all
+            .iter()
+            .map(|recorded| match &recorded.event {
+                TokenManagerEvent::AccountSelected { .. } => "selected",
+                TokenManagerEvent::AccountRateLimited { .. } => "rate_limited",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(types, vec!["selected", "selected", "rate_limited"]);
+        assert!(all.windows(2).all(|pair| pair[0].at_ms <= pair[1].at_ms));
+
+        let claude = EventFilter {
+            scope_group: Some("claude".to_string()),
+            ..EventFilter::default()
+        };
+        let claude_events = manager.recent_events(10, &claude);
+        assert_eq!(claude_events.len(), 2);
+        assert!(matches!(
+            &claude_events[0].event,
+            TokenManagerEvent::AccountSelected { session_id: Some(id), .. } if id == "session-1"
+        ));
+
+        // Only the newest events come back when there are more than asked for
+        let last = manager.recent_events(1, &EventFilter::default());
+        assert_eq!(last, all[2..].to_vec());
+
+        let json = serde_json::to_string(&all).unwrap();
+        assert!(json.contains(r#""at_ms":"#));
+        assert!(!json.contains("token-a") && !json.contains("refresh-a"));
+        assert!(!json.contains("token-b") && !json.contains("refresh-b"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_follow_selection_and_rate_limits() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+
+        let snapshot = manager.metrics_snapshot();
+        assert_eq!(snapshot.selections.len(), 1);
+        assert_eq!(
+            (
+                snapshot.selections[0].account.as_str(),
+                snapshot.selections[0].count
+            ),
+            ("a", 1)
+        );
+        assert_eq!(snapshot.rate_limit_hits[0].scope_group, "claude");
+        assert_eq!(snapshot.healthy_accounts, 0);
+        assert_eq!(snapshot.session_bindings, 1);
+        assert_eq!(snapshot.get_token_latency.count, 1);
+
+        manager.set_metrics_account_label(AccountLabel::EmailHash);
+        let text = manager.metrics_text();
+        assert!(!text.contains("a@test.com") && !text.contains("account=\"a\""));
+        assert!(text.contains(&format!(
+            "account=\"{}\"",
+            metrics::email_hash("a@test.com")
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_quota_observations_steer_selection() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b", "c"]).await;
+        manager
+            .update_sticky_config(StickySessionConfig {
+                mode: SchedulingMode::PerformanceFirst,
+                prefer_most_remaining_quota: true,
+                ..StickySessionConfig::default()
+            })
+            .await;
+        let reset_at = chrono::Utc::now().timestamp() + 3600;
+        manager.record_quota_observation(
+            "gemini",
+            "chat",
+            None,
+            "a",
+            Some(900),
+            Some(1000),
+            Some(reset_at),
+        );
+        manager.record_quota_observation(
+            "gemini",
+            "chat",
+            None,
+            "b",
+            Some(20),
+            Some(1000),
+            Some(reset_at),
+        );
+        manager.record_quota_observation("gemini", "chat", None, "c", Some(400), Some(1000), None);
+
+        for _ in 0..3 {
+            let selected = manager
+                .get_token("gemini", "chat", None, false, None)
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, "a");
+        }
+        // Observations are per scope group
+        let mut picks = std::collections::HashSet::new();
+        for _ in 0..3 {
+            picks.insert(
+                manager
+                    .get_token("claude", "chat", None, false, None)
+                    .await
+                    .unwrap()
+                    .account_id,
+            );
+        }
+        assert_eq!(picks.len(), 3);
+
+        let health = manager.pool_health("gemini", "chat");
+        assert_eq!(health.quota_remaining, Some(1320));
+        assert_eq!(manager.pool_health("claude", "chat").quota_remaining, None);
+
+        let accounts = manager.list_accounts();
+        let a = accounts.iter().find(|s| s.account_id == "a").unwrap();
+        assert_eq!(a.quotas.len(), 1);
+        assert_eq!(a.quotas[0].scope_group, "gemini");
+        assert_eq!(a.quotas[0].observation.remaining, Some(900));
+        assert_eq!(a.quotas[0].observation.limit, Some(1000));
+    }
+
+    /// Serve a webhook on a local port that answers 500 to the first
+    /// `failures` POSTs and hands the body of every POST to the channel
+    async fn capture_webhook(
+        failures: usize,
+    ) -> (
+        String,
+        tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>,
+    ) {
+        use axum::http::StatusCode;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let seen = std::sync::Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new().route(
+            "/alerts",
+            axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+                let tx = tx.clone();
+                let seen = seen.clone();
+                async move {
+                    tx.send(body).unwrap();
+                    if seen.fetch_add(1, Ordering::SeqCst) < failures {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, rx)
+    }
+
+    async fn next_alert(
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>,
+    ) -> serde_json::Value {
+        tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_alert_webhook_receives_deduplicated_alerts() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b", "c"]).await;
+        let (url, mut rx) = capture_webhook(0).await;
+        manager.set_alert_webhook(2, &url);
+
+        // One account down leaves enough healthy ones
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("60"), "");
+        assert_eq!(
+            next_alert(&mut rx).await,
+            serde_json::json!({"type": "pool_health_below_threshold", "scope_group": "claude", "healthy": 1, "total": 3})
+        );
+
+        // The pool is still degraded, but the alert already fired
+        manager.mark_rate_limited("claude", "chat", None, "c", 429, Some("30"), "");
+        assert!(manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .is_err());
+        assert!(manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .is_err());
+        let alert = next_alert(&mut rx).await;
+        assert_eq!(alert["type"], "all_accounts_rate_limited");
+        assert_eq!(alert["scope_group"], "claude");
+        assert!(
+            matches!(alert["min_wait_seconds"].as_u64(), Some(29..=30)),
+            "{}",
+            alert
+        );
+
+        manager.disable_account("c", "invalid_grant").await.unwrap();
+        assert_eq!(
+            next_alert(&mut rx).await,
+            serde_json::json!({"type": "account_disabled", "account_id": "c", "reason": "invalid_grant", "kind": "permanent"})
+        );
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(200), rx.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_alert_delivery_is_retried() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        let (url, mut rx) = capture_webhook(1).await;
+        manager.set_alert_webhook(1, &url);
+
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        let first = next_alert(&mut rx).await;
+        let retried = next_alert(&mut rx).await;
+        assert_eq!(first, retried);
+        assert_eq!(first["type"], "pool_health_below_threshold");
+        // Selection is unaffected by the failing endpoint
+        assert!(manager
+            .get_token("gemini", "chat", None, false, None)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_long_limit_unbinds_sessions_of_the_account() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        for (quota_group, session_id) in [
+            ("claude", "session-1"),
+            ("claude", "session-2"),
+            ("gemini", "session-3"),
+        ] {
+            manager
+                .get_token(quota_group, "chat", None, false, Some(session_id))
+                .await
+                .unwrap();
+        }
+
+        let unbind_on_long_limit = |enabled| StickySessionConfig {
+            unbind_on_long_limit: enabled,
+            ..StickySessionConfig::default()
+        };
+
+        // A limit the sessions would wait out keeps them bound
+        manager
+            .update_sticky_config(unbind_on_long_limit(true))
+            .await;
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("30"), "");
+        assert_eq!(
+            manager
+                .get_session_binding("claude", "chat", "session-1")
+                .as_deref(),
+            Some("a")
+        );
+        manager.clear_rate_limit("claude", "chat", None, "a");
+
+        // Without the flag the bindings wait out even a long limit
+        manager
+            .update_sticky_config(unbind_on_long_limit(false))
+            .await;
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("86400"), "");
+        assert_eq!(
+            manager
+                .get_session_binding("claude", "chat", "session-1")
+                .as_deref(),
+            Some("a")
+        );
+        manager.clear_rate_limit("claude", "chat", None, "a");
+
+        manager
+            .update_sticky_config(unbind_on_long_limit(true))
+            .await;
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("86400"), "");
+        assert_eq!(
+            manager.get_session_binding("claude", "chat", "session-1"),
+            None
+        );
+        assert_eq!(
+            manager.get_session_binding("claude", "chat", "session-2"),
+            None
+        );
+        assert_eq!(
+            manager
+                .get_session_binding("gemini", "chat", "session-3")
+                .as_deref(),
+            Some("a")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_wide_limit_short_circuits_selection() {
+        use crate::proxy::sticky_config::GroupLimitSignature;
+        use crate::proxy::token_manager::GetTokenErrorKind;
+
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager
+            .update_sticky_config(StickySessionConfig {
+                group_limit_signatures: vec![GroupLimitSignature {
+                    status: 429,
+                    body_patterns: vec!["per source IP".to_string()],
+                }],
+                ..StickySessionConfig::default()
+            })
+            .await;
+
+        // An ordinary 429 still limits the account only
+        manager.mark_rate_limited(
+            "claude",
+            "chat",
+            None,
+            "a",
+            429,
+            Some("60"),
+            "Resource exhausted",
+        );
+        assert!(manager.is_rate_limited("claude", "chat", None, "a"));
+        assert_eq!(
+            manager.pool_health("claude", "chat").group_limited_seconds,
+            None
+        );
+        manager.clear_rate_limit("claude", "chat", None, "a");
+
+        // A matching 429 limits the group, not the account that saw it
+        let body = r#"{"error": {"code": 429, "message": "Quota exceeded per source IP"}}"#;
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("90"), body);
+        assert!(!manager.is_rate_limited("claude", "chat", None, "b"));
+        let snapshot = manager.rate_limit_snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(
+            (
+                snapshot[0].scope_group.as_str(),
+                snapshot[0].account_id.as_deref()
+            ),
+            ("claude", None)
+        );
+
+        let err = manager
+            .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::Unavailable);
+        assert!(err.attempts.is_empty());
+        assert!(matches!(err.retry_after_seconds, Some(s) if s > 85 && s <= 90));
+        assert_eq!(err.scope_group.as_deref(), Some("claude"));
+        assert!(err.soonest_account.is_some());
+        let preview_err = manager
+            .dry_run_selection("claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert_eq!(preview_err.scope_group.as_deref(), Some("claude"));
+        assert!(preview_err.soonest_account.is_some());
+        assert!(
+            matches!(manager.next_available_in("claude", "chat"), Some(s) if s > 85 && s <= 90)
+        );
+
+        // An account limited past the group's reset is not the soonest
+        manager.mark_rate_limited(
+            "claude",
+            "chat",
+            None,
+            "a",
+            429,
+            Some("300"),
+            "Resource exhausted",
+        );
+        let err = manager
+            .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.soonest_account.as_deref(), Some("b"));
+        assert!(matches!(err.retry_after_seconds, Some(s) if s > 85 && s <= 90));
+        manager.clear_rate_limit("claude", "chat", None, "a");
+        let health = manager.pool_health("claude", "chat");
+        assert_eq!(health.healthy, 0);
+        assert!(health.group_limited_seconds.is_some());
+
+        // Other groups are unaffected
+        assert!(manager
+            .get_token("gemini", "chat", None, false, None)
+            .await
+            .is_ok());
+
+        assert!(manager.clear_group_limit("claude", "chat", None));
+        assert!(!manager.clear_group_limit("claude", "chat", None));
+        assert!(manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .is_ok());
+
+        manager.mark_group_limited("gemini", "chat", None, 30);
+        assert!(manager
+            .get_token("gemini", "chat", None, false, None)
+            .await
+            .is_err());
+        assert_eq!(manager.pool_health("gemini", "chat").healthy, 0);
+    }
+
+    #[tokio::test]
+    async fn test_clear_rate_limit_and_snapshot() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("3600"), "");
+        manager.mark_rate_limited("gemini", "image_gen", None, "b", 503, None, "");
+
+        let snapshot = manager.rate_limit_snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(
+            (
+                snapshot[0].scope_group.as_str(),
+                snapshot[0].account_id.as_deref()
+            ),
+            ("claude", Some("a"))
+        );
+        assert_eq!(snapshot[0].status, Some(429));
+        assert!(snapshot[0].remaining_seconds > 3500);
+        assert_eq!(snapshot[1].scope_group, "gemini::image_gen");
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json[1]["reason"], "server_error");
+
+        assert!(manager.clear_rate_limit("claude", "chat", None, "a"));
+        assert!(!manager.clear_rate_limit("claude", "chat", None, "a"));
+        assert!(!manager.is_rate_limited("claude", "chat", None, "a"));
+        assert!(manager.is_rate_limited("gemini", "image_gen", None, "b"));
+        assert_eq!(manager.rate_limit_snapshot().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_model_scoped_limit_leaves_other_models_usable() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+        manager
+            .update_sticky_config_for(
+                "gemini",
+                StickySessionConfig {
+                    scope_by_model: true,
+                    ..StickySessionConfig::default()
+                },
+            )
+            .await;
+
+        let pro = Some("gemini-2.5-pro");
+        let flash = Some("gemini-2.0-flash");
+        manager.mark_rate_limited("gemini", "chat", pro, "a", 429, Some("60"), "");
+        assert!(manager.is_rate_limited("gemini", "chat", pro, "a"));
+        assert!(!manager.is_rate_limited("gemini", "chat", flash, "a"));
+
+        assert!(manager
+            .get_token("gemini", "chat", pro, false, None)
+            .await
+            .is_err());
+        let selected = manager
+            .get_token("gemini", "chat", flash, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "a");
+        assert_eq!(selected.scope_group, "gemini::gemini-2.0-flash");
+
+        // The session is bound per group, not per model
+        assert_eq!(
+            manager.session_binding_for_test("gemini", "session-1"),
+            Some("a".to_string())
+        );
+
+        // Explicit requests honour the same model scope
+        let err = manager
+            .get_token_for_account("a", "gemini", "chat", pro)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, AccountTokenError::RateLimited { .. }),
+            "{:?}",
+            err
+        );
+        let selected = manager
+            .get_token_for_account("a", "gemini", "chat", flash)
+            .await
+            .unwrap();
+        assert_eq!(selected.scope_group, "gemini::gemini-2.0-flash");
+    }
+
+    #[tokio::test]
+    async fn test_model_is_ignored_unless_group_opts_in() {
+        let (_dir, manager) = manager_with_accounts(&["a"]).await;
+
+        manager.mark_rate_limited(
+            "gemini",
+            "chat",
+            Some("gemini-2.5-pro"),
+            "a",
+            429,
+            Some("60"),
+            "",
+        );
+        assert!(manager.is_rate_limited("gemini", "chat", Some("gemini-2.0-flash"), "a"));
+        assert!(manager.is_rate_limited("gemini", "chat", None, "a"));
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_skips_saturated_accounts() {
+        let (dir, manager) = manager_with_accounts(&["b"]).await;
+        let path = write_account_file(&dir.path().join("accounts"), "a", Some("ULTRA"));
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        account["max_concurrent"] = serde_json::json!(1);
+        std::fs::write(&path, account.to_string()).unwrap();
+        manager.add_account(path).await.unwrap();
+
+        let first = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(first.account_id, "a");
+
+        // The bound account is full, so the session spills over
+        let second = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(second.account_id, "b");
+
+        let accounts = manager.list_accounts();
+        assert_eq!(
+            (accounts[0].in_flight, accounts[0].max_concurrent),
+            (1, Some(1))
+        );
+        assert_eq!(
+            (accounts[1].in_flight, accounts[1].max_concurrent),
+            (1, None)
+        );
+
+        drop(first);
+        let third = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(third.account_id, "a");
+    }
+
+    #[tokio::test]
+    async fn test_max_rpm_throttles_selection() {
+        let (dir, manager) = manager_with_accounts(&["b"]).await;
+        let path = write_account_file(&dir.path().join("accounts"), "a", Some("ULTRA"));
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        account["max_rpm"] = serde_json::json!(2);
+        std::fs::write(&path, account.to_string()).unwrap();
+        manager.add_account(path).await.unwrap();
+
+        for _ in 0..2 {
+            let selected = manager
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, "a");
+        }
+        // a's minute is full, so the third request goes elsewhere
+        let third = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(third.account_id, "b");
+        // Each scope group has its own minute
+        let other = manager
+            .get_token("gemini", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(other.account_id, "a");
+        assert_eq!(manager.list_accounts()[0].max_rpm, Some(2));
+
+        let snapshot = manager.metrics_snapshot();
+        let counted: Vec<(&str, &str, u64)> = snapshot
+            .requests_last_minute
+            .iter()
+            .map(|c| (c.account.as_str(), c.scope_group.as_str(), c.count))
+            .collect();
+        assert_eq!(
+            counted,
+            vec![("a", "claude", 2), ("a", "gemini", 1), ("b", "claude", 1)]
+        );
+
+        // With nothing else left, the error says when the minute frees up
+        manager.pause_account("b");
+        let err = manager
+            .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::Unavailable);
+        assert_eq!(err.soonest_account.as_deref(), Some("a"));
+        let wait = err.retry_after_seconds.unwrap();
+        assert!((59..=60).contains(&wait), "{}", wait);
+        assert!(err.attempts.iter().any(|a| a.account_id == "a"
+            && matches!(a.outcome, AttemptOutcome::Throttled { max_rpm: 2, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_all_busy_is_distinct_from_unavailable() {
+        let (dir, manager) = manager_with_accounts(&[]).await;
+        let path = write_account_file(&dir.path().join("accounts"), "a", None);
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        account["max_concurrent"] = serde_json::json!(1);
+        std::fs::write(&path, account.to_string()).unwrap();
+        manager.add_account(path).await.unwrap();
+
+        let options = GetTokenOptions::default();
+        let held = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        let err = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::AllBusy);
+        assert!(matches!(
+            err.attempts[0].outcome,
+            AttemptOutcome::Busy {
+                in_flight: 1,
+                max_concurrent: 1
+            }
+        ));
+
+        let err = manager
+            .get_token_for_account("a", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            AccountTokenError::Busy {
+                in_flight: 1,
+                max_concurrent: 1
+            }
+        );
+
+        drop(held);
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        let err = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn test_failover_binding_stays_within_tier() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for (id, tier) in [("pro", "PRO"), ("ultra", "ULTRA"), ("free", "FREE")] {
+            write_account_file(&accounts, id, Some(tier));
+        }
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        manager.load_accounts().await.unwrap();
+        manager
+            .update_sticky_config(StickySessionConfig {
+                mode: SchedulingMode::PerformanceFirst,
+                prefer_same_tier_on_failover: true,
+                ..StickySessionConfig::default()
+            })
+            .await;
+
+        manager.bind_session_for_test("claude", "session-1", "pro");
+        manager.mark_rate_limited("claude", "chat", None, "pro", 429, Some("60"), "");
+
+        for _ in 0..3 {
+            let selected = manager
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, "ultra");
+        }
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("ultra".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tier_order_reload_applies_without_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for (id, tier) in [
+            ("free", "FREE"),
+            ("standard", "standard-tier"),
+            ("ultra", "ULTRA"),
+        ] {
+            write_account_file(&accounts, id, Some(tier));
+        }
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        manager.load_accounts().await.unwrap();
+        let order = |manager: &TokenManager| -> Vec<String> {
+            manager
+                .list_accounts()
+                .into_iter()
+                .map(|a| a.account_id)
+                .collect()
+        };
+
+        assert_eq!(order(&manager), vec!["ultra", "free", "standard"]);
+
+        manager
+            .update_sticky_config(StickySessionConfig {
+                unknown_tier_position: Some(2),
+                ..StickySessionConfig::default()
+            })
+            .await;
+        assert_eq!(order(&manager), vec!["ultra", "standard", "free"]);
+
+        // Least-recently-used breaks ties between fresh accounts by tier
+        manager
+            .update_sticky_config(StickySessionConfig {
+                mode: SchedulingMode::LeastRecentlyUsed,
+                tier_order: vec!["free".to_string()],
+                ..StickySessionConfig::default()
+            })
+            .await;
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "free");
+    }
+
+    #[tokio::test]
+    async fn test_only_accounts_serving_the_provider_are_selected() {
+        use crate::proxy::token_manager::types::{AccountTokenError, GetTokenErrorKind};
+        use std::collections::HashSet;
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for (id, providers) in [
+            ("both", None),
+            ("gemini-only", Some(vec!["Gemini"])),
+            ("claude-only", Some(vec!["claude"])),
+        ] {
+            let path = write_account_file(&accounts, id, Some("PRO"));
+            let mut account: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            if let Some(providers) = providers {
+                account["providers"] = serde_json::json!(providers);
+            }
+            if id == "claude-only" {
+                account["base_url_override"] = serde_json::json!("https://claude.internal.example");
+            }
+            std::fs::write(&path, account.to_string()).unwrap();
+        }
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        manager.load_accounts().await.unwrap();
+
+        let mut claude = HashSet::new();
+        for _ in 0..6 {
+            let selected = manager
+                .get_token("claude", "chat", None, true, None)
+                .await
+                .unwrap();
+            assert_eq!(selected.provider, "claude");
+            let expected_url = (selected.account_id == "claude-only")
+                .then(|| "https://claude.internal.example".to_string());
+            assert_eq!(selected.base_url_override, expected_url);
+            claude.insert(selected.account_id.clone());
+        }
+        assert_eq!(
+            claude,
+            HashSet::from(["both".to_string(), "claude-only".to_string()])
+        );
+
+        let mut gemini = HashSet::new();
+        for _ in 0..6 {
+            let selected = manager
+                .get_token("gemini", "chat", None, true, None)
+                .await
+                .unwrap();
+            assert_eq!(selected.provider, "gemini");
+            gemini.insert(selected.account_id.clone());
+        }
+        assert_eq!(
+            gemini,
+            HashSet::from(["both".to_string(), "gemini-only".to_string()])
+        );
+        assert_eq!(manager.pool_health("claude", "chat").total, 2);
+
+        // Explicit requests respect the capability too
+        let err = manager
+            .get_token_for_account("gemini-only", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, AccountTokenError::ProviderNotServed { ref provider } if provider == "claude")
+        );
+
+        // Without an account serving claude the error says so
+        assert!(manager.remove_account("both"));
+        assert!(manager.remove_account("claude-only"));
+        let err = manager
+            .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::NoEligibleAccounts);
+        assert!(err.message.contains("serves claude"), "{}", err.message);
+    }
+
+    #[tokio::test]
+    async fn test_next_available_in_counts_only_accounts_serving_the_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for (id, provider) in [("gemini-only", "gemini"), ("claude-only", "claude")] {
+            let path = write_account_file(&accounts, id, Some("PRO"));
+            let mut account: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            account["providers"] = serde_json::json!([provider]);
+            std::fs::write(&path, account.to_string()).unwrap();
+        }
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        manager.load_accounts().await.unwrap();
+
+        // The free gemini account can't take a claude request
+        manager.mark_rate_limited("claude", "chat", None, "claude-only", 429, Some("90"), "");
+        assert!(matches!(
+            manager.next_available_in("claude", "chat"),
+            Some(89..=90)
+        ));
+        assert_eq!(manager.next_available_in("gemini", "chat"), Some(0));
+
+        assert!(manager.remove_account("claude-only"));
+        assert_eq!(manager.next_available_in("claude", "chat"), None);
+    }
+
+    #[tokio::test]
+    async fn test_request_type_policy_limits_image_gen_to_ultra() {
+        use crate::proxy::sticky_config::RequestTypePolicy;
+        use crate::proxy::token_manager::types::GetTokenErrorKind;
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for (id, tier) in [("ultra", "ULTRA"), ("pro", "PRO"), ("free", "FREE")] {
+            write_account_file(&accounts, id, Some(tier));
+        }
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        manager.load_accounts().await.unwrap();
+        manager
+            .update_sticky_config(StickySessionConfig {
+                mode: SchedulingMode::PerformanceFirst,
+                request_type_policies: [(
+                    "image_gen".to_string(),
+                    RequestTypePolicy {
+                        allowed_tiers: vec!["ULTRA".to_string()],
+                        ..RequestTypePolicy::default()
+                    },
+                )]
+                .into(),
+                ..StickySessionConfig::default()
+            })
+            .await;
+
+        for _ in 0..3 {
+            let selected = manager
+                .get_token("gemini", "image_gen", None, false, None)
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, "ultra");
+        }
+        let mut chat = std::collections::HashSet::new();
+        for _ in 0..3 {
+            let selected = manager
+                .get_token("gemini", "chat", None, false, None)
+                .await
+                .unwrap();
+            chat.insert(selected.account_id.clone());
+        }
+        assert_eq!(chat.len(), 3);
+
+        // Without any ULTRA account image_gen fails outright instead of waiting
+        assert!(manager.remove_account("ultra"));
+        let options = GetTokenOptions::default();
+        let err = manager
+            .get_token_with_options("gemini", "image_gen", &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::NoEligibleAccounts);
+        assert_eq!(
+            err.message,
+            "No eligible accounts for request_type image_gen"
+        );
+    }
+
+    /// Tag an account file on disk
+    fn tag_account(dir: &Path, id: &str, tags: &[&str]) {
+        let path = dir.join("accounts").join(format!("{}.json", id));
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        account["tags"] = serde_json::json!(tags);
+        std::fs::write(&path, account.to_string()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tag_filters_select_from_subset() {
+        let (dir, manager) = manager_with_accounts(&["a", "b", "c"]).await;
+        tag_account(dir.path(), "a", &["team-a"]);
+        tag_account(dir.path(), "b", &["team-a", "burner"]);
+        manager.load_accounts().await.unwrap();
+
+        let options = GetTokenOptions {
+            required_tags: vec!["TEAM-A".to_string()],
+            excluded_tags: vec!["burner".to_string()],
+            ..GetTokenOptions::default()
+        };
+        for _ in 0..3 {
+            let selected = manager
+                .get_token_with_options("claude", "chat", &options)
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, "a");
+        }
+
+        let tags: Vec<Vec<String>> = manager
+            .list_accounts()
+            .into_iter()
+            .map(|a| a.tags)
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                vec!["team-a".to_string()],
+                vec!["team-a".to_string(), "burner".to_string()],
+                vec![]
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_binding_outside_tag_filter_is_rebound() {
+        let (dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        tag_account(dir.path(), "a", &["team-a"]);
+        manager.load_accounts().await.unwrap();
+        manager.bind_session_for_test("claude", "session-1", "b");
+
+        let options = GetTokenOptions {
+            session_id: Some("session-1".to_string()),
+            required_tags: vec!["team-a".to_string()],
+            ..GetTokenOptions::default()
+        };
+        let selected = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "a");
+        assert_eq!(selected.selected_reason, SelectionReason::Scheduled);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("a".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tenants_get_their_own_accounts_and_sessions() {
+        use crate::proxy::token_manager::{tenant_session_id, GetTokenErrorKind, TenantPolicy};
+
+        let (dir, manager) = manager_with_accounts(&["a", "b", "c"]).await;
+        tag_account(dir.path(), "c", &["team-b"]);
+        manager.load_accounts().await.unwrap();
+        manager.set_tenant_policy(
+            "team-a",
+            TenantPolicy {
+                allowed_account_ids: vec!["a".to_string(), "b".to_string()],
+                ..TenantPolicy::default()
+            },
+        );
+        manager.set_tenant_policy(
+            "team-b",
+            TenantPolicy {
+                allowed_tags: vec!["TEAM-B".to_string()],
+                ..TenantPolicy::default()
+            },
+        );
+        let options = |tenant: &str, session_id: Option<&str>| GetTokenOptions {
+            tenant: Some(tenant.to_string()),
+            session_id: session_id.map(str::to_string),
+            ..GetTokenOptions::default()
+        };
+
+        for _ in 0..6 {
+            let a = manager
+                .get_token_with_options("claude", "chat", &options("team-a", None))
+                .await
+                .unwrap();
+            assert_ne!(a.account_id, "c");
+            let b = manager
+                .get_token_with_options("claude", "chat", &options("team-b", None))
+                .await
+                .unwrap();
+            assert_eq!(b.account_id, "c");
+        }
+
+        // The same session ID binds apart per tenant
+        let a = manager
+            .get_token_with_options("claude", "chat", &options("team-a", Some("session-1")))
+            .await
+            .unwrap();
+        let b = manager
+            .get_token_with_options("claude", "chat", &options("team-b", Some("session-1")))
+            .await
+            .unwrap();
+        assert_eq!(b.account_id, "c");
+        assert_eq!(
+            manager.session_binding_for_test("claude", &tenant_session_id("team-a", "session-1")),
+            Some(a.account_id.clone())
+        );
+        assert_eq!(
+            manager.session_binding_for_test("claude", &tenant_session_id("team-b", "session-1")),
+            Some("c".to_string())
+        );
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            None
+        );
+        let again = manager
+            .get_token_with_options("claude", "chat", &options("team-a", Some("session-1")))
+            .await
+            .unwrap();
+        assert_eq!(again.account_id, a.account_id);
+        assert_eq!(again.selected_reason, SelectionReason::StickyHit);
+
+        // Rate limits stay per account, whichever tenant hit them
+        manager.mark_rate_limited("claude", "chat", None, "c", 429, Some("60"), "");
+        let err = manager
+            .get_token_with_options("claude", "chat", &options("team-b", None))
+            .await
+            .unwrap_err();
+        assert!(err.retry_after_seconds.is_some());
+
+        manager.remove_account("c");
+        let err = manager
+            .get_token_with_options("claude", "chat", &options("team-b", None))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::NoEligibleAccounts);
+        assert_eq!(err.message, "No eligible accounts for tenant team-b");
+    }
+
+    #[tokio::test]
+    async fn test_min_wait_only_counts_filtered_accounts() {
+        let (dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        tag_account(dir.path(), "a", &["team-a"]);
+        manager.load_accounts().await.unwrap();
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("300"), "");
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("30"), "");
+
+        let options = GetTokenOptions {
+            required_tags: vec!["team-a".to_string()],
+            ..GetTokenOptions::default()
+        };
+        let err = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap_err();
+        assert!(
+            err.message.contains("wait 300s") || err.message.contains("wait 299s"),
+            "{}",
+            err.message
+        );
+        assert_eq!(err.attempts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_paused_account_is_never_selected() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        assert!(manager.pause_account("a"));
+        assert!(!manager.pause_account("missing"));
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("300"), "");
+
+        // "a" is the only healthy account, yet it stays out of rotation
+        let err = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap_err();
+        assert!(err.contains("1 paused account(s) skipped"), "{}", err);
+
+        manager.remove_account("b");
+        let err = manager
+            .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "All 1 eligible account(s) are paused");
+        assert_eq!(err.attempts.len(), 1);
+        assert_eq!(err.attempts[0].outcome, AttemptOutcome::Paused);
+
+        assert!(manager.resume_account("a"));
+        assert!(!manager.resume_account("a"));
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "a");
+    }
+
+    #[tokio::test]
+    async fn test_session_bound_to_paused_account_fails_over() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.pause_account("a");
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("b".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pause_survives_reload_for_existing_accounts() {
+        let (dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.pause_account("a");
+        manager.pause_account("b");
+        std::fs::remove_file(dir.path().join("accounts").join("b.json")).unwrap();
+        manager.load_accounts().await.unwrap();
+
+        let paused: Vec<(String, bool)> = manager
+            .list_accounts()
+            .into_iter()
+            .map(|a| (a.account_id, a.paused))
+            .collect();
+        assert_eq!(paused, vec![("a".to_string(), true)]);
+
+        // A returning account does not inherit its old pause
+        write_account_file(&dir.path().join("accounts"), "b", Some("PRO"));
+        manager.load_accounts().await.unwrap();
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+    }
+
+    #[tokio::test]
+    async fn test_draining_account_keeps_bound_sessions_only() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        assert!(manager.drain_account("a"));
+        assert!(!manager.drain_account("missing"));
+        assert_eq!(manager.draining_sessions_remaining("a"), Some(1));
+        assert_eq!(manager.draining_sessions_remaining("b"), None);
+
+        let bound = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(bound.account_id, "a");
+        assert_eq!(bound.selected_reason, SelectionReason::StickyHit);
+
+        for i in 0..4 {
+            let session = format!("fresh-{}", i);
+            let fresh = manager
+                .get_token("claude", "chat", None, false, Some(&session))
+                .await
+                .unwrap();
+            assert_eq!(fresh.account_id, "b");
+            let unbound = manager
+                .get_token("claude", "chat", None, false, None)
+                .await
+                .unwrap();
+            assert_eq!(unbound.account_id, "b");
+        }
+        assert_eq!(manager.draining_sessions_remaining("a"), Some(1));
+
+        let status = manager.list_accounts();
+        assert!(status
+            .iter()
+            .any(|a| a.account_id == "a" && a.draining && !a.paused));
+    }
+
+    #[tokio::test]
+    async fn test_drained_account_is_paused_when_configured() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.drain_account("a");
+
+        // Without pause_when_drained the drain just carries on
+        manager.unbind_session("claude", "chat", "session-1");
+        assert!(manager.finish_drains().await.is_empty());
+        assert_eq!(manager.draining_sessions_remaining("a"), Some(0));
+
+        manager
+            .update_sticky_config(StickySessionConfig {
+                pause_when_drained: true,
+                ..StickySessionConfig::default()
+            })
+            .await;
+        assert_eq!(manager.finish_drains().await, vec!["a".to_string()]);
+        assert_eq!(manager.draining_sessions_remaining("a"), None);
+        let status = manager.list_accounts();
+        assert!(status
+            .iter()
+            .any(|a| a.account_id == "a" && a.paused && !a.draining));
+
+        assert!(manager.resume_account("a"));
+        assert!(manager
+            .list_accounts()
+            .iter()
+            .all(|a| !a.paused && !a.draining));
+    }
+
+    #[tokio::test]
+    async fn test_drain_times_out_with_sessions_left() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager
+            .update_sticky_config(StickySessionConfig {
+                pause_when_drained: true,
+                max_drain_seconds: 3600,
+                ..StickySessionConfig::default()
+            })
+            .await;
+        manager.drain_account("a");
+        assert!(manager.finish_drains().await.is_empty());
+
+        manager
+            .update_sticky_config(StickySessionConfig {
+                pause_when_drained: true,
+                max_drain_seconds: 0,
+                ..StickySessionConfig::default()
+            })
+            .await;
+        assert!(manager.finish_drains().await.is_empty());
+
+        // Back-date the drain past a one second limit
+        manager.set_drain_started_for_test("a", chrono::Utc::now().timestamp() - 5);
+        manager
+            .update_sticky_config(StickySessionConfig {
+                pause_when_drained: true,
+                max_drain_seconds: 1,
+                ..StickySessionConfig::default()
+            })
+            .await;
+        assert_eq!(manager.finish_drains().await, vec!["a".to_string()]);
+
+        // The session bound to the now paused account fails over
+        let selected = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+    }
+
+    #[tokio::test]
+    async fn test_selection_spreads_evenly_within_tier() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b", "c", "d"]).await;
+
+        let mut counts: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+        for _ in 0..400 {
+            let selected = manager
+                .get_token("claude", "chat", None, false, None)
+                .await
+                .unwrap();
+            *counts.entry(selected.account_id).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 4, "{:?}", counts);
+        for (account_id, count) in &counts {
+            assert!(
+                (85..=115).contains(count),
+                "{} selected {} times: {:?}",
+                account_id,
+                count,
+                counts
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_matches_next_selection() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b", "c"]).await;
+        manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+
+        for _ in 0..4 {
+            let first = manager
+                .dry_run_selection("claude", "chat", None)
+                .await
+                .unwrap();
+            let second = manager
+                .dry_run_selection("claude", "chat", None)
+                .await
+                .unwrap();
+            assert_eq!(first, second);
+            assert_eq!(first.reason, SelectionReason::Scheduled);
+            assert_eq!(first.tier.as_deref(), Some("PRO"));
+
+            let selected = manager
+                .get_token("claude", "chat", None, false, None)
+                .await
+                .unwrap();
+            assert_eq!(selected.account_id, first.account_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_leaves_sessions_alone() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+
+        let preview = manager
+            .dry_run_selection("claude", "chat", Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(preview.would_wait_seconds, 0);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            None
+        );
+
+        manager.bind_session_for_test("claude", "session-1", "b");
+        let preview = manager
+            .dry_run_selection("claude", "chat", Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(preview.account_id, "b");
+        assert_eq!(preview.reason, SelectionReason::StickyHit);
+
+        // CacheFirst would wait for the bound account
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("30"), "");
+        let preview = manager
+            .dry_run_selection("claude", "chat", Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(preview.account_id, "b");
+        assert_eq!(preview.reason, SelectionReason::WaitedForSticky);
+        assert!(preview.would_wait_seconds > 0);
+
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        let err = manager
+            .dry_run_selection("claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(
+            err.message
+                .starts_with("All accounts are currently limited"),
+            "{}",
+            err.message
+        );
+        assert_eq!(err.attempts.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_is_structured() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        assert_eq!(manager.next_available_in("claude", "chat"), Some(0));
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("300"), "");
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("90"), "");
+
+        for force_rotate in [false, true] {
+            let options = GetTokenOptions {
+                force_rotate,
+                ..GetTokenOptions::default()
+            };
+            let err = manager
+                .get_token_with_options("claude", "chat", &options)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind, GetTokenErrorKind::Unavailable);
+            assert!(
+                matches!(err.retry_after_seconds, Some(89..=90)),
+                "{:?}",
+                err.retry_after_seconds
+            );
+            let wait = err.retry_after_seconds.unwrap();
+            assert!(
+                err.message.contains(&format!("Please wait {}s", wait)),
+                "{}",
+                err.message
+            );
+            assert!(err.is_all_limited());
+            assert_eq!(err.soonest_account.as_deref(), Some("b"));
+            assert_eq!(err.scope_group.as_deref(), Some("claude"));
+        }
+
+        // The same answer before any selection is attempted
+        assert!(matches!(
+            manager.next_available_in("claude", "chat"),
+            Some(89..=90)
+        ));
+        assert_eq!(manager.next_available_in("gemini", "chat"), Some(0));
+
+        // Paused accounts never come back by waiting
+        manager.pause_account("a");
+        manager.pause_account("b");
+        let err = manager
+            .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::NoRecoverableAccounts);
+        assert_eq!(err.retry_after_seconds, None);
+        assert!(!err.is_all_limited());
+        assert_eq!(manager.next_available_in("claude", "chat"), None);
+    }
+
+    #[tokio::test]
+    async fn test_selection_trace_names_accounts_by_log_identity() {
+        use crate::proxy::token_manager::LogIdentity;
+
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("90"), "");
+
+        for identity in [LogIdentity::AccountId, LogIdentity::HashedEmail] {
+            manager.set_log_identity(identity);
+            let err = manager
+                .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+                .await
+                .unwrap_err();
+            assert_eq!(err.attempts.len(), 2);
+            for attempt in &err.attempts {
+                assert!(!attempt.email.contains("@test.com"), "{:?}", attempt);
+                assert_eq!(
+                    attempt.email,
+                    identity.display(
+                        &attempt.account_id,
+                        &format!("{}@test.com", attempt.account_id)
+                    )
+                );
+            }
+            let summary = err.summary();
+            assert!(!summary.contains("@test.com"), "{}", summary);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_usage_stats_writes_are_coalesced() {
+        let (dir, manager) = manager_with_accounts(&["a"]).await;
+        let path = dir.path().join("accounts").join("a.json");
+        let read_stats = || {
+            let account: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            account.get("proxy_stats").cloned()
+        };
+
+        drop(
+            manager
+                .get_token("claude", "chat", None, false, None)
+                .await
+                .unwrap(),
+        );
+        drop(
+            manager
+                .get_token("claude", "chat", None, false, None)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(read_stats(), None);
+
+        assert_eq!(manager.flush_stats().await, 1);
+        let stats = read_stats().unwrap();
+        assert_eq!(stats["total_requests"], 2);
+        assert_eq!(stats["total_rate_limits"], 0);
+        assert!(stats["last_used_at"].as_i64().is_some());
+        assert_eq!(manager.flush_stats().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_usage_stats_carry_over_restarts() {
+        let (dir, manager) = manager_with_accounts(&["a"]).await;
+        drop(
+            manager
+                .get_token("claude", "chat", None, false, None)
+                .await
+                .unwrap(),
+        );
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("1"), "");
+        manager.flush_stats().await;
+
+        let restarted = TokenManager::new(dir.path().to_path_buf());
+        restarted.load_accounts().await.unwrap();
+        let stats = restarted.account_usage("a").unwrap();
+        assert_eq!((stats.total_requests, stats.total_rate_limits), (1, 1));
+
+        // A failed write is retried later and never fails a request
+        std::fs::remove_file(dir.path().join("accounts").join("a.json")).unwrap();
+        restarted.mark_rate_limited("claude", "chat", None, "a", 429, Some("1"), "");
+        assert_eq!(restarted.flush_stats().await, 0);
+        assert_eq!(restarted.account_usage("a").unwrap().total_rate_limits, 2);
+    }
+
+    #[tokio::test]
+    async fn test_unbind_single_session() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+
+        let chat = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        let image = manager
+            .get_token("gemini", "image_gen", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        manager
+            .get_token("claude", "chat", None, false, Some("session-2"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            manager.get_session_binding("gemini", "image_gen", "session-1"),
+            Some(image.account_id.clone())
+        );
+        let sessions = manager.list_sessions();
+        assert_eq!(
+            sessions
+                .iter()
+                .map(|s| (s.scope_group.as_str(), s.session_id.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("claude", "session-1"),
+                ("claude", "session-2"),
+                ("gemini::image_gen", "session-1")
+            ]
+        );
+        let json = serde_json::to_string(&sessions).unwrap();
+        assert!(!json.contains("token-") && !json.contains("refresh-"));
+
+        assert_eq!(
+            manager.unbind_session("claude", "chat", "session-1"),
+            Some(chat.account_id.clone())
+        );
+        assert_eq!(manager.unbind_session("claude", "chat", "session-1"), None);
+        assert_eq!(
+            manager.get_session_binding("claude", "chat", "session-1"),
+            None
+        );
+
+        // Other sessions keep their accounts
+        assert!(manager
+            .get_session_binding("claude", "chat", "session-2")
+            .is_some());
+        assert_eq!(
+            manager.get_session_binding("gemini", "image_gen", "session-1"),
+            Some(image.account_id)
+        );
+        assert_eq!(manager.list_sessions().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_clear_sessions_for_one_group() {
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.bind_session_for_test("gemini", "session-2", "a");
+        manager.bind_session_for_test("gemini::image_gen", "session-3", "b");
+
+        assert_eq!(manager.clear_sessions_for_group("gemini", "chat", false), 1);
+        assert_eq!(
+            manager.get_session_binding("gemini", "chat", "session-2"),
+            None
+        );
+        assert_eq!(
+            manager.get_session_binding("gemini", "image_gen", "session-3"),
+            Some("b".to_string())
+        );
+        assert_eq!(
+            manager.get_session_binding("claude", "chat", "session-1"),
+            Some("a".to_string())
+        );
+
+        manager.bind_session_for_test("gemini", "session-2", "a");
+        assert_eq!(manager.clear_sessions_for_group("gemini", "chat", true), 2);
+        assert_eq!(
+            manager.get_session_binding("gemini", "image_gen", "session-3"),
+            None
+        );
+        assert_eq!(
+            manager.get_session_binding("claude", "chat", "session-1"),
+            Some("a".to_string())
+        );
+
+        manager.bind_session_for_test("gemini::image_gen", "session-3", "b");
+        assert_eq!(
+            manager.clear_sessions_for_group("gemini", "image_gen", false),
+            1
+        );
+        assert_eq!(manager.list_sessions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_session_bindings_survive_restart() {
+        let (dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        manager
+            .update_sticky_config(StickySessionConfig {
+                persist_sessions: true,
+                ..StickySessionConfig::default()
+            })
+            .await;
+
+        manager.bind_session_for_test("claude", "session-1", "a");
+        manager.bind_session_for_test("claude", "session-2", "b");
+        assert!(manager.save_sessions().await.unwrap());
+        // Nothing changed since the last save
+        assert!(!manager.save_sessions().await.unwrap());
+        drop(manager);
+
+        std::fs::remove_file(dir.path().join("accounts").join("b.json")).unwrap();
+        let restarted = TokenManager::new(dir.path().to_path_buf());
+        restarted.load_accounts().await.unwrap();
+
+        let token = restarted
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        assert_eq!(token.account_id, "a");
+        assert_eq!(token.selected_reason, SelectionReason::StickyHit);
+        // The account behind session-2 is gone
+        assert_eq!(
+            restarted.session_binding_for_test("claude", "session-2"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_tasks_and_flushes_state() {
+        let (dir, manager) = manager_with_accounts(&["a"]).await;
+        let manager = std::sync::Arc::new(manager);
+        manager
+            .update_sticky_config(StickySessionConfig {
+                persist_sessions: true,
+                ..StickySessionConfig::default()
+            })
+            .await;
+
+        let watcher = manager.watch_accounts_with(
+            std::time::Duration::from_millis(10),
+            std::time::Duration::from_millis(10),
+        );
+        let refresher = manager.start_background_refresh(std::time::Duration::from_secs(3600));
+        assert_eq!(manager.running_tasks(), 2);
+
+        drop(
+            manager
+                .get_token("claude", "chat", None, false, Some("session-1"))
+                .await
+                .unwrap(),
+        );
+        manager
+            .shutdown(std::time::Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        assert!(manager.is_shut_down());
+        assert_eq!(manager.running_tasks(), 0);
+        assert!(watcher.is_finished() && refresher.is_finished());
+
+        let account: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join("accounts").join("a.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(account["proxy_stats"]["total_requests"], 1);
+        let sessions = std::fs::read_to_string(dir.path().join("sessions.json")).unwrap();
+        assert!(sessions.contains("session-1"));
+
+        let err = manager
+            .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::ShutDown);
+        assert!(manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap_err()
+            .contains("shut down"));
+        // Tasks started after shutdown exit right away
+        let late = manager.start_session_sweeper(std::time::Duration::from_secs(3600));
+        manager
+            .shutdown(std::time::Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(late.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_conversation_prefix_keeps_account_without_session_id() {
+        use crate::proxy::token_manager::types::ConversationPrefix;
+
+        let (_dir, manager) = manager_with_accounts(&["a", "b", "c"]).await;
+        manager
+            .update_sticky_config(StickySessionConfig {
+                mode: SchedulingMode::Balance,
+                fingerprint_sessions: true,
+                ..StickySessionConfig::default()
+            })
+            .await;
+
+        let prefix = ConversationPrefix {
+            system_prompt: "You are a code reviewer.".to_string(),
+            first_user_message: "Review this patch".to_string(),
+        };
+        let first = manager
+            .get_token_for_conversation("claude", "chat", None, false, None, &prefix)
+            .await
+            .unwrap();
+
+        for turn in 0..3 {
+            // Later turns resend the same opening, sometimes with a trailing newline
+            let prefix = ConversationPrefix {
+                first_user_message: format!("Review this patch{}", "\n".repeat(turn)),
+                ..prefix.clone()
+            };
+            let next = manager
+                .get_token_for_conversation("claude", "chat", None, false, None, &prefix)
+                .await
+                .unwrap();
+            assert_eq!(next.account_id, first.account_id);
+            assert_eq!(next.selected_reason, SelectionReason::StickyHit);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conversation_prefix_is_ignored_unless_enabled() {
+        use crate::proxy::token_manager::types::ConversationPrefix;
+
+        let (_dir, manager) = manager_with_accounts(&["a", "b"]).await;
+        let prefix = ConversationPrefix {
+            system_prompt: "You are a code reviewer.".to_string(),
+            first_user_message: "Review this patch".to_string(),
+        };
+
+        for _ in 0..2 {
+            let selected = manager
+                .get_token_for_conversation("claude", "chat", None, false, None, &prefix)
+                .await
+                .unwrap();
+            assert_eq!(selected.selected_reason, SelectionReason::Scheduled);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_persistence_is_off_by_default() {
+        let (dir, manager) = manager_with_accounts(&["a"]).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+
+        assert!(!manager.save_sessions().await.unwrap());
+        assert!(!dir.path().join("sessions.json").exists());
+    }
+}
+
+#[cfg(test)]
+mod oauth_tests {
+    use super::*;
+    use crate::proxy::token_manager::core::TokenManager;
+    use crate::proxy::token_manager::refresh::{
+        OAuthCallPolicy, RefreshError, RefreshErrorKind, TokenResponse, DEFAULT_OAUTH_RETRIES,
+    };
+    use crate::proxy::token_manager::types::{
+        AccountTokenError, AddAccountError, AttemptOutcome, GetTokenOptions,
+        OAuthClientCredentials, SelectionReason,
+    };
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// OAuth client that never leaves the process
+    ///
+    /// Refreshes succeed with `fresh-{refresh_token}` unless an error was
+    /// registered for the refresh token; project lookups answer
+    /// `discovered-{access_token}` unless `project_error` is set.
+    #[derive(Default)]
+    struct MockOAuthClient {
+        refresh_errors: Mutex<HashMap<String, OAuthError>>,
+        refresh_calls: AtomicUsize,
+        project_calls: AtomicUsize,
+        /// Delay before a refresh answers
+        refresh_delay: Option<std::time::Duration>,
+        /// Delay before a project lookup answers
+        project_delay: Option<std::time::Duration>,
+        /// Error every project lookup fails with
+        project_error: Option<OAuthError>,
+    }
+
+    impl MockOAuthClient {
+        fn fail_refresh(&self, refresh_token: &str, error: OAuthError) {
+            self.refresh_errors
+                .lock()
+                .unwrap()
+                .insert(refresh_token.to_string(), error);
+        }
+    }
+
+    #[async_trait]
+    impl OAuthClient for MockOAuthClient {
+        async fn refresh(
+            &self,
+            refresh_token: &str,
+            _client: Option<&OAuthClientCredentials>,
+            _proxy: Option<&str>,
+        ) -> Result<TokenResponse, OAuthError> {
+            self.refresh_calls.fetch_add(1, Ordering::SeqCst);
+            if let Some(delay) = self.refresh_delay {
+                tokio::time::sleep(delay).await;
+            }
+            if let Some(error) = self.refresh_errors.lock().unwrap().get(refresh_token) {
+                return Err(error.clone());
+            }
+            Ok(TokenResponse {
+                access_token: format!("fresh-{}", refresh_token),
+                expires_in: 3600,
+                refresh_token: None,
+            })
+        }
+
+        async fn fetch_project_id(
+            &self,
+            access_token: &str,
+            _proxy: Option<&str>,
+        ) -> Result<String, OAuthError> {
+            self.project_calls.fetch_add(1, Ordering::SeqCst);
+            if let Some(delay) = self.project_delay {
+                tokio::time::sleep(delay).await;
+            }
+            if let Some(error) = &self.project_error {
+                return Err(error.clone());
+            }
+            Ok(format!("discovered-{}", access_token))
+        }
+    }
+
+    /// Google's answer to a refresh it turned down
+    fn rejected(body: &str) -> OAuthError {
+        OAuthError::new(
+            OAuthErrorKind::Status(400),
+            format!("刷新失败 (400 Bad Request): {}", body),
+        )
+    }
+
+    /// A refresh that got no answer in time
+    fn timed_out() -> OAuthError {
+        OAuthError::new(OAuthErrorKind::Timeout, "刷新请求失败: operation timed out")
+    }
+
+    /// Rewrite part of an account file written by `write_account_file`
+    fn edit_account_file(path: &Path, edit: impl FnOnce(&mut serde_json::Value)) {
+        let mut account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        edit(&mut account);
+        std::fs::write(path, serde_json::to_string_pretty(&account).unwrap()).unwrap();
+    }
+
+    fn expire(account: &mut serde_json::Value) {
+        account["token"]["expiry_timestamp"] =
+            serde_json::json!(chrono::Utc::now().timestamp() - 60);
+    }
+
+    fn read_account_file(path: &Path) -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    async fn manager_with_client(dir: &Path, client: Arc<MockOAuthClient>) -> TokenManager {
+        let manager = TokenManager::new_with_client(dir.to_path_buf(), client);
+        // Retry right away so tests don't sleep through the backoff
+        manager.set_oauth_call_policy(OAuthCallPolicy {
+            retry_backoff: std::time::Duration::from_millis(1),
+            ..OAuthCallPolicy::default()
+        });
+        manager.load_accounts().await.unwrap();
+        manager
+    }
+
+    /// Collects the fields recorded on `token_select` spans
+    #[derive(Clone, Default)]
+    struct SelectSpans(Arc<Mutex<Vec<HashMap<String, String>>>>);
+
+    struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for SelectSpans
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if attrs.metadata().name() != "token_select" {
+                return;
+            }
+            let mut fields = HashMap::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            ctx.span(id).unwrap().extensions_mut().insert(spans.len());
+            spans.push(fields);
+        }
+
+        fn on_record(
+            &self,
+            id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let span = ctx.span(id).unwrap();
+            let Some(index) = span.extensions().get::<usize>().copied() else {
+                return;
+            };
+            values.record(&mut FieldVisitor(&mut self.0.lock().unwrap()[index]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_select_span_records_each_phase() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let spans = SelectSpans::default();
+        let _subscriber =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(spans.clone()));
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for id in ["a", "b"] {
+            let path = write_account_file(&accounts, id, Some("PRO"));
+            edit_account_file(&path, expire);
+        }
+        let manager = manager_with_client(dir.path(), Arc::new(MockOAuthClient::default())).await;
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, Some("session-1"))
+            .await
+            .unwrap();
+        let spans = spans.0.lock().unwrap().clone();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span["scope_group"], "claude");
+        assert_eq!(span["session"], "Some(\"session-1\")");
+        assert_eq!(span["force_rotate"], "false");
+        assert_eq!(span["attempts"], "1");
+        assert_eq!(span["refreshes"], "1");
+        assert_eq!(span["account_id"], format!("{:?}", selected.account_id));
+        assert_eq!(
+            span["email_hash"],
+            format!(
+                "{:?}",
+                crate::proxy::token_manager::metrics::email_hash(&selected.email)
+            )
+        );
+        assert_eq!(span["outcome"], "\"selected\"");
+        assert!(span.contains_key("duration_ms"));
+        assert!(!span.contains_key("wait_seconds"));
+        assert!(!span.values().any(|value| value.contains(&selected.email)));
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_is_refreshed_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, expire);
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "fresh-refresh-a");
+        manager.flush_token_writes().await;
+        assert_eq!(
+            read_account_file(&path)["token"]["access_token"],
+            "fresh-refresh-a"
+        );
+        drop(selected);
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "fresh-refresh-a");
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_mock_clock_drives_expiry_and_cooldowns() {
+        use crate::proxy::clock::{Clock, MockClock};
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+
+        let client = Arc::new(MockOAuthClient::default());
+        let clock = MockClock::starting_now();
+        let manager = TokenManager::new_with_clock(
+            dir.path().to_path_buf(),
+            client.clone(),
+            None,
+            clock.clone(),
+        );
+        manager.load_accounts().await.unwrap();
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "token-a");
+        drop(selected);
+
+        // A cooldown lasts exactly as long as the clock says
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+        clock.advance(59);
+        assert!(manager.is_rate_limited("claude", "chat", None, "a"));
+        clock.advance(1);
+        assert!(!manager.is_rate_limited("claude", "chat", None, "a"));
+
+        // The file's token expires an hour after it was written, without waiting for it
+        clock.advance(3600);
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "fresh-refresh-a");
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(selected.expires_at, clock.now() + 3600);
+    }
+
+    #[tokio::test]
+    async fn test_expired_rate_limit_admits_one_trial_request() {
+        use crate::proxy::clock::MockClock;
+        use crate::proxy::token_manager::health::RequestOutcome;
+        use crate::proxy::token_manager::types::GetTokenErrorKind;
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+
+        let clock = MockClock::starting_now();
+        let manager = TokenManager::new_with_clock(
+            dir.path().to_path_buf(),
+            Arc::new(MockOAuthClient::default()),
+            None,
+            clock.clone(),
+        );
+        manager.load_accounts().await.unwrap();
+        let options = GetTokenOptions::default();
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("30"), "");
+
+        // Each expiry lets one trial through, which is limited again right away
+        let mut cooldown = 30;
+        for round in 0..3 {
+            clock.advance(cooldown);
+            let trial = manager
+                .get_token_with_options("claude", "chat", &options)
+                .await
+                .unwrap();
+            let err = manager
+                .get_token_with_options("claude", "chat", &options)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind, GetTokenErrorKind::AllBusy);
+            assert_eq!(err.attempts[0].outcome, AttemptOutcome::Probation);
+
+            cooldown *= 2;
+            if round % 2 == 0 {
+                manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("30"), "");
+            } else {
+                manager
+                    .report_result(
+                        "claude",
+                        "chat",
+                        None,
+                        "a",
+                        RequestOutcome::RateLimited {
+                            retry_after: Some(30),
+                        },
+                    )
+                    .await;
+            }
+            drop(trial);
+            let err = manager
+                .get_token_with_options("claude", "chat", &options)
+                .await
+                .unwrap_err();
+            assert_eq!(err.retry_after_seconds, Some(cooldown as u64));
+        }
+
+        // A failure that says nothing about the limit frees the trial
+        clock.advance(cooldown);
+        let trial = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap();
+        manager
+            .report_result(
+                "claude",
+                "chat",
+                None,
+                "a",
+                RequestOutcome::Upstream5xx { status: 503 },
+            )
+            .await;
+        drop(trial);
+        let trial = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap();
+
+        // A successful trial ends probation
+        manager
+            .report_result(
+                "claude",
+                "chat",
+                None,
+                "a",
+                RequestOutcome::Success { latency_ms: 50 },
+            )
+            .await;
+        let second = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            (trial.account_id.as_str(), second.account_id.as_str()),
+            ("a", "a")
+        );
+        drop((trial, second));
+
+        // The next limit starts from the base cooldown again
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("30"), "");
+        let err = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.retry_after_seconds, Some(30));
+    }
+
+    #[tokio::test]
+    async fn test_trial_limited_without_retry_after_ends_with_default_cooldown() {
+        use crate::proxy::clock::MockClock;
+        use crate::proxy::token_manager::health::RequestOutcome;
+        use crate::proxy::token_manager::types::GetTokenErrorKind;
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+
+        let clock = MockClock::starting_now();
+        let manager = TokenManager::new_with_clock(
+            dir.path().to_path_buf(),
+            Arc::new(MockOAuthClient::default()),
+            None,
+            clock.clone(),
+        );
+        manager.load_accounts().await.unwrap();
+        let options = GetTokenOptions::default();
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("30"), "");
+
+        clock.advance(30);
+        let trial = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap();
+        manager
+            .report_result(
+                "claude",
+                "chat",
+                None,
+                "a",
+                RequestOutcome::RateLimited { retry_after: None },
+            )
+            .await;
+        drop(trial);
+
+        let err = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::Unavailable);
+        assert!(matches!(
+            err.attempts[0].outcome,
+            AttemptOutcome::RateLimited { .. }
+        ));
+        let cooldown = err.retry_after_seconds.unwrap();
+
+        // A handler that marked the limit itself is not penalized twice
+        manager
+            .report_result(
+                "claude",
+                "chat",
+                None,
+                "a",
+                RequestOutcome::RateLimited { retry_after: None },
+            )
+            .await;
+        let err = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.retry_after_seconds, Some(cooldown));
+    }
+
+    #[tokio::test]
+    async fn test_rapid_refreshes_are_written_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, expire);
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        manager.set_token_write_delay(std::time::Duration::from_secs(3600));
+
+        drop(
+            manager
+                .get_token("claude", "chat", None, false, None)
+                .await
+                .unwrap(),
+        );
+        // Each rejected token is refreshed again at once
+        for _ in 0..2 {
+            manager
+                .report_result("claude", "chat", None, "a", RequestOutcome::Unauthorized)
+                .await;
+        }
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 3);
+        assert_eq!(read_account_file(&path)["token"]["access_token"], "token-a");
+
+        assert_eq!(manager.flush_token_writes().await, 1);
+        assert_eq!(manager.flush_token_writes().await, 0);
+        let saved = read_account_file(&path);
+        assert_eq!(saved["token"]["access_token"], "fresh-refresh-a");
+        assert!(
+            saved["token"]["expiry_timestamp"].as_i64().unwrap() > chrono::Utc::now().timestamp()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_cold_start_selections_are_coalesced() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for id in ["a", "b"] {
+            let path = write_account_file(&accounts, id, Some("PRO"));
+            edit_account_file(&path, expire);
+        }
+
+        let client = Arc::new(MockOAuthClient {
+            refresh_delay: Some(std::time::Duration::from_millis(100)),
+            ..MockOAuthClient::default()
+        });
+        let manager = Arc::new(manager_with_client(dir.path(), client.clone()).await);
+        manager.set_coalesce_selections(true);
+
+        let options = GetTokenOptions {
+            session_id: Some("session-1".to_string()),
+            ..GetTokenOptions::default()
+        };
+        let handles: Vec<_> = (0..50)
+            .map(|_| {
+                let manager = manager.clone();
+                let options = options.clone();
+                tokio::spawn(async move {
+                    manager
+                        .get_token_with_options("claude", "chat", &options)
+                        .await
+                })
+            })
+            .collect();
+        let mut selected = Vec::new();
+        for handle in handles {
+            selected.push(handle.await.unwrap().unwrap());
+        }
+
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 1);
+        let account_id = selected[0].account_id.clone();
+        assert!(selected.iter().all(|s| s.account_id == account_id));
+        assert!(selected
+            .iter()
+            .all(|s| s.access_token == format!("fresh-refresh-{}", account_id)));
+        // Every caller holds its own in-flight slot
+        assert_eq!(selected[0].in_flight.account_in_flight(), 50);
+        drop(selected);
+        assert_eq!(
+            manager
+                .list_accounts()
+                .iter()
+                .map(|a| a.in_flight)
+                .sum::<usize>(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_without_expiry_is_refreshed_on_first_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["token"] = serde_json::json!({
+                "access_token": "stale-a",
+                "refresh_token": "refresh-a",
+            });
+        });
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        assert_eq!(manager.len(), 1);
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "fresh-refresh-a");
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 1);
+        manager.flush_token_writes().await;
+        let saved = read_account_file(&path);
+        assert_eq!(saved["token"]["expires_in"], 3600);
+        assert!(
+            saved["token"]["expiry_timestamp"].as_i64().unwrap() > chrono::Utc::now().timestamp()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_account_survives_refresh_save() {
+        use crate::proxy::token_manager::{encrypt_account_file, AccountKey, ENCRYPTED_HEADER};
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let encrypted = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&encrypted, expire);
+        let plain = write_account_file(&accounts, "b", Some("PRO"));
+        edit_account_file(&plain, expire);
+
+        let key = AccountKey::from_bytes(&[42; 32]).unwrap();
+        assert!(encrypt_account_file(&encrypted, &key).unwrap());
+        assert!(!encrypt_account_file(&encrypted, &key).unwrap());
+        let sealed = std::fs::read_to_string(&encrypted).unwrap();
+        assert!(sealed.starts_with(ENCRYPTED_HEADER) && !sealed.contains("refresh-a"));
+
+        // Without the key the encrypted account cannot be loaded
+        let keyless = TokenManager::new_with_client_and_key(
+            dir.path().to_path_buf(),
+            Arc::new(MockOAuthClient::default()),
+            None,
+        );
+        let report = keyless.load_accounts().await.unwrap();
+        assert_eq!(report.failed.len(), 1);
+        assert!(
+            report.failed[0].1.contains("no account key"),
+            "{}",
+            report.failed[0].1
+        );
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = TokenManager::new_with_client_and_key(
+            dir.path().to_path_buf(),
+            client,
+            Some(key.clone()),
+        );
+        assert_eq!(manager.load_accounts().await.unwrap().added, 2);
+        for id in ["a", "b"] {
+            drop(
+                manager
+                    .get_token_for_account(id, "claude", "chat", None)
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(manager.flush_token_writes().await, 2);
+
+        // The refresh was saved encrypted; the plaintext file stays plaintext
+        let sealed = std::fs::read_to_string(&encrypted).unwrap();
+        assert!(sealed.starts_with(ENCRYPTED_HEADER) && !sealed.contains("fresh-refresh-a"));
+        let account = AccountFile::from_json(
+            &crate::proxy::token_manager::crypto::decrypt(&key, &sealed).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(account.token.access_token, "fresh-refresh-a");
+        assert_eq!(
+            read_account_file(&plain)["token"]["access_token"],
+            "fresh-refresh-b"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_permanent_refresh_error_disables_and_falls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("ULTRA"));
+        edit_account_file(&path, expire);
+        write_account_file(&accounts, "b", Some("PRO"));
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh(
+            "refresh-a",
+            rejected(r#"{"error": "invalid_grant", "error_description": "Token has been expired or revoked."}"#),
+        );
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        manager.bind_session_for_test("gemini", "session-1", "a");
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+        assert_eq!(manager.len(), 1);
+        let account = read_account_file(&path);
+        assert_eq!(account["disabled"], true);
+        // invalid_grant may be an outage on Google's side
+        assert_eq!(account["disabled_kind"], "temporary");
+        // The disabled account's bindings go with it
+        assert_eq!(
+            manager.session_binding_for_test("gemini", "session-1"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_counts_refresh_backoff() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("ULTRA"));
+        edit_account_file(&path, expire);
+        write_account_file(&accounts, "b", Some("PRO"));
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh("refresh-a", timed_out());
+        let manager = manager_with_client(dir.path(), client).await;
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("300"), "");
+
+        // "a" failed its refresh and backs off for 5s, well before "b" recovers
+        let options = GetTokenOptions::default();
+        let err = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, GetTokenErrorKind::Unavailable);
+        assert!(
+            matches!(err.retry_after_seconds, Some(1..=5)),
+            "{:?}",
+            err.retry_after_seconds
+        );
+
+        // While backing off "a" is filtered out before scheduling
+        let err = manager
+            .get_token_with_options("claude", "chat", &options)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err.retry_after_seconds, Some(1..=5)),
+            "{:?}",
+            err.retry_after_seconds
+        );
+        assert!(err.message.contains("Please wait"), "{}", err.message);
+    }
+
+    #[tokio::test]
+    async fn test_temporary_refresh_error_rotates_and_backs_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("ULTRA"));
+        edit_account_file(&path, expire);
+        write_account_file(&accounts, "b", Some("PRO"));
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh("refresh-a", timed_out());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+        drop(selected);
+
+        // The account stays in the pool but is not retried during the backoff
+        assert_eq!(manager.len(), 2);
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+        // One refresh, retried right away, then nothing until the backoff ends
+        assert_eq!(
+            client.refresh_calls.load(Ordering::SeqCst),
+            1 + DEFAULT_OAUTH_RETRIES as usize
+        );
+
+        let status = manager
+            .list_accounts()
+            .into_iter()
+            .find(|a| a.account_id == "a")
+            .unwrap();
+        assert_eq!(status.refresh_failure.unwrap().failures, 1);
+        assert!(read_account_file(&path)
+            .get("disabled")
+            .is_none_or(|d| d != true));
+    }
+
+    #[tokio::test]
+    async fn test_missing_project_id_is_discovered_and_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["token"]
+                .as_object_mut()
+                .unwrap()
+                .remove("project_id");
+        });
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.project_id, "discovered-token-a");
+        assert_eq!(
+            read_account_file(&path)["token"]["project_id"],
+            "discovered-token-a"
+        );
+        assert_eq!(client.project_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_project_id_lookups_share_one_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["token"]
+                .as_object_mut()
+                .unwrap()
+                .remove("project_id");
+        });
+
+        let client = Arc::new(MockOAuthClient {
+            project_delay: Some(std::time::Duration::from_millis(50)),
+            ..MockOAuthClient::default()
+        });
+        let manager = Arc::new(manager_with_client(dir.path(), client.clone()).await);
+
+        let mut handles = Vec::new();
+        for _ in 0..20 {
+            let manager = manager.clone();
+            handles.push(tokio::spawn(async move {
+                manager
+                    .get_token("claude", "chat", None, false, None)
+                    .await
+                    .map(|selected| selected.project_id.clone())
+            }));
+        }
+        for handle in handles {
+            assert_eq!(handle.await.unwrap().unwrap(), "discovered-token-a");
+        }
+
+        assert_eq!(client.project_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            read_account_file(&path)["token"]["project_id"],
+            "discovered-token-a"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolved_project_id_survives_failed_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["token"]
+                .as_object_mut()
+                .unwrap()
+                .remove("project_id");
+        });
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        // The file disappears, so saving the project_id fails
+        std::fs::remove_file(&path).unwrap();
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.project_id, "discovered-token-a");
+        drop(selected);
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.project_id, "discovered-token-a");
+        assert_eq!(client.project_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_token_for_account_refreshes_without_binding() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("ULTRA"));
+        let path = write_account_file(&accounts, "b", Some("PRO"));
+        edit_account_file(&path, expire);
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        manager.bind_session_for_test("claude", "session-1", "a");
+
+        let selected = manager
+            .get_token_for_account("b", "claude", "chat", None)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "b");
+        assert_eq!(selected.access_token, "fresh-refresh-b");
+        assert_eq!(selected.selected_reason, SelectionReason::Explicit);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("a".to_string())
+        );
+
+        let err = manager
+            .get_token_for_account("c", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            AccountTokenError::NotFound {
+                account_id: "c".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_token_for_account_reports_rate_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+        write_account_file(&accounts, "b", Some("PRO"));
+
+        let manager = manager_with_client(dir.path(), Arc::new(MockOAuthClient::default())).await;
+        manager.mark_rate_limited("claude", "chat", None, "a", 429, Some("60"), "");
+
+        match manager
+            .get_token_for_account("a", "claude", "chat", None)
+            .await
+        {
+            Err(AccountTokenError::RateLimited { remaining_seconds }) => {
+                assert!(
+                    (59..=60).contains(&remaining_seconds),
+                    "{}",
+                    remaining_seconds
+                )
+            }
+            other => panic!("unexpected result: {:?}", other.map(|t| t.account_id)),
+        }
+
+        // Other scope groups are unaffected
+        let selected = manager
+            .get_token_for_account("a", "gemini", "chat", None)
+            .await
+            .unwrap();
+        assert_eq!(selected.account_id, "a");
+    }
+
+    #[tokio::test]
+    async fn test_get_token_for_account_refresh_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let revoked = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&revoked, expire);
+        let flaky = write_account_file(&accounts, "b", Some("PRO"));
+        edit_account_file(&flaky, expire);
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh("refresh-a", rejected(r#"{"error": "invalid_grant"}"#));
+        client.fail_refresh("refresh-b", timed_out());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        // A permanent failure disables the account
+        let err = manager
+            .get_token_for_account("a", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, AccountTokenError::Refresh(e) if e.kind() == RefreshErrorKind::Permanent)
+        );
+        assert!(manager.token_for_test("a").is_none());
+        assert_eq!(read_account_file(&revoked)["disabled"], true);
+
+        // A temporary one leaves it in the pool, backing off
+        let err = manager
+            .get_token_for_account("b", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, AccountTokenError::Refresh(e) if e.kind() == RefreshErrorKind::Temporary)
+        );
+        assert!(manager.token_for_test("b").is_some());
+        let err = manager
+            .get_token_for_account("b", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            AccountTokenError::Refresh(RefreshError::BackingOff { .. })
+        ));
+        // The permanent failure is not retried, the temporary one is
+        assert_eq!(
+            client.refresh_calls.load(Ordering::SeqCst),
+            2 + DEFAULT_OAUTH_RETRIES as usize
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_token_for_account_project_id_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["token"]
+                .as_object_mut()
+                .unwrap()
+                .remove("project_id");
+        });
+
+        let failing = Arc::new(MockOAuthClient {
+            project_error: Some("no project".to_string().into()),
+            ..MockOAuthClient::default()
+        });
+        let manager = manager_with_client(dir.path(), failing).await;
+        let err = manager
+            .get_token_for_account("a", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, AccountTokenError::ProjectId("no project".to_string()));
+        assert!(read_account_file(&path)["token"]
+            .get("project_id")
+            .is_none());
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        let selected = manager
+            .get_token_for_account("a", "claude", "chat", None)
+            .await
+            .unwrap();
+        assert_eq!(selected.project_id, "discovered-token-a");
+        assert_eq!(
+            read_account_file(&path)["token"]["project_id"],
+            "discovered-token-a"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_image_gen_uses_longer_expiry_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["token"]["expiry_timestamp"] =
+                serde_json::json!(chrono::Utc::now().timestamp() + 450);
+        });
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        // 450s left is enough for a chat request
+        let selected = manager
+            .get_token("gemini", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "token-a");
+        drop(selected);
+
+        // but not for an image batch
+        let selected = manager
+            .get_token("gemini", "image_gen", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "fresh-refresh-a");
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_buffer_is_configurable() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["token"]["expiry_timestamp"] =
+                serde_json::json!(chrono::Utc::now().timestamp() + 120);
+        });
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        assert_eq!(manager.expiry_buffer(), 300);
+
+        manager.set_expiry_buffer(60);
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "token-a");
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 0);
+        assert!(!manager.list_accounts()[0].token_expired);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_reports_every_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let expired = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&expired, expire);
+        let no_project = write_account_file(&accounts, "b", Some("PRO"));
+        edit_account_file(&no_project, |account| {
+            account["token"]
+                .as_object_mut()
+                .unwrap()
+                .remove("project_id");
+        });
+        let revoked = write_account_file(&accounts, "c", Some("PRO"));
+        edit_account_file(&revoked, expire);
+        write_account_file(&accounts, "d", Some("PRO"));
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh("refresh-c", rejected(r#"{"error": "invalid_grant"}"#));
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        let report = manager.warm_up(2).await;
+        assert_eq!((report.total, report.warmed, report.failed), (4, 3, 1));
+
+        let result = |id: &str| report.accounts.iter().find(|r| r.account_id == id).unwrap();
+        assert!(result("a").refreshed && !result("a").project_id_resolved);
+        assert!(!result("b").refreshed && result("b").project_id_resolved);
+        assert!(!result("d").refreshed && !result("d").project_id_resolved);
+        assert!(result("c")
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("invalid_grant"));
+
+        // The warmed state is in the pool, the revoked account is gone
+        assert_eq!(
+            manager.token_for_test("a").unwrap().access_token,
+            "fresh-refresh-a"
+        );
+        assert_eq!(
+            read_account_file(&no_project)["token"]["project_id"],
+            "discovered-token-b"
+        );
+        assert!(manager.token_for_test("c").is_none());
+        assert_eq!(read_account_file(&revoked)["disabled"], true);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["warmed"], 3);
+        assert_eq!(json["accounts"].as_array().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_failed_selection_traces_every_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let flaky = write_account_file(&accounts, "a", Some("ULTRA"));
+        edit_account_file(&flaky, expire);
+        write_account_file(&accounts, "b", Some("ULTRA"));
+        let no_project = write_account_file(&accounts, "c", Some("PRO"));
+        edit_account_file(&no_project, |account| {
+            account["token"]
+                .as_object_mut()
+                .unwrap()
+                .remove("project_id");
+        });
+        let revoked = write_account_file(&accounts, "d", Some("FREE"));
+        edit_account_file(&revoked, expire);
+
+        let client = Arc::new(MockOAuthClient {
+            project_error: Some("no project".to_string().into()),
+            ..MockOAuthClient::default()
+        });
+        client.fail_refresh("refresh-a", timed_out());
+        client.fail_refresh("refresh-d", rejected(r#"{"error": "invalid_grant"}"#));
+        let manager = manager_with_client(dir.path(), client).await;
+        manager.mark_rate_limited("claude", "chat", None, "b", 429, Some("60"), "");
+
+        let err = manager
+            .get_token_with_options("claude", "chat", &GetTokenOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.attempts.len(), 4);
+
+        let outcome = |id: &str| {
+            err.attempts
+                .iter()
+                .find(|a| a.account_id == id)
+                .map(|a| a.outcome.clone())
+                .unwrap()
+        };
+        assert!(
+            matches!(outcome("a"), AttemptOutcome::RefreshFailed { error } if error.contains("timed out"))
+        );
+        assert!(
+            matches!(outcome("b"), AttemptOutcome::RateLimited { remaining_seconds } if remaining_seconds > 0)
+        );
+        assert_eq!(
+            outcome("c"),
+            AttemptOutcome::ProjectIdFailed {
+                error: "no project".to_string()
+            }
+        );
+        assert!(
+            matches!(outcome("d"), AttemptOutcome::DisabledMidFlight { reason } if reason.contains("invalid_grant"))
+        );
+
+        // The plain wrapper still returns the last error only
+        assert!(err
+            .summary()
+            .contains("c@test.com: project_id failed (no project)"));
+        let message = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap_err();
+        assert!(!message.contains("c@test.com: "), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_enable_account_validates_and_restores() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["custom_field"] = serde_json::json!("kept")
+        });
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        manager.disable_account("a", "invalid_grant").await.unwrap();
+        assert!(manager.is_empty());
+
+        let status = manager.enable_account("a", true).await.unwrap();
+        assert_eq!(status.account_id, "a");
+        assert!(!status.token_expired);
+        assert_eq!(
+            manager.token_for_test("a").unwrap().access_token,
+            "fresh-refresh-a"
+        );
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 1);
+
+        let account = read_account_file(&path);
+        assert_eq!(account["disabled"], false);
+        assert!(account.get("disabled_reason").is_none_or(|r| r.is_null()));
+        assert!(account.get("disabled_at").is_none_or(|r| r.is_null()));
+        assert!(account.get("disabled_kind").is_none_or(|r| r.is_null()));
+        assert_eq!(account["custom_field"], "kept");
+    }
+
+    #[tokio::test]
+    async fn test_enable_account_keeps_disabled_when_refresh_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh("refresh-a", rejected(r#"{"error": "invalid_grant"}"#));
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        manager.disable_account("a", "invalid_grant").await.unwrap();
+
+        let err = manager.enable_account("a", true).await.unwrap_err();
+        assert!(err.contains("invalid_grant"), "{}", err);
+        assert!(manager.is_empty());
+        assert_eq!(read_account_file(&path)["disabled"], true);
+
+        // Skipping the check enables it anyway, addressed by path
+        let status = manager
+            .enable_account(path.to_str().unwrap(), false)
+            .await
+            .unwrap();
+        assert_eq!(status.account_id, "a");
+        assert_eq!(manager.len(), 1);
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 1);
+
+        let err = manager.enable_account("missing", false).await.unwrap_err();
+        assert!(err.contains("not found"), "{}", err);
+
+        // Paths outside the accounts directory are refused
+        let outside = write_account_file(dir.path(), "outside", Some("PRO"));
+        let err = manager
+            .enable_account(outside.to_str().unwrap(), false)
+            .await
+            .unwrap_err();
+        assert!(err.contains("outside the accounts directory"), "{}", err);
+        let sneaky = accounts.join("..").join("outside.json");
+        let err = manager
+            .enable_account(sneaky.to_str().unwrap(), false)
+            .await
+            .unwrap_err();
+        assert!(err.contains("outside the accounts directory"), "{}", err);
+        assert!(manager.token_for_test("outside").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disabled_accounts_are_quarantined_and_come_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(accounts.join("gemini")).unwrap();
+        let path_a = write_account_file(&accounts, "a", Some("PRO"));
+        let path_b = write_account_file(&accounts.join("gemini"), "b", Some("PRO"));
+        edit_account_file(&path_b, expire);
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh("refresh-b", rejected(r#"{"error": "invalid_grant"}"#));
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        manager.set_quarantine_disabled(true);
+
+        // Disabled by hand and by a failed refresh
+        manager
+            .disable_account("a", "paused by admin")
+            .await
+            .unwrap();
+        drop(
+            manager
+                .get_token_for_account("b", "claude", "chat", None)
+                .await
+                .unwrap_err(),
+        );
+        let quarantined_a = accounts.join("disabled").join("a.json");
+        let quarantined_b = accounts.join("disabled").join("gemini").join("b.json");
+        assert!(!path_a.exists() && !path_b.exists());
+        assert_eq!(read_account_file(&quarantined_a)["disabled"], true);
+        assert_eq!(
+            read_account_file(&quarantined_b)["disabled_kind"],
+            "temporary"
+        );
+        assert!(manager.is_empty());
+
+        // A reload lists the quarantined files but never loads them
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(report.active(), 0);
+        assert_eq!(report.skipped_disabled, 2);
+        let mut disabled = report.disabled.clone();
+        disabled.sort();
+        assert_eq!(disabled, vec![quarantined_a.clone(), quarantined_b.clone()]);
+        assert!(manager.token_for_test("a").is_none());
+
+        // Enabling and reviving move the files back where they were
+        manager.enable_account("a", false).await.unwrap();
+        assert!(path_a.exists() && !quarantined_a.exists());
+        assert_eq!(manager.token_for_test("a").unwrap().account_path, path_a);
+
+        client.refresh_errors.lock().unwrap().clear();
+        manager.set_revive_cooldown(0);
+        assert_eq!(manager.revive_disabled_accounts().await, 1);
+        assert!(path_b.exists() && !quarantined_b.exists());
+        assert_eq!(
+            manager.token_for_test("b").unwrap().source_dir.as_deref(),
+            Some("gemini")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dead_client_disables_permanently() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, expire);
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh("refresh-a", rejected(r#"{"error": "invalid_client"}"#));
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        assert!(manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .is_err());
+        let account = read_account_file(&path);
+        assert_eq!(account["disabled"], true);
+        assert_eq!(account["disabled_kind"], "permanent");
+    }
+
+    #[tokio::test]
+    async fn test_temporary_disable_is_revived_after_cooldown() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, expire);
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh("refresh-a", rejected(r#"{"error": "invalid_grant"}"#));
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        assert!(manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .is_err());
+        assert!(manager.is_empty());
+
+        // The incident is over, but the cooldown is not
+        client.refresh_errors.lock().unwrap().clear();
+        let calls = client.refresh_calls.load(Ordering::SeqCst);
+        assert_eq!(manager.revive_disabled_accounts().await, 0);
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), calls);
+
+        manager.set_revive_cooldown(0);
+        assert_eq!(manager.revive_disabled_accounts().await, 1);
+        assert_eq!(
+            manager.token_for_test("a").unwrap().access_token,
+            "fresh-refresh-a"
+        );
+
+        let account = read_account_file(&path);
+        assert_eq!(account["disabled"], false);
+        assert!(account.get("disabled_kind").is_none_or(|r| r.is_null()));
+        assert!(account.get("disabled_at").is_none_or(|r| r.is_null()));
+    }
+
+    #[tokio::test]
+    async fn test_failed_revival_restarts_cooldown_or_turns_permanent() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["disabled"] = serde_json::json!(true);
+            account["disabled_at"] = serde_json::json!(1_700_000_000);
+            account["disabled_kind"] = serde_json::json!("temporary");
+        });
+
+        let client = Arc::new(MockOAuthClient::default());
+        client.fail_refresh("refresh-a", rejected(r#"{"error": "invalid_grant"}"#));
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+
+        // Still failing: stays temporary with a new disabled_at
+        assert_eq!(manager.revive_disabled_accounts().await, 0);
+        let account = read_account_file(&path);
+        assert_eq!(account["disabled"], true);
+        assert_eq!(account["disabled_kind"], "temporary");
+        assert!(account["disabled_at"].as_i64().unwrap() > 1_700_000_000);
+
+        // Failing for good: turns permanent and is never tried again
+        manager.set_revive_cooldown(0);
+        client.fail_refresh("refresh-a", rejected(r#"{"error": "invalid_client"}"#));
+        assert_eq!(manager.revive_disabled_accounts().await, 0);
+        assert_eq!(read_account_file(&path)["disabled_kind"], "permanent");
+
+        client.refresh_errors.lock().unwrap().clear();
+        assert_eq!(manager.revive_disabled_accounts().await, 0);
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 2);
+        assert!(manager.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_permanent_and_legacy_disables_are_never_revived() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+        let legacy = write_account_file(&accounts, "b", Some("PRO"));
+        edit_account_file(&legacy, |account| {
+            account["disabled"] = serde_json::json!(true);
+            account["disabled_at"] = serde_json::json!(1_700_000_000);
+        });
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        manager
+            .disable_account("a", "disabled by hand")
+            .await
+            .unwrap();
+        manager.set_revive_cooldown(0);
+
+        assert_eq!(manager.revive_disabled_accounts().await, 0);
+        assert_eq!(client.refresh_calls.load(Ordering::SeqCst), 0);
+        assert!(manager.is_empty());
+    }
+
+    /// OAuth client whose device login talks to a mock server; project
+    /// lookups answer `project-of-{access_token}`
+    struct DeviceLoginClient {
+        endpoints: crate::modules::oauth::OAuthEndpoints,
+    }
+
+    #[async_trait]
+    impl OAuthClient for DeviceLoginClient {
+        async fn refresh(
+            &self,
+            _refresh_token: &str,
+            _client: Option<&OAuthClientCredentials>,
+            _proxy: Option<&str>,
+        ) -> Result<TokenResponse, OAuthError> {
+            Err("unexpected refresh".to_string().into())
+        }
+
+        async fn fetch_project_id(
+            &self,
+            access_token: &str,
+            _proxy: Option<&str>,
+        ) -> Result<String, OAuthError> {
+            Ok(format!("project-of-{}", access_token))
+        }
+
+        async fn request_device_code(&self) -> Result<crate::modules::oauth::DeviceCode, String> {
+            crate::modules::oauth::request_device_code(&self.endpoints).await
+        }
+
+        async fn poll_device_token(
+            &self,
+            device_code: &str,
+        ) -> Result<crate::modules::oauth::DeviceTokenPoll, String> {
+            crate::modules::oauth::poll_device_token(&self.endpoints, device_code).await
+        }
+
+        async fn fetch_email(&self, access_token: &str) -> Result<String, String> {
+            crate::modules::oauth::get_user_info_from(&self.endpoints.userinfo_url, access_token)
+                .await
+                .map(|info| info.email)
+        }
+    }
+
+    /// Serve Google's device login endpoints on a local port
+    ///
+    /// The token endpoint answers `authorization_pending` to the first
+    /// `pending` polls, then `outcome`: an OAuth error code, or `None` to
+    /// grant `device-access` / `device-refresh`.
+    async fn mock_device_login_server(
+        pending: usize,
+        outcome: Option<&'static str>,
+    ) -> crate::modules::oauth::OAuthEndpoints {
+        use axum::http::StatusCode;
+        use axum::routing::{get, post};
+
+        let polls = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new()
+            .route(
+                "/device/code",
+                post(|| async {
+                    axum::Json(serde_json::json!({
+                        "device_code": "device-1",
+                        "user_code": "ABCD-EFGH",
+                        "verification_url": "https://www.google.com/device",
+                        "expires_in": 1800,
+                        "interval": 0,
+                    }))
+                }),
+            )
+            .route(
+                "/token",
+                post(
+                    move |axum::Form(form): axum::Form<HashMap<String, String>>| {
+                        let polls = polls.clone();
+                        async move {
+                            assert_eq!(
+                                form["grant_type"],
+                                "urn:ietf:params:oauth:grant-type:device_code"
+                            );
+                            assert_eq!(form["device_code"], "device-1");
+                            if polls.fetch_add(1, Ordering::SeqCst) < pending {
+                                return (
+                                    StatusCode::PRECONDITION_REQUIRED,
+                                    axum::Json(
+                                        serde_json::json!({ "error": "authorization_pending" }),
+                                    ),
+                                );
+                            }
+                            match outcome {
+                                Some(error) => (
+                                    StatusCode::BAD_REQUEST,
+                                    axum::Json(serde_json::json!({ "error": error })),
+                                ),
+                                None => (
+                                    StatusCode::OK,
+                                    axum::Json(serde_json::json!({
+                                        "access_token": "device-access",
+                                        "expires_in": 3599,
+                                        "refresh_token": "device-refresh",
+                                        "token_type": "Bearer",
+                                    })),
+                                ),
+                            }
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/userinfo",
+                get(|| async { axum::Json(serde_json::json!({ "email": "new@test.com" })) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        crate::modules::oauth::OAuthEndpoints {
+            device_code_url: format!("{}/device/code", base),
+            token_url: format!("{}/token", base),
+            userinfo_url: format!("{}/userinfo", base),
+            ..Default::default()
+        }
+    }
+
+    async fn manager_with_device_login(
+        dir: &Path,
+        endpoints: crate::modules::oauth::OAuthEndpoints,
+    ) -> TokenManager {
+        let manager = TokenManager::new_with_client(
+            dir.to_path_buf(),
+            Arc::new(DeviceLoginClient { endpoints }),
+        );
+        manager.load_accounts().await.unwrap();
+        manager
+    }
+
+    #[tokio::test]
+    async fn test_device_login_adds_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+        let endpoints = mock_device_login_server(2, None).await;
+        let manager = manager_with_device_login(dir.path(), endpoints).await;
+
+        let pending = manager.begin_add_account().await.unwrap();
+        assert_eq!(pending.user_code, "ABCD-EFGH");
+        assert_eq!(pending.verification_url, "https://www.google.com/device");
+
+        // Two pending polls, then the grant
+        let token = manager.complete_add_account(&pending).await.unwrap();
+        assert_eq!(token.email, "new@test.com");
+        assert_eq!(
+            token.project_id.as_deref(),
+            Some("project-of-device-access")
+        );
+        assert_eq!(manager.len(), 2);
+
+        let file = read_account_file(&token.account_path);
+        assert_eq!(
+            token.account_path,
+            accounts.join(format!("{}.json", token.account_id))
+        );
+        assert_eq!(file["id"], token.account_id.as_str());
+        assert_eq!(file["email"], "new@test.com");
+        assert_eq!(file["token"]["refresh_token"], "device-refresh");
+        assert_eq!(file["token"]["project_id"], "project-of-device-access");
+
+        // Logging in to the same account again keeps its file
+        let pending = manager.begin_add_account().await.unwrap();
+        let again = manager.complete_add_account(&pending).await.unwrap();
+        assert_eq!(again.account_id, token.account_id);
+        assert_eq!(manager.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_device_login_revives_a_quarantined_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        let quarantine = accounts.join(crate::proxy::token_manager::storage::DISABLED_ACCOUNTS_DIR);
+        std::fs::create_dir_all(&quarantine).unwrap();
+        let old = write_account_file(&quarantine, "old", Some("PRO"));
+        edit_account_file(&old, |account| {
+            account["email"] = serde_json::json!("New@Test.com");
+            account["disabled"] = serde_json::json!(true);
+            account["disabled_reason"] = serde_json::json!("invalid_grant");
+        });
+        let endpoints = mock_device_login_server(1, None).await;
+        let manager = manager_with_device_login(dir.path(), endpoints).await;
+        assert_eq!(manager.len(), 0);
+
+        let pending = manager.begin_add_account().await.unwrap();
+        let token = manager.complete_add_account(&pending).await.unwrap();
+
+        // The old file is updated in place rather than duplicated
+        assert_eq!(token.account_id, "old");
+        assert_eq!(token.account_path, accounts.join("old.json"));
+        assert!(!old.exists());
+        let file = read_account_file(&token.account_path);
+        assert_eq!(file["disabled"], false);
+        assert!(file.get("disabled_reason").is_none_or(|r| r.is_null()));
+        assert_eq!(file["token"]["refresh_token"], "device-refresh");
+        assert_eq!(manager.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_expired_device_login_adds_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+        let endpoints = mock_device_login_server(1, Some("expired_token")).await;
+        let manager = manager_with_device_login(dir.path(), endpoints).await;
+
+        let pending = manager.begin_add_account().await.unwrap();
+        assert_eq!(
+            manager.complete_add_account(&pending).await.unwrap_err(),
+            AddAccountError::Expired
+        );
+        assert_eq!(manager.len(), 1);
+        assert_eq!(std::fs::read_dir(&accounts).unwrap().count(), 1);
+
+        // A code that ran out locally is not polled at all
+        let mut stale = manager.begin_add_account().await.unwrap();
+        stale.expires_at = chrono::Utc::now().timestamp() - 1;
+        assert_eq!(
+            manager.complete_add_account(&stale).await.unwrap_err(),
+            AddAccountError::Expired
+        );
+    }
+
+    /// Serve a token endpoint on a local port that grants
+    /// `access-{refresh_token}` and hands each refresh's form to the channel
+    async fn mock_token_server() -> (
+        crate::modules::oauth::OAuthEndpoints,
+        tokio::sync::mpsc::UnboundedReceiver<HashMap<String, String>>,
+    ) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let app = axum::Router::new().route(
+            "/token",
+            axum::routing::post(
+                move |axum::Form(form): axum::Form<HashMap<String, String>>| {
+                    let tx = tx.clone();
+                    async move {
+                        let access_token = format!("access-{}", form["refresh_token"]);
+                        tx.send(form).unwrap();
+                        axum::Json(
+                            serde_json::json!({ "access_token": access_token, "expires_in": 3600 }),
+                        )
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let endpoints = crate::modules::oauth::OAuthEndpoints {
+            token_url: format!("{}/token", base),
+            ..Default::default()
+        };
+        (endpoints, rx)
+    }
+
+    #[tokio::test]
+    async fn test_refresh_uses_each_accounts_oauth_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for id in ["global", "inline", "named"] {
+            let path = write_account_file(&accounts, id, Some("PRO"));
+            edit_account_file(&path, expire);
+        }
+        edit_account_file(&accounts.join("inline.json"), |account| {
+            account["oauth_client_id"] = serde_json::json!("inline-client");
+            account["oauth_client_secret"] = serde_json::json!("inline-secret");
+        });
+        edit_account_file(&accounts.join("named.json"), |account| {
+            account["oauth_client"] = serde_json::json!("team");
+        });
+        std::fs::write(
+            dir.path().join("oauth_clients.json"),
+            r#"{ "team": { "client_id": "team-client", "client_secret": "team-secret" } }"#,
+        )
+        .unwrap();
+
+        let (endpoints, mut forms) = mock_token_server().await;
+        let client =
+            Arc::new(crate::proxy::token_manager::GoogleOAuthClient::with_endpoints(endpoints));
+        let manager = TokenManager::new_with_client(dir.path().to_path_buf(), client);
+        manager.load_accounts().await.unwrap();
+        assert_eq!(manager.refresh_expiring_tokens().await, 3);
+
+        let mut sent = HashMap::new();
+        while let Ok(form) = forms.try_recv() {
+            sent.insert(form["refresh_token"].clone(), form);
+        }
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent["refresh-inline"]["client_id"], "inline-client");
+        assert_eq!(sent["refresh-inline"]["client_secret"], "inline-secret");
+        assert_eq!(sent["refresh-named"]["client_id"], "team-client");
+        assert_eq!(sent["refresh-named"]["client_secret"], "team-secret");
+        // Accounts without a client of their own use the global one
+        let global = &sent["refresh-global"];
+        assert!(!["inline-client", "team-client"].contains(&global["client_id"].as_str()));
+        assert!(global.contains_key("client_secret"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_named_oauth_client_is_not_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+        let path = write_account_file(&accounts, "b", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["oauth_client"] = serde_json::json!("missing")
+        });
+
+        let client = Arc::new(MockOAuthClient::default());
+        let manager = TokenManager::new_with_client(dir.path().to_path_buf(), client);
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(manager.len(), 1);
+        assert!(
+            report
+                .failed
+                .iter()
+                .any(|(_, e)| e.contains("Unknown OAuth client")),
+            "{:?}",
+            report.failed
+        );
+    }
+
+    /// Token endpoint for service accounts
+    ///
+    /// Answers JWT bearer grants whose assertion is signed by the test key,
+    /// unless `reject` is set; otherwise it fails like Google does for a
+    /// key that was deleted.
+    async fn mock_service_account_server(reject: bool) -> (String, Arc<AtomicUsize>) {
+        use crate::proxy::token_manager::service_account::{parse_private_key, TEST_PRIVATE_KEY};
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+        use ring::signature::{KeyPair, UnparsedPublicKey, RSA_PKCS1_2048_8192_SHA256};
+
+        let public_key = parse_private_key(TEST_PRIVATE_KEY)
+            .unwrap()
+            .public_key()
+            .as_ref()
+            .to_vec();
+        let grants = Arc::new(AtomicUsize::new(0));
+        let counter = grants.clone();
+        let app = axum::Router::new().route(
+            "/token",
+            axum::routing::post(move |axum::Form(form): axum::Form<HashMap<String, String>>| {
+                let public_key = UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, public_key.clone());
+                let counter = counter.clone();
+                async move {
+                    assert_eq!(form["grant_type"], "urn:ietf:params:oauth:grant-type:jwt-bearer");
+                    let (signed, signature) = form["assertion"].rsplit_once('.').unwrap();
+                    let signature = URL_SAFE_NO_PAD.decode(signature).unwrap();
+                    if reject || public_key.verify(signed.as_bytes(), &signature).is_err() {
+                        return (
+                            axum::http::StatusCode::BAD_REQUEST,
+                            axum::Json(serde_json::json!({
+                                "error": "invalid_grant",
+                                "error_description": "Invalid JWT Signature.",
+                            })),
+                        );
+                    }
+                    let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                    (
+                        axum::http::StatusCode::OK,
+                        axum::Json(serde_json::json!({ "access_token": format!("sa-token-{}", n), "expires_in": 3600 })),
+                    )
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let token_uri = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (token_uri, grants)
+    }
+
+    /// Write a service account file without a token section
+    fn write_service_account_file(
+        accounts: &Path,
+        id: &str,
+        token_uri: &str,
+    ) -> std::path::PathBuf {
+        let path = accounts.join(format!("{}.json", id));
+        let account = serde_json::json!({
+            "id": id,
+            "email": format!("{}@example-project.iam.gserviceaccount.com", id),
+            "auth_type": "service_account",
+            "service_account_key": {
+                "type": "service_account",
+                "project_id": "example-project",
+                "client_email": format!("{}@example-project.iam.gserviceaccount.com", id),
+                "private_key": crate::proxy::token_manager::service_account::TEST_PRIVATE_KEY,
+                "token_uri": token_uri,
+            },
+            "quota": { "subscription_tier": "PRO" },
+        });
+        std::fs::write(&path, serde_json::to_string_pretty(&account).unwrap()).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_service_account_mints_tokens_from_its_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let (token_uri, grants) = mock_service_account_server(false).await;
+        let path = write_service_account_file(&accounts, "sa", &token_uri);
+
+        let client = Arc::new(crate::proxy::token_manager::GoogleOAuthClient::new());
+        let manager = TokenManager::new_with_client(dir.path().to_path_buf(), client);
+        manager.load_accounts().await.unwrap();
+        assert_eq!(manager.len(), 1);
+
+        let selected = manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "sa-token-1");
+        // The project comes from the key, without a lookup
+        assert_eq!(selected.project_id, "example-project");
+        assert_eq!(grants.load(Ordering::SeqCst), 1);
+
+        manager.flush_token_writes().await;
+        let account = read_account_file(&path);
+        assert_eq!(account["token"]["access_token"], "sa-token-1");
+        assert_eq!(account["auth_type"], "service_account");
+        assert!(account["service_account_key"]["private_key"]
+            .as_str()
+            .unwrap()
+            .contains("PRIVATE KEY"));
+
+        // The minted token is reused until it expires
+        manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .unwrap();
+        assert_eq!(grants.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_service_account_key_disables_permanently() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let (token_uri, _) = mock_service_account_server(true).await;
+        let path = write_service_account_file(&accounts, "sa", &token_uri);
+
+        let client = Arc::new(crate::proxy::token_manager::GoogleOAuthClient::new());
+        let manager = TokenManager::new_with_client(dir.path().to_path_buf(), client);
+        manager.load_accounts().await.unwrap();
+
+        assert!(manager
+            .get_token("claude", "chat", None, false, None)
+            .await
+            .is_err());
+        let account = read_account_file(&path);
+        assert_eq!(account["disabled"], true);
+        assert_eq!(account["disabled_kind"], "permanent");
+    }
+
+    #[tokio::test]
+    async fn test_service_account_without_key_fails_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_service_account_file(&accounts, "sa", "http://127.0.0.1:9/token");
+        edit_account_file(&path, |account| {
+            account
+                .as_object_mut()
+                .unwrap()
+                .remove("service_account_key");
+        });
+
+        let manager = manager_with_client(dir.path(), Arc::new(MockOAuthClient::default())).await;
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(manager.len(), 0);
+        assert!(
+            report
+                .failed
+                .iter()
+                .any(|(_, e)| e.contains("service_account_key")),
+            "{:?}",
+            report.failed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_outbound_proxy_fails_account_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+        let path = write_account_file(&accounts, "b", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["outbound_proxy"] = serde_json::json!("ftp://10.0.0.1:21")
+        });
+
+        let manager = manager_with_client(dir.path(), Arc::new(MockOAuthClient::default())).await;
+        let report = manager.load_accounts().await.unwrap();
+        assert_eq!(manager.len(), 1);
+        assert!(
+            report
+                .failed
+                .iter()
+                .any(|(_, e)| e.contains("Invalid outbound_proxy")),
+            "{:?}",
+            report.failed
+        );
+
+        assert!(manager.set_outbound_proxy(Some("not a proxy")).is_err());
+        assert!(manager
+            .set_outbound_proxy(Some("socks5://127.0.0.1:1080"))
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_outbound_proxy_is_a_temporary_refresh_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        for id in ["own", "global", "direct"] {
+            let path = write_account_file(&accounts, id, Some("PRO"));
+            edit_account_file(&path, expire);
+        }
+        // Nothing listens on port 1
+        edit_account_file(&accounts.join("own.json"), |account| {
+            account["outbound_proxy"] = serde_json::json!("http://127.0.0.1:1");
+        });
+
+        let (endpoints, _forms) = mock_token_server().await;
+        let manager = manager_with_endpoints(dir.path(), endpoints).await;
+
+        let err = manager
+            .get_token_for_account("own", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, AccountTokenError::Refresh(e) if e.kind() == RefreshErrorKind::Temporary),
+            "{:?}",
+            err
+        );
+        assert!(manager.token_for_test("own").is_some());
+
+        manager
+            .set_outbound_proxy(Some("socks5://127.0.0.1:1"))
+            .unwrap();
+        let err = manager
+            .get_token_for_account("global", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, AccountTokenError::Refresh(e) if e.kind() == RefreshErrorKind::Temporary),
+            "{:?}",
+            err
+        );
+        assert!(manager.token_for_test("global").is_some());
+
+        manager.set_outbound_proxy(None).unwrap();
+        let selected = manager
+            .get_token_for_account("direct", "claude", "chat", None)
+            .await
+            .unwrap();
+        assert_eq!(selected.access_token, "access-refresh-direct");
+    }
+
+    /// Serve a revocation endpoint on a local port answering `status` and
+    /// handing each revoked token to the channel
+    async fn mock_revoke_server(
+        status: u16,
+    ) -> (
+        crate::modules::oauth::OAuthEndpoints,
+        tokio::sync::mpsc::UnboundedReceiver<String>,
+    ) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let app = axum::Router::new().route(
+            "/revoke",
+            axum::routing::post(
+                move |axum::Form(form): axum::Form<HashMap<String, String>>| {
+                    let tx = tx.clone();
+                    async move {
+                        tx.send(form["token"].clone()).unwrap();
+                        axum::http::StatusCode::from_u16(status).unwrap()
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let endpoints = crate::modules::oauth::OAuthEndpoints {
+            revoke_url: format!("{}/revoke", base),
+            ..Default::default()
+        };
+        (endpoints, rx)
+    }
+
+    async fn manager_with_endpoints(
+        dir: &Path,
+        endpoints: crate::modules::oauth::OAuthEndpoints,
+    ) -> TokenManager {
+        let client =
+            Arc::new(crate::proxy::token_manager::GoogleOAuthClient::with_endpoints(endpoints));
+        let manager = TokenManager::new_with_client(dir.to_path_buf(), client);
+        manager.set_oauth_call_policy(OAuthCallPolicy {
+            retry_backoff: std::time::Duration::from_millis(1),
+            ..OAuthCallPolicy::default()
+        });
+        manager.load_accounts().await.unwrap();
+        manager
+    }
+
+    #[tokio::test]
+    async fn test_retired_account_is_revoked_and_removed() {
+        use crate::proxy::token_manager::types::{RetireOptions, RetiredAccountFile};
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let kept = write_account_file(&accounts, "a", Some("PRO"));
+        let quarantined = write_account_file(&accounts, "b", Some("PRO"));
+        let deleted = write_account_file(&accounts, "c", Some("PRO"));
+        let (endpoints, mut revoked) = mock_revoke_server(200).await;
+        let manager = manager_with_endpoints(dir.path(), endpoints).await;
+
+        let report = manager
+            .remove_account_and_revoke("a", RetireOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(report.revocation_error, None);
+        assert_eq!(revoked.try_recv().unwrap(), "refresh-a");
+        assert!(manager.token_for_test("a").is_none());
+        let file = read_account_file(&kept);
+        assert_eq!(file["disabled"], true);
+        assert_eq!(file["disabled_kind"], "permanent");
+        assert_ne!(file["token"]["refresh_token"], "refresh-a");
+
+        let options = RetireOptions {
+            file: RetiredAccountFile::Quarantine,
+            ..RetireOptions::default()
+        };
+        manager
+            .remove_account_and_revoke("b", options)
+            .await
+            .unwrap();
+        assert!(!quarantined.exists());
+        let file = read_account_file(&accounts.join("disabled").join("b.json"));
+        assert!(!file.to_string().contains("refresh-b"));
+
+        let options = RetireOptions {
+            file: RetiredAccountFile::Delete,
+            ..RetireOptions::default()
+        };
+        manager
+            .remove_account_and_revoke("c", options)
+            .await
+            .unwrap();
+        assert!(!deleted.exists());
+        assert!(manager.is_empty());
+
+        // A reload doesn't bring retired accounts back
+        manager.load_accounts().await.unwrap();
+        assert!(manager.is_empty());
+        assert!(manager
+            .remove_account_and_revoke("a", RetireOptions::default())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_already_revoked_token_counts_as_revoked() {
+        use crate::proxy::token_manager::types::RetireOptions;
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+        let (endpoints, _revoked) = mock_revoke_server(400).await;
+        let manager = manager_with_endpoints(dir.path(), endpoints).await;
+
+        let options = RetireOptions {
+            require_revocation: true,
+            ..RetireOptions::default()
+        };
+        let report = manager
+            .remove_account_and_revoke("a", options)
+            .await
+            .unwrap();
+        assert_eq!(report.revocation_error, None);
+        assert!(manager.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_revocation_is_reported() {
+        use crate::proxy::token_manager::types::RetireOptions;
+
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+        write_account_file(&accounts, "b", Some("PRO"));
+        write_service_account_file(&accounts, "sa", "http://127.0.0.1:1/token");
+        // Nothing listens on port 1
+        let endpoints = crate::modules::oauth::OAuthEndpoints {
+            revoke_url: "http://127.0.0.1:1/revoke".to_string(),
+            ..Default::default()
+        };
+        let manager = manager_with_endpoints(dir.path(), endpoints).await;
+
+        // Required revocation keeps the account
+        let options = RetireOptions {
+            require_revocation: true,
+            ..RetireOptions::default()
+        };
+        let err = manager
+            .remove_account_and_revoke("a", options)
+            .await
+            .unwrap_err();
+        assert!(!err.contains("refresh-a"), "{}", err);
+        assert!(manager.token_for_test("a").is_some());
+
+        // Otherwise the failure is reported and the account removed anyway
+        let report = manager
+            .remove_account_and_revoke("b", RetireOptions::default())
+            .await
+            .unwrap();
+        let error = report.revocation_error.unwrap();
+        assert!(!error.contains("refresh-b"), "{}", error);
+        assert!(manager.token_for_test("b").is_none());
+        assert_eq!(manager.len(), 2);
+
+        // A service account has no token to revoke, so nothing can fail
+        let options = RetireOptions {
+            require_revocation: true,
+            ..RetireOptions::default()
+        };
+        let report = manager
+            .remove_account_and_revoke("sa", options)
+            .await
+            .unwrap();
+        assert_eq!(report.revocation_error, None);
+        assert_eq!(manager.len(), 1);
+    }
+
+    /// Serve a token endpoint on a local port that answers `status` after
+    /// `delay`, counting the calls
+    async fn slow_token_server(
+        delay: std::time::Duration,
+        status: u16,
+    ) -> (crate::modules::oauth::OAuthEndpoints, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = axum::Router::new().route(
+            "/token",
+            axum::routing::post(move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(delay).await;
+                    (
+                        axum::http::StatusCode::from_u16(status).unwrap(),
+                        axum::Json(serde_json::json!({ "access_token": "slow-access", "expires_in": 3600 })),
+                    )
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let endpoints = crate::modules::oauth::OAuthEndpoints {
+            token_url: format!("{}/token", base),
+            ..Default::default()
+        };
+        (endpoints, calls)
+    }
+
+    #[tokio::test]
+    async fn test_hung_refresh_times_out_and_releases_the_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, expire);
+        let (endpoints, calls) = slow_token_server(std::time::Duration::from_secs(30), 200).await;
+        let manager = manager_with_endpoints(dir.path(), endpoints).await;
+        manager.set_oauth_call_policy(OAuthCallPolicy {
+            timeout: std::time::Duration::from_millis(300),
+            retries: 1,
+            retry_backoff: std::time::Duration::from_millis(1),
+        });
+
+        let started = std::time::Instant::now();
+        let err = manager
+            .get_token_for_account("a", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        let elapsed = started.elapsed();
+        assert!(
+            matches!(&err, AccountTokenError::Refresh(e) if e.kind() == RefreshErrorKind::Temporary),
+            "{:?}",
+            err
+        );
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        // Two attempts of ~300ms each, not the server's 30s
+        assert!(
+            elapsed >= std::time::Duration::from_millis(600),
+            "{:?}",
+            elapsed
+        );
+        assert!(elapsed < std::time::Duration::from_secs(5), "{:?}", elapsed);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // The refresh lock was released: the next request answers at once
+        let started = std::time::Instant::now();
+        let err = manager
+            .get_token_for_account("a", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            AccountTokenError::Refresh(RefreshError::BackingOff { .. })
+        ));
+        assert!(started.elapsed() < std::time::Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried_then_temporary() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, expire);
+        let (endpoints, calls) = slow_token_server(std::time::Duration::ZERO, 500).await;
+        let manager = manager_with_endpoints(dir.path(), endpoints).await;
+        manager.set_oauth_call_policy(OAuthCallPolicy {
+            retry_backoff: std::time::Duration::from_millis(1),
+            ..OAuthCallPolicy::default()
+        });
+
+        let err = manager
+            .get_token_for_account("a", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, AccountTokenError::Refresh(e) if e.kind() == RefreshErrorKind::Temporary),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            calls.load(Ordering::SeqCst),
+            1 + DEFAULT_OAUTH_RETRIES as usize
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hung_project_lookup_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        let path = write_account_file(&accounts, "a", Some("PRO"));
+        edit_account_file(&path, |account| {
+            account["token"]
+                .as_object_mut()
+                .unwrap()
+                .remove("project_id");
+        });
+
+        let client = Arc::new(MockOAuthClient {
+            project_delay: Some(std::time::Duration::from_secs(30)),
+            ..MockOAuthClient::default()
+        });
+        let manager = manager_with_client(dir.path(), client.clone()).await;
+        manager.set_oauth_call_policy(OAuthCallPolicy {
+            timeout: std::time::Duration::from_millis(200),
+            retries: 0,
+            ..OAuthCallPolicy::default()
+        });
+
+        let started = std::time::Instant::now();
+        let err = manager
+            .get_token_for_account("a", "claude", "chat", None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(client.project_calls.load(Ordering::SeqCst), 1);
+    }
 }
@@ -0,0 +1,191 @@
+//! Per-Account Request Throttle
+//!
+//! Accounts may declare `max_rpm` in their file. Requests are counted per
+//! account and scope group over a sliding minute, and the scheduler passes
+//! over an account whose minute is full instead of waiting for Google to
+//! answer 429 and put it into a cooldown. Counts live in one-second buckets,
+//! so the window slides in whole seconds.
+//!
+//! The check at selection and the count after it are separate steps, so
+//! concurrent selections may overshoot `max_rpm` by a request or two.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+
+/// Length of the throttle window in seconds
+pub const WINDOW_SECONDS: i64 = 60;
+
+/// Requests of one account in one scope group, one bucket per second
+///
+/// A bucket packs the second it counts (high 32 bits) with its count (low
+/// 32 bits); the first request landing on a bucket from a minute ago resets it.
+struct Window {
+    buckets: [AtomicU64; WINDOW_SECONDS as usize],
+}
+
+impl Window {
+    fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn pack(second: i64, count: u64) -> u64 {
+        ((second as u64) << 32) | count.min(u32::MAX as u64)
+    }
+
+    fn unpack(packed: u64) -> (i64, u64) {
+        ((packed >> 32) as i64, packed & u32::MAX as u64)
+    }
+
+    fn record(&self, now: i64) {
+        let bucket = &self.buckets[now.rem_euclid(WINDOW_SECONDS) as usize];
+        let _ = bucket.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |packed| {
+            let (second, count) = Self::unpack(packed);
+            Some(if second == now {
+                Self::pack(now, count + 1)
+            } else {
+                Self::pack(now, 1)
+            })
+        });
+    }
+
+    /// (second, count) of the buckets in the minute ending at `now`, oldest first
+    fn live(&self, now: i64) -> Vec<(i64, u64)> {
+        let mut live: Vec<(i64, u64)> = self
+            .buckets
+            .iter()
+            .map(|b| Self::unpack(b.load(Ordering::SeqCst)))
+            .filter(|&(second, count)| count > 0 && second <= now && now - second < WINDOW_SECONDS)
+            .collect();
+        live.sort_unstable();
+        live
+    }
+
+    fn count(&self, now: i64) -> u64 {
+        self.live(now).iter().map(|(_, count)| count).sum()
+    }
+
+    /// Seconds until the minute holds fewer than `max` requests
+    fn wait(&self, now: i64, max: u32) -> u64 {
+        let live = self.live(now);
+        let total: u64 = live.iter().map(|(_, count)| count).sum();
+        if total < max as u64 {
+            return 0;
+        }
+        // The oldest requests have to age out until one slot is free
+        let mut excess = total - max as u64 + 1;
+        for (second, count) in live {
+            if count >= excess {
+                return (second + WINDOW_SECONDS - now) as u64;
+            }
+            excess -= count;
+        }
+        WINDOW_SECONDS as u64
+    }
+}
+
+/// Request windows keyed by (account_id, scope_group)
+pub struct RequestThrottle {
+    windows: DashMap<(String, String), Window>,
+}
+
+impl RequestThrottle {
+    pub fn new() -> Self {
+        Self {
+            windows: DashMap::new(),
+        }
+    }
+
+    /// Count a request sent with the account in a scope group at `now`
+    pub fn record_at(&self, scope_group: &str, account_id: &str, now: i64) {
+        self.windows
+            .entry((account_id.to_string(), scope_group.to_string()))
+            .or_insert_with(Window::new)
+            .record(now);
+    }
+
+    /// Seconds until the account may take another request in a scope group
+    ///
+    /// 0 if it may right now, always so without a `max_rpm`.
+    pub fn wait_at(
+        &self,
+        scope_group: &str,
+        account_id: &str,
+        max_rpm: Option<u32>,
+        now: i64,
+    ) -> u64 {
+        let Some(max) = max_rpm else {
+            return 0;
+        };
+        self.windows
+            .get(&(account_id.to_string(), scope_group.to_string()))
+            .map_or(0, |window| window.wait(now, max))
+    }
+
+    /// Requests in the minute ending at `now` as (account_id, scope_group, count)
+    ///
+    /// Windows without requests in that minute are left out.
+    pub fn counts_at(&self, now: i64) -> Vec<(String, String, u64)> {
+        self.windows
+            .iter()
+            .map(|e| (e.key().0.clone(), e.key().1.clone(), e.value().count(now)))
+            .filter(|(_, _, count)| *count > 0)
+            .collect()
+    }
+
+    /// Drop the windows of accounts not in `retained_account_ids`
+    pub fn cleanup(&self, retained_account_ids: &std::collections::HashSet<String>) {
+        self.windows
+            .retain(|(account_id, _), _| retained_account_ids.contains(account_id));
+    }
+}
+
+impl Default for RequestThrottle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_slides_by_the_second() {
+        let throttle = RequestThrottle::new();
+        let t0 = 1_700_000_000;
+        throttle.record_at("claude", "a", t0);
+        throttle.record_at("claude", "a", t0);
+        throttle.record_at("claude", "a", t0 + 10);
+
+        assert_eq!(throttle.wait_at("claude", "a", None, t0 + 10), 0);
+        assert_eq!(throttle.wait_at("claude", "a", Some(4), t0 + 10), 0);
+        // Both requests of t0 must age out before a third slot frees up
+        assert_eq!(throttle.wait_at("claude", "a", Some(3), t0 + 10), 50);
+        // One of them is enough for two slots
+        assert_eq!(throttle.wait_at("claude", "a", Some(2), t0 + 10), 50);
+        assert_eq!(throttle.wait_at("claude", "a", Some(1), t0 + 10), 60);
+        // Other scope groups and accounts count apart
+        assert_eq!(throttle.wait_at("gemini", "a", Some(1), t0 + 10), 0);
+        assert_eq!(throttle.wait_at("claude", "b", Some(1), t0 + 10), 0);
+
+        assert_eq!(
+            throttle.counts_at(t0 + 10),
+            vec![("a".to_string(), "claude".to_string(), 3)]
+        );
+        assert_eq!(
+            throttle.counts_at(t0 + 60),
+            vec![("a".to_string(), "claude".to_string(), 1)]
+        );
+        assert!(throttle.counts_at(t0 + 70).is_empty());
+
+        // A bucket a minute old is reused, not added to
+        throttle.record_at("claude", "a", t0 + 60);
+        assert_eq!(
+            throttle.counts_at(t0 + 60),
+            vec![("a".to_string(), "claude".to_string(), 2)]
+        );
+    }
+}
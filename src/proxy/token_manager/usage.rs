@@ -0,0 +1,195 @@
+//! Account Usage Statistics
+//!
+//! Selections and rate limits are counted in memory and written back into
+//! each account file's `proxy_stats` object, so the desktop app can show
+//! usage without a separate store. Writes are coalesced: an account's file
+//! is written at most once per flush interval, and once more on shutdown.
+
+use std::collections::HashSet;
+
+use dashmap::DashMap;
+
+use super::types::ProxyStats;
+
+/// Minimum seconds between two stats writes to the same account file
+pub const STATS_FLUSH_INTERVAL_SECONDS: i64 = 60;
+
+/// Counters for one account and how much of them is on disk
+#[derive(Debug, Default)]
+struct UsageEntry {
+    stats: ProxyStats,
+    /// Bumped on every change
+    revision: u64,
+    /// Revision last written to the account file
+    flushed_revision: u64,
+    /// Unix timestamp of the last write
+    flushed_at: Option<i64>,
+}
+
+/// Stats waiting to be written to an account file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingStats {
+    pub account_id: String,
+    pub stats: ProxyStats,
+    pub revision: u64,
+}
+
+/// Per-account usage counters
+pub struct UsageTracker {
+    entries: DashMap<String, UsageEntry>,
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self {
+            entries: DashMap::new(),
+        }
+    }
+
+    /// Start counting from the stats found in the account file
+    ///
+    /// Accounts already tracked keep their in-memory counters, which are
+    /// never behind the file.
+    pub fn seed(&self, account_id: &str, stats: ProxyStats) {
+        self.entries
+            .entry(account_id.to_string())
+            .or_insert_with(|| UsageEntry {
+                stats,
+                ..UsageEntry::default()
+            });
+    }
+
+    /// Count a selection of the account at `now`
+    pub fn record_request(&self, account_id: &str, now: i64) {
+        let mut entry = self.entries.entry(account_id.to_string()).or_default();
+        entry.stats.total_requests += 1;
+        entry.stats.last_used_at = Some(now);
+        entry.revision += 1;
+    }
+
+    /// Count a rate limit hit by the account
+    pub fn record_rate_limit(&self, account_id: &str) {
+        let mut entry = self.entries.entry(account_id.to_string()).or_default();
+        entry.stats.total_rate_limits += 1;
+        entry.revision += 1;
+    }
+
+    /// Current counters for an account
+    pub fn get(&self, account_id: &str) -> Option<ProxyStats> {
+        self.entries.get(account_id).map(|e| e.stats.clone())
+    }
+
+    /// Accounts with changes not yet on disk
+    ///
+    /// With `min_interval`, accounts written less than that many seconds
+    /// before `now` are left for a later flush.
+    pub fn pending(&self, now: i64, min_interval: Option<i64>) -> Vec<PendingStats> {
+        self.entries
+            .iter()
+            .filter(|e| e.revision != e.flushed_revision)
+            .filter(|e| match (min_interval, e.flushed_at) {
+                (Some(interval), Some(flushed_at)) => now - flushed_at >= interval,
+                _ => true,
+            })
+            .map(|e| PendingStats {
+                account_id: e.key().clone(),
+                stats: e.stats.clone(),
+                revision: e.revision,
+            })
+            .collect()
+    }
+
+    /// Note that `revision` of an account's stats was written at `now`
+    ///
+    /// Changes made while the write was in flight stay pending.
+    pub fn mark_flushed(&self, account_id: &str, revision: u64, now: i64) {
+        if let Some(mut entry) = self.entries.get_mut(account_id) {
+            entry.flushed_revision = entry.flushed_revision.max(revision);
+            entry.flushed_at = Some(now);
+        }
+    }
+
+    /// Drop counters of accounts that left the pool
+    pub fn retain(&self, account_ids: &HashSet<String>) {
+        self.entries.retain(|id, _| account_ids.contains(id));
+    }
+
+    /// Drop one account's counters
+    pub fn remove(&self, account_id: &str) {
+        self.entries.remove(account_id);
+    }
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn test_seed_then_count() {
+        let tracker = UsageTracker::new();
+        tracker.seed(
+            "a",
+            ProxyStats {
+                last_used_at: Some(NOW - 100),
+                total_requests: 10,
+                total_rate_limits: 2,
+            },
+        );
+        tracker.record_request("a", NOW);
+        tracker.record_rate_limit("a");
+        // A later seed from an older file does not roll the counters back
+        tracker.seed("a", ProxyStats::default());
+
+        let stats = tracker.get("a").unwrap();
+        assert_eq!(stats.total_requests, 11);
+        assert_eq!(stats.total_rate_limits, 3);
+        assert_eq!(stats.last_used_at, Some(NOW));
+    }
+
+    #[test]
+    fn test_pending_respects_flush_interval() {
+        let tracker = UsageTracker::new();
+        tracker.seed("idle", ProxyStats::default());
+        tracker.record_request("a", NOW);
+        tracker.record_request("a", NOW);
+
+        let pending = tracker.pending(NOW, Some(STATS_FLUSH_INTERVAL_SECONDS));
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].stats.total_requests, 2);
+        tracker.mark_flushed("a", pending[0].revision, NOW);
+        assert!(tracker.pending(NOW, None).is_empty());
+
+        tracker.record_request("a", NOW + 1);
+        assert!(tracker
+            .pending(NOW + 1, Some(STATS_FLUSH_INTERVAL_SECONDS))
+            .is_empty());
+        assert_eq!(tracker.pending(NOW + 1, None).len(), 1);
+        assert_eq!(
+            tracker
+                .pending(NOW + 60, Some(STATS_FLUSH_INTERVAL_SECONDS))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_changes_during_write_stay_pending() {
+        let tracker = UsageTracker::new();
+        tracker.record_request("a", NOW);
+        let pending = tracker.pending(NOW, None);
+        tracker.record_rate_limit("a");
+        tracker.mark_flushed("a", pending[0].revision, NOW);
+
+        let pending = tracker.pending(NOW, None);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].stats.total_rate_limits, 1);
+    }
+}
@@ -0,0 +1,378 @@
+//! Accounts Directory Watcher
+//!
+//! Polls the accounts directory, subdirectories included, and applies file
+//! changes to the live pool one file at a time, so adding or removing an
+//! account from the GUI never disturbs sessions bound to the other accounts.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::core::TokenManager;
+use super::storage::find_account_files;
+use super::types::UpsertOutcome;
+
+/// Observed state of an account file: its mtime, or `None` once deleted
+type FileState = Option<SystemTime>;
+
+/// Polling watcher state for the accounts directory
+pub(super) struct AccountWatcher {
+    dir: PathBuf,
+    /// How long a change must stay unchanged before it is applied
+    debounce: Duration,
+    /// File states already reflected in the pool
+    applied: HashMap<PathBuf, SystemTime>,
+    /// Changes seen but not yet applied, with when they were first observed
+    pending: HashMap<PathBuf, (FileState, Instant)>,
+}
+
+impl AccountWatcher {
+    /// Create a watcher, treating the directory's current contents as applied
+    pub(super) fn new(dir: PathBuf, debounce: Duration) -> Self {
+        let applied = scan_account_files(&dir).unwrap_or_else(|e| {
+            tracing::warn!("[AccountWatcher] Failed to scan {:?}: {}", dir, e);
+            HashMap::new()
+        });
+        Self {
+            dir,
+            debounce,
+            applied,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Scan the directory once and apply every change that has settled
+    pub(super) async fn poll(&mut self, manager: &TokenManager) {
+        let dir = self.dir.clone();
+        let scanned = tokio::task::spawn_blocking(move || scan_account_files(&dir))
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+        // An unreadable directory says nothing about its files; keep the pool as is
+        let current = match scanned {
+            Ok(current) => current,
+            Err(e) => {
+                tracing::warn!("[AccountWatcher] Failed to scan {:?}: {}", self.dir, e);
+                return;
+            }
+        };
+
+        let paths: HashSet<PathBuf> = self.applied.keys().chain(current.keys()).cloned().collect();
+
+        for path in paths {
+            let observed = current.get(&path).copied();
+            if observed == self.applied.get(&path).copied() {
+                self.pending.remove(&path);
+                continue;
+            }
+
+            let settled = match self.pending.get(&path) {
+                Some((state, since)) if *state == observed => since.elapsed() >= self.debounce,
+                _ => {
+                    self.pending
+                        .insert(path.clone(), (observed, Instant::now()));
+                    self.debounce.is_zero()
+                }
+            };
+
+            if !settled {
+                continue;
+            }
+
+            self.pending.remove(&path);
+            match observed {
+                Some(mtime) => {
+                    self.applied.insert(path.clone(), mtime);
+                    apply_file_change(manager, &path).await;
+                }
+                None => {
+                    self.applied.remove(&path);
+                    apply_file_removal(manager, &path);
+                }
+            }
+        }
+    }
+}
+
+/// List account JSON files with their modification times
+fn scan_account_files(dir: &Path) -> std::io::Result<HashMap<PathBuf, SystemTime>> {
+    Ok(find_account_files(dir)?
+        .into_iter()
+        .filter_map(|p| {
+            let mtime = std::fs::metadata(&p).and_then(|m| m.modified()).ok()?;
+            Some((p, mtime))
+        })
+        .collect())
+}
+
+/// Load a created or modified file into the pool
+async fn apply_file_change(manager: &TokenManager, path: &Path) {
+    match manager.load_single_account(path).await {
+        Ok(Some(token)) => {
+            // A copy of a loaded account's file must not take the account over
+            if let Some((field, value, kept)) = manager.conflicting_account(&token) {
+                tracing::warn!(
+                    "[AccountWatcher] Skipping account file {:?}: {} {} is already loaded from {:?}",
+                    path,
+                    field,
+                    value,
+                    kept
+                );
+                return;
+            }
+            let name = manager.display_identity(&token);
+            // The file may now carry a different id than before
+            if let Some(old_id) = manager.account_id_for_path(path) {
+                if old_id != token.account_id {
+                    manager.evict_account(&old_id);
+                }
+            }
+            match manager.upsert_account(token) {
+                UpsertOutcome::Added => {
+                    tracing::info!("[AccountWatcher] Added account {} from {:?}", name, path);
+                }
+                UpsertOutcome::Updated => {
+                    tracing::info!("[AccountWatcher] Updated account {} from {:?}", name, path);
+                }
+                UpsertOutcome::Unchanged => {
+                    tracing::debug!("[AccountWatcher] Account {} unchanged ({:?})", name, path);
+                }
+            }
+        }
+        Ok(None) => {
+            if let Some(account_id) = manager.account_id_for_path(path) {
+                manager.evict_account(&account_id);
+                tracing::info!(
+                    "[AccountWatcher] Removed disabled account {} ({:?})",
+                    account_id,
+                    path
+                );
+            }
+        }
+        Err(e) => {
+            tracing::warn!("[AccountWatcher] Failed to load {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Remove the account backed by a deleted file
+fn apply_file_removal(manager: &TokenManager, path: &Path) {
+    if let Some(account_id) = manager.account_id_for_path(path) {
+        manager.evict_account(&account_id);
+        tracing::info!(
+            "[AccountWatcher] Removed account {} (file deleted: {:?})",
+            account_id,
+            path
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::token_manager::tests::write_account_file;
+
+    async fn setup() -> (tempfile::TempDir, TokenManager) {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = dir.path().join("accounts");
+        std::fs::create_dir_all(&accounts).unwrap();
+        write_account_file(&accounts, "a", Some("PRO"));
+
+        let manager = TokenManager::new(dir.path().to_path_buf());
+        manager.load_accounts().await.unwrap();
+        (dir, manager)
+    }
+
+    #[tokio::test]
+    async fn test_added_file_is_loaded_without_touching_bindings() {
+        let (dir, manager) = setup().await;
+        let accounts = dir.path().join("accounts");
+        manager.bind_session_for_test("claude", "session-1", "a");
+
+        let mut watcher = AccountWatcher::new(accounts.clone(), Duration::ZERO);
+        write_account_file(&accounts, "b", Some("ULTRA"));
+        watcher.poll(&manager).await;
+
+        assert_eq!(manager.len(), 2);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("a".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_added_in_subdirectory_is_loaded() {
+        let (dir, manager) = setup().await;
+        let accounts = dir.path().join("accounts");
+        let gemini = accounts.join("gemini");
+        std::fs::create_dir_all(&gemini).unwrap();
+
+        let mut watcher = AccountWatcher::new(accounts.clone(), Duration::ZERO);
+        let path = write_account_file(&gemini, "g", None);
+        watcher.poll(&manager).await;
+        assert_eq!(
+            manager.token_for_test("g").unwrap().source_dir.as_deref(),
+            Some("gemini")
+        );
+
+        std::fs::remove_file(path).unwrap();
+        watcher.poll(&manager).await;
+        assert!(manager.token_for_test("g").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_modified_file_updates_in_place() {
+        let (dir, manager) = setup().await;
+        let accounts = dir.path().join("accounts");
+        manager.bind_session_for_test("claude", "session-1", "a");
+
+        let mut watcher = AccountWatcher::new(accounts.clone(), Duration::ZERO);
+        let path = write_account_file(&accounts, "a", Some("ULTRA"));
+        // Make sure the mtime differs from the baseline scan
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        watcher.poll(&manager).await;
+
+        assert_eq!(manager.len(), 1);
+        assert_eq!(
+            manager
+                .token_for_test("a")
+                .unwrap()
+                .subscription_tier
+                .as_deref(),
+            Some("ULTRA")
+        );
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("a".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deleted_file_removes_account_and_bindings() {
+        let (dir, manager) = setup().await;
+        let accounts = dir.path().join("accounts");
+        write_account_file(&accounts, "b", None);
+        manager.load_accounts().await.unwrap();
+        manager.bind_session_for_test("claude", "session-a", "a");
+        manager.bind_session_for_test("claude", "session-b", "b");
+
+        let mut watcher = AccountWatcher::new(accounts.clone(), Duration::ZERO);
+        std::fs::remove_file(accounts.join("b.json")).unwrap();
+        watcher.poll(&manager).await;
+
+        assert_eq!(manager.len(), 1);
+        assert!(manager.token_for_test("b").is_none());
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-b"),
+            None
+        );
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-a"),
+            Some("a".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_file_is_skipped() {
+        let (dir, manager) = setup().await;
+        let accounts = dir.path().join("accounts");
+        let original = accounts.join("a.json");
+
+        let mut watcher = AccountWatcher::new(accounts.clone(), Duration::ZERO);
+        let backup = accounts.join("a-backup.json");
+        std::fs::copy(&original, &backup).unwrap();
+        watcher.poll(&manager).await;
+        assert_eq!(manager.len(), 1);
+        assert_eq!(manager.token_for_test("a").unwrap().account_path, original);
+
+        // Deleting the copy leaves the account in place
+        std::fs::remove_file(&backup).unwrap();
+        watcher.poll(&manager).await;
+        assert_eq!(manager.token_for_test("a").unwrap().account_path, original);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_directory_evicts_nothing() {
+        let (dir, manager) = setup().await;
+        let accounts = dir.path().join("accounts");
+        manager.bind_session_for_test("claude", "session-1", "a");
+
+        let mut watcher = AccountWatcher::new(accounts.clone(), Duration::ZERO);
+        let moved = dir.path().join("accounts-moved");
+        std::fs::rename(&accounts, &moved).unwrap();
+        watcher.poll(&manager).await;
+        watcher.poll(&manager).await;
+
+        assert_eq!(manager.len(), 1);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("a".to_string())
+        );
+
+        // Once the directory is back, its unchanged files stay as they were
+        std::fs::rename(&moved, &accounts).unwrap();
+        watcher.poll(&manager).await;
+        assert_eq!(manager.len(), 1);
+        assert_eq!(
+            manager.session_binding_for_test("claude", "session-1"),
+            Some("a".to_string())
+        );
+    }
+
+    /// Log output written to a shared buffer
+    #[derive(Clone, Default)]
+    struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watcher_logs_name_accounts_by_log_identity() {
+        use crate::proxy::token_manager::LogIdentity;
+
+        let (dir, manager) = setup().await;
+        let accounts = dir.path().join("accounts");
+        manager.set_log_identity(LogIdentity::AccountId);
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let mut watcher = AccountWatcher::new(accounts.clone(), Duration::ZERO);
+        write_account_file(&accounts, "b", None);
+        watcher.poll(&manager).await;
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("Added account b from"), "{}", logs);
+        assert!(!logs.contains("b@test.com"), "{}", logs);
+    }
+
+    #[tokio::test]
+    async fn test_changes_wait_for_debounce() {
+        let (dir, manager) = setup().await;
+        let accounts = dir.path().join("accounts");
+
+        let mut watcher = AccountWatcher::new(accounts.clone(), Duration::from_millis(50));
+        write_account_file(&accounts, "b", None);
+
+        watcher.poll(&manager).await;
+        assert_eq!(manager.len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        watcher.poll(&manager).await;
+        assert_eq!(manager.len(), 2);
+    }
+}
@@ -0,0 +1,443 @@
+//! Write-Behind of Refreshed Tokens
+//!
+//! A refreshed token takes effect in memory right away (the pool and the
+//! refresh coordinator's cache); writing it to the account file can wait.
+//! [`TokenWriter`] queues the new token and writes the file once, `delay`
+//! after the first queued change, so several refreshes of one account in
+//! that window cost a single read-modify-write. [`TokenWriter::flush`]
+//! writes everything still queued, e.g. on shutdown.
+//!
+//! The tradeoff is crash safety: a crash loses the access tokens refreshed
+//! in the last `delay`. That only costs one extra refresh after restart,
+//! since the refresh token on disk still works. A rotated refresh token is
+//! different, as the old one may stop working, so it is written right away,
+//! and a queued one is kept when a later refresh replaces the queued token.
+//!
+//! A failed write is retried with growing waits, up to
+//! `MAX_WRITE_RETRIES` times. A file that isn't a valid account is not
+//! retried at all.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use tokio::sync::Mutex;
+
+use super::refresh::TokenResponse;
+use super::storage::{AccountFileStore, INVALID_ACCOUNT_FILE};
+
+/// Default time a refreshed token waits before it is written
+pub const DEFAULT_TOKEN_WRITE_DELAY: Duration = Duration::from_secs(5);
+
+/// Shortest wait before a failed write is retried
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest wait before a failed write is retried
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Failed writes of a queued token before it is dropped
+const MAX_WRITE_RETRIES: u32 = 5;
+
+/// A refreshed token waiting to be written
+#[derive(Debug, Clone)]
+struct PendingWrite {
+    path: PathBuf,
+    access_token: String,
+    expires_in: i64,
+    expiry_timestamp: i64,
+    refresh_token: Option<String>,
+    /// Failed attempts to write this token so far
+    retries: u32,
+}
+
+impl PendingWrite {
+    /// `newer` with this write's rotated refresh token if `newer` has none,
+    /// so an unwritten rotation is never lost
+    fn merged_into(&self, newer: PendingWrite) -> PendingWrite {
+        PendingWrite {
+            refresh_token: newer.refresh_token.or_else(|| self.refresh_token.clone()),
+            ..newer
+        }
+    }
+}
+
+/// Wait before retry number `retry` (counting from 1): `base`, doubled
+/// for each further retry, at most `MAX_RETRY_DELAY`
+fn retry_delay(base: Duration, retry: u32) -> Duration {
+    let base = base.max(MIN_RETRY_DELAY);
+    base.saturating_mul(1 << retry.saturating_sub(1).min(16))
+        .min(MAX_RETRY_DELAY.max(base))
+}
+
+/// Queues refreshed tokens and writes each account file at most once per delay
+pub struct TokenWriter {
+    files: Arc<AccountFileStore>,
+    /// Latest unwritten token per account
+    pending: DashMap<String, PendingWrite>,
+    /// Held while an account's pending token is taken and written, so an
+    /// older token never lands after a newer one
+    locks: DashMap<String, Arc<Mutex<()>>>,
+    delay_ms: AtomicU64,
+    /// Account file writes done so far
+    writes: AtomicU64,
+}
+
+impl TokenWriter {
+    pub fn new(files: Arc<AccountFileStore>) -> Self {
+        Self {
+            files,
+            pending: DashMap::new(),
+            locks: DashMap::new(),
+            delay_ms: AtomicU64::new(DEFAULT_TOKEN_WRITE_DELAY.as_millis() as u64),
+            writes: AtomicU64::new(0),
+        }
+    }
+
+    /// Set how long a refreshed token waits before it is written (zero:
+    /// write at once)
+    pub fn set_delay(&self, delay: Duration) {
+        self.delay_ms
+            .store(delay.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms.load(Ordering::Relaxed))
+    }
+
+    /// Number of account file writes done so far
+    #[cfg(test)]
+    pub fn writes(&self) -> u64 {
+        self.writes.load(Ordering::Relaxed)
+    }
+
+    /// Number of accounts with a token waiting to be written
+    #[cfg(test)]
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Queue a token refreshed at `now` for the account file at `path`
+    ///
+    /// The write happens on a spawned task once the delay is over; only a
+    /// write done right away (no delay, or a rotated refresh token) can
+    /// return an error here.
+    pub async fn save(
+        self: &Arc<Self>,
+        account_id: &str,
+        path: &Path,
+        response: &TokenResponse,
+        now: i64,
+    ) -> Result<(), String> {
+        let write = PendingWrite {
+            path: path.to_path_buf(),
+            access_token: response.access_token.clone(),
+            expires_in: response.expires_in,
+            expiry_timestamp: now + response.expires_in,
+            refresh_token: response.refresh_token.clone(),
+            retries: 0,
+        };
+        let delay = self.delay();
+        let (first, rotated) = match self.pending.entry(account_id.to_string()) {
+            Entry::Occupied(mut entry) => {
+                let write = entry.get().merged_into(write);
+                let rotated = write.refresh_token.is_some();
+                entry.insert(write);
+                (false, rotated)
+            }
+            Entry::Vacant(entry) => (true, entry.insert(write).refresh_token.is_some()),
+        };
+
+        if delay.is_zero() || rotated {
+            return self.flush_account(account_id).await.map(|_| ());
+        }
+        if first {
+            self.flush_later(account_id, delay);
+        }
+        Ok(())
+    }
+
+    /// Write an account's queued token once `delay` is over
+    fn flush_later(self: &Arc<Self>, account_id: &str, delay: Duration) {
+        let writer = Arc::clone(self);
+        let account_id = account_id.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            // flush_account logs its own failures
+            let _ = writer.flush_account(&account_id).await;
+        });
+    }
+
+    /// Write an account's queued token, if any; returns whether it wrote
+    ///
+    /// A failed write is queued again, merged into any newer token queued
+    /// meanwhile, and retried after a growing wait. It is dropped once it
+    /// has failed `MAX_WRITE_RETRIES` times, or at once if the file is not
+    /// a valid account.
+    async fn flush_account(self: &Arc<Self>, account_id: &str) -> Result<bool, String> {
+        let lock = self
+            .locks
+            .entry(account_id.to_string())
+            .or_default()
+            .clone();
+        let _guard = lock.lock().await;
+
+        let Some((_, write)) = self.pending.remove(account_id) else {
+            return Ok(false);
+        };
+        let update = write.clone();
+        let result = self
+            .files
+            .update(&write.path, move |account| {
+                account.token.access_token = update.access_token;
+                account.token.expires_in = update.expires_in;
+                account.token.expiry_timestamp = update.expiry_timestamp;
+                if let Some(refresh_token) = update.refresh_token {
+                    account.token.refresh_token = refresh_token;
+                }
+            })
+            .await;
+
+        match result {
+            Ok(()) => {
+                self.writes.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!("Saved refreshed token for account {}", account_id);
+                Ok(true)
+            }
+            Err(e) if e.starts_with(INVALID_ACCOUNT_FILE) || write.retries >= MAX_WRITE_RETRIES => {
+                tracing::error!(
+                    "Dropping refreshed token for {} after {} failed write(s): {}",
+                    account_id,
+                    write.retries + 1,
+                    e
+                );
+                Err(e)
+            }
+            Err(e) => {
+                let retries = write.retries + 1;
+                match self.pending.entry(account_id.to_string()) {
+                    Entry::Occupied(mut entry) => {
+                        let newer = entry.get().clone();
+                        entry.insert(PendingWrite {
+                            retries,
+                            ..write.merged_into(newer)
+                        });
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(PendingWrite { retries, ..write });
+                    }
+                }
+                let delay = retry_delay(self.delay(), retries);
+                tracing::warn!(
+                    "Failed to save refreshed token for {}, retrying in {:?}: {}",
+                    account_id,
+                    delay,
+                    e
+                );
+                self.flush_later(account_id, delay);
+                Err(e)
+            }
+        }
+    }
+
+    /// Write every queued token now; returns the number of files written
+    pub async fn flush(self: &Arc<Self>) -> usize {
+        let account_ids: Vec<String> = self.pending.iter().map(|e| e.key().clone()).collect();
+        let mut written = 0;
+        for account_id in account_ids {
+            // flush_account logs its own failures
+            if let Ok(true) = self.flush_account(&account_id).await {
+                written += 1;
+            }
+        }
+        written
+    }
+
+    /// Drop an account's queued token, e.g. before its file is rewritten
+    /// with a newer one
+    pub fn discard(&self, account_id: &str) {
+        self.pending.remove(account_id);
+    }
+
+    /// Drop queued tokens of accounts not in `account_ids`
+    pub fn retain(&self, account_ids: &HashSet<String>) {
+        self.pending.retain(|id, _| account_ids.contains(id));
+        self.locks
+            .retain(|id, lock| account_ids.contains(id) || Arc::strong_count(lock) > 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refreshed(access_token: &str) -> TokenResponse {
+        TokenResponse {
+            access_token: access_token.to_string(),
+            expires_in: 3600,
+            refresh_token: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rapid_saves_are_coalesced_into_one_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = crate::proxy::token_manager::tests::write_account_file(dir.path(), "a", None);
+        let writer = Arc::new(TokenWriter::new(Arc::new(AccountFileStore::new())));
+        writer.set_delay(Duration::from_millis(100));
+
+        for (i, token) in ["first", "second", "final"].iter().enumerate() {
+            writer
+                .save("a", &path, &refreshed(token), 1_700_000_000 + i as i64)
+                .await
+                .unwrap();
+        }
+        assert_eq!(writer.pending(), 1);
+        assert_eq!(writer.writes(), 0);
+        assert!(std::fs::read_to_string(&path).unwrap().contains("token-a"));
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(writer.writes(), 1);
+        assert_eq!(writer.pending(), 0);
+        let account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(account["token"]["access_token"], "final");
+        assert_eq!(account["token"]["expiry_timestamp"], 1_700_000_002 + 3600);
+    }
+
+    #[tokio::test]
+    async fn test_flush_and_rotated_refresh_tokens_write_at_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = crate::proxy::token_manager::tests::write_account_file(dir.path(), "a", None);
+        let writer = Arc::new(TokenWriter::new(Arc::new(AccountFileStore::new())));
+        writer.set_delay(Duration::from_secs(3600));
+
+        writer
+            .save("a", &path, &refreshed("queued"), 1_700_000_000)
+            .await
+            .unwrap();
+        assert_eq!(writer.flush().await, 1);
+        assert_eq!(writer.flush().await, 0);
+        assert!(std::fs::read_to_string(&path).unwrap().contains("queued"));
+
+        let rotated = TokenResponse {
+            refresh_token: Some("rotated-refresh".to_string()),
+            ..refreshed("rotated")
+        };
+        writer
+            .save("a", &path, &rotated, 1_700_000_000)
+            .await
+            .unwrap();
+        assert_eq!(writer.pending(), 0);
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("rotated-refresh"));
+        assert_eq!(writer.writes(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rotated_refresh_token_survives_a_failed_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.json");
+        let writer = Arc::new(TokenWriter::new(Arc::new(AccountFileStore::new())));
+        writer.set_delay(Duration::from_secs(3600));
+
+        // The account file is missing, so the rotated token stays queued
+        let rotated = TokenResponse {
+            refresh_token: Some("rotated-refresh".to_string()),
+            ..refreshed("rotated")
+        };
+        assert!(writer
+            .save("a", &path, &rotated, 1_700_000_000)
+            .await
+            .is_err());
+        assert_eq!(writer.pending(), 1);
+
+        // A later refresh without rotation keeps the queued refresh token
+        crate::proxy::token_manager::tests::write_account_file(dir.path(), "a", None);
+        writer
+            .save("a", &path, &refreshed("later"), 1_700_000_100)
+            .await
+            .unwrap();
+        assert_eq!(writer.pending(), 0);
+        let account: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(account["token"]["access_token"], "later");
+        assert_eq!(account["token"]["refresh_token"], "rotated-refresh");
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_is_retried_without_another_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.json");
+        let writer = Arc::new(TokenWriter::new(Arc::new(AccountFileStore::new())));
+        writer.set_delay(Duration::from_millis(100));
+
+        writer
+            .save("a", &path, &refreshed("queued"), 1_700_000_000)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!((writer.writes(), writer.pending()), (0, 1));
+
+        crate::proxy::token_manager::tests::write_account_file(dir.path(), "a", None);
+        tokio::time::sleep(MIN_RETRY_DELAY + Duration::from_millis(300)).await;
+        assert_eq!((writer.writes(), writer.pending()), (1, 0));
+        assert!(std::fs::read_to_string(&path).unwrap().contains("queued"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_is_dropped_after_max_retries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.json");
+        let writer = Arc::new(TokenWriter::new(Arc::new(AccountFileStore::new())));
+        writer.set_delay(Duration::from_secs(3600));
+
+        writer
+            .save("a", &path, &refreshed("queued"), 1_700_000_000)
+            .await
+            .unwrap();
+        for _ in 0..MAX_WRITE_RETRIES {
+            assert_eq!(writer.flush().await, 0);
+            assert_eq!(writer.pending(), 1);
+        }
+        assert_eq!(writer.flush().await, 0);
+        assert_eq!(writer.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_account_file_is_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.json");
+        std::fs::write(&path, "{\"id\": ").unwrap();
+        let writer = Arc::new(TokenWriter::new(Arc::new(AccountFileStore::new())));
+        writer.set_delay(Duration::from_secs(3600));
+
+        writer
+            .save("a", &path, &refreshed("queued"), 1_700_000_000)
+            .await
+            .unwrap();
+        assert_eq!(writer.flush().await, 0);
+        assert_eq!(writer.pending(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"id\": ");
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_the_cap() {
+        let delays: Vec<u64> = (1..=8)
+            .map(|retry| retry_delay(Duration::ZERO, retry).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(
+            retry_delay(Duration::from_secs(5), 1),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            retry_delay(Duration::from_secs(120), 3),
+            Duration::from_secs(120)
+        );
+    }
+}